@@ -0,0 +1,129 @@
+//! C ABI between dms_agent and probe plugins loaded from shared libraries.
+//!
+//! Plugin is a shared library exporting following functions:
+//!
+//! * `uint32_t dms_plugin_abi_version()` - has to return `ABI_VERSION` the plugin was built with; agent checks it before using any other function
+//! * `const char *dms_plugin_name()` - name of the module provided by the plugin as used in agent configuration
+//! * `const char *dms_plugin_probe(size_t index)` - name of probe of given index or NULL past the last probe
+//! * `int dms_plugin_run(const char *probe, const char *options, const DmsCollector *collector)` - runs probe with its options
+//!   given as TOML table; returns 0 on success
+//!
+//! All strings are NUL terminated UTF-8; strings returned by the plugin have to stay valid while the plugin is loaded.
+//!
+//! Unwinding out of these functions is undefined behaviour: Rust plugins have to wrap body of `dms_plugin_run` in
+//! `std::panic::catch_unwind` and return non-zero when it panicked.
+use std::os::raw::{c_char, c_int, c_void};
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+/// Version of this ABI; changed on every incompatible change
+pub const ABI_VERSION: u32 = 1;
+
+pub const ABI_VERSION_SYMBOL: &'static [u8] = b"dms_plugin_abi_version\0";
+pub const NAME_SYMBOL: &'static [u8] = b"dms_plugin_name\0";
+pub const PROBE_SYMBOL: &'static [u8] = b"dms_plugin_probe\0";
+pub const RUN_SYMBOL: &'static [u8] = b"dms_plugin_run\0";
+
+pub type AbiVersionFn = extern "C" fn() -> u32;
+pub type NameFn = extern "C" fn() -> *const c_char;
+pub type ProbeFn = extern "C" fn(index: usize) -> *const c_char;
+pub type RunFn = extern "C" fn(probe: *const c_char, options: *const c_char, collector: *const DmsCollector) -> c_int;
+
+pub const VALUE_INTEGER: u32 = 0;
+pub const VALUE_FLOAT: u32 = 1;
+pub const VALUE_BOOL: u32 = 2;
+pub const VALUE_TEXT: u32 = 3;
+
+/// Collected value; only field matching the kind is meaningful
+#[repr(C)]
+pub struct DmsValue {
+    pub kind: u32,
+    pub integer: i64,
+    pub float: f64,
+    pub boolean: u8,
+    pub text: *const c_char
+}
+
+/// Callbacks provided by agent for single probe run
+#[repr(C)]
+pub struct DmsCollector {
+    pub context: *mut c_void,
+    /// Returns 0 if value was collected
+    pub collect: extern "C" fn(context: *mut c_void, path: *const c_char, component: *const c_char, value: *const DmsValue) -> c_int,
+    /// Reports reason of failed run
+    pub error: extern "C" fn(context: *mut c_void, message: *const c_char)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<'a> {
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Text(&'a str)
+}
+
+/// Safe wrapper of collector given to dms_plugin_run
+pub struct Collector<'a> {
+    collector: &'a DmsCollector
+}
+
+impl<'a> Collector<'a> {
+    pub unsafe fn from_ptr(collector: *const DmsCollector) -> Collector<'a> {
+        Collector {
+            collector: &*collector
+        }
+    }
+
+    pub fn collect(&self, path: &str, component: &str, value: Value) -> Result<(), String> {
+        let path = try!(CString::new(path).map_err(|_| "path contains NUL byte".to_string()));
+        let component = try!(CString::new(component).map_err(|_| "component contains NUL byte".to_string()));
+
+        let mut text = None;
+        let mut raw_value = DmsValue {
+            kind: VALUE_INTEGER,
+            integer: 0,
+            float: 0.0,
+            boolean: 0,
+            text: ptr::null()
+        };
+
+        match value {
+            Value::Integer(value) => raw_value.integer = value,
+            Value::Float(value) => {
+                raw_value.kind = VALUE_FLOAT;
+                raw_value.float = value;
+            }
+            Value::Bool(value) => {
+                raw_value.kind = VALUE_BOOL;
+                raw_value.boolean = value as u8;
+            }
+            Value::Text(value) => {
+                let value = try!(CString::new(value).map_err(|_| "text value contains NUL byte".to_string()));
+                raw_value.kind = VALUE_TEXT;
+                raw_value.text = value.as_ptr();
+                text = Some(value);
+            }
+        }
+
+        let result = (self.collector.collect)(self.collector.context, path.as_ptr(), component.as_ptr(), &raw_value);
+        drop(text);
+
+        if result != 0 {
+            return Err(format!("failed to collect {}/{}", path.to_string_lossy(), component.to_string_lossy()))
+        }
+        Ok(())
+    }
+
+    pub fn error(&self, message: &str) {
+        let message = CString::new(message.replace('\0', " ")).expect("message without NUL bytes");
+        (self.collector.error)(self.collector.context, message.as_ptr());
+    }
+}
+
+/// Reads string passed over the ABI
+pub unsafe fn from_c_str<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        return None
+    }
+    CStr::from_ptr(string).to_str().ok()
+}
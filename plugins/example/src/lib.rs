@@ -0,0 +1,71 @@
+//! Example dms_agent probe plugin providing module 'example'.
+//!
+//! Enable it by pointing `plugin_dir` in agent configuration to directory containing the built library:
+//!
+//! ```toml
+//! plugin_dir = "plugins/example/target/release"
+//!
+//! [modules.example.probes.answer]
+//! every = "10s"
+//! ```
+extern crate dms_plugin_api;
+
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::panic;
+use dms_plugin_api::*;
+
+#[no_mangle]
+pub extern "C" fn dms_plugin_abi_version() -> u32 {
+    ABI_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn dms_plugin_name() -> *const c_char {
+    b"example\0".as_ptr() as *const c_char
+}
+
+#[no_mangle]
+pub extern "C" fn dms_plugin_probe(index: usize) -> *const c_char {
+    match index {
+        0 => b"answer\0".as_ptr() as *const c_char,
+        1 => b"options\0".as_ptr() as *const c_char,
+        2 => b"failing\0".as_ptr() as *const c_char,
+        3 => b"panicking\0".as_ptr() as *const c_char,
+        _ => ptr::null()
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn dms_plugin_run(probe: *const c_char, options: *const c_char, collector: *const DmsCollector) -> c_int {
+    // panic must not unwind into the agent
+    match panic::catch_unwind(|| run(probe, options, collector)) {
+        Ok(result) => result,
+        Err(_) => {
+            unsafe { Collector::from_ptr(collector) }.error("probe panicked");
+            1
+        }
+    }
+}
+
+fn run(probe: *const c_char, options: *const c_char, collector: *const DmsCollector) -> c_int {
+    let (probe, options, collector) = unsafe {
+        (from_c_str(probe).unwrap_or(""), from_c_str(options).unwrap_or(""), Collector::from_ptr(collector))
+    };
+
+    let result = match probe {
+        "answer" => collector.collect("example/answer", "value", Value::Integer(42)),
+        "options" => collector.collect("example/options", "toml", Value::Text(options)),
+        "failing" => Err("this probe always fails".to_string()),
+        "panicking" => panic!("this probe always panics"),
+        _ => Err(format!("no probe named '{}'", probe))
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            collector.error(&err);
+            1
+        }
+    }
+}
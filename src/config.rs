@@ -0,0 +1,576 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::fmt;
+use std::error::Error;
+use time::Duration;
+use toml::{Parser, Value, Table};
+
+/// Name used in errors about default configuration
+const DEFAULT_CONFIG_FILE: &'static str = "<default>";
+
+/// Configuration used when no configuration file was given
+pub const DEFAULT_CONFIG: &'static str = r#"
+location = "localhost"
+
+[modules.hello_world.probes.hello]
+every = "1s"
+timeout = "500ms"
+"#;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, String),
+    MissingKey(PathBuf, String),
+    InvalidValue(PathBuf, String, String),
+    UnknownModule(PathBuf, String),
+    UnknownProbe(PathBuf, String),
+    NoProbes(PathBuf)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ConfigError::Io(ref file, ref err) => write!(f, "{}: {}: {}", self.description(), file.display(), err),
+            &ConfigError::Parse(ref file, ref err) => write!(f, "{}: {}:{}", self.description(), file.display(), err),
+            &ConfigError::MissingKey(ref file, ref key) => write!(f, "{}: {}: missing required key '{}'", self.description(), file.display(), key),
+            &ConfigError::InvalidValue(ref file, ref key, ref err) => write!(f, "{}: {}: key '{}': {}", self.description(), file.display(), key, err),
+            &ConfigError::UnknownModule(ref file, ref key) => write!(f, "{}: {}: key '{}': no such module", self.description(), file.display(), key),
+            &ConfigError::UnknownProbe(ref file, ref key) => write!(f, "{}: {}: key '{}': module has no such probe", self.description(), file.display(), key),
+            &ConfigError::NoProbes(ref file) => write!(f, "{}: {}: no probes configured", self.description(), file.display())
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn description(&self) -> &str {
+        "Configuration error"
+    }
+}
+
+/// Probe or module specific configuration values
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    file: PathBuf,
+    key: String,
+    table: Table
+}
+
+impl Options {
+    fn new(file: &Path, key: String, table: Table) -> Options {
+        Options {
+            file: file.to_path_buf(),
+            key: key,
+            table: table
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}.{}", self.key, name)
+    }
+
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.table.get(name)
+    }
+
+    fn wrong_type(&self, name: &str, expected: &str, value: &Value) -> ConfigError {
+        self.error(name, format!("expected {} but got {}", expected, value.type_str()))
+    }
+
+    /// Error about value of given key
+    pub fn error<M>(&self, name: &str, message: M) -> ConfigError where M: Into<String> {
+        ConfigError::InvalidValue(self.file.clone(), self.key(name), message.into())
+    }
+
+    pub fn string(&self, name: &str) -> Result<Option<&str>, ConfigError> {
+        match self.get(name) {
+            None => Ok(None),
+            Some(&Value::String(ref value)) => Ok(Some(value)),
+            Some(value) => Err(self.wrong_type(name, "string", value))
+        }
+    }
+
+    pub fn strings(&self, name: &str) -> Result<Option<Vec<String>>, ConfigError> {
+        match self.get(name) {
+            None => Ok(None),
+            Some(&Value::Array(ref values)) => {
+                let mut strings = Vec::with_capacity(values.len());
+                for value in values {
+                    match value {
+                        &Value::String(ref value) => strings.push(value.clone()),
+                        value => return Err(self.wrong_type(name, "array of strings", value))
+                    }
+                }
+                Ok(Some(strings))
+            }
+            Some(value) => Err(self.wrong_type(name, "array of strings", value))
+        }
+    }
+
+    pub fn integer(&self, name: &str) -> Result<Option<i64>, ConfigError> {
+        match self.get(name) {
+            None => Ok(None),
+            Some(&Value::Integer(value)) => Ok(Some(value)),
+            Some(value) => Err(self.wrong_type(name, "integer", value))
+        }
+    }
+
+    pub fn boolean(&self, name: &str) -> Result<Option<bool>, ConfigError> {
+        match self.get(name) {
+            None => Ok(None),
+            Some(&Value::Boolean(value)) => Ok(Some(value)),
+            Some(value) => Err(self.wrong_type(name, "boolean", value))
+        }
+    }
+
+    /// Nested tables by name, e.g. `[modules.foo.probes.bar.rules.baz]` for name `rules`
+    pub fn tables(&self, name: &str) -> Result<Vec<(String, Options)>, ConfigError> {
+        match self.get(name) {
+            None => Ok(Vec::new()),
+            Some(&Value::Table(ref table)) => {
+                let mut tables = Vec::with_capacity(table.len());
+                for (key, value) in table {
+                    match value {
+                        &Value::Table(ref value) => tables.push((key.clone(), Options::new(&self.file, format!("{}.{}", self.key(name), key), value.clone()))),
+                        value => return Err(self.wrong_type(&format!("{}.{}", name, key), "table", value))
+                    }
+                }
+                Ok(tables)
+            }
+            Some(value) => Err(self.wrong_type(name, "table", value))
+        }
+    }
+
+    /// Options formatted as TOML table
+    pub fn to_toml(&self) -> String {
+        Value::Table(self.table.clone()).to_string()
+    }
+
+    /// Duration given as string with unit, e.g. "500ms", "10s", "5m" or "1h"
+    pub fn duration(&self, name: &str) -> Result<Option<Duration>, ConfigError> {
+        match try!(self.string(name)) {
+            None => Ok(None),
+            Some(value) => parse_duration(value).map(Some).map_err(|err| self.error(name, err))
+        }
+    }
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let unit_at = value.find(|c: char| !c.is_digit(10)).unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_at);
+
+    let number: i64 = try!(number.parse().map_err(|_| format!("invalid duration '{}'; expected number followed by unit, e.g. 10s", value)));
+    let duration = match unit {
+        "ms" => Duration::milliseconds(number),
+        "s" => Duration::seconds(number),
+        "m" => Duration::minutes(number),
+        "h" => Duration::hours(number),
+        _ => return Err(format!("invalid duration unit in '{}'; expected one of: ms, s, m, h", value))
+    };
+
+    if duration <= Duration::zero() {
+        return Err(format!("duration '{}' is not greater than zero", value))
+    }
+    Ok(duration)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeConfig {
+    pub name: String,
+    pub every: Duration,
+    pub timeout: Duration,
+    pub options: Options
+}
+
+impl ProbeConfig {
+    /// Error reported by module that does not provide probe of this name
+    pub fn unknown(&self) -> ConfigError {
+        ConfigError::UnknownProbe(self.options.file.clone(), self.options.key.clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleConfig {
+    pub name: String,
+    pub probes: Vec<ProbeConfig>,
+    pub options: Options
+}
+
+impl ModuleConfig {
+    /// Error reported when there is no module of this name
+    pub fn unknown(&self) -> ConfigError {
+        ConfigError::UnknownModule(self.options.file.clone(), self.options.key.clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// File configuration was loaded from; None for default configuration
+    pub file: Option<PathBuf>,
+    pub location: String,
+    /// Directory to load plugin modules from
+    pub plugin_dir: Option<PathBuf>,
+    /// Enabled modules
+    pub modules: Vec<ModuleConfig>
+}
+
+impl Config {
+    pub fn load<P>(file: P) -> Result<Config, ConfigError> where P: AsRef<Path> {
+        let file = file.as_ref();
+        let mut text = String::new();
+        try!(File::open(file).and_then(|mut f| f.read_to_string(&mut text)).map_err(|err| ConfigError::Io(file.to_path_buf(), err)));
+
+        let mut config = try!(Config::parse(file, &text));
+        config.file = Some(file.to_path_buf());
+        Ok(config)
+    }
+
+    /// Error about value of given top level key
+    pub fn error<M>(&self, key: &str, message: M) -> ConfigError where M: Into<String> {
+        let file = self.file.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE));
+        ConfigError::InvalidValue(file, key.to_string(), message.into())
+    }
+
+    /// Configuration of enabled module of given name
+    pub fn module(&self, name: &str) -> Option<&ModuleConfig> {
+        self.modules.iter().find(|module| module.name == name)
+    }
+
+    /// Configuration with only given module enabled formatted as TOML; plugin directory is given as resolved path
+    pub fn module_toml(&self, name: &str) -> String {
+        let mut table = Table::new();
+        table.insert("location".to_string(), Value::String(self.location.clone()));
+        if let Some(ref plugin_dir) = self.plugin_dir {
+            table.insert("plugin_dir".to_string(), Value::String(plugin_dir.to_string_lossy().into_owned()));
+        }
+
+        let mut modules = Table::new();
+        if let Some(module) = self.module(name) {
+            let mut probes = Table::new();
+            for probe in &module.probes {
+                let mut probe_table = probe.options.table.clone();
+                probe_table.insert("every".to_string(), Value::String(format!("{}ms", probe.every.num_milliseconds())));
+                probe_table.insert("timeout".to_string(), Value::String(format!("{}ms", probe.timeout.num_milliseconds())));
+                probes.insert(probe.name.clone(), Value::Table(probe_table));
+            }
+
+            let mut module_table = module.options.table.clone();
+            module_table.insert("probes".to_string(), Value::Table(probes));
+            modules.insert(module.name.clone(), Value::Table(module_table));
+        }
+        table.insert("modules".to_string(), Value::Table(modules));
+
+        Value::Table(table).to_string()
+    }
+
+    pub fn parse(file: &Path, text: &str) -> Result<Config, ConfigError> {
+        let mut parser = Parser::new(text);
+        let table = match parser.parse() {
+            Some(table) => table,
+            None => {
+                let errors: Vec<String> = parser.errors.iter().map(|err| {
+                    let (line, column) = parser.to_linecol(err.lo);
+                    format!("{}:{}: {}", line + 1, column + 1, err.desc)
+                }).collect();
+                return Err(ConfigError::Parse(file.to_path_buf(), errors.join("; ")))
+            }
+        };
+
+        for key in table.keys() {
+            match key.as_str() {
+                "location" | "plugin_dir" | "modules" => (),
+                _ => return Err(ConfigError::InvalidValue(file.to_path_buf(), key.clone(), "unknown key".to_string()))
+            }
+        }
+
+        let location = match table.get("location") {
+            Some(&Value::String(ref location)) => location.clone(),
+            Some(value) => return Err(ConfigError::InvalidValue(file.to_path_buf(), "location".to_string(), format!("expected string but got {}", value.type_str()))),
+            None => return Err(ConfigError::MissingKey(file.to_path_buf(), "location".to_string()))
+        };
+
+        let plugin_dir = match table.get("plugin_dir") {
+            Some(&Value::String(ref plugin_dir)) => Some(file.parent().unwrap_or(Path::new("")).join(plugin_dir)),
+            Some(value) => return Err(ConfigError::InvalidValue(file.to_path_buf(), "plugin_dir".to_string(), format!("expected string but got {}", value.type_str()))),
+            None => None
+        };
+
+        let mut modules = Vec::new();
+        for (name, value) in try!(Config::tables(file, "modules", table.get("modules"))) {
+            let module_key = format!("modules.{}", name);
+            let mut options = Options::new(file, module_key.clone(), value);
+
+            if let Some(false) = try!(options.boolean("enabled")) {
+                continue
+            }
+            options.table.remove("enabled");
+
+            let mut probes = Vec::new();
+            for (probe_name, probe_value) in try!(Config::tables(file, &format!("{}.probes", module_key), options.table.remove("probes").as_ref())) {
+                let mut probe_options = Options::new(file, format!("{}.probes.{}", module_key, probe_name), probe_value);
+
+                let every = match try!(probe_options.duration("every")) {
+                    Some(every) => every,
+                    None => return Err(ConfigError::MissingKey(file.to_path_buf(), probe_options.key("every")))
+                };
+                let timeout = try!(probe_options.duration("timeout")).unwrap_or(every);
+
+                probe_options.table.remove("every");
+                probe_options.table.remove("timeout");
+
+                probes.push(ProbeConfig {
+                    name: probe_name,
+                    every: every,
+                    timeout: timeout,
+                    options: probe_options
+                });
+            }
+
+            modules.push(ModuleConfig {
+                name: name,
+                probes: probes,
+                options: options
+            });
+        }
+
+        if modules.iter().all(|module| module.probes.is_empty()) {
+            return Err(ConfigError::NoProbes(file.to_path_buf()))
+        }
+
+        Ok(Config {
+            file: None,
+            location: location,
+            plugin_dir: plugin_dir,
+            modules: modules
+        })
+    }
+
+    fn tables(file: &Path, key: &str, value: Option<&Value>) -> Result<Vec<(String, Table)>, ConfigError> {
+        let table = match value {
+            None => return Ok(Vec::new()),
+            Some(&Value::Table(ref table)) => table,
+            Some(value) => return Err(ConfigError::InvalidValue(file.to_path_buf(), key.to_string(), format!("expected table but got {}", value.type_str())))
+        };
+
+        let mut tables = Vec::new();
+        for (name, value) in table {
+            match value {
+                &Value::Table(ref value) => tables.push((name.clone(), value.clone())),
+                value => return Err(ConfigError::InvalidValue(file.to_path_buf(), format!("{}.{}", key, name), format!("expected table but got {}", value.type_str())))
+            }
+        }
+        Ok(tables)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::parse(Path::new(DEFAULT_CONFIG_FILE), DEFAULT_CONFIG).expect("valid default configuration")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use std::path::Path;
+    pub use time::Duration;
+
+    fn parse(text: &str) -> Result<Config, ConfigError> {
+        Config::parse(Path::new("agent.toml"), text)
+    }
+
+    #[test]
+    fn should_parse_modules_and_probes() {
+        let config = parse(r#"
+            location = "web-01"
+
+            [modules.foo]
+            bar = "baz"
+
+            [modules.foo.probes.p1]
+            every = "10s"
+            timeout = "500ms"
+            path = "/tmp"
+
+            [modules.foo.probes.p2]
+            every = "1m"
+
+            [modules.disabled]
+            enabled = false
+
+            [modules.disabled.probes.p1]
+            every = "10s"
+        "#).unwrap();
+
+        assert_eq!(config.location, "web-01".to_string());
+        assert_eq!(config.modules.len(), 1);
+
+        let module = &config.modules[0];
+        assert_eq!(module.name, "foo".to_string());
+        assert_eq!(module.options.string("bar").unwrap(), Some("baz"));
+        assert_eq!(module.probes.iter().map(|probe| (probe.name.as_str(), probe.every, probe.timeout)).collect::<Vec<_>>(), vec![
+            ("p1", Duration::seconds(10), Duration::milliseconds(500)),
+            ("p2", Duration::minutes(1), Duration::minutes(1))
+        ]);
+        assert_eq!(module.probes[0].options.string("path").unwrap(), Some("/tmp"));
+        assert_eq!(module.probes[0].options.string("every").unwrap(), None);
+    }
+
+    #[test]
+    fn should_resolve_plugin_dir_relative_to_config_file() {
+        let config = Config::parse(Path::new("/etc/dms/agent.toml"), r#"
+            location = "web-01"
+            plugin_dir = "plugins"
+
+            [modules.foo.probes.p1]
+            every = "10s"
+        "#).unwrap();
+        assert_eq!(config.plugin_dir, Some(Path::new("/etc/dms/plugins").to_path_buf()));
+
+        let config = Config::parse(Path::new("/etc/dms/agent.toml"), r#"
+            location = "web-01"
+            plugin_dir = "/usr/lib/dms/plugins"
+
+            [modules.foo.probes.p1]
+            every = "10s"
+        "#).unwrap();
+        assert_eq!(config.plugin_dir, Some(Path::new("/usr/lib/dms/plugins").to_path_buf()));
+    }
+
+    #[test]
+    fn should_parse_string_arrays() {
+        let config = parse(r#"
+            location = "web-01"
+
+            [modules.foo.probes.p1]
+            every = "10s"
+            args = ["-w", "10%"]
+            bad = "-w"
+        "#).unwrap();
+
+        let options = &config.modules[0].probes[0].options;
+        assert_eq!(options.strings("args").unwrap(), Some(vec!["-w".to_string(), "10%".to_string()]));
+        assert_eq!(options.strings("missing").unwrap(), None);
+        assert_eq!(options.strings("bad").unwrap_err().to_string(), "Configuration error: agent.toml: key 'modules.foo.probes.p1.bad': expected array of strings but got string".to_string());
+    }
+
+    #[test]
+    fn should_parse_nested_tables() {
+        let config = parse(r#"
+            location = "web-01"
+
+            [modules.foo.probes.p1]
+            every = "10s"
+            bad = "baz"
+
+            [modules.foo.probes.p1.rules.r1]
+            pattern = "a"
+
+            [modules.foo.probes.p1.rules.r2]
+            pattern = "b"
+        "#).unwrap();
+
+        let options = &config.modules[0].probes[0].options;
+        let rules = options.tables("rules").unwrap();
+        assert_eq!(rules.iter().map(|&(ref name, ref rule)| (name.as_str(), rule.string("pattern").unwrap())).collect::<Vec<_>>(), vec![
+            ("r1", Some("a")),
+            ("r2", Some("b"))
+        ]);
+        assert_eq!(rules[0].1.error("pattern", "bad").to_string(), "Configuration error: agent.toml: key 'modules.foo.probes.p1.rules.r1.pattern': bad".to_string());
+        assert!(options.tables("missing").unwrap().is_empty());
+        assert!(options.tables("bad").is_err());
+    }
+
+    #[test]
+    fn should_format_single_module_as_toml() {
+        let config = Config::parse(Path::new("/etc/dms/agent.toml"), r#"
+            location = "web-01"
+            plugin_dir = "plugins"
+
+            [modules.foo]
+            bar = "baz"
+
+            [modules.foo.probes.p1]
+            every = "10s"
+            timeout = "500ms"
+            args = ["-w", "10%"]
+
+            [modules.other.probes.p1]
+            every = "1m"
+        "#).unwrap();
+
+        let module_config = Config::parse(Path::new("child.toml"), &config.module_toml("foo")).unwrap();
+        assert_eq!(module_config.location, "web-01".to_string());
+        assert_eq!(module_config.plugin_dir, Some(Path::new("/etc/dms/plugins").to_path_buf()));
+        assert_eq!(module_config.modules.len(), 1);
+
+        let module = &module_config.modules[0];
+        assert_eq!(module.name, "foo".to_string());
+        assert_eq!(module.options.string("bar").unwrap(), Some("baz"));
+        assert_eq!(module.probes.iter().map(|probe| (probe.name.as_str(), probe.every, probe.timeout)).collect::<Vec<_>>(), vec![
+            ("p1", Duration::seconds(10), Duration::milliseconds(500))
+        ]);
+        assert_eq!(module.probes[0].options.strings("args").unwrap(), Some(vec!["-w".to_string(), "10%".to_string()]));
+    }
+
+    #[test]
+    fn should_parse_default_config() {
+        let config = Config::default();
+        assert!(config.file.is_none());
+        assert_eq!(config.modules[0].name, "hello_world".to_string());
+    }
+
+    #[test]
+    fn should_name_file_and_key_of_invalid_value() {
+        let err = parse(r#"
+            location = "web-01"
+
+            [modules.foo.probes.p1]
+            every = "10 parsecs"
+        "#).unwrap_err();
+
+        assert!(err.to_string().starts_with("Configuration error: agent.toml: key 'modules.foo.probes.p1.every': invalid duration"), err.to_string());
+    }
+
+    #[test]
+    fn should_report_missing_keys() {
+        match parse("[modules.foo.probes.p1]\nevery = \"1s\"").unwrap_err() {
+            ConfigError::MissingKey(_, key) => assert_eq!(key, "location".to_string()),
+            err => panic!("unexpected error: {}", err)
+        }
+
+        match parse("location = \"web-01\"\n[modules.foo.probes.p1]\ntimeout = \"1s\"").unwrap_err() {
+            ConfigError::MissingKey(_, key) => assert_eq!(key, "modules.foo.probes.p1.every".to_string()),
+            err => panic!("unexpected error: {}", err)
+        }
+    }
+
+    #[test]
+    fn should_report_wrong_value_types() {
+        let err = parse("location = \"web-01\"\n[modules.foo.probes.p1]\nevery = 10").unwrap_err();
+        assert_eq!(err.to_string(), "Configuration error: agent.toml: key 'modules.foo.probes.p1.every': expected string but got integer".to_string());
+
+        let err = parse("location = \"web-01\"\nmodules = 1").unwrap_err();
+        assert_eq!(err.to_string(), "Configuration error: agent.toml: key 'modules': expected table but got integer".to_string());
+    }
+
+    #[test]
+    fn should_report_syntax_error_position() {
+        let err = parse("location = \"web-01\"\n[modules.foo\n").unwrap_err();
+        assert!(err.to_string().starts_with("Configuration error: agent.toml:2:"), err.to_string());
+    }
+
+    #[test]
+    fn should_require_some_probes() {
+        match parse("location = \"web-01\"\n[modules.foo]\n").unwrap_err() {
+            ConfigError::NoProbes(_) => (),
+            err => panic!("unexpected error: {}", err)
+        }
+    }
+
+    #[test]
+    fn should_reject_non_positive_durations() {
+        assert!(parse("location = \"web-01\"\n[modules.foo.probes.p1]\nevery = \"0s\"").is_err());
+    }
+}
@@ -9,6 +9,15 @@ extern crate nanomsg;
 extern crate url;
 extern crate chan;
 extern crate chan_signal;
+extern crate rustc_serialize;
+extern crate toml;
+extern crate libloading;
+extern crate dms_plugin_api;
+extern crate regex;
+extern crate libc;
+extern crate native_tls;
+#[cfg(test)]
+extern crate tempdir;
 
 extern crate capnp;
 extern crate capnpc;
@@ -16,6 +25,8 @@ extern crate capnpc;
 extern crate token_scheduler;
 
 use std::str::FromStr;
+use std::path::PathBuf;
+use std::time::Duration;
 use std::sync::mpsc::{channel, Receiver};
 use clap::{App, Arg};
 use url::Url;
@@ -28,19 +39,21 @@ mod raw_data_point_capnp {
 
 mod program;
 mod messaging;
+mod config;
 mod sender;
 mod producer;
 
 use program::Signal;
-use sender::Sender;
+use config::Config;
+use sender::{Sender, SpoolConfig, OverflowPolicy, BackPressure};
 
-fn dms_agent(signals: &Receiver<Signal>, processor_url: &Url) -> Result<(), (String, i32)> {
-    //TODO: don't panic on wrong processor address + shutdown correctly
-    let sender = Sender::start(processor_url.to_owned()).unwrap();
+fn dms_agent(signals: &Receiver<Signal>, processor_url: &Url, config: Config, spool_config: SpoolConfig, back_pressure: BackPressure) -> Result<(), (String, i32)> {
+    let modules = try!(producer::modules(&config).map_err(|err| (err.to_string(), 2)));
+    let sender = try!(Sender::start(processor_url.to_owned(), spool_config, back_pressure).map_err(|err| (format!("Failed to start sender: {}", err), 2)));
 
     let collector = sender.collector();
     let (producer_signal, producer_signals) = channel();
-    let producer = producer::spawn(collector, producer_signals);
+    let producer = producer::spawn(collector, producer_signals, config, modules);
 
     loop {
         match signals.recv() {
@@ -61,7 +74,6 @@ fn dms_agent(signals: &Receiver<Signal>, processor_url: &Url) -> Result<(), (Str
 }
 
 //TODO: update capnp
-//TODO: pass location arg to raw data points
 //TODO: set timestamp on raw data points to current batch
 fn main() {
 
@@ -75,16 +87,58 @@ fn main() {
              .value_name("LOG_LEVEL_SPEC")
              .help("Logging level specification, e.g: [info]")
              .takes_value(true))
+        .arg(Arg::with_name("config")
+             .short("f")
+             .long("config")
+             .value_name("FILE")
+             .help("TOML file configuring location, modules and probe schedules [built-in hello world configuration]")
+             .takes_value(true))
         .arg(Arg::with_name("processor-url")
              .short("c")
              .long("processor-url")
              .value_name("URL")
              .help("Nanomsg URL to raw data processor [ipc:///tmp/dms_processor.ipc]")
              .takes_value(true))
+        .arg(Arg::with_name("spool-dir")
+             .long("spool-dir")
+             .value_name("DIR")
+             .help("Directory to spool raw data points to when processor is not available [/tmp/dms_agent_spool]")
+             .takes_value(true))
+        .arg(Arg::with_name("spool-max-size")
+             .long("spool-max-size")
+             .value_name("MIB")
+             .help("Maximum disk space used by spool in MiB [100]")
+             .takes_value(true))
+        .arg(Arg::with_name("spool-overflow-policy")
+             .long("spool-overflow-policy")
+             .value_name("POLICY")
+             .help("What to drop when spool is full: drop-oldest or drop-newest [drop-oldest]")
+             .takes_value(true))
+        .arg(Arg::with_name("back-pressure")
+             .long("back-pressure")
+             .value_name("POLICY")
+             .help("What probes do when sender can't keep up: block[:TIMEOUT_MS], drop-newest, drop-oldest or sample:N [block:1000]")
+             .takes_value(true))
+        .arg(Arg::with_name("run-probe")
+             .long("run-probe")
+             .value_name("PROBE")
+             .help("Run single probe configured on stdin writing collected data to stdout; used for probes running in dedicated process")
+             .hidden(true)
+             .takes_value(true))
         .get_matches();
 
     let signals = program::init(Some(args.value_of("log-spec").unwrap_or("info")));
 
+    if let Some(probe) = args.value_of("run-probe") {
+        producer::run_probe_process(probe).unwrap_or_else(|err| program::exit_with_error(format!("Probe '{}' failed: {}", probe, err), 1));
+        return
+    }
+
+    let config = match args.value_of("config") {
+        Some(file) => Config::load(file).unwrap_or_else(|err| program::exit_with_error(err.to_string(), 2)),
+        None => Config::default()
+    };
+
     let processor_url = value_t!(args, "processor-url", Url).unwrap_or_else(|err|
         match err.kind {
             clap::ErrorKind::ArgumentNotFound => FromStr::from_str("ipc:///tmp/rdms_data_store.ipc").unwrap(),
@@ -92,7 +146,34 @@ fn main() {
         }
     );
 
-    dms_agent(&signals, &processor_url).unwrap_or_else(|(err, code)| program::exit_with_error(err, code));
+    let spool_max_size = value_t!(args, "spool-max-size", u64).unwrap_or_else(|err|
+        match err.kind {
+            clap::ErrorKind::ArgumentNotFound => 100,
+            _ => err.exit()
+        }
+    );
+
+    let spool_overflow_policy = value_t!(args, "spool-overflow-policy", OverflowPolicy).unwrap_or_else(|err|
+        match err.kind {
+            clap::ErrorKind::ArgumentNotFound => OverflowPolicy::DropOldest,
+            _ => err.exit()
+        }
+    );
+
+    let spool_config = SpoolConfig {
+        dir: PathBuf::from(args.value_of("spool-dir").unwrap_or("/tmp/dms_agent_spool")),
+        max_size: spool_max_size * 1024 * 1024,
+        overflow_policy: spool_overflow_policy
+    };
+
+    let back_pressure = value_t!(args, "back-pressure", BackPressure).unwrap_or_else(|err|
+        match err.kind {
+            clap::ErrorKind::ArgumentNotFound => BackPressure::Block(Duration::from_millis(1000)),
+            _ => err.exit()
+        }
+    );
+
+    dms_agent(&signals, &processor_url, config, spool_config, back_pressure).unwrap_or_else(|(err, code)| program::exit_with_error(err, code));
 
     info!("Exiting cleanly");
 }
@@ -0,0 +1,118 @@
+#[macro_use]
+extern crate clap;
+#[macro_use]
+extern crate log;
+extern crate flexi_logger;
+extern crate time;
+extern crate chrono;
+extern crate nanomsg;
+extern crate url;
+extern crate chan;
+extern crate chan_signal;
+extern crate rustc_serialize;
+
+extern crate capnp;
+extern crate capnpc;
+
+#[cfg(test)]
+extern crate tempdir;
+
+use std::str::FromStr;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use clap::{App, Arg};
+use url::Url;
+
+// this needs to be in root module, see: https://github.com/dwrensha/capnproto-rust/issues/16
+#[allow(dead_code)]
+mod raw_data_point_capnp {
+    include!(concat!(env!("OUT_DIR"), "/messaging/schema/raw_data_point_capnp.rs"));
+}
+
+mod program;
+mod messaging;
+mod storage;
+mod receiver;
+mod query;
+
+use program::Signal;
+use storage::Storage;
+use receiver::DataReceiver;
+use query::QueryServer;
+
+fn dms_processor(signals: &Receiver<Signal>, listen_url: &Url, query_url: &Url, data_dir: &PathBuf) -> Result<(), (String, i32)> {
+    let storage = try!(Storage::open(data_dir).map_err(|err| (format!("Failed to open storage at '{}': {}", data_dir.display(), err), 2)));
+    let storage = Arc::new(Mutex::new(storage));
+
+    let receiver = try!(DataReceiver::start(listen_url.to_owned(), storage.clone()).map_err(|err| (format!("Failed to start receiver on '{}': {}", listen_url, err), 3)));
+    let query_server = try!(QueryServer::start(query_url.to_owned(), storage.clone()).map_err(|err| (format!("Failed to start query server on '{}': {}", query_url, err), 3)));
+
+    loop {
+        match signals.recv() {
+            Ok(Signal::Reload) => {
+                info!("Nothing to reload");
+            }
+            Err(_) => {
+                receiver.stop();
+                query_server.stop();
+                break
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = App::new("Distributed Monitoring System Processor")
+        .version(crate_version!())
+        .author("Jakub Pastuszek <jpastuszek@whatclinic.com>")
+        .about("Receives raw measurement data from agents, stores it on disk and serves queries over it")
+        .arg(Arg::with_name("log-spec")
+             .short("l")
+             .long("log-sepc")
+             .value_name("LOG_LEVEL_SPEC")
+             .help("Logging level specification, e.g: [info]")
+             .takes_value(true))
+        .arg(Arg::with_name("listen-url")
+             .short("u")
+             .long("listen-url")
+             .value_name("URL")
+             .help("Nanomsg URL to receive raw data points on [ipc:///tmp/rdms_data_store.ipc]")
+             .takes_value(true))
+        .arg(Arg::with_name("query-url")
+             .short("q")
+             .long("query-url")
+             .value_name("URL")
+             .help("Nanomsg URL to serve queries on [ipc:///tmp/rdms_query.ipc]")
+             .takes_value(true))
+        .arg(Arg::with_name("data-dir")
+             .short("d")
+             .long("data-dir")
+             .value_name("DIR")
+             .help("Directory to store received data in [/tmp/rdms_data_store]")
+             .takes_value(true))
+        .get_matches();
+
+    let signals = program::init(Some(args.value_of("log-spec").unwrap_or("info")));
+
+    let listen_url = value_t!(args, "listen-url", Url).unwrap_or_else(|err|
+        match err.kind {
+            clap::ErrorKind::ArgumentNotFound => FromStr::from_str("ipc:///tmp/rdms_data_store.ipc").unwrap(),
+            _ => err.exit()
+        }
+    );
+
+    let query_url = value_t!(args, "query-url", Url).unwrap_or_else(|err|
+        match err.kind {
+            clap::ErrorKind::ArgumentNotFound => FromStr::from_str("ipc:///tmp/rdms_query.ipc").unwrap(),
+            _ => err.exit()
+        }
+    );
+
+    let data_dir = PathBuf::from(args.value_of("data-dir").unwrap_or("/tmp/rdms_data_store"));
+
+    dms_processor(&signals, &listen_url, &query_url, &data_dir).unwrap_or_else(|(err, code)| program::exit_with_error(err, code));
+
+    info!("Exiting cleanly");
+}
@@ -1,5 +1,7 @@
 use super::super::serde::*;
 use std::str::FromStr;
+use std::collections::BTreeMap;
+use rustc_serialize::json::Json;
 
 #[derive(Debug)]
 pub struct MessageHeader {
@@ -17,7 +19,15 @@ impl SerDeMessage for MessageHeader {
                 let data_type = self.data_type.to_string();
                 Ok(format!("{}/{}\n{}\n{}\n\n", data_type, self.topic, self.version, encoding).into_bytes())
             },
-            _ => unimplemented!()
+            Encoding::Json => {
+                let mut object = BTreeMap::new();
+                object.insert("data_type".to_string(), Json::String(self.data_type.to_string()));
+                object.insert("topic".to_string(), Json::String(self.topic.clone()));
+                object.insert("version".to_string(), Json::U64(self.version as u64));
+                object.insert("encoding".to_string(), Json::String(self.encoding.to_string()));
+                Ok(Json::Object(object).to_string().into_bytes())
+            },
+            _ => Err(SerializationError::new(SerDeErrorKind::EncodingNotImplemented(encoding)))
         }
     }
 
@@ -84,9 +94,22 @@ impl SerDeMessage for MessageHeader {
                     encoding: encoding
                 })
             },
-            _ => {
-                unimplemented!()
-            }
+            Encoding::Json => {
+                let json = try!(json_from_bytes(bytes));
+
+                let version = try!(json_u64_field(&json, "version"));
+                if version > u8::max_value() as u64 {
+                    return Err(DeserializationError::new(SerDeErrorKind::InvalidFieldValue("version", version.to_string())))
+                }
+
+                Ok(MessageHeader {
+                    data_type: try!(DataType::from_str(&*try!(json_string_field(&json, "data_type")))),
+                    topic: try!(json_string_field(&json, "topic")),
+                    version: version as u8,
+                    encoding: try!(Encoding::from_str(&*try!(json_string_field(&json, "encoding"))))
+                })
+            },
+            _ => Err(DeserializationError::new(SerDeErrorKind::EncodingNotImplemented(encoding)))
         }
     }
 }
@@ -213,6 +236,48 @@ mod test {
                 }
             }
         }
+
+        mod json_encoding {
+            pub use super::*;
+
+            #[test]
+            fn should_be_serializable() {
+                let header = MessageHeader {
+                    data_type: DataType::RawDataPoint,
+                    topic: "hello".to_string(),
+                    version: 42,
+                    encoding: Encoding::Json
+                };
+
+                let bytes = header.to_bytes(Encoding::Json).unwrap();
+                assert_eq!(bytes, r#"{"data_type":"RawDataPoint","encoding":"json","topic":"hello","version":42}"#.to_string().into_bytes());
+            }
+
+            #[test]
+            fn should_deserialize_correctly_formated_message_header() {
+                let bytes = r#"{"data_type": "RawDataPoint", "topic": "", "version": 42, "encoding": "capnp"}"#.to_string().into_bytes();
+
+                let header = MessageHeader::from_bytes(&bytes, Encoding::Json).unwrap();
+                assert_eq!(header.data_type, DataType::RawDataPoint);
+                assert_eq!(header.topic, "".to_string());
+                assert_eq!(header.version, 42);
+                assert_eq!(header.encoding, Encoding::Capnp);
+            }
+
+            #[test]
+            fn should_provide_error_when_field_is_missing() {
+                let bytes = r#"{"data_type": "RawDataPoint", "topic": "", "version": 42}"#.to_string().into_bytes();
+                let result = MessageHeader::from_bytes(&bytes, Encoding::Json);
+                assert_error_display_message!(result, "failed to deserializae message for type MessageHeader: no encoding field found in JSON object");
+            }
+
+            #[test]
+            fn should_provide_error_when_version_does_not_fit_u8() {
+                let bytes = r#"{"data_type": "RawDataPoint", "topic": "", "version": 300, "encoding": "capnp"}"#.to_string().into_bytes();
+                let result = MessageHeader::from_bytes(&bytes, Encoding::Json);
+                assert_error_display_message!(result, "failed to deserializae message for type MessageHeader: invalid version value: \"300\"");
+            }
+        }
     }
 }
 
@@ -1,6 +1,10 @@
 pub use self::raw_data_point::*;
+pub use self::raw_data_point_batch::*;
 pub use self::message_header::*;
+pub use self::query::*;
 
 mod raw_data_point;
+mod raw_data_point_batch;
 mod message_header;
+mod query;
 
@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use chrono::{DateTime, UTC, Duration};
+use rustc_serialize::json::Json;
+
+use super::super::serde::*;
+use super::raw_data_point::DataValue;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aggregation {
+    Min,
+    Max,
+    Avg,
+    Sum,
+    Count,
+    Last,
+    Rate
+}
+
+impl ToString for Aggregation {
+    fn to_string(&self) -> String {
+        match self {
+            &Aggregation::Min => "min".to_string(),
+            &Aggregation::Max => "max".to_string(),
+            &Aggregation::Avg => "avg".to_string(),
+            &Aggregation::Sum => "sum".to_string(),
+            &Aggregation::Count => "count".to_string(),
+            &Aggregation::Last => "last".to_string(),
+            &Aggregation::Rate => "rate".to_string(),
+        }
+    }
+}
+
+impl FromStr for Aggregation {
+    type Err = SerDeErrorKind;
+    fn from_str(string: &str) -> Result<Self, SerDeErrorKind> {
+        match string {
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            "avg" => Ok(Aggregation::Avg),
+            "sum" => Ok(Aggregation::Sum),
+            "count" => Ok(Aggregation::Count),
+            "last" => Ok(Aggregation::Last),
+            "rate" => Ok(Aggregation::Rate),
+            _ => Err(SerDeErrorKind::InvalidFieldValue("aggregation", string.to_string()))
+        }
+    }
+}
+
+/// Query for series matching location, path and component glob patterns (`*` and `?` wildcards)
+/// aggregated in buckets of `step` length starting at `from`
+#[derive(Debug, PartialEq)]
+pub struct QueryRequest {
+    pub location: String,
+    pub path: String,
+    pub component: String,
+    pub from: DateTime<UTC>,
+    pub to: DateTime<UTC>,
+    pub step: Duration,
+    pub aggregations: Vec<Aggregation>
+}
+
+impl SerDeMessage for QueryRequest {
+    fn to_bytes(&self, encoding: Encoding) -> Result<Vec<u8>, SerializationError<Self>> {
+        match encoding {
+            Encoding::Json => {
+                let step = match self.step.num_nanoseconds() {
+                    Some(step) => step,
+                    None => return Err(SerializationError::new(SerDeErrorKind::InvalidFieldValue("step", self.step.to_string())))
+                };
+
+                let mut object = BTreeMap::new();
+                object.insert("location".to_string(), Json::String(self.location.clone()));
+                object.insert("path".to_string(), Json::String(self.path.clone()));
+                object.insert("component".to_string(), Json::String(self.component.clone()));
+                object.insert("from".to_string(), timestamp_to_json(&self.from));
+                object.insert("to".to_string(), timestamp_to_json(&self.to));
+                object.insert("step_nanoseconds".to_string(), Json::I64(step));
+                object.insert("aggregations".to_string(), Json::Array(self.aggregations.iter().map(|aggregation| Json::String(aggregation.to_string())).collect()));
+
+                Ok(Json::Object(object).to_string().into_bytes())
+            },
+            _ => Err(SerializationError::new(SerDeErrorKind::EncodingNotImplemented(encoding)))
+        }
+    }
+
+    fn data_type() -> DataType {
+        DataType::QueryRequest
+    }
+
+    fn from_bytes(bytes: &Vec<u8>, encoding: Encoding) -> Result<Self, DeserializationError<Self>> {
+        match encoding {
+            Encoding::Json => {
+                let json = try!(json_from_bytes(bytes));
+
+                let aggregations = {
+                    let field = try!(json_field(&json, "aggregations"));
+                    let mut aggregations = Vec::new();
+                    match field.as_array() {
+                        Some(array) => for aggregation in array {
+                            match aggregation.as_string() {
+                                Some(aggregation) => aggregations.push(try!(Aggregation::from_str(aggregation))),
+                                None => return Err(DeserializationError::new(SerDeErrorKind::InvalidFieldValue("aggregation", aggregation.to_string())))
+                            }
+                        },
+                        None => return Err(DeserializationError::new(SerDeErrorKind::InvalidFieldValue("aggregations", field.to_string())))
+                    }
+                    aggregations
+                };
+
+                let step = try!(json_i64_field(&json, "step_nanoseconds"));
+                if step <= 0 {
+                    return Err(DeserializationError::new(SerDeErrorKind::InvalidFieldValue("step_nanoseconds", step.to_string())))
+                }
+
+                Ok(QueryRequest {
+                    location: try!(json_string_field(&json, "location")),
+                    path: try!(json_string_field(&json, "path")),
+                    component: try!(json_string_field(&json, "component")),
+                    from: try!(json_timestamp_field(&json, "from")),
+                    to: try!(json_timestamp_field(&json, "to")),
+                    step: Duration::nanoseconds(step),
+                    aggregations: aggregations
+                })
+            },
+            _ => Err(DeserializationError::new(SerDeErrorKind::EncodingNotImplemented(encoding)))
+        }
+    }
+}
+
+/// Aggregated points of single series
+#[derive(Debug, PartialEq)]
+pub struct SeriesData {
+    pub location: String,
+    pub path: String,
+    pub component: String,
+    pub aggregation: Aggregation,
+    pub points: Vec<(DateTime<UTC>, DataValue)>
+}
+
+impl SeriesData {
+    fn to_json(&self) -> Json {
+        let points = self.points.iter().map(|&(ref timestamp, ref value)| {
+            let mut point = BTreeMap::new();
+            point.insert("timestamp".to_string(), timestamp_to_json(timestamp));
+            point.insert("value".to_string(), value.to_json());
+            Json::Object(point)
+        }).collect();
+
+        let mut object = BTreeMap::new();
+        object.insert("location".to_string(), Json::String(self.location.clone()));
+        object.insert("path".to_string(), Json::String(self.path.clone()));
+        object.insert("component".to_string(), Json::String(self.component.clone()));
+        object.insert("aggregation".to_string(), Json::String(self.aggregation.to_string()));
+        object.insert("points".to_string(), Json::Array(points));
+        Json::Object(object)
+    }
+
+    fn from_json(json: &Json) -> Result<SeriesData, SerDeErrorKind> {
+        let field = try!(json_field(json, "points"));
+        let mut points = Vec::new();
+        match field.as_array() {
+            Some(array) => for point in array {
+                points.push((try!(json_timestamp_field(point, "timestamp")), try!(DataValue::from_json(try!(json_field(point, "value"))))));
+            },
+            None => return Err(SerDeErrorKind::InvalidFieldValue("points", field.to_string()))
+        }
+
+        Ok(SeriesData {
+            location: try!(json_string_field(json, "location")),
+            path: try!(json_string_field(json, "path")),
+            component: try!(json_string_field(json, "component")),
+            aggregation: try!(Aggregation::from_str(&*try!(json_string_field(json, "aggregation")))),
+            points: points
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct QueryResponse {
+    pub error: Option<String>,
+    pub series: Vec<SeriesData>
+}
+
+impl QueryResponse {
+    pub fn error<E>(error: E) -> QueryResponse where E: Into<String> {
+        QueryResponse {
+            error: Some(error.into()),
+            series: Vec::new()
+        }
+    }
+}
+
+impl SerDeMessage for QueryResponse {
+    fn to_bytes(&self, encoding: Encoding) -> Result<Vec<u8>, SerializationError<Self>> {
+        match encoding {
+            Encoding::Json => {
+                let mut object = BTreeMap::new();
+                object.insert("error".to_string(), match self.error {
+                    Some(ref error) => Json::String(error.clone()),
+                    None => Json::Null
+                });
+                object.insert("series".to_string(), Json::Array(self.series.iter().map(|series| series.to_json()).collect()));
+
+                Ok(Json::Object(object).to_string().into_bytes())
+            },
+            _ => Err(SerializationError::new(SerDeErrorKind::EncodingNotImplemented(encoding)))
+        }
+    }
+
+    fn data_type() -> DataType {
+        DataType::QueryResponse
+    }
+
+    fn from_bytes(bytes: &Vec<u8>, encoding: Encoding) -> Result<Self, DeserializationError<Self>> {
+        match encoding {
+            Encoding::Json => {
+                let json = try!(json_from_bytes(bytes));
+
+                let error = match try!(json_field(&json, "error")) {
+                    &Json::Null => None,
+                    &Json::String(ref error) => Some(error.clone()),
+                    error => return Err(DeserializationError::new(SerDeErrorKind::InvalidFieldValue("error", error.to_string())))
+                };
+
+                let field = try!(json_field(&json, "series"));
+                let mut series = Vec::new();
+                match field.as_array() {
+                    Some(array) => for series_data in array {
+                        series.push(try!(SeriesData::from_json(series_data)));
+                    },
+                    None => return Err(DeserializationError::new(SerDeErrorKind::InvalidFieldValue("series", field.to_string())))
+                }
+
+                Ok(QueryResponse {
+                    error: error,
+                    series: series
+                })
+            },
+            _ => Err(DeserializationError::new(SerDeErrorKind::EncodingNotImplemented(encoding)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::super::serde::*;
+    pub use super::super::raw_data_point::DataValue;
+    pub use chrono::*;
+
+    pub fn query_request() -> QueryRequest {
+        QueryRequest {
+            location: "myserver".to_string(),
+            path: "os/cpu/*".to_string(),
+            component: "?ser".to_string(),
+            from: UTC.timestamp(1455200000, 1),
+            to: UTC.timestamp(1455203600, 0),
+            step: Duration::seconds(60),
+            aggregations: vec![Aggregation::Min, Aggregation::Max, Aggregation::Avg, Aggregation::Sum, Aggregation::Count, Aggregation::Last, Aggregation::Rate]
+        }
+    }
+
+    mod query_request {
+        pub use super::*;
+
+        #[test]
+        fn should_round_trip_in_json_encoding() {
+            let bytes = query_request().to_bytes(Encoding::Json).unwrap();
+            assert_eq!(QueryRequest::from_bytes(&bytes, Encoding::Json).unwrap(), query_request());
+        }
+
+        #[test]
+        fn should_provide_error_on_unknown_aggregation() {
+            let bytes = r#"{"location":"*","path":"*","component":"*","from":{"unix_timestamp":0,"nanosecond":0},"to":{"unix_timestamp":60,"nanosecond":0},"step_nanoseconds":1000000000,"aggregations":["median"]}"#.to_string().into_bytes();
+            let result = QueryRequest::from_bytes(&bytes, Encoding::Json);
+            assert!(result.is_err());
+            assert_eq!(format!("{}", result.unwrap_err()), "failed to deserializae message for type QueryRequest: invalid aggregation value: \"median\"");
+        }
+
+        #[test]
+        fn should_provide_error_on_non_positive_step() {
+            let bytes = r#"{"location":"*","path":"*","component":"*","from":{"unix_timestamp":0,"nanosecond":0},"to":{"unix_timestamp":60,"nanosecond":0},"step_nanoseconds":0,"aggregations":["avg"]}"#.to_string().into_bytes();
+            let result = QueryRequest::from_bytes(&bytes, Encoding::Json);
+            assert!(result.is_err());
+            assert_eq!(format!("{}", result.unwrap_err()), "failed to deserializae message for type QueryRequest: invalid step_nanoseconds value: \"0\"");
+        }
+
+        #[test]
+        fn should_not_support_capnp_encoding() {
+            let result = query_request().to_bytes(Encoding::Capnp);
+            assert!(result.is_err());
+            assert_eq!(format!("{}", result.unwrap_err()), "failed to serialize message for type QueryRequest: encoding 'capnp' not implemented");
+        }
+    }
+
+    mod query_response {
+        pub use super::*;
+
+        #[test]
+        fn should_round_trip_in_json_encoding() {
+            let response = QueryResponse {
+                error: None,
+                series: vec![
+                    SeriesData {
+                        location: "myserver".to_string(),
+                        path: "os/cpu/usage".to_string(),
+                        component: "user".to_string(),
+                        aggregation: Aggregation::Avg,
+                        points: vec![
+                            (UTC.timestamp(1455200000, 0), DataValue::Float(0.1 + 0.2)),
+                            (UTC.timestamp(1455200060, 0), DataValue::Float(-1.5e300))
+                        ]
+                    },
+                    SeriesData {
+                        location: "myserver".to_string(),
+                        path: "os/cpu/usage".to_string(),
+                        component: "user".to_string(),
+                        aggregation: Aggregation::Count,
+                        points: vec![]
+                    }
+                ]
+            };
+
+            let bytes = response.to_bytes(Encoding::Json).unwrap();
+            assert_eq!(QueryResponse::from_bytes(&bytes, Encoding::Json).unwrap(), response);
+        }
+
+        #[test]
+        fn should_round_trip_error_in_json_encoding() {
+            let response = QueryResponse::error("storage unavailable");
+            let bytes = response.to_bytes(Encoding::Json).unwrap();
+            assert_eq!(QueryResponse::from_bytes(&bytes, Encoding::Json).unwrap(), response);
+        }
+    }
+}
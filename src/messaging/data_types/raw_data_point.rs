@@ -3,11 +3,16 @@ use std::io::Cursor;
 use capnp::serialize_packed;
 use capnp::{MessageBuilder, MallocMessageBuilder, MessageReader};
 use capnp::message::ReaderOptions;
-use chrono::{DateTime, UTC, Timelike};
+use capnp::NotInSchema;
+use chrono::{DateTime, UTC, Timelike, TimeZone, LocalResult};
+use std::str::FromStr;
+use std::collections::BTreeMap;
+use std::f64;
+use rustc_serialize::json::Json;
 
 use super::super::serde::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub enum DataValue {
     Integer(i64),
@@ -16,7 +21,7 @@ pub enum DataValue {
     Text(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct RawDataPoint {
     pub location: String,
     pub path: String,
@@ -58,7 +63,31 @@ impl SerDeMessage for RawDataPoint {
                 try!(serialize_packed::write_message(&mut data, &mut message));
                 Ok(data)
             },
-            Encoding::Plain => unimplemented!()
+            Encoding::Plain => {
+                let value = match self.value {
+                    DataValue::Integer(value) => format!("integer:{}", value),
+                    DataValue::Float(value) => format!("float:{}", value),
+                    DataValue::Bool(value) => format!("bool:{}", value),
+                    DataValue::Text(ref value) => format!("text:{}", escape_plain(value))
+                };
+
+                Ok(format!("{} {} {} {} {}\n",
+                           escape_plain(&self.location),
+                           escape_plain(&self.path),
+                           escape_plain(&self.component),
+                           format_plain_timestamp(&self.timestamp),
+                           value).into_bytes())
+            },
+            Encoding::Json => {
+                let mut object = BTreeMap::new();
+                object.insert("location".to_string(), Json::String(self.location.clone()));
+                object.insert("path".to_string(), Json::String(self.path.clone()));
+                object.insert("component".to_string(), Json::String(self.component.clone()));
+                object.insert("timestamp".to_string(), timestamp_to_json(&self.timestamp));
+                object.insert("value".to_string(), self.value.to_json());
+
+                Ok(Json::Object(object).to_string().into_bytes())
+            }
         }
     }
 
@@ -73,18 +102,563 @@ impl SerDeMessage for RawDataPoint {
                 let reader = try!(serialize_packed::read_message(&mut buf_reader, ReaderOptions::new()));
                 let raw_data_point = try!(reader.get_root::<::raw_data_point_capnp::raw_data_point::Reader>());
 
+                let timestamp = {
+                    let date_time = try!(raw_data_point.get_timestamp());
+                    let unix_timestamp = date_time.get_unix_timestamp();
+                    let nanosecond = date_time.get_nanosecond();
+
+                    match UTC.timestamp_opt(unix_timestamp, nanosecond) {
+                        LocalResult::Single(timestamp) => timestamp,
+                        _ => return Err(From::from(SerDeErrorKind::InvalidTimestamp(unix_timestamp, nanosecond)))
+                    }
+                };
+
+                let value = {
+                    use ::raw_data_point_capnp::raw_data_point::value::Which;
+
+                    match raw_data_point.get_value().which() {
+                        Ok(Which::Integer(value)) => DataValue::Integer(value),
+                        Ok(Which::Float(value)) => DataValue::Float(value),
+                        Ok(Which::Boolean(value)) => DataValue::Bool(value),
+                        Ok(Which::Text(value)) => DataValue::Text(try!(value).to_string()),
+                        Err(NotInSchema(variant)) => return Err(From::from(SerDeErrorKind::UnknownUnionVariant("value", variant)))
+                    }
+                };
+
                 Ok(
                     RawDataPoint {
                         location: try!(raw_data_point.get_location()).to_string(),
                         path: try!(raw_data_point.get_path()).to_string(),
-                        component: "iowait".to_string(),
-                        timestamp: UTC::now(),
-                        value: DataValue::Float(0.2)
+                        component: try!(raw_data_point.get_component()).to_string(),
+                        timestamp: timestamp,
+                        value: value
+                    }
+                )
+            },
+            Encoding::Plain => {
+                let string = match String::from_utf8(bytes.clone()) {
+                    Ok(string) => string,
+                    Err(utf8_error) => return Err(DeserializationError::new(SerDeErrorKind::FromUtf8Error("body", utf8_error)))
+                };
+
+                let mut lines = string.lines();
+                let line = match lines.next() {
+                    Some(line) => line,
+                    None => return Err(DeserializationError::new(SerDeErrorKind::MissingBodyField("location")))
+                };
+
+                for line in lines.filter(|line| !line.is_empty()) {
+                    warn!("found extra line in plain raw data point body: {:?}", line);
+                }
+
+                let mut fields = line.splitn(5, ' ');
+
+                let location = try!(unescape_plain("location", try!(next_plain_field(&mut fields, "location"))));
+                let path = try!(unescape_plain("path", try!(next_plain_field(&mut fields, "path"))));
+                let component = try!(unescape_plain("component", try!(next_plain_field(&mut fields, "component"))));
+                let timestamp = try!(parse_plain_timestamp(try!(next_plain_field(&mut fields, "timestamp"))));
+                let value = try!(parse_plain_value(try!(next_plain_field(&mut fields, "value"))));
+
+                Ok(
+                    RawDataPoint {
+                        location: location,
+                        path: path,
+                        component: component,
+                        timestamp: timestamp,
+                        value: value
                     }
                 )
             },
-            Encoding::Plain => Err(From::from(SerDeErrorKind::EncodingNotImplemented(Encoding::Plain)))
+            Encoding::Json => {
+                let json = try!(json_from_bytes(bytes));
+
+                Ok(
+                    RawDataPoint {
+                        location: try!(json_string_field(&json, "location")),
+                        path: try!(json_string_field(&json, "path")),
+                        component: try!(json_string_field(&json, "component")),
+                        timestamp: try!(json_timestamp_field(&json, "timestamp")),
+                        value: try!(DataValue::from_json(try!(json_field(&json, "value"))))
+                    }
+                )
+            }
+        }
+    }
+}
+
+// Plain encoding is a single line of space separated fields:
+// <location> <path> <component> <unix seconds>.<nanosecond> <type>:<value>
+// Backslash, space, tab and line breaks are escaped in text fields so the line can be split on spaces.
+fn escape_plain(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ' ' => escaped.push_str("\\s"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c)
         }
     }
+    escaped
 }
 
+fn unescape_plain(field_name: &'static str, string: &str) -> Result<String, SerDeErrorKind> {
+    let mut unescaped = String::with_capacity(string.len());
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('s') => unescaped.push(' '),
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            _ => return Err(SerDeErrorKind::InvalidFieldValue(field_name, string.to_string()))
+        }
+    }
+    Ok(unescaped)
+}
+
+fn next_plain_field<'s, I>(fields: &mut I, field_name: &'static str) -> Result<&'s str, SerDeErrorKind> where I: Iterator<Item=&'s str> {
+    match fields.next() {
+        Some(field) => Ok(field),
+        None => Err(SerDeErrorKind::MissingBodyField(field_name))
+    }
+}
+
+/// Formats timestamp as decimal seconds since Unix epoch; fraction of pre-epoch timestamp counts towards the past
+fn format_plain_timestamp(timestamp: &DateTime<UTC>) -> String {
+    let (seconds, nanosecond) = (timestamp.timestamp(), timestamp.nanosecond());
+    if seconds < 0 && nanosecond > 0 {
+        format!("-{}.{:09}", -(seconds + 1), 1_000_000_000 - nanosecond)
+    } else {
+        format!("{}.{:09}", seconds, nanosecond)
+    }
+}
+
+fn parse_plain_timestamp(string: &str) -> Result<DateTime<UTC>, SerDeErrorKind> {
+    // sign applies to fraction as well; e.g. -0.5 is half a second before epoch
+    let negative = string.starts_with('-');
+    let mut parts = string.splitn(2, '.');
+
+    let unix_timestamp = match parts.next().map(|seconds| seconds.parse::<i64>()) {
+        Some(Ok(seconds)) => seconds,
+        _ => return Err(SerDeErrorKind::InvalidFieldValue("timestamp", string.to_string()))
+    };
+
+    // fraction is optional so that `date +%s` output can be used directly
+    let nanosecond = match parts.next() {
+        None => 0,
+        Some(fraction) if fraction.len() > 0 && fraction.len() <= 9 && fraction.chars().all(|c| c.is_digit(10)) => {
+            match format!("{:0<9}", fraction).parse::<u32>() {
+                Ok(nanosecond) => nanosecond,
+                Err(_) => return Err(SerDeErrorKind::InvalidFieldValue("timestamp", string.to_string()))
+            }
+        },
+        Some(_) => return Err(SerDeErrorKind::InvalidFieldValue("timestamp", string.to_string()))
+    };
+
+    let (unix_timestamp, nanosecond) = if negative && nanosecond > 0 {
+        match unix_timestamp.checked_sub(1) {
+            Some(unix_timestamp) => (unix_timestamp, 1_000_000_000 - nanosecond),
+            None => return Err(SerDeErrorKind::InvalidFieldValue("timestamp", string.to_string()))
+        }
+    } else {
+        (unix_timestamp, nanosecond)
+    };
+
+    match UTC.timestamp_opt(unix_timestamp, nanosecond) {
+        LocalResult::Single(timestamp) => Ok(timestamp),
+        _ => Err(SerDeErrorKind::InvalidTimestamp(unix_timestamp, nanosecond))
+    }
+}
+
+fn parse_plain_value(string: &str) -> Result<DataValue, SerDeErrorKind> {
+    let mut parts = string.splitn(2, ':');
+    let value_type = parts.next().unwrap_or("");
+    let value = match parts.next() {
+        Some(value) => value,
+        None => return Err(SerDeErrorKind::InvalidFieldValue("value", string.to_string()))
+    };
+
+    match value_type {
+        "integer" => i64::from_str(value).map(DataValue::Integer).map_err(|_| SerDeErrorKind::InvalidFieldValue("integer", value.to_string())),
+        "float" => f64::from_str(value).map(DataValue::Float).map_err(|_| SerDeErrorKind::InvalidFieldValue("float", value.to_string())),
+        "bool" => bool::from_str(value).map(DataValue::Bool).map_err(|_| SerDeErrorKind::InvalidFieldValue("bool", value.to_string())),
+        "text" => unescape_plain("text", value).map(DataValue::Text),
+        _ => Err(SerDeErrorKind::UnknownValueType(value_type.to_string()))
+    }
+}
+
+impl DataValue {
+    /// JSON object with single field named after value type
+    pub fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        match self {
+            &DataValue::Integer(value) => object.insert("integer".to_string(), Json::I64(value)),
+            &DataValue::Float(value) => object.insert("float".to_string(), float_to_json(value)),
+            &DataValue::Bool(value) => object.insert("boolean".to_string(), Json::Boolean(value)),
+            &DataValue::Text(ref value) => object.insert("text".to_string(), Json::String(value.clone()))
+        };
+        Json::Object(object)
+    }
+
+    pub fn from_json(json: &Json) -> Result<DataValue, SerDeErrorKind> {
+        let mut variants = match json.as_object() {
+            Some(object) => object.iter(),
+            None => return Err(SerDeErrorKind::InvalidFieldValue("value", json.to_string()))
+        };
+
+        match variants.next() {
+            Some((value_type, value)) => data_value_from_json(value_type, value),
+            None => Err(SerDeErrorKind::MissingJsonField("value"))
+        }
+    }
+}
+
+// JSON numbers are not parsed back to the same float and there is no representation for NaN and infinities so floats are encoded as strings
+fn float_to_json(value: f64) -> Json {
+    Json::String(format!("{:?}", value))
+}
+
+fn data_value_from_json(value_type: &str, value: &Json) -> Result<DataValue, SerDeErrorKind> {
+    match (value_type, value) {
+        ("integer", &Json::I64(value)) => Ok(DataValue::Integer(value)),
+        ("integer", &Json::U64(value)) if value <= i64::max_value() as u64 => Ok(DataValue::Integer(value as i64)),
+        ("float", &Json::F64(value)) => Ok(DataValue::Float(value)),
+        ("float", &Json::I64(value)) => Ok(DataValue::Float(value as f64)),
+        ("float", &Json::U64(value)) => Ok(DataValue::Float(value as f64)),
+        ("float", &Json::String(ref string)) => f64::from_str(string).map(DataValue::Float).map_err(|_| SerDeErrorKind::InvalidFieldValue("float", string.clone())),
+        ("boolean", &Json::Boolean(value)) => Ok(DataValue::Bool(value)),
+        ("text", &Json::String(ref value)) => Ok(DataValue::Text(value.clone())),
+        ("integer", _) | ("float", _) | ("boolean", _) | ("text", _) => Err(SerDeErrorKind::InvalidFieldValue("value", value.to_string())),
+        (value_type, _) => Err(SerDeErrorKind::UnknownValueType(value_type.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::super::serde::*;
+    pub use chrono::*;
+    pub use std::{i64, f64};
+
+    pub fn raw_data_point(value: DataValue) -> RawDataPoint {
+        RawDataPoint {
+            location: "myserver".to_string(),
+            path: "cpu/usage".to_string(),
+            component: "iowait".to_string(),
+            timestamp: UTC.timestamp(1455200000, 123456789),
+            value: value
+        }
+    }
+
+    pub fn round_trip(raw_data_point: &RawDataPoint, encoding: Encoding) -> RawDataPoint {
+        let bytes = raw_data_point.to_bytes(encoding).unwrap();
+        RawDataPoint::from_bytes(&bytes, encoding).unwrap()
+    }
+
+    mod capnp_encoding {
+        pub use super::*;
+
+        #[test]
+        fn should_round_trip_all_fields() {
+            let original = raw_data_point(DataValue::Float(0.2));
+            let decoded = round_trip(&original, Encoding::Capnp);
+
+            assert_eq!(decoded.location, "myserver".to_string());
+            assert_eq!(decoded.path, "cpu/usage".to_string());
+            assert_eq!(decoded.component, "iowait".to_string());
+            assert_eq!(decoded.timestamp, UTC.timestamp(1455200000, 123456789));
+            assert_eq!(decoded, original);
+        }
+
+        #[test]
+        fn should_round_trip_timestamps_before_unix_epoch() {
+            let mut original = raw_data_point(DataValue::Bool(true));
+            original.timestamp = UTC.timestamp(-1000, 999999999);
+            assert_eq!(round_trip(&original, Encoding::Capnp), original);
+        }
+
+        #[test]
+        fn should_round_trip_integer_values() {
+            for value in vec![0, 42, -42, i64::MIN, i64::MAX] {
+                let original = raw_data_point(DataValue::Integer(value));
+                assert_eq!(round_trip(&original, Encoding::Capnp), original);
+            }
+        }
+
+        #[test]
+        fn should_round_trip_float_values() {
+            for value in vec![0.0, -0.0, 0.2, -1.5e300, f64::MIN_POSITIVE, f64::INFINITY, f64::NEG_INFINITY] {
+                let original = raw_data_point(DataValue::Float(value));
+                let decoded = round_trip(&original, Encoding::Capnp);
+                assert_eq!(decoded, original);
+                // -0.0 equals 0.0 so sign is checked separately
+                match decoded.value {
+                    DataValue::Float(decoded) => assert_eq!(decoded.is_sign_negative(), value.is_sign_negative()),
+                    value => panic!("expected float value but got: {:?}", value)
+                }
+            }
+        }
+
+        #[test]
+        fn should_round_trip_nan_float_value() {
+            let original = raw_data_point(DataValue::Float(f64::NAN));
+            match round_trip(&original, Encoding::Capnp).value {
+                DataValue::Float(value) => assert!(value.is_nan()),
+                value => panic!("expected float value but got: {:?}", value)
+            }
+        }
+
+        #[test]
+        fn should_round_trip_bool_values() {
+            for value in vec![true, false] {
+                let original = raw_data_point(DataValue::Bool(value));
+                assert_eq!(round_trip(&original, Encoding::Capnp), original);
+            }
+        }
+
+        #[test]
+        fn should_round_trip_text_values() {
+            for value in vec!["", "blah", "zażółć gęślą jaźń", "日本語\ttab\nnewline"] {
+                let original = raw_data_point(DataValue::Text(value.to_string()));
+                assert_eq!(round_trip(&original, Encoding::Capnp), original);
+            }
+        }
+
+        #[test]
+        fn should_round_trip_empty_and_non_ascii_names() {
+            let mut original = raw_data_point(DataValue::Integer(1));
+            original.location = "".to_string();
+            original.path = "dysk/użycie".to_string();
+            original.component = "".to_string();
+            assert_eq!(round_trip(&original, Encoding::Capnp), original);
+        }
+    }
+
+    mod plain_encoding {
+        pub use super::*;
+        pub use std::fmt::Write;
+
+        macro_rules! assert_error_display_message {
+            ($result:expr, $msg:expr) => {{
+                assert!($result.is_err());
+                let err = $result.unwrap_err();
+                let mut message = String::new();
+                write!(&mut message, "{}", err).unwrap();
+                assert_eq!(message, $msg);
+            }}
+        }
+
+        #[test]
+        fn should_serialize_to_single_line() {
+            let bytes = raw_data_point(DataValue::Float(0.2)).to_bytes(Encoding::Plain).unwrap();
+            assert_eq!(bytes, "myserver cpu/usage iowait 1455200000.123456789 float:0.2\n".to_string().into_bytes());
+        }
+
+        #[test]
+        fn should_serialize_all_value_types() {
+            assert_eq!(raw_data_point(DataValue::Integer(-42)).to_bytes(Encoding::Plain).unwrap(),
+                       "myserver cpu/usage iowait 1455200000.123456789 integer:-42\n".to_string().into_bytes());
+            assert_eq!(raw_data_point(DataValue::Bool(true)).to_bytes(Encoding::Plain).unwrap(),
+                       "myserver cpu/usage iowait 1455200000.123456789 bool:true\n".to_string().into_bytes());
+            assert_eq!(raw_data_point(DataValue::Text("foo: bar".to_string())).to_bytes(Encoding::Plain).unwrap(),
+                       "myserver cpu/usage iowait 1455200000.123456789 text:foo:\\sbar\n".to_string().into_bytes());
+        }
+
+        #[test]
+        fn should_round_trip_values() {
+            for value in vec![
+                DataValue::Integer(i64::MIN),
+                DataValue::Integer(i64::MAX),
+                DataValue::Float(0.1 + 0.2),
+                DataValue::Float(-1.5e300),
+                DataValue::Float(f64::INFINITY),
+                DataValue::Float(f64::NEG_INFINITY),
+                DataValue::Bool(false),
+                DataValue::Text("".to_string()),
+                DataValue::Text("zażółć gęślą jaźń".to_string()),
+                DataValue::Text("back\\slash \\s tab\t new\nline\r\n".to_string())
+            ] {
+                let original = raw_data_point(value);
+                assert_eq!(round_trip(&original, Encoding::Plain), original);
+            }
+        }
+
+        #[test]
+        fn should_round_trip_nan_float_value() {
+            let original = raw_data_point(DataValue::Float(f64::NAN));
+            match round_trip(&original, Encoding::Plain).value {
+                DataValue::Float(value) => assert!(value.is_nan()),
+                value => panic!("expected float value but got: {:?}", value)
+            }
+        }
+
+        #[test]
+        fn should_round_trip_names_with_spaces_and_empty_names() {
+            let mut original = raw_data_point(DataValue::Integer(1));
+            original.location = "".to_string();
+            original.path = "my disk/usage".to_string();
+            original.component = "".to_string();
+            assert_eq!(round_trip(&original, Encoding::Plain), original);
+        }
+
+        #[test]
+        fn should_round_trip_timestamps_before_unix_epoch() {
+            let mut original = raw_data_point(DataValue::Bool(true));
+            original.timestamp = UTC.timestamp(-1000, 999999999);
+            assert_eq!(round_trip(&original, Encoding::Plain), original);
+
+            original.timestamp = UTC.timestamp(-1, 500000000);
+            assert_eq!(round_trip(&original, Encoding::Plain), original);
+
+            original.timestamp = UTC.timestamp(-1000, 0);
+            assert_eq!(round_trip(&original, Encoding::Plain), original);
+        }
+
+        #[test]
+        fn should_serialize_timestamps_before_unix_epoch_as_decimal_seconds() {
+            let mut original = raw_data_point(DataValue::Integer(1));
+            original.timestamp = UTC.timestamp(-1000, 999999999);
+            assert_eq!(original.to_bytes(Encoding::Plain).unwrap(), "myserver cpu/usage iowait -999.000000001 integer:1\n".to_string().into_bytes());
+
+            original.timestamp = UTC.timestamp(-1000, 0);
+            assert_eq!(original.to_bytes(Encoding::Plain).unwrap(), "myserver cpu/usage iowait -1000.000000000 integer:1\n".to_string().into_bytes());
+
+            let bytes = "myserver cpu/usage iowait -0.5 integer:1\n".to_string().into_bytes();
+            assert_eq!(RawDataPoint::from_bytes(&bytes, Encoding::Plain).unwrap().timestamp, UTC.timestamp(-1, 500000000));
+        }
+
+        #[test]
+        fn should_parse_timestamp_without_or_with_short_fraction() {
+            let bytes = "myserver cpu/usage iowait 1455200000 integer:1".to_string().into_bytes();
+            assert_eq!(RawDataPoint::from_bytes(&bytes, Encoding::Plain).unwrap().timestamp, UTC.timestamp(1455200000, 0));
+
+            let bytes = "myserver cpu/usage iowait 1455200000.5 integer:1\n".to_string().into_bytes();
+            assert_eq!(RawDataPoint::from_bytes(&bytes, Encoding::Plain).unwrap().timestamp, UTC.timestamp(1455200000, 500000000));
+        }
+
+        mod error_handling {
+            pub use super::*;
+
+            #[test]
+            fn should_provide_error_when_fields_are_missing() {
+                let bytes = "myserver cpu/usage iowait 1455200000".to_string().into_bytes();
+                let result = RawDataPoint::from_bytes(&bytes, Encoding::Plain);
+                assert_error_display_message!(result, "failed to deserializae message for type RawDataPoint: no value found in message body");
+
+                let bytes = "".to_string().into_bytes();
+                let result = RawDataPoint::from_bytes(&bytes, Encoding::Plain);
+                assert_error_display_message!(result, "failed to deserializae message for type RawDataPoint: no location found in message body");
+            }
+
+            #[test]
+            fn should_provide_error_on_invalid_timestamp() {
+                let bytes = "myserver cpu/usage iowait yesterday integer:1".to_string().into_bytes();
+                let result = RawDataPoint::from_bytes(&bytes, Encoding::Plain);
+                assert_error_display_message!(result, "failed to deserializae message for type RawDataPoint: invalid timestamp value: \"yesterday\"");
+            }
+
+            #[test]
+            fn should_provide_error_on_unknown_value_type() {
+                let bytes = "myserver cpu/usage iowait 1455200000 complex:1+2i".to_string().into_bytes();
+                let result = RawDataPoint::from_bytes(&bytes, Encoding::Plain);
+                assert_error_display_message!(result, "failed to deserializae message for type RawDataPoint: unknown value type: complex");
+            }
+
+            #[test]
+            fn should_provide_error_on_value_not_matching_its_type() {
+                let bytes = "myserver cpu/usage iowait 1455200000 integer:0.2".to_string().into_bytes();
+                let result = RawDataPoint::from_bytes(&bytes, Encoding::Plain);
+                assert_error_display_message!(result, "failed to deserializae message for type RawDataPoint: invalid integer value: \"0.2\"");
+            }
+
+            #[test]
+            fn should_provide_error_on_invalid_escape_sequence() {
+                let bytes = "my\\xserver cpu/usage iowait 1455200000 integer:1".to_string().into_bytes();
+                let result = RawDataPoint::from_bytes(&bytes, Encoding::Plain);
+                assert_error_display_message!(result, "failed to deserializae message for type RawDataPoint: invalid location value: \"my\\\\xserver\"");
+            }
+        }
+    }
+
+    mod json_encoding {
+        pub use super::*;
+
+        #[test]
+        fn should_serialize_to_json_object() {
+            let bytes = raw_data_point(DataValue::Integer(42)).to_bytes(Encoding::Json).unwrap();
+            assert_eq!(String::from_utf8(bytes).unwrap(),
+                       r#"{"component":"iowait","location":"myserver","path":"cpu/usage","timestamp":{"nanosecond":123456789,"unix_timestamp":1455200000},"value":{"integer":42}}"#);
+        }
+
+        #[test]
+        fn should_round_trip_values() {
+            for value in vec![
+                DataValue::Integer(i64::MIN),
+                DataValue::Integer(i64::MAX),
+                DataValue::Float(0.1 + 0.2),
+                DataValue::Float(-1.5e300),
+                DataValue::Float(f64::MIN_POSITIVE),
+                DataValue::Float(f64::INFINITY),
+                DataValue::Float(f64::NEG_INFINITY),
+                DataValue::Bool(true),
+                DataValue::Text("".to_string()),
+                DataValue::Text("zażółć \"gęślą\" jaźń\n".to_string())
+            ] {
+                let original = raw_data_point(value);
+                assert_eq!(round_trip(&original, Encoding::Json), original);
+            }
+        }
+
+        #[test]
+        fn should_round_trip_nan_float_value() {
+            let original = raw_data_point(DataValue::Float(f64::NAN));
+            match round_trip(&original, Encoding::Json).value {
+                DataValue::Float(value) => assert!(value.is_nan()),
+                value => panic!("expected float value but got: {:?}", value)
+            }
+        }
+
+        #[test]
+        fn should_keep_nanosecond_precision() {
+            let mut original = raw_data_point(DataValue::Bool(false));
+            original.timestamp = UTC.timestamp(-1000, 1);
+            assert_eq!(round_trip(&original, Encoding::Json).timestamp, UTC.timestamp(-1000, 1));
+        }
+
+        #[test]
+        fn should_encode_float_value_as_string() {
+            let bytes = raw_data_point(DataValue::Float(0.1 + 0.2)).to_bytes(Encoding::Json).unwrap();
+            assert!(String::from_utf8(bytes).unwrap().contains(r#""value":{"float":"0.30000000000000004"}"#));
+        }
+
+        #[test]
+        fn should_accept_integer_literal_as_float_value() {
+            let bytes = r#"{"location":"a","path":"b","component":"c","timestamp":{"unix_timestamp":0,"nanosecond":0},"value":{"float":1}}"#.to_string().into_bytes();
+            assert_eq!(RawDataPoint::from_bytes(&bytes, Encoding::Json).unwrap().value, DataValue::Float(1.0));
+        }
+
+        #[test]
+        fn should_provide_error_on_unknown_value_type() {
+            let bytes = r#"{"location":"a","path":"b","component":"c","timestamp":{"unix_timestamp":0,"nanosecond":0},"value":{"complex":1}}"#.to_string().into_bytes();
+            let result = RawDataPoint::from_bytes(&bytes, Encoding::Json);
+            assert!(result.is_err());
+            assert_eq!(format!("{}", result.unwrap_err()), "failed to deserializae message for type RawDataPoint: unknown value type: complex");
+        }
+
+        #[test]
+        fn should_provide_error_on_missing_field() {
+            let bytes = r#"{"location":"a","path":"b","timestamp":{"unix_timestamp":0,"nanosecond":0},"value":{"integer":1}}"#.to_string().into_bytes();
+            let result = RawDataPoint::from_bytes(&bytes, Encoding::Json);
+            assert!(result.is_err());
+            assert_eq!(format!("{}", result.unwrap_err()), "failed to deserializae message for type RawDataPoint: no component field found in JSON object");
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use std::io::BufReader;
+use std::io::Cursor;
+use capnp::serialize_packed;
+use capnp::{MessageBuilder, MallocMessageBuilder, MessageReader};
+use capnp::message::ReaderOptions;
+use capnp::NotInSchema;
+use chrono::{DateTime, UTC, Timelike, TimeZone, LocalResult};
+use std::collections::BTreeMap;
+use rustc_serialize::json::Json;
+
+use super::super::serde::*;
+use super::raw_data_point::{RawDataPoint, DataValue};
+
+/// Data point of a batch; location and timestamp are shared by the whole batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchDataPoint {
+    pub path: String,
+    pub component: String,
+    pub value: DataValue
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawDataPointBatch {
+    pub location: String,
+    pub timestamp: DateTime<UTC>,
+    pub points: Vec<BatchDataPoint>
+}
+
+impl RawDataPointBatch {
+    pub fn new<L>(location: L, timestamp: DateTime<UTC>) -> RawDataPointBatch where L: Into<String> {
+        RawDataPointBatch {
+            location: location.into(),
+            timestamp: timestamp,
+            points: Vec::new()
+        }
+    }
+
+    /// Adds raw data point to this batch if it has matching location and timestamp; otherwise gives it back
+    pub fn push(&mut self, raw_data_point: RawDataPoint) -> Result<(), RawDataPoint> {
+        if raw_data_point.location != self.location || raw_data_point.timestamp != self.timestamp {
+            return Err(raw_data_point)
+        }
+
+        self.points.push(BatchDataPoint {
+            path: raw_data_point.path,
+            component: raw_data_point.component,
+            value: raw_data_point.value
+        });
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn into_raw_data_points(self) -> Vec<RawDataPoint> {
+        let RawDataPointBatch { location, timestamp, points } = self;
+        points.into_iter().map(|point| RawDataPoint {
+            location: location.clone(),
+            path: point.path,
+            component: point.component,
+            timestamp: timestamp,
+            value: point.value
+        }).collect()
+    }
+}
+
+impl SerDeMessage for RawDataPointBatch {
+    fn to_bytes(&self, encoding: Encoding) -> Result<Vec<u8>, SerializationError<Self>> {
+        match encoding {
+            Encoding::Capnp => {
+                let mut message = MallocMessageBuilder::new_default();
+                {
+                    let mut batch_builder = message.init_root::<::raw_data_point_capnp::raw_data_point_batch::Builder>();
+
+                    batch_builder.set_location(&*self.location);
+
+                    {
+                        let mut date_time_builder = batch_builder.borrow().init_timestamp();
+                        date_time_builder.set_unix_timestamp(self.timestamp.timestamp());
+                        date_time_builder.set_nanosecond(self.timestamp.nanosecond());
+                    }
+
+                    let mut points_builder = batch_builder.borrow().init_points(self.points.len() as u32);
+                    for (index, point) in self.points.iter().enumerate() {
+                        let mut point_builder = points_builder.borrow().get(index as u32);
+                        point_builder.set_path(&*point.path);
+                        point_builder.set_component(&*point.component);
+
+                        let mut value_builder = point_builder.borrow().init_value();
+                        match point.value {
+                            DataValue::Integer(value) => value_builder.set_integer(value),
+                            DataValue::Float(value) => value_builder.set_float(value),
+                            DataValue::Bool(value) => value_builder.set_boolean(value),
+                            DataValue::Text(ref value) => value_builder.set_text(&*value)
+                        }
+                    }
+                }
+
+                let mut data = Vec::new();
+                try!(serialize_packed::write_message(&mut data, &mut message));
+                Ok(data)
+            },
+            Encoding::Json => {
+                let points = self.points.iter().map(|point| {
+                    let mut object = BTreeMap::new();
+                    object.insert("path".to_string(), Json::String(point.path.clone()));
+                    object.insert("component".to_string(), Json::String(point.component.clone()));
+                    object.insert("value".to_string(), point.value.to_json());
+                    Json::Object(object)
+                }).collect();
+
+                let mut object = BTreeMap::new();
+                object.insert("location".to_string(), Json::String(self.location.clone()));
+                object.insert("timestamp".to_string(), timestamp_to_json(&self.timestamp));
+                object.insert("points".to_string(), Json::Array(points));
+
+                Ok(Json::Object(object).to_string().into_bytes())
+            },
+            _ => Err(SerializationError::new(SerDeErrorKind::EncodingNotImplemented(encoding)))
+        }
+    }
+
+    fn data_type() -> DataType {
+        DataType::RawDataPointBatch
+    }
+
+    fn from_bytes(bytes: &Vec<u8>, encoding: Encoding) -> Result<Self, DeserializationError<Self>> {
+        match encoding {
+            Encoding::Capnp => {
+                let mut buf_reader = BufReader::new(Cursor::new(bytes.clone()));
+                let reader = try!(serialize_packed::read_message(&mut buf_reader, ReaderOptions::new()));
+                let batch = try!(reader.get_root::<::raw_data_point_capnp::raw_data_point_batch::Reader>());
+
+                let timestamp = {
+                    let date_time = try!(batch.get_timestamp());
+                    let unix_timestamp = date_time.get_unix_timestamp();
+                    let nanosecond = date_time.get_nanosecond();
+
+                    match UTC.timestamp_opt(unix_timestamp, nanosecond) {
+                        LocalResult::Single(timestamp) => timestamp,
+                        _ => return Err(From::from(SerDeErrorKind::InvalidTimestamp(unix_timestamp, nanosecond)))
+                    }
+                };
+
+                let points_reader = try!(batch.get_points());
+                let mut points = Vec::with_capacity(points_reader.len() as usize);
+                for index in 0..points_reader.len() {
+                    use ::raw_data_point_capnp::raw_data_point_batch::point::value::Which;
+
+                    let point = points_reader.get(index);
+                    let value = match point.get_value().which() {
+                        Ok(Which::Integer(value)) => DataValue::Integer(value),
+                        Ok(Which::Float(value)) => DataValue::Float(value),
+                        Ok(Which::Boolean(value)) => DataValue::Bool(value),
+                        Ok(Which::Text(value)) => DataValue::Text(try!(value).to_string()),
+                        Err(NotInSchema(variant)) => return Err(From::from(SerDeErrorKind::UnknownUnionVariant("value", variant)))
+                    };
+
+                    points.push(BatchDataPoint {
+                        path: try!(point.get_path()).to_string(),
+                        component: try!(point.get_component()).to_string(),
+                        value: value
+                    });
+                }
+
+                Ok(
+                    RawDataPointBatch {
+                        location: try!(batch.get_location()).to_string(),
+                        timestamp: timestamp,
+                        points: points
+                    }
+                )
+            },
+            Encoding::Json => {
+                let json = try!(json_from_bytes(bytes));
+
+                let field = try!(json_field(&json, "points"));
+                let mut points = Vec::new();
+                match field.as_array() {
+                    Some(array) => for point in array {
+                        points.push(BatchDataPoint {
+                            path: try!(json_string_field(point, "path")),
+                            component: try!(json_string_field(point, "component")),
+                            value: try!(DataValue::from_json(try!(json_field(point, "value"))))
+                        });
+                    },
+                    None => return Err(DeserializationError::new(SerDeErrorKind::InvalidFieldValue("points", field.to_string())))
+                }
+
+                Ok(
+                    RawDataPointBatch {
+                        location: try!(json_string_field(&json, "location")),
+                        timestamp: try!(json_timestamp_field(&json, "timestamp")),
+                        points: points
+                    }
+                )
+            },
+            _ => Err(DeserializationError::new(SerDeErrorKind::EncodingNotImplemented(encoding)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::super::serde::*;
+    pub use super::super::raw_data_point::{RawDataPoint, DataValue};
+    pub use chrono::*;
+
+    pub fn raw_data_point(path: &str, value: DataValue) -> RawDataPoint {
+        RawDataPoint {
+            location: "myserver".to_string(),
+            path: path.to_string(),
+            component: "iowait".to_string(),
+            timestamp: UTC.timestamp(1455200000, 123456789),
+            value: value
+        }
+    }
+
+    pub fn batch() -> RawDataPointBatch {
+        let mut batch = RawDataPointBatch::new("myserver", UTC.timestamp(1455200000, 123456789));
+        batch.push(raw_data_point("cpu/usage", DataValue::Float(0.2))).unwrap();
+        batch.push(raw_data_point("cpu/count", DataValue::Integer(8))).unwrap();
+        batch.push(raw_data_point("cpu/online", DataValue::Bool(true))).unwrap();
+        batch.push(raw_data_point("cpu/model", DataValue::Text("zażółć".to_string()))).unwrap();
+        batch
+    }
+
+    #[test]
+    fn should_only_accept_points_with_same_location_and_timestamp() {
+        let mut batch = RawDataPointBatch::new("myserver", UTC.timestamp(1455200000, 123456789));
+
+        let mut other_location = raw_data_point("cpu/usage", DataValue::Float(0.2));
+        other_location.location = "foobar".to_string();
+        assert_eq!(batch.push(other_location).unwrap_err().location, "foobar".to_string());
+
+        let mut other_timestamp = raw_data_point("cpu/usage", DataValue::Float(0.2));
+        other_timestamp.timestamp = UTC.timestamp(1455200001, 0);
+        assert!(batch.push(other_timestamp).is_err());
+
+        assert!(batch.push(raw_data_point("cpu/usage", DataValue::Float(0.2))).is_ok());
+        assert_eq!(batch.len(), 1);
+    }
+
+    #[test]
+    fn should_convert_back_to_raw_data_points() {
+        assert_eq!(batch().into_raw_data_points(), vec![
+            raw_data_point("cpu/usage", DataValue::Float(0.2)),
+            raw_data_point("cpu/count", DataValue::Integer(8)),
+            raw_data_point("cpu/online", DataValue::Bool(true)),
+            raw_data_point("cpu/model", DataValue::Text("zażółć".to_string()))
+        ]);
+    }
+
+    #[test]
+    fn should_round_trip_capnp_encoding() {
+        let bytes = batch().to_bytes(Encoding::Capnp).unwrap();
+        assert_eq!(RawDataPointBatch::from_bytes(&bytes, Encoding::Capnp).unwrap(), batch());
+    }
+
+    #[test]
+    fn should_round_trip_empty_batch() {
+        let empty = RawDataPointBatch::new("myserver", UTC.timestamp(1455200000, 0));
+        let bytes = empty.to_bytes(Encoding::Capnp).unwrap();
+        assert_eq!(RawDataPointBatch::from_bytes(&bytes, Encoding::Capnp).unwrap(), empty);
+    }
+
+    #[test]
+    fn should_round_trip_json_encoding() {
+        let bytes = batch().to_bytes(Encoding::Json).unwrap();
+        assert_eq!(RawDataPointBatch::from_bytes(&bytes, Encoding::Json).unwrap(), batch());
+    }
+
+    #[test]
+    fn should_round_trip_float_values_in_json_encoding() {
+        let mut batch = RawDataPointBatch::new("myserver", UTC.timestamp(1455200000, 123456789));
+        batch.push(raw_data_point("cpu/usage", DataValue::Float(0.1 + 0.2))).unwrap();
+        batch.push(raw_data_point("cpu/usage", DataValue::Float(-1.5e300))).unwrap();
+
+        let bytes = batch.to_bytes(Encoding::Json).unwrap();
+        assert_eq!(RawDataPointBatch::from_bytes(&bytes, Encoding::Json).unwrap(), batch);
+    }
+
+    #[test]
+    fn should_be_smaller_than_separate_messages() {
+        let batch_size = batch().to_bytes(Encoding::Capnp).unwrap().len();
+        let separate_size = batch().into_raw_data_points().iter().fold(0, |size, point| size + point.to_bytes(Encoding::Capnp).unwrap().len());
+        assert!(batch_size < separate_size);
+    }
+
+    #[test]
+    fn should_not_support_plain_encoding() {
+        assert!(batch().to_bytes(Encoding::Plain).is_err());
+    }
+}
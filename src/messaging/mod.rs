@@ -7,6 +7,7 @@ use std::io::Error as IoError;
 use std::fmt;
 use nanomsg::Socket;
 use std::io::Write;
+use std::io::Read;
 
 pub use self::serde::*;
 pub use self::data_types::*;
@@ -15,8 +16,12 @@ pub mod serde;
 pub mod data_types;
 
 #[derive(Debug)]
-enum MessagingErrorKind {
+pub enum MessagingErrorKind {
     SerializationError(DataType, SerDeErrorKind),
+    DeserializationError(DataType, SerDeErrorKind),
+    UnexpectedDataType(DataType, DataType),
+    UnsupportedVersion(DataType, u8),
+    TruncatedMessage(&'static str),
     IoError(IoError)
 }
 
@@ -24,20 +29,23 @@ impl fmt::Display for MessagingErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             &MessagingErrorKind::SerializationError(ref data_type, ref error) => write!(f, "serialization error for {:?}: {}", data_type, error),
+            &MessagingErrorKind::DeserializationError(ref data_type, ref error) => write!(f, "deserialization error for {:?}: {}", data_type, error),
+            &MessagingErrorKind::UnexpectedDataType(ref expected, ref got) => write!(f, "expected message of type {:?} but got {:?}", expected, got),
+            &MessagingErrorKind::UnsupportedVersion(ref data_type, version) => write!(f, "unsupported {:?} message version: {}", data_type, version),
+            &MessagingErrorKind::TruncatedMessage(ref part) => write!(f, "message truncated: no {} found", part),
             &MessagingErrorKind::IoError(ref error) => write!(f, "IO Error: {}", error),
         }
     }
 }
 
-trait MessagingDirection: Debug + Any {
+pub trait MessagingDirection: Debug + Any {
     fn direction_name() -> &'static str;
 }
 
 #[derive(Debug)]
-struct SendingDirection;
+pub struct SendingDirection;
 #[derive(Debug)]
-#[allow(dead_code)]
-struct ReceivingDirection;
+pub struct ReceivingDirection;
 
 impl MessagingDirection for SendingDirection {
     fn direction_name() -> &'static str {
@@ -52,8 +60,8 @@ impl MessagingDirection for ReceivingDirection {
 }
 
 #[derive(Debug)]
-struct MessagingError<D> where D: MessagingDirection {
-    kind: MessagingErrorKind,
+pub struct MessagingError<D> where D: MessagingDirection {
+    pub kind: MessagingErrorKind,
     phantom: PhantomData<D>
 }
 
@@ -81,6 +89,12 @@ impl<T, D> From<SerializationError<T>> for MessagingError<D> where D: MessagingD
     }
 }
 
+impl<T, D> From<DeserializationError<T>> for MessagingError<D> where D: MessagingDirection, T: SerDeMessage {
+    fn from(error: DeserializationError<T>) -> MessagingError<D> {
+        MessagingError::new(MessagingErrorKind::DeserializationError(error.data_type, error.kind))
+    }
+}
+
 impl<D> From<IoError> for MessagingError<D> where D: MessagingDirection {
     fn from(error: IoError) -> MessagingError<D> {
         MessagingError::new(MessagingErrorKind::IoError(error))
@@ -96,28 +110,82 @@ pub trait SendMessage<T> where T: SerDeMessage {
 
 impl<T> SendMessage<T> for Socket where T: SerDeMessage {
     fn send_message<S>(&mut self, topic: S, message: T, encoding: Encoding) -> Result<(), SendingError> where S: Into<String>, T: Debug {
+        let data = try!(encode_message(topic, message, encoding));
 
-        let topic: String = topic.into();
-        trace!("Sending message on topic '{}': {:?}", topic, message);
+        try!(self.write(&data));
+        trace!("Message sent");
+        Ok(())
+    }
+}
 
-        let mut data: Vec<u8>;
+/// Encodes message with plain encoded header into single frame ready to be written to socket
+pub fn encode_message<S, T>(topic: S, message: T, encoding: Encoding) -> Result<Vec<u8>, SendingError> where S: Into<String>, T: SerDeMessage {
+    let topic: String = topic.into();
+    trace!("Encoding message on topic '{}': {:?}", topic, message);
 
-        let header = MessageHeader {
-            data_type: T::data_type(),
-            topic: topic,
-            version: T::version(),
-            encoding: encoding
-        };
-        data = try!(header.to_bytes(Encoding::Plain));
+    let mut data: Vec<u8>;
 
-        let body = try!(message.to_bytes(encoding));
+    let header = MessageHeader {
+        data_type: T::data_type(),
+        topic: topic,
+        version: T::version(),
+        encoding: encoding
+    };
+    data = try!(header.to_bytes(Encoding::Plain));
 
-        data.extend(body);
+    let body = try!(message.to_bytes(encoding));
 
-        try!(self.write(&data));
-        trace!("Message sent");
-        Ok(())
+    data.extend(body);
+    Ok(data)
+}
+
+pub trait ReceiveMessage<T> where T: SerDeMessage {
+        fn receive_message(&mut self) -> Result<(String, T), ReceivingError>;
+}
+
+impl<T> ReceiveMessage<T> for Socket where T: SerDeMessage {
+    fn receive_message(&mut self) -> Result<(String, T), ReceivingError> {
+        let mut data = Vec::new();
+        try!(self.read_to_end(&mut data));
+
+        let (header, body) = try!(split_message(data));
+        trace!("Received message header: {:?}", header);
+
+        let message: T = try!(decode_message_body(&header, &body));
+        trace!("Received message on topic '{}': {:?}", header.topic, message);
+
+        Ok((header.topic, message))
+    }
+}
+
+/// Splits received message frame into its plain encoded header and the body
+pub fn split_message(mut data: Vec<u8>) -> Result<(MessageHeader, Vec<u8>), ReceivingError> {
+    let body_offset = match data.windows(2).position(|window| window == b"\n\n") {
+        Some(header_end) => header_end + 2,
+        None => return Err(MessagingError::new(MessagingErrorKind::TruncatedMessage("message header")))
+    };
+
+    let body = data.split_off(body_offset);
+    let header = try!(MessageHeader::from_bytes(&data, Encoding::Plain));
+
+    Ok((header, body))
+}
+
+/// Decodes message body after checking that header describes body of type T
+pub fn decode_message_body<T>(header: &MessageHeader, body: &Vec<u8>) -> Result<T, ReceivingError> where T: SerDeMessage {
+    if header.data_type != T::data_type() {
+        return Err(MessagingError::new(MessagingErrorKind::UnexpectedDataType(T::data_type(), header.data_type)))
     }
+
+    if header.version != T::version() {
+        return Err(MessagingError::new(MessagingErrorKind::UnsupportedVersion(header.data_type, header.version)))
+    }
+
+    if body.is_empty() {
+        return Err(MessagingError::new(MessagingErrorKind::TruncatedMessage("message body")))
+    }
+
+    Ok(try!(T::from_bytes(body, header.encoding)))
 }
 
 #[cfg(test)]
@@ -174,6 +242,101 @@ mod test {
                 thread.join().unwrap();
             }
         }
+
+        mod receive_message {
+            pub use super::*;
+
+            pub fn raw_data_point() -> RawDataPoint {
+                RawDataPoint {
+                    location: "myserver".to_string(),
+                    path: "cpu/usage".to_string(),
+                    component: "iowait".to_string(),
+                    timestamp: UTC.timestamp(1455200000, 123456789),
+                    value: DataValue::Float(0.2)
+                }
+            }
+
+            pub fn receive_raw_bytes(url: &str, bytes: Vec<u8>) -> Result<(String, RawDataPoint), ReceivingError> {
+                let mut pull = Socket::new(Protocol::Pull).unwrap();
+                let mut _endpoint = pull.bind(url).unwrap();
+
+                let mut push = Socket::new(Protocol::Push).unwrap();
+                let mut _push_endpoint = push.connect(url).unwrap();
+                ::std::io::Write::write_all(&mut push, &bytes).unwrap();
+
+                pull.receive_message()
+            }
+
+            #[test]
+            fn should_receive_message_sent_with_send_message_in_any_encoding() {
+                let mut pull = Socket::new(Protocol::Pull).unwrap();
+                let mut _endpoint = pull.bind("ipc:///tmp/test-receive.ipc").unwrap();
+
+                let thread = thread::spawn(move || {
+                    let mut socket = Socket::new(Protocol::Push).unwrap();
+                    let mut _endpoint = socket.connect("ipc:///tmp/test-receive.ipc").unwrap();
+
+                    socket.send_message("hello", raw_data_point(), Encoding::Capnp).unwrap();
+                    socket.send_message("world", raw_data_point(), Encoding::Plain).unwrap();
+                    socket.send_message("", raw_data_point(), Encoding::Json).unwrap();
+                });
+
+                for expected_topic in vec!["hello", "world", ""] {
+                    let (topic, message): (String, RawDataPoint) = pull.receive_message().unwrap();
+                    assert_eq!(topic, expected_topic.to_string());
+                    assert_eq!(message, raw_data_point());
+                }
+
+                thread.join().unwrap();
+            }
+
+            mod error_handling {
+                pub use super::*;
+
+                #[test]
+                fn should_provide_error_when_message_is_of_different_type() {
+                    let result = receive_raw_bytes("ipc:///tmp/test-receive-type.ipc", "MessageHeader/\n0\nplain\n\nRawDataPoint/\n0\ncapnp\n\n".to_string().into_bytes());
+                    match result {
+                        Err(MessagingError { kind: MessagingErrorKind::UnexpectedDataType(DataType::RawDataPoint, DataType::MessageHeader), .. }) => (),
+                        result => panic!("expected unexpected data type error but got: {:?}", result)
+                    }
+                }
+
+                #[test]
+                fn should_provide_error_when_message_version_is_not_supported() {
+                    let result = receive_raw_bytes("ipc:///tmp/test-receive-version.ipc", "RawDataPoint/\n1\nplain\n\nmyserver cpu/usage iowait 1455200000 float:0.2\n".to_string().into_bytes());
+                    match result {
+                        Err(MessagingError { kind: MessagingErrorKind::UnsupportedVersion(DataType::RawDataPoint, 1), .. }) => (),
+                        result => panic!("expected unsupported version error but got: {:?}", result)
+                    }
+                }
+
+                #[test]
+                fn should_provide_error_when_message_header_is_truncated() {
+                    let result = receive_raw_bytes("ipc:///tmp/test-receive-header.ipc", "RawDataPoint/\n0\nplain\n".to_string().into_bytes());
+                    match result {
+                        Err(MessagingError { kind: MessagingErrorKind::TruncatedMessage("message header"), .. }) => (),
+                        result => panic!("expected truncated message error but got: {:?}", result)
+                    }
+                }
+
+                #[test]
+                fn should_provide_error_when_message_body_is_missing() {
+                    let result = receive_raw_bytes("ipc:///tmp/test-receive-body.ipc", "RawDataPoint/\n0\ncapnp\n\n".to_string().into_bytes());
+                    match result {
+                        Err(MessagingError { kind: MessagingErrorKind::TruncatedMessage("message body"), .. }) => (),
+                        result => panic!("expected truncated message error but got: {:?}", result)
+                    }
+                }
+
+                #[test]
+                fn should_provide_error_when_message_body_cannot_be_decoded() {
+                    let result = receive_raw_bytes("ipc:///tmp/test-receive-decode.ipc", "RawDataPoint/\n0\nplain\n\nmyserver cpu/usage\n".to_string().into_bytes());
+                    let err = result.unwrap_err();
+                    assert_eq!(format!("{}", err), "failed to receive message caused by: deserialization error for RawDataPoint: no component found in message body");
+                }
+            }
+        }
     }
 }
 
@@ -9,6 +9,9 @@ use std::io::Error as IoError;
 use std::string::FromUtf8Error;
 use std::num::ParseIntError;
 use capnp::Error as CapnpError;
+use rustc_serialize::json::{Json, ParserError as JsonParserError};
+use std::collections::BTreeMap;
+use chrono::{DateTime, UTC, Timelike, TimeZone, LocalResult};
 
 #[derive(Debug)]
 pub enum SerDeErrorKind {
@@ -20,6 +23,13 @@ pub enum SerDeErrorKind {
     FromUtf8Error(&'static str, FromUtf8Error),
     MissingField(&'static str),
     InvalidVersionNumber(ParseIntError),
+    InvalidTimestamp(i64, u32),
+    UnknownUnionVariant(&'static str, u16),
+    MissingBodyField(&'static str),
+    InvalidFieldValue(&'static str, String),
+    UnknownValueType(String),
+    JsonParserError(JsonParserError),
+    MissingJsonField(&'static str),
 }
 
 impl Display for SerDeErrorKind {
@@ -33,6 +43,13 @@ impl Display for SerDeErrorKind {
             &SerDeErrorKind::FromUtf8Error(ref field_name, ref error) => write!(f, "error decoding {} string: {}", field_name, error),
             &SerDeErrorKind::MissingField(ref field_name) => write!(f, "no {} found in message header", field_name),
             &SerDeErrorKind::InvalidVersionNumber(ref error) => write!(f, "message version is not u8 number: {}", error),
+            &SerDeErrorKind::InvalidTimestamp(seconds, nanosecond) => write!(f, "invalid timestamp: {} seconds and {} nanoseconds", seconds, nanosecond),
+            &SerDeErrorKind::UnknownUnionVariant(ref union_name, variant) => write!(f, "unknown {} variant: {}", union_name, variant),
+            &SerDeErrorKind::MissingBodyField(ref field_name) => write!(f, "no {} found in message body", field_name),
+            &SerDeErrorKind::InvalidFieldValue(ref field_name, ref value) => write!(f, "invalid {} value: {:?}", field_name, value),
+            &SerDeErrorKind::UnknownValueType(ref value_type) => write!(f, "unknown value type: {}", value_type),
+            &SerDeErrorKind::JsonParserError(ref error) => write!(f, "JSON parser error: {}", error),
+            &SerDeErrorKind::MissingJsonField(ref field_name) => write!(f, "no {} field found in JSON object", field_name),
         }
     }
 }
@@ -43,9 +60,9 @@ pub trait SerDeDirection: Debug + Any {
 }
 
 #[derive(Debug)]
-struct SerializationDirection;
+pub struct SerializationDirection;
 #[derive(Debug)]
-struct DeserializationDirection;
+pub struct DeserializationDirection;
 
 impl SerDeDirection for SerializationDirection {
     fn direction_name() -> &'static str {
@@ -115,13 +132,22 @@ impl<T, D> From<CapnpError> for SerDeError<T, D> where T: SerDeMessage, D: SerDe
     }
 }
 
+impl<T, D> From<JsonParserError> for SerDeError<T, D> where T: SerDeMessage, D: SerDeDirection {
+    fn from(error: JsonParserError) -> SerDeError<T, D> {
+        From::from(SerDeErrorKind::JsonParserError(error))
+    }
+}
+
 pub type SerializationError<T> = SerDeError<T, SerializationDirection>;
 pub type DeserializationError<T> = SerDeError<T, DeserializationDirection>;
 
 #[derive(Clone,Copy,Debug,PartialEq)]
 pub enum DataType {
     RawDataPoint,
-    MessageHeader
+    RawDataPointBatch,
+    MessageHeader,
+    QueryRequest,
+    QueryResponse
 }
 
 #[derive(Debug)]
@@ -151,7 +177,10 @@ impl ToString for DataType {
      fn to_string(&self) -> String {
         match self {
             &DataType::RawDataPoint => "RawDataPoint".to_string(),
+            &DataType::RawDataPointBatch => "RawDataPointBatch".to_string(),
             &DataType::MessageHeader => "MessageHeader".to_string(),
+            &DataType::QueryRequest => "QueryRequest".to_string(),
+            &DataType::QueryResponse => "QueryResponse".to_string(),
         }
      }
 }
@@ -161,7 +190,10 @@ impl FromStr for DataType {
     fn from_str(string: &str) -> Result<Self, UnknownDataTypeError> {
         match string {
             "RawDataPoint" => Ok(DataType::RawDataPoint),
+            "RawDataPointBatch" => Ok(DataType::RawDataPointBatch),
             "MessageHeader" => Ok(DataType::MessageHeader),
+            "QueryRequest" => Ok(DataType::QueryRequest),
+            "QueryResponse" => Ok(DataType::QueryResponse),
             _ => Err(UnknownDataTypeError::new(string.to_string()))
         }
     }
@@ -170,7 +202,8 @@ impl FromStr for DataType {
 #[derive(Clone,Copy,Debug,PartialEq)]
 pub enum Encoding {
     Capnp,
-    Plain
+    Plain,
+    Json
 }
 
 #[derive(Debug)]
@@ -201,6 +234,7 @@ impl ToString for Encoding {
          match self {
              &Encoding::Capnp => "capnp".to_string(),
              &Encoding::Plain => "plain".to_string(),
+             &Encoding::Json => "json".to_string(),
          }
      }
 }
@@ -211,6 +245,7 @@ impl FromStr for Encoding {
         match string {
             "capnp" => Ok(Encoding::Capnp),
             "plain" => Ok(Encoding::Plain),
+            "json" => Ok(Encoding::Json),
             _ => Err(UnknownEncodingError::new(string.to_string()))
         }
     }
@@ -225,3 +260,53 @@ pub trait SerDeMessage: Debug + Any + Sized {
     fn from_bytes(bytes: &Vec<u8>, encoding: Encoding) -> Result<Self, DeserializationError<Self>>;
 }
 
+
+// Helpers for decoding JSON encoded messages
+pub fn json_from_bytes(bytes: &Vec<u8>) -> Result<Json, SerDeErrorKind> {
+    match String::from_utf8(bytes.clone()) {
+        Ok(string) => Json::from_str(&*string).map_err(SerDeErrorKind::JsonParserError),
+        Err(utf8_error) => Err(SerDeErrorKind::FromUtf8Error("JSON", utf8_error))
+    }
+}
+
+pub fn json_field<'j>(json: &'j Json, field_name: &'static str) -> Result<&'j Json, SerDeErrorKind> {
+    json.find(field_name).ok_or(SerDeErrorKind::MissingJsonField(field_name))
+}
+
+pub fn json_string_field(json: &Json, field_name: &'static str) -> Result<String, SerDeErrorKind> {
+    let field = try!(json_field(json, field_name));
+    field.as_string().map(|string| string.to_string()).ok_or(SerDeErrorKind::InvalidFieldValue(field_name, field.to_string()))
+}
+
+pub fn json_i64_field(json: &Json, field_name: &'static str) -> Result<i64, SerDeErrorKind> {
+    let field = try!(json_field(json, field_name));
+    field.as_i64().ok_or(SerDeErrorKind::InvalidFieldValue(field_name, field.to_string()))
+}
+
+pub fn json_u64_field(json: &Json, field_name: &'static str) -> Result<u64, SerDeErrorKind> {
+    let field = try!(json_field(json, field_name));
+    field.as_u64().ok_or(SerDeErrorKind::InvalidFieldValue(field_name, field.to_string()))
+}
+
+pub fn json_timestamp_field(json: &Json, field_name: &'static str) -> Result<DateTime<UTC>, SerDeErrorKind> {
+    let date_time = try!(json_field(json, field_name));
+    let unix_timestamp = try!(json_i64_field(date_time, "unix_timestamp"));
+    let nanosecond = try!(json_u64_field(date_time, "nanosecond"));
+
+    if nanosecond > u32::max_value() as u64 {
+        return Err(SerDeErrorKind::InvalidFieldValue("nanosecond", nanosecond.to_string()))
+    }
+
+    match UTC.timestamp_opt(unix_timestamp, nanosecond as u32) {
+        LocalResult::Single(timestamp) => Ok(timestamp),
+        _ => Err(SerDeErrorKind::InvalidTimestamp(unix_timestamp, nanosecond as u32))
+    }
+}
+
+/// Timestamp as JSON object mirroring Cap'n Proto DateTime struct so that nanosecond precision is kept
+pub fn timestamp_to_json(timestamp: &DateTime<UTC>) -> Json {
+    let mut object = BTreeMap::new();
+    object.insert("unix_timestamp".to_string(), Json::I64(timestamp.timestamp()));
+    object.insert("nanosecond".to_string(), Json::U64(timestamp.nanosecond() as u64));
+    Json::Object(object)
+}
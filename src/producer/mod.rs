@@ -1,13 +1,16 @@
 use std::sync::mpsc::{channel, Receiver};
 use program::{self, JoinHandle, Signal};
 use sender::Collector;
+use config::Config;
 
 mod probe;
 
-pub fn spawn(collector: Collector, signals: Receiver<Signal>) -> JoinHandle<()> {
+pub use self::probe::{run_probe_process, modules, Module};
+
+pub fn spawn(collector: Collector, signals: Receiver<Signal>, config: Config, modules: Vec<Box<Module>>) -> JoinHandle<()> {
     program::spawn("producer", move || {
         let (probe_signal, probe_signals) = channel();
-        let probe = probe::spawn(probe_signals, collector.clone());
+        let probe = probe::spawn(probe_signals, collector.clone(), config, modules);
 
         loop {
             match signals.recv() {
@@ -0,0 +1,548 @@
+use std::sync::{Arc, Mutex};
+use std::slice::Iter;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::time::Instant;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::io;
+use std::mem;
+use libc;
+
+use sender::Collect;
+use messaging::DataValue;
+use config::{ModuleConfig, ProbeConfig, ConfigError};
+use super::{RunMode, ProbeRunPlan, Probe, Module};
+use super::procfs::{self, Filter};
+
+/// Devices not reported unless exclude_devices option is given
+const DEFAULT_EXCLUDE_DEVICES: &'static [&'static str] = &["loop[0-9]+", "ram[0-9]+"];
+
+/// Filesystem types not reported unless exclude_fs_types option is given
+const DEFAULT_EXCLUDE_FS_TYPES: &'static [&'static str] = &[
+    "proc", "sysfs", "devpts", "devtmpfs", "tmpfs", "cgroup2?", "securityfs", "pstore", "debugfs", "tracefs",
+    "mqueue", "hugetlbfs", "autofs", "binfmt_misc", "configfs", "fusectl", "rpc_pipefs", "nsfs"
+];
+
+/// Size of sector used by diskstats counters regardless of device sector size
+const SECTOR_SIZE: u64 = 512;
+
+/// Cumulative IO counters of block device
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiskCounters {
+    pub reads: u64,
+    pub sectors_read: u64,
+    pub ms_reading: u64,
+    pub writes: u64,
+    pub sectors_written: u64,
+    pub ms_writing: u64,
+    pub ms_io: u64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiskRates {
+    pub read_iops: f64,
+    pub write_iops: f64,
+    pub read_bytes_per_second: f64,
+    pub write_bytes_per_second: f64,
+    /// Average time IO request took to complete in milliseconds
+    pub await_ms: f64,
+    /// Percentage of time device was busy with IO
+    pub utilisation_percent: f64
+}
+
+impl DiskCounters {
+    /// Rates since previous reading taken given number of seconds ago; None if counters were reset
+    pub fn rates(&self, previous: &DiskCounters, seconds: f64) -> Option<DiskRates> {
+        let deltas = (
+            procfs::wrapping_delta(previous.reads, self.reads),
+            procfs::wrapping_delta(previous.sectors_read, self.sectors_read),
+            procfs::wrapping_delta(previous.ms_reading, self.ms_reading),
+            procfs::wrapping_delta(previous.writes, self.writes),
+            procfs::wrapping_delta(previous.sectors_written, self.sectors_written),
+            procfs::wrapping_delta(previous.ms_writing, self.ms_writing),
+            procfs::wrapping_delta(previous.ms_io, self.ms_io)
+        );
+        let (reads, sectors_read, ms_reading, writes, sectors_written, ms_writing, ms_io) = match deltas {
+            (Some(reads), Some(sectors_read), Some(ms_reading), Some(writes), Some(sectors_written), Some(ms_writing), Some(ms_io)) =>
+                (reads, sectors_read, ms_reading, writes, sectors_written, ms_writing, ms_io),
+            _ => return None
+        };
+
+        if seconds <= 0.0 {
+            return None
+        }
+
+        Some(DiskRates {
+            read_iops: reads as f64 / seconds,
+            write_iops: writes as f64 / seconds,
+            read_bytes_per_second: (sectors_read * SECTOR_SIZE) as f64 / seconds,
+            write_bytes_per_second: (sectors_written * SECTOR_SIZE) as f64 / seconds,
+            await_ms: if reads + writes > 0 { (ms_reading + ms_writing) as f64 / (reads + writes) as f64 } else { 0.0 },
+            utilisation_percent: (ms_io as f64 / (seconds * 10.0)).min(100.0)
+        })
+    }
+}
+
+/// Parses diskstats into counters by device name
+pub fn parse_diskstats(diskstats: &str) -> Result<HashMap<String, DiskCounters>, String> {
+    let mut devices = HashMap::new();
+    for line in diskstats.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue
+        }
+        if fields.len() < 14 {
+            return Err(format!("expected at least 14 fields in diskstats line but got: {}", line))
+        }
+
+        let values = try!(procfs::parse_fields(&fields[3..14], "diskstats"));
+        devices.insert(fields[2].to_string(), DiskCounters {
+            reads: values[0],
+            sectors_read: values[2],
+            ms_reading: values[3],
+            writes: values[4],
+            sectors_written: values[6],
+            ms_writing: values[7],
+            ms_io: values[9]
+        });
+    }
+    Ok(devices)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub device: String,
+    pub mount_point: String,
+    pub fs_type: String
+}
+
+/// Decodes octal escapes used in mounts for space, tab, new line and backslash
+fn unescape_mount_field(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(at) = rest.find('\\') {
+        unescaped.push_str(&rest[..at]);
+        let octal = if rest.len() >= at + 4 && rest.is_char_boundary(at + 4) { u8::from_str_radix(&rest[at + 1..at + 4], 8).ok() } else { None };
+        match octal {
+            Some(byte) => {
+                unescaped.push(byte as char);
+                rest = &rest[at + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[at + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+pub fn parse_mounts(mounts: &str) -> Vec<Mount> {
+    mounts.lines().filter_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 {
+            return None
+        }
+        Some(Mount {
+            device: unescape_mount_field(fields[0]),
+            mount_point: unescape_mount_field(fields[1]),
+            fs_type: fields[2].to_string()
+        })
+    }).collect()
+}
+
+/// Filesystem size and usage as returned by statvfs
+#[derive(Debug, Clone, PartialEq)]
+pub struct FsStats {
+    pub block_size: u64,
+    pub blocks: u64,
+    pub blocks_free: u64,
+    pub blocks_available: u64,
+    pub files: u64,
+    pub files_free: u64
+}
+
+pub fn statvfs(path: &Path) -> io::Result<FsStats> {
+    let path = try!(CString::new(path.as_os_str().as_bytes()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains NUL byte")));
+    unsafe {
+        let mut stats: libc::statvfs = mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stats) != 0 {
+            return Err(io::Error::last_os_error())
+        }
+        Ok(FsStats {
+            block_size: stats.f_frsize as u64,
+            blocks: stats.f_blocks as u64,
+            blocks_free: stats.f_bfree as u64,
+            blocks_available: stats.f_bavail as u64,
+            files: stats.f_files as u64,
+            files_free: stats.f_ffree as u64
+        })
+    }
+}
+
+/// Path of data points of given mount point, e.g. os/filesystem/ for / and os/filesystem/home for /home
+pub fn filesystem_path(mount_point: &str) -> String {
+    format!("os/filesystem{}", mount_point)
+}
+
+enum Metrics {
+    Disks {
+        devices: Filter,
+        previous: Mutex<Option<(Instant, HashMap<String, DiskCounters>)>>
+    },
+    Filesystems {
+        fs_types: Filter,
+        statvfs: fn(&Path) -> io::Result<FsStats>
+    }
+}
+
+pub struct DiskProbe {
+    name: String,
+    location: String,
+    proc_root: PathBuf,
+    metrics: Metrics
+}
+
+impl DiskProbe {
+    fn new(location: &str, config: &ModuleConfig, probe: &ProbeConfig) -> Result<DiskProbe, ConfigError> {
+        let metrics = match probe.name.as_str() {
+            "diskstats" => Metrics::Disks {
+                devices: try!(Filter::from_options(&probe.options, "devices", "exclude_devices", DEFAULT_EXCLUDE_DEVICES)),
+                previous: Mutex::new(None)
+            },
+            "filesystems" => Metrics::Filesystems {
+                fs_types: try!(Filter::from_options(&probe.options, "fs_types", "exclude_fs_types", DEFAULT_EXCLUDE_FS_TYPES)),
+                statvfs: statvfs
+            },
+            _ => return Err(probe.unknown())
+        };
+
+        Ok(DiskProbe {
+            name: format!("{}/{}", config.name, probe.name),
+            location: location.to_string(),
+            proc_root: try!(procfs::proc_root(&config.options)),
+            metrics: metrics
+        })
+    }
+
+    fn data_points(&self, now: Instant) -> Result<Vec<(String, &'static str, DataValue)>, String> {
+        let mut data_points = Vec::new();
+
+        match self.metrics {
+            Metrics::Disks { ref devices, ref previous } => {
+                let counters: HashMap<String, DiskCounters> = try!(parse_diskstats(&try!(procfs::read(&self.proc_root, "diskstats"))))
+                    .into_iter().filter(|&(ref device, _)| devices.matches(device)).collect();
+
+                let mut previous = previous.lock().unwrap();
+                if let Some((previous_time, ref previous_counters)) = *previous {
+                    let seconds = procfs::seconds_between(previous_time, now);
+
+                    let mut device_names: Vec<&String> = counters.keys().collect();
+                    device_names.sort();
+
+                    // devices that just appeared are reported from next run
+                    for device in device_names {
+                        let rates = match previous_counters.get(device).and_then(|previous| counters[device].rates(previous, seconds)) {
+                            Some(rates) => rates,
+                            None => continue
+                        };
+
+                        let path = format!("os/disk/{}", device);
+                        data_points.push((path.clone(), "read_iops", DataValue::Float(rates.read_iops)));
+                        data_points.push((path.clone(), "write_iops", DataValue::Float(rates.write_iops)));
+                        data_points.push((path.clone(), "read_bytes_per_second", DataValue::Float(rates.read_bytes_per_second)));
+                        data_points.push((path.clone(), "write_bytes_per_second", DataValue::Float(rates.write_bytes_per_second)));
+                        data_points.push((path.clone(), "await_ms", DataValue::Float(rates.await_ms)));
+                        data_points.push((path, "utilisation_percent", DataValue::Float(rates.utilisation_percent)));
+                    }
+                }
+                *previous = Some((now, counters));
+            }
+            Metrics::Filesystems { ref fs_types, statvfs } => {
+                for mount in parse_mounts(&try!(procfs::read(&self.proc_root, "mounts"))) {
+                    if !fs_types.matches(&mount.fs_type) {
+                        continue
+                    }
+
+                    let stats = match statvfs(Path::new(&mount.mount_point)) {
+                        Ok(stats) => stats,
+                        Err(err) => {
+                            warn!("Probe '{}': failed to get usage of filesystem mounted at {}: {}", self.name, mount.mount_point, err);
+                            continue
+                        }
+                    };
+                    if stats.blocks == 0 {
+                        continue
+                    }
+
+                    // filesystems such as network ones may report more free blocks than there are
+                    let used = stats.blocks.saturating_sub(stats.blocks_free) * stats.block_size;
+                    let available = stats.blocks_available * stats.block_size;
+                    let path = filesystem_path(&mount.mount_point);
+
+                    data_points.push((path.clone(), "used_bytes", DataValue::Integer(used as i64)));
+                    data_points.push((path.clone(), "free_bytes", DataValue::Integer(available as i64)));
+                    // same as df: space reserved for root is not counted
+                    if used + available > 0 {
+                        data_points.push((path.clone(), "used_percent", DataValue::Float(used as f64 * 100.0 / (used + available) as f64)));
+                    }
+                    if stats.files > 0 {
+                        data_points.push((path, "inodes_used_percent", DataValue::Float((stats.files - stats.files_free) as f64 * 100.0 / stats.files as f64)));
+                    }
+                }
+            }
+        }
+
+        Ok(data_points)
+    }
+}
+
+impl Probe for DiskProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, collector: &mut Collect) -> Result<(), String> {
+        let mut collector = collector;
+        for (path, component, value) in try!(self.data_points(Instant::now())) {
+            try!(collector.collect(&self.location, &path, component, value).map_err(|err| err.to_string()));
+        }
+        Ok(())
+    }
+
+    fn run_mode(&self) -> RunMode {
+        // statvfs can block on unresponsive network filesystems
+        RunMode::DedicatedThread
+    }
+}
+
+pub struct DiskModule {
+    schedule: Vec<ProbeRunPlan>
+}
+
+impl Module for DiskModule {
+    fn name(&self) -> &str {
+        "disk"
+    }
+
+    fn schedule(&self) -> Iter<ProbeRunPlan> {
+        self.schedule.iter()
+    }
+}
+
+pub fn init(location: &str, config: &ModuleConfig) -> Result<Box<Module>, ConfigError> {
+    let mut schedule = Vec::new();
+
+    for probe in &config.probes {
+        schedule.push(ProbeRunPlan {
+            every: probe.every,
+            timeout: probe.timeout,
+            probe: Arc::new(try!(DiskProbe::new(location, config, probe)))
+        });
+    }
+
+    Ok(Box::new(DiskModule {
+        schedule: schedule
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::test_support::{fixture, fixture_config};
+    pub use super::Metrics;
+    pub use super::super::procfs::Filter;
+    pub use config::Config;
+    pub use messaging::DataValue;
+    pub use std::path::Path;
+    pub use std::time::{Duration, Instant};
+    pub use std::io;
+
+    fn filter(include: &str, exclude: &str, default_exclude: &[&str]) -> Filter {
+        let config = Config::parse(Path::new("agent.toml"), &format!("location = \"web-01\"\n[modules.disk.probes.p1]\nevery = \"1s\"\n{}\n{}", include, exclude)).unwrap();
+        Filter::from_options(&config.modules[0].probes[0].options, "include", "exclude", default_exclude).unwrap()
+    }
+
+    fn probe(name: &str) -> DiskProbe {
+        let config = fixture_config("disk", name, "");
+        DiskProbe::new("web-01", &config, &config.probes[0]).unwrap()
+    }
+
+    fn filesystems_probe(statvfs: fn(&Path) -> io::Result<FsStats>) -> DiskProbe {
+        let mut probe = probe("filesystems");
+        if let Metrics::Filesystems { statvfs: ref mut probe_statvfs, .. } = probe.metrics {
+            *probe_statvfs = statvfs;
+        }
+        probe
+    }
+
+    fn fake_statvfs(path: &Path) -> io::Result<FsStats> {
+        match path.to_str() {
+            Some("/") => Ok(FsStats { block_size: 4096, blocks: 1000, blocks_free: 300, blocks_available: 200, files: 100, files_free: 75 }),
+            Some("/mnt/my data") => Ok(FsStats { block_size: 1024, blocks: 100, blocks_free: 100, blocks_available: 100, files: 0, files_free: 0 }),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, "not mounted"))
+        }
+    }
+
+    fn components(data_points: &[(String, &'static str, DataValue)], path: &str) -> Vec<(&'static str, DataValue)> {
+        data_points.iter().filter(|&&(ref point_path, _, _)| point_path == path).map(|&(_, component, ref value)| (component, value.clone())).collect()
+    }
+
+    #[test]
+    fn should_filter_names() {
+        let devices = filter("", "", DEFAULT_EXCLUDE_DEVICES);
+        assert!(devices.matches("sda"));
+        assert!(!devices.matches("loop0"));
+        assert!(devices.matches("myloop0"));
+
+        let devices = filter("include = [\"sd[a-z]\", \"nvme.*\"]", "exclude = [\"sdb\"]", DEFAULT_EXCLUDE_DEVICES);
+        assert!(devices.matches("sda"));
+        assert!(!devices.matches("sda1"));
+        assert!(!devices.matches("sdb"));
+        assert!(devices.matches("nvme0n1"));
+        assert!(devices.matches("loop0") == false);
+    }
+
+    #[test]
+    fn should_unescape_mount_points() {
+        assert_eq!(parse_mounts("/dev/sdb1 /mnt/my\\040data xfs rw 0 0\n/dev/sdc1 /back\\\\slash ext4 rw 0 0"), vec![
+            Mount { device: "/dev/sdb1".to_string(), mount_point: "/mnt/my data".to_string(), fs_type: "xfs".to_string() },
+            Mount { device: "/dev/sdc1".to_string(), mount_point: "/back\\slash".to_string(), fs_type: "ext4".to_string() }
+        ]);
+    }
+
+    #[test]
+    fn should_report_device_rates() {
+        let mut probe = probe("diskstats");
+        let start = Instant::now();
+
+        assert!(probe.data_points(start).unwrap().is_empty());
+
+        probe.proc_root = fixture("proc/2");
+        let data_points = probe.data_points(start + Duration::from_secs(10)).unwrap();
+
+        assert_eq!(components(&data_points, "os/disk/sda"), vec![
+            ("read_iops", DataValue::Float(50.0)),
+            ("write_iops", DataValue::Float(100.0)),
+            ("read_bytes_per_second", DataValue::Float(1024000.0)),
+            ("write_bytes_per_second", DataValue::Float(2048000.0)),
+            ("await_ms", DataValue::Float(2.0)),
+            ("utilisation_percent", DataValue::Float(20.0))
+        ]);
+
+        // excluded by default
+        assert!(components(&data_points, "os/disk/loop0").is_empty());
+    }
+
+    #[test]
+    fn should_handle_counter_wrap() {
+        let mut probe = probe("diskstats");
+        let start = Instant::now();
+        probe.data_points(start).unwrap();
+
+        probe.proc_root = fixture("proc/2");
+        let data_points = probe.data_points(start + Duration::from_secs(10)).unwrap();
+
+        let sdb = components(&data_points, "os/disk/sdb");
+        assert_eq!(sdb[0], ("read_iops", DataValue::Float(10.0)));
+        assert_eq!(sdb[4], ("await_ms", DataValue::Float(1.0)));
+        // io time counter wrapped around 32 bits: 4294967000 -> 200
+        assert_eq!(sdb[5], ("utilisation_percent", DataValue::Float(4.96)));
+    }
+
+    #[test]
+    fn should_skip_rates_of_reset_device() {
+        let mut probe = probe("diskstats");
+        let start = Instant::now();
+        probe.data_points(start).unwrap();
+
+        // counters of sde dropped far below 32 bit limit
+        probe.proc_root = fixture("proc/2");
+        let data_points = probe.data_points(start + Duration::from_secs(10)).unwrap();
+        assert!(components(&data_points, "os/disk/sde").is_empty());
+
+        // reported again once there is reading after the reset
+        let data_points = probe.data_points(start + Duration::from_secs(20)).unwrap();
+        assert_eq!(components(&data_points, "os/disk/sde")[0], ("read_iops", DataValue::Float(0.0)));
+    }
+
+    #[test]
+    fn should_tell_counter_wrap_from_reset() {
+        use super::super::procfs::wrapping_delta;
+
+        assert_eq!(wrapping_delta(4294967000, 200), Some(496));
+        assert_eq!(wrapping_delta(3000000, 5), None);
+        assert_eq!(wrapping_delta(4294967000, 2200000000), None);
+        assert_eq!(wrapping_delta(5000000000, 200), None);
+        assert_eq!(wrapping_delta(100, 300), Some(200));
+    }
+
+    #[test]
+    fn should_handle_devices_appearing_and_disappearing() {
+        let mut probe = probe("diskstats");
+        let start = Instant::now();
+        probe.data_points(start).unwrap();
+
+        probe.proc_root = fixture("proc/2");
+        let data_points = probe.data_points(start + Duration::from_secs(10)).unwrap();
+
+        // sdc is gone and sdd has no previous reading yet
+        let mut paths: Vec<&str> = data_points.iter().map(|&(ref path, _, _)| path.as_str()).collect();
+        paths.dedup();
+        assert_eq!(paths, vec!["os/disk/sda", "os/disk/sda1", "os/disk/sdb"]);
+
+        // sdd is reported once it has previous reading
+        let data_points = probe.data_points(start + Duration::from_secs(20)).unwrap();
+        assert_eq!(components(&data_points, "os/disk/sdd")[0], ("read_iops", DataValue::Float(0.0)));
+
+        probe.proc_root = fixture("proc/1");
+        let data_points = probe.data_points(start + Duration::from_secs(30)).unwrap();
+        assert!(components(&data_points, "os/disk/sdc").is_empty());
+    }
+
+    #[test]
+    fn should_report_filesystem_usage() {
+        let probe = filesystems_probe(fake_statvfs);
+        let data_points = probe.data_points(Instant::now()).unwrap();
+
+        assert_eq!(components(&data_points, "os/filesystem/"), vec![
+            ("used_bytes", DataValue::Integer(700 * 4096)),
+            ("free_bytes", DataValue::Integer(200 * 4096)),
+            ("used_percent", DataValue::Float(700.0 * 100.0 / 900.0)),
+            ("inodes_used_percent", DataValue::Float(25.0))
+        ]);
+        assert_eq!(components(&data_points, "os/filesystem/mnt/my data"), vec![
+            ("used_bytes", DataValue::Integer(0)),
+            ("free_bytes", DataValue::Integer(100 * 1024)),
+            ("used_percent", DataValue::Float(0.0))
+        ]);
+        assert_eq!(data_points.len(), 7);
+    }
+
+    fn inconsistent_statvfs(path: &Path) -> io::Result<FsStats> {
+        match path.to_str() {
+            Some("/") => Ok(FsStats { block_size: 4096, blocks: 100, blocks_free: 120, blocks_available: 0, files: 0, files_free: 0 }),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, "not mounted"))
+        }
+    }
+
+    #[test]
+    fn should_report_filesystem_with_more_free_than_total_blocks_as_unused() {
+        let probe = filesystems_probe(inconsistent_statvfs);
+        let data_points = probe.data_points(Instant::now()).unwrap();
+
+        assert_eq!(components(&data_points, "os/filesystem/"), vec![
+            ("used_bytes", DataValue::Integer(0)),
+            ("free_bytes", DataValue::Integer(0))
+        ]);
+    }
+
+    #[test]
+    fn should_get_usage_of_root_filesystem() {
+        let stats = statvfs(Path::new("/")).unwrap();
+        assert!(stats.blocks > 0);
+        assert!(statvfs(Path::new("/this/does/not/exist")).is_err());
+    }
+}
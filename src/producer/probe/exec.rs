@@ -0,0 +1,554 @@
+use std::sync::Arc;
+use std::slice::Iter;
+use std::str::FromStr;
+use std::io::{self, Read};
+use std::process::{Command, Stdio};
+use std::os::unix::process::CommandExt;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration as StdDuration;
+use libc;
+
+use program;
+use sender::Collect;
+use messaging::DataValue;
+use config::{ModuleConfig, ConfigError};
+use super::{RunMode, ProbeRunPlan, ProbeRunResult, Probe, Module, millis};
+
+/// How command output is turned into data points
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Each line is `path component value`
+    Lines,
+    /// Nagios plugin output with exit code status and performance data
+    Nagios
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "lines" => Ok(OutputFormat::Lines),
+            "nagios" => Ok(OutputFormat::Nagios),
+            _ => Err(format!("unknown output format '{}'; expected lines or nagios", s))
+        }
+    }
+}
+
+/// Parses value as integer, float or boolean falling back to text; surrounding double quotes are removed from text
+pub fn parse_value(value: &str) -> DataValue {
+    if let Ok(value) = value.parse::<i64>() {
+        return DataValue::Integer(value)
+    }
+    if let Ok(value) = value.parse::<f64>() {
+        return DataValue::Float(value)
+    }
+    match value {
+        "true" => DataValue::Bool(true),
+        "false" => DataValue::Bool(false),
+        _ if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') => DataValue::Text(value[1..value.len() - 1].to_string()),
+        _ => DataValue::Text(value.to_string())
+    }
+}
+
+/// Parses `path component value` line; value is the rest of the line
+pub fn parse_line(line: &str) -> Result<(String, String, DataValue), String> {
+    let mut fields = line.trim().splitn(3, char::is_whitespace);
+    match (fields.next(), fields.next(), fields.next().map(|value| value.trim())) {
+        (Some(path), Some(component), Some(value)) if !path.is_empty() && !component.is_empty() && !value.is_empty() => {
+            Ok((path.to_string(), component.to_string(), parse_value(value)))
+        }
+        _ => Err(format!("expected 'path component value' but got '{}'", line))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PerfData {
+    pub label: String,
+    pub value: Option<DataValue>,
+    pub unit: String,
+    pub warn: Option<DataValue>,
+    pub crit: Option<DataValue>,
+    pub min: Option<DataValue>,
+    pub max: Option<DataValue>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NagiosOutput {
+    pub status: i32,
+    pub text: String,
+    pub perfdata: Vec<PerfData>,
+    /// Errors of perfdata items that could not be parsed
+    pub invalid: Vec<String>
+}
+
+/// Name of Nagios plugin status returned as exit code
+pub fn nagios_state(status: i32) -> &'static str {
+    match status {
+        0 => "OK",
+        1 => "WARNING",
+        2 => "CRITICAL",
+        _ => "UNKNOWN"
+    }
+}
+
+/// Splits perfdata into `label=value...` items; labels may be single quoted and contain spaces
+fn perfdata_items(perfdata: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut quoted = false;
+
+    for c in perfdata.chars() {
+        match c {
+            '\'' => {
+                quoted = !quoted;
+                item.push(c);
+            }
+            c if c.is_whitespace() && !quoted => if !item.is_empty() {
+                items.push(item);
+                item = String::new();
+            },
+            c => item.push(c)
+        }
+    }
+    if !item.is_empty() {
+        items.push(item);
+    }
+    items
+}
+
+fn parse_perfdata_item(item: &str) -> Result<PerfData, String> {
+    let split_at = try!(item.rfind('=').ok_or_else(|| format!("expected 'label=value' in perfdata but got '{}'", item)));
+    let (label, data) = (&item[..split_at], &item[split_at + 1..]);
+
+    let label = label.trim_matches('\'').replace("''", "'");
+    if label.is_empty() {
+        return Err(format!("empty label in perfdata '{}'", item))
+    }
+
+    let mut fields = data.split(';');
+    let value = fields.next().unwrap_or("");
+    let unit_at = value.find(|c: char| !(c.is_digit(10) || c == '.' || c == '-' || c == '+' || c == 'e' || c == 'E')).unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_at);
+
+    let value = match number {
+        "" if unit == "U" => None,
+        number => match parse_value(number) {
+            DataValue::Text(_) => return Err(format!("invalid value in perfdata '{}'", item)),
+            value => Some(value)
+        }
+    };
+
+    let unit = if value.is_none() { String::new() } else { unit.to_string() };
+    let mut threshold = || fields.next().and_then(|field| if field.is_empty() { None } else { Some(parse_value(field)) });
+    let warn = threshold();
+    let crit = threshold();
+    let min = threshold();
+    let max = threshold();
+
+    Ok(PerfData {
+        label: label,
+        value: value,
+        unit: unit,
+        warn: warn,
+        crit: crit,
+        min: min,
+        max: max
+    })
+}
+
+/// Parses Nagios plugin output: `TEXT | PERFDATA` first line followed by optional long text lines with more perfdata after `|`
+pub fn parse_nagios(status: i32, output: &str) -> NagiosOutput {
+    let mut lines = output.lines();
+    let first_line = lines.next().unwrap_or("");
+
+    let mut perfdata = String::new();
+    let text = match first_line.find('|') {
+        Some(split_at) => {
+            perfdata.push_str(&first_line[split_at + 1..]);
+            first_line[..split_at].trim().to_string()
+        }
+        None => first_line.trim().to_string()
+    };
+
+    let mut in_perfdata = false;
+    for line in lines {
+        if in_perfdata {
+            perfdata.push(' ');
+            perfdata.push_str(line);
+        } else if let Some(split_at) = line.find('|') {
+            in_perfdata = true;
+            perfdata.push(' ');
+            perfdata.push_str(&line[split_at + 1..]);
+        }
+    }
+
+    let mut parsed = Vec::new();
+    let mut invalid = Vec::new();
+    for item in perfdata_items(&perfdata) {
+        match parse_perfdata_item(&item) {
+            Ok(item) => parsed.push(item),
+            Err(err) => invalid.push(err)
+        }
+    }
+
+    NagiosOutput {
+        status: status,
+        text: text,
+        perfdata: parsed,
+        invalid: invalid
+    }
+}
+
+/// Perfdata label made usable as path segment; labels are often mount points like `/boot`
+fn perfdata_path_label(label: &str) -> String {
+    label.replace('/', "_")
+}
+
+/// Runs configured command and collects data points from its output
+pub struct ExecProbe {
+    name: String,
+    location: String,
+    command: String,
+    args: Vec<String>,
+    format: OutputFormat,
+    path: String,
+    timeout: StdDuration
+}
+
+impl ExecProbe {
+    fn collect_lines(&self, output: &str, collector: &mut Collect) -> ProbeRunResult {
+        let mut invalid = 0;
+        for line in output.lines() {
+            if line.trim().is_empty() || line.trim().starts_with('#') {
+                continue
+            }
+            match parse_line(line) {
+                Ok((path, component, value)) => if let Err(err) = collector.collect(&self.location, &path, &component, value) {
+                    return ProbeRunResult::Error(err.to_string())
+                },
+                Err(err) => {
+                    warn!("Probe '{}': {}", self.name, err);
+                    invalid = invalid + 1;
+                }
+            }
+        }
+
+        if invalid > 0 {
+            return ProbeRunResult::Error(format!("command output had {} invalid lines", invalid))
+        }
+        ProbeRunResult::Ok
+    }
+
+    fn collect_nagios(&self, status: i32, output: &str, collector: &mut Collect) -> ProbeRunResult {
+        let nagios = parse_nagios(status, output);
+        for err in &nagios.invalid {
+            warn!("Probe '{}': skipping {}", self.name, err);
+        }
+
+        let mut data_points = vec![
+            (self.path.clone(), "status".to_string(), DataValue::Integer(nagios.status as i64)),
+            (self.path.clone(), "state".to_string(), DataValue::Text(nagios_state(nagios.status).to_string())),
+            (self.path.clone(), "output".to_string(), DataValue::Text(nagios.text))
+        ];
+
+        for perfdata in nagios.perfdata {
+            let path = format!("{}/{}", self.path, perfdata_path_label(&perfdata.label));
+            let components = vec![("value", perfdata.value), ("warn", perfdata.warn), ("crit", perfdata.crit), ("min", perfdata.min), ("max", perfdata.max)];
+            for (component, value) in components {
+                if let Some(value) = value {
+                    data_points.push((path.clone(), component.to_string(), value));
+                }
+            }
+            if !perfdata.unit.is_empty() {
+                data_points.push((path, "unit".to_string(), DataValue::Text(perfdata.unit)));
+            }
+        }
+
+        for (path, component, value) in data_points {
+            if let Err(err) = collector.collect(&self.location, &path, &component, value) {
+                return ProbeRunResult::Error(err.to_string())
+            }
+        }
+        ProbeRunResult::Ok
+    }
+}
+
+impl Probe for ExecProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, collector: &mut Collect) -> Result<(), String> {
+        match self.run_with_result(collector) {
+            ProbeRunResult::Ok => Ok(()),
+            ProbeRunResult::Error(error) => Err(error),
+            timeout => Err(timeout.to_string())
+        }
+    }
+
+    fn run_with_result(&self, collector: &mut Collect) -> ProbeRunResult {
+        let mut child = match Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            // own process group so that commands started by it can be killed with it on timeout
+            .before_exec(|| if unsafe { libc::setpgid(0, 0) } == 0 { Ok(()) } else { Err(io::Error::last_os_error()) })
+            .spawn() {
+            Ok(child) => child,
+            Err(err) => return ProbeRunResult::Error(format!("failed to run command '{}': {}", self.command, err))
+        };
+
+        let mut stdout = child.stdout.take().expect("child stdout");
+        let (output, received_output) = channel();
+        let reader = program::spawn(&format!("producer/probe/{}/reader", self.name), move || {
+            let mut buffer = Vec::new();
+            output.send(stdout.read_to_end(&mut buffer).map(|_| buffer)).ok();
+        });
+
+        let output = match received_output.recv_timeout(self.timeout) {
+            Ok(output) => output,
+            Err(RecvTimeoutError::Timeout) => {
+                warn!("Command of probe '{}' did not finish within {}ms; killing it", self.name, millis(self.timeout));
+                unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+                child.wait().ok();
+                // reader is not waited for as processes that left the group may still hold command output open
+                return ProbeRunResult::Timeout(self.timeout)
+            }
+            Err(RecvTimeoutError::Disconnected) => return ProbeRunResult::Error("failed to read command output".to_string())
+        };
+        reader.join().ok();
+
+        let status = match child.wait() {
+            Ok(status) => status,
+            Err(err) => return ProbeRunResult::Error(format!("failed to wait for command: {}", err))
+        };
+        let output = match output {
+            Ok(output) => String::from_utf8_lossy(&output).into_owned(),
+            Err(err) => return ProbeRunResult::Error(format!("failed to read command output: {}", err))
+        };
+
+        match self.format {
+            OutputFormat::Lines => {
+                if !status.success() {
+                    return ProbeRunResult::Error(format!("command failed: {}", status))
+                }
+                self.collect_lines(&output, collector)
+            }
+            OutputFormat::Nagios => match status.code() {
+                Some(code) if code >= 0 && code <= 3 => self.collect_nagios(code, &output, collector),
+                _ => ProbeRunResult::Error(format!("command failed: {}", status))
+            }
+        }
+    }
+
+    fn run_mode(&self) -> RunMode {
+        RunMode::DedicatedThread
+    }
+}
+
+pub struct ExecModule {
+    schedule: Vec<ProbeRunPlan>
+}
+
+impl Module for ExecModule {
+    fn name(&self) -> &str {
+        "exec"
+    }
+
+    fn schedule(&self) -> Iter<ProbeRunPlan> {
+        self.schedule.iter()
+    }
+}
+
+pub fn init(location: &str, config: &ModuleConfig) -> Result<Box<Module>, ConfigError> {
+    let mut schedule = Vec::new();
+
+    for probe in &config.probes {
+        let command = match try!(probe.options.string("command")) {
+            Some(command) => command.to_string(),
+            None => return Err(probe.options.error("command", "command to run is required"))
+        };
+        let format = match try!(probe.options.string("format")) {
+            Some(format) => try!(format.parse::<OutputFormat>().map_err(|err: String| probe.options.error("format", err))),
+            None => OutputFormat::Lines
+        };
+        let timeout = try!(probe.timeout.to_std().map_err(|_| probe.options.error("timeout", "timeout out of range")));
+
+        schedule.push(ProbeRunPlan {
+            every: probe.every,
+            timeout: probe.timeout,
+            probe: Arc::new(ExecProbe {
+                name: format!("{}/{}", config.name, probe.name),
+                location: location.to_string(),
+                command: command,
+                args: try!(probe.options.strings("args")).unwrap_or_else(Vec::new),
+                format: format,
+                path: try!(probe.options.string("path")).unwrap_or(&probe.name[..]).to_string(),
+                timeout: timeout
+            })
+        });
+    }
+
+    Ok(Box::new(ExecModule {
+        schedule: schedule
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::test_support::{VecCollector, point};
+    pub use super::super::{Probe, ProbeRunResult};
+    pub use messaging::DataValue;
+    pub use std::time::Duration as StdDuration;
+    pub use std::fs::File;
+    pub use std::io::Read;
+    pub use std::thread;
+    pub use tempdir::TempDir;
+
+    fn command_probe(format: OutputFormat, script: &str, timeout: StdDuration) -> ExecProbe {
+        ExecProbe {
+            name: "exec/test".to_string(),
+            location: "web-01".to_string(),
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            format: format,
+            path: "check".to_string(),
+            timeout: timeout
+        }
+    }
+
+    #[test]
+    fn should_parse_values() {
+        assert_eq!(parse_value("42"), DataValue::Integer(42));
+        assert_eq!(parse_value("-0.5"), DataValue::Float(-0.5));
+        assert_eq!(parse_value("true"), DataValue::Bool(true));
+        assert_eq!(parse_value("\"hello world\""), DataValue::Text("hello world".to_string()));
+        assert_eq!(parse_value("hello"), DataValue::Text("hello".to_string()));
+    }
+
+    #[test]
+    fn should_parse_lines() {
+        assert_eq!(parse_line("cpu/usage user 0.5").unwrap(), point("cpu/usage", "user", DataValue::Float(0.5)));
+        assert_eq!(parse_line("  os  name  Linux 4.4  ").unwrap(), point("os", "name", DataValue::Text("Linux 4.4".to_string())));
+        assert!(parse_line("cpu/usage user").is_err());
+        assert!(parse_line("").is_err());
+    }
+
+    #[test]
+    fn should_parse_nagios_output() {
+        let output = "DISK OK - free space: / 3326 MB (56%); | /=2643MB;5948;5958;0;5968\n/ 15272 MB (77%);\n/boot 68 MB (69%);\n| /boot=68MB;88;93;0;98\n'home dir'=69%;80:90;@95\ntime=U\n";
+        let nagios = parse_nagios(0, output);
+
+        assert_eq!(nagios.text, "DISK OK - free space: / 3326 MB (56%);".to_string());
+        assert_eq!(nagios.perfdata.iter().map(|perfdata| perfdata.label.as_str()).collect::<Vec<_>>(), vec!["/", "/boot", "home dir", "time"]);
+        assert_eq!(nagios.perfdata[0], PerfData {
+            label: "/".to_string(),
+            value: Some(DataValue::Integer(2643)),
+            unit: "MB".to_string(),
+            warn: Some(DataValue::Integer(5948)),
+            crit: Some(DataValue::Integer(5958)),
+            min: Some(DataValue::Integer(0)),
+            max: Some(DataValue::Integer(5968))
+        });
+        assert_eq!(nagios.perfdata[2].warn, Some(DataValue::Text("80:90".to_string())));
+        assert_eq!(nagios.perfdata[2].crit, Some(DataValue::Text("@95".to_string())));
+        assert_eq!(nagios.perfdata[2].min, None);
+        assert_eq!(nagios.perfdata[3].value, None);
+        assert!(nagios.invalid.is_empty());
+    }
+
+    #[test]
+    fn should_parse_nagios_output_without_perfdata() {
+        let nagios = parse_nagios(2, "PING CRITICAL - Packet loss = 100%\n");
+        assert_eq!(nagios, NagiosOutput { status: 2, text: "PING CRITICAL - Packet loss = 100%".to_string(), perfdata: Vec::new(), invalid: Vec::new() });
+    }
+
+    #[test]
+    fn should_skip_invalid_nagios_perfdata_items() {
+        let nagios = parse_nagios(0, "OK | garbage load1=0.5 load5=high");
+        assert_eq!(nagios.text, "OK".to_string());
+        assert_eq!(nagios.perfdata.iter().map(|perfdata| perfdata.label.as_str()).collect::<Vec<_>>(), vec!["load1"]);
+        assert_eq!(nagios.invalid.len(), 2);
+    }
+
+    #[test]
+    fn should_collect_lines_from_command_output() {
+        let probe = command_probe(OutputFormat::Lines, "echo 'cpu/usage user 0.5'; echo '# comment'; echo; echo 'cpu/count online 8'", StdDuration::from_secs(5));
+        let mut collector = VecCollector::new();
+        assert_eq!(probe.run_with_result(&mut collector), ProbeRunResult::Ok);
+        assert_eq!(collector.values, vec![point("cpu/usage", "user", DataValue::Float(0.5)), point("cpu/count", "online", DataValue::Integer(8))]);
+    }
+
+    #[test]
+    fn should_report_invalid_lines_and_failed_command() {
+        let probe = command_probe(OutputFormat::Lines, "echo 'cpu/usage user 0.5'; echo 'garbage'", StdDuration::from_secs(5));
+        let mut collector = VecCollector::new();
+        assert!(probe.run(&mut collector).is_err());
+        assert_eq!(collector.values.len(), 1);
+
+        let probe = command_probe(OutputFormat::Lines, "exit 1", StdDuration::from_secs(5));
+        assert!(probe.run(&mut collector).is_err());
+    }
+
+    #[test]
+    fn should_collect_nagios_status_and_perfdata() {
+        let probe = command_probe(OutputFormat::Nagios, "echo 'LOAD WARNING - load average: 5.1 | load1=5.1;5;10;0'; exit 1", StdDuration::from_secs(5));
+        let mut collector = VecCollector::new();
+        assert_eq!(probe.run_with_result(&mut collector), ProbeRunResult::Ok);
+        assert_eq!(collector.values, vec![
+            point("check", "status", DataValue::Integer(1)),
+            point("check", "state", DataValue::Text("WARNING".to_string())),
+            point("check", "output", DataValue::Text("LOAD WARNING - load average: 5.1".to_string())),
+            point("check/load1", "value", DataValue::Float(5.1)),
+            point("check/load1", "warn", DataValue::Integer(5)),
+            point("check/load1", "crit", DataValue::Integer(10)),
+            point("check/load1", "min", DataValue::Integer(0))
+        ]);
+
+        let probe = command_probe(OutputFormat::Nagios, "exit 4", StdDuration::from_secs(5));
+        assert!(probe.run(&mut collector).is_err());
+    }
+
+    #[test]
+    fn should_collect_nagios_status_despite_invalid_perfdata() {
+        let probe = command_probe(OutputFormat::Nagios, "echo 'DISK CRITICAL | garbage /boot=68MB'; exit 2", StdDuration::from_secs(5));
+        let mut collector = VecCollector::new();
+        assert_eq!(probe.run_with_result(&mut collector), ProbeRunResult::Ok);
+        assert_eq!(collector.values, vec![
+            point("check", "status", DataValue::Integer(2)),
+            point("check", "state", DataValue::Text("CRITICAL".to_string())),
+            point("check", "output", DataValue::Text("DISK CRITICAL".to_string())),
+            point("check/_boot", "value", DataValue::Integer(68)),
+            point("check/_boot", "unit", DataValue::Text("MB".to_string()))
+        ]);
+    }
+
+    #[test]
+    fn should_kill_command_on_timeout() {
+        let probe = command_probe(OutputFormat::Lines, "exec sleep 10", StdDuration::from_millis(100));
+        let mut collector = VecCollector::new();
+        assert_eq!(probe.run_with_result(&mut collector), ProbeRunResult::Timeout(StdDuration::from_millis(100)));
+    }
+
+    #[test]
+    fn should_kill_processes_started_by_command_on_timeout() {
+        let dir = TempDir::new("dms_exec").unwrap();
+        let pid_file = dir.path().join("sleep.pid");
+        let probe = command_probe(OutputFormat::Lines, &format!("sleep 10 & echo $! > '{}'; wait", pid_file.display()), StdDuration::from_millis(200));
+        let mut collector = VecCollector::new();
+        assert_eq!(probe.run_with_result(&mut collector), ProbeRunResult::Timeout(StdDuration::from_millis(200)));
+
+        let mut pid = String::new();
+        File::open(&pid_file).unwrap().read_to_string(&mut pid).unwrap();
+        thread::sleep(StdDuration::from_millis(100));
+
+        // killed sleep is gone or left as zombie if nothing reaps it
+        let mut stat = String::new();
+        if File::open(format!("/proc/{}/stat", pid.trim())).and_then(|mut file| file.read_to_string(&mut stat)).is_ok() {
+            assert_eq!(stat.rsplit(") ").next().and_then(|fields| fields.chars().next()), Some('Z'));
+        }
+    }
+}
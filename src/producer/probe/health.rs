@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use std::fmt;
+
+use super::millis;
+
+/// Number of consecutive failed runs after which probe is considered failing
+const FAILING_AFTER: u64 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProbeRunResult {
+    Ok,
+    Error(String),
+    Timeout(Duration)
+}
+
+impl fmt::Display for ProbeRunResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ProbeRunResult::Ok => write!(f, "ok"),
+            &ProbeRunResult::Error(ref error) => write!(f, "error: {}", error),
+            &ProbeRunResult::Timeout(timeout) => write!(f, "timed out after {}ms", millis(timeout)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Failing
+}
+
+/// Run outcome counts of single probe
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProbeHealth {
+    pub runs: u64,
+    pub errors: u64,
+    pub timeouts: u64,
+    pub skipped: u64,
+    pub consecutive_failures: u64
+}
+
+impl ProbeHealth {
+    pub fn record(&mut self, result: &ProbeRunResult) {
+        self.runs = self.runs + 1;
+        match result {
+            &ProbeRunResult::Ok => self.consecutive_failures = 0,
+            &ProbeRunResult::Error(_) => {
+                self.errors = self.errors + 1;
+                self.consecutive_failures = self.consecutive_failures + 1;
+            }
+            &ProbeRunResult::Timeout(_) => {
+                self.timeouts = self.timeouts + 1;
+                self.consecutive_failures = self.consecutive_failures + 1;
+            }
+        }
+    }
+
+    pub fn status(&self) -> HealthStatus {
+        match self.consecutive_failures {
+            0 => HealthStatus::Healthy,
+            failures if failures < FAILING_AFTER => HealthStatus::Degraded,
+            _ => HealthStatus::Failing
+        }
+    }
+}
+
+/// Health of all probes by name
+pub struct HealthRegistry {
+    probes: HashMap<String, ProbeHealth>
+}
+
+impl HealthRegistry {
+    pub fn new() -> HealthRegistry {
+        HealthRegistry {
+            probes: HashMap::new()
+        }
+    }
+
+    pub fn record(&mut self, probe: &str, result: &ProbeRunResult) {
+        let health = self.probes.entry(probe.to_string()).or_insert_with(ProbeHealth::default);
+        let previous_status = health.status();
+        health.record(result);
+
+        match result {
+            &ProbeRunResult::Ok => trace!("Probe '{}' run finished", probe),
+            &ProbeRunResult::Error(ref error) => error!("Probe '{}' reported an error: {}", probe, error),
+            &ProbeRunResult::Timeout(_) => error!("Probe '{}' {}", probe, result),
+        }
+
+        let status = health.status();
+        if status != previous_status {
+            match status {
+                HealthStatus::Healthy => info!("Probe '{}' is healthy again", probe),
+                _ => warn!("Probe '{}' health status changed to {:?} after {} consecutive failed runs ({} errors and {} timeouts in {} runs since start)",
+                           probe, status, health.consecutive_failures, health.errors, health.timeouts, health.runs)
+            }
+        }
+    }
+
+    pub fn skip(&mut self, probe: &str) {
+        self.probes.entry(probe.to_string()).or_insert_with(ProbeHealth::default).skipped += 1;
+    }
+
+    /// Forgets health of probe that is no longer scheduled
+    pub fn remove(&mut self, probe: &str) {
+        self.probes.remove(probe);
+    }
+
+    #[allow(dead_code)]
+    pub fn get(&self, probe: &str) -> Option<&ProbeHealth> {
+        self.probes.get(probe)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use std::time::Duration;
+
+    #[test]
+    fn should_count_run_results() {
+        let mut health = ProbeHealth::default();
+        health.record(&ProbeRunResult::Ok);
+        health.record(&ProbeRunResult::Error("boom".to_string()));
+        health.record(&ProbeRunResult::Timeout(Duration::from_millis(100)));
+
+        assert_eq!(health, ProbeHealth { runs: 3, errors: 1, timeouts: 1, skipped: 0, consecutive_failures: 2 });
+    }
+
+    #[test]
+    fn should_change_status_with_consecutive_failures() {
+        let mut health = ProbeHealth::default();
+        assert_eq!(health.status(), HealthStatus::Healthy);
+
+        health.record(&ProbeRunResult::Timeout(Duration::from_millis(100)));
+        assert_eq!(health.status(), HealthStatus::Degraded);
+
+        health.record(&ProbeRunResult::Error("boom".to_string()));
+        health.record(&ProbeRunResult::Error("boom".to_string()));
+        assert_eq!(health.status(), HealthStatus::Failing);
+
+        health.record(&ProbeRunResult::Ok);
+        assert_eq!(health.status(), HealthStatus::Healthy);
+    }
+
+    #[test]
+    fn should_track_probes_by_name() {
+        let mut registry = HealthRegistry::new();
+        registry.record("p1", &ProbeRunResult::Ok);
+        registry.record("p2", &ProbeRunResult::Timeout(Duration::from_millis(100)));
+        registry.skip("p2");
+
+        assert_eq!(registry.get("p1").unwrap().status(), HealthStatus::Healthy);
+        assert_eq!(registry.get("p2").unwrap(), &ProbeHealth { runs: 1, errors: 0, timeouts: 1, skipped: 1, consecutive_failures: 1 });
+        assert!(registry.get("p3").is_none());
+    }
+
+    #[test]
+    fn should_display_timeout_distinctly_from_error() {
+        assert_eq!(ProbeRunResult::Timeout(Duration::from_millis(1500)).to_string(), "timed out after 1500ms".to_string());
+        assert_eq!(ProbeRunResult::Error("boom".to_string()).to_string(), "error: boom".to_string());
+    }
+}
@@ -1,26 +1,31 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use std::slice::Iter;
-use time::Duration;
 
 use sender::Collect;
 use messaging::DataValue;
+use config::{ModuleConfig, ConfigError};
 use super::{RunMode, ProbeRunPlan, Probe, Module};
 
-pub struct HelloWorldProbe;
+pub struct HelloWorldProbe {
+    name: String,
+    location: String,
+    greeting: String
+}
+
 pub struct HelloWorldModule {
     schedule: Vec<ProbeRunPlan>
 }
 
 impl Probe for HelloWorldProbe {
     fn name(&self) -> &str {
-        "hello world probe"
+        &self.name
     }
 
     fn run(&self, collector: &mut Collect) -> Result<(), String> {
         let mut collector = collector;
-        info!("Hello world!");
-        collector.collect("hello", "foo", "c1", DataValue::Text("blah".to_string()));
-        collector.collect("world", "bar", "c2", DataValue::Integer(42));
+        info!("{}", self.greeting);
+        try!(collector.collect(&self.location, "hello/foo", "c1", DataValue::Text(self.greeting.clone())).map_err(|err| err.to_string()));
+        try!(collector.collect(&self.location, "hello/bar", "c2", DataValue::Integer(42)).map_err(|err| err.to_string()));
         Ok(())
     }
 
@@ -31,7 +36,7 @@ impl Probe for HelloWorldProbe {
 
 impl Module for HelloWorldModule {
     fn name(&self) -> &str {
-        "hello world module"
+        "hello_world"
     }
 
     fn schedule(&self) -> Iter<ProbeRunPlan> {
@@ -39,14 +44,26 @@ impl Module for HelloWorldModule {
     }
 }
 
-pub fn init() -> Box<Module> {
-    Box::new(HelloWorldModule {
-        schedule: vec![
-            ProbeRunPlan {
-                every: Duration::milliseconds(1000),
-                probe: Rc::new(HelloWorldProbe)
-            }
-        ]
-    })
+pub fn init(location: &str, config: &ModuleConfig) -> Result<Box<Module>, ConfigError> {
+    let mut schedule = Vec::new();
+
+    for probe in &config.probes {
+        match probe.name.as_str() {
+            "hello" => schedule.push(ProbeRunPlan {
+                every: probe.every,
+                timeout: probe.timeout,
+                probe: Arc::new(HelloWorldProbe {
+                    name: format!("{}/{}", config.name, probe.name),
+                    location: location.to_string(),
+                    greeting: try!(probe.options.string("greeting")).unwrap_or("Hello world!").to_string()
+                })
+            }),
+            _ => return Err(probe.unknown())
+        }
+    }
+
+    Ok(Box::new(HelloWorldModule {
+        schedule: schedule
+    }))
 }
 
@@ -0,0 +1,580 @@
+use std::sync::Arc;
+use std::slice::Iter;
+use std::net::{TcpStream, SocketAddr, ToSocketAddrs};
+use std::io::{Read, Write};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use regex::Regex;
+use url::Url;
+use rustc_serialize::json::Json;
+use native_tls::TlsConnector;
+
+use program;
+use sender::Collect;
+use messaging::DataValue;
+use config::{ModuleConfig, ConfigError, Options};
+use super::{RunMode, ProbeRunPlan, Probe, Module, millis};
+
+/// Limit of response body size used unless max_body_bytes option is given
+const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Limit of response status line and headers size
+const MAX_HEADERS_BYTES: usize = 64 * 1024;
+
+/// Check of response body reported as body_matches component
+#[derive(Debug)]
+pub enum Assertion {
+    /// Body matches regular expression
+    BodyRegex(Regex),
+    /// Body is JSON with value at dot separated path, optionally equal to given value
+    JsonPath(Vec<String>, Option<String>)
+}
+
+impl Assertion {
+    fn from_options(options: &Options) -> Result<Option<Assertion>, ConfigError> {
+        match (try!(options.string("body_regex")), try!(options.string("json_path"))) {
+            (None, None) => {
+                if try!(options.string("json_value")).is_some() {
+                    return Err(options.error("json_value", "json_path option is required"))
+                }
+                Ok(None)
+            }
+            (Some(pattern), None) => Regex::new(pattern)
+                .map(|regex| Some(Assertion::BodyRegex(regex)))
+                .map_err(|err| options.error("body_regex", format!("invalid pattern '{}': {}", pattern, err))),
+            (None, Some(path)) => Ok(Some(Assertion::JsonPath(
+                path.split('.').map(|key| key.to_string()).collect(),
+                try!(options.string("json_value")).map(|value| value.to_string())))),
+            (Some(_), Some(_)) => Err(options.error("json_path", "only one of body_regex or json_path options can be given"))
+        }
+    }
+
+    pub fn check(&self, body: &[u8]) -> bool {
+        let body = String::from_utf8_lossy(body);
+        match self {
+            &Assertion::BodyRegex(ref regex) => regex.is_match(&body),
+            &Assertion::JsonPath(ref path, ref expected) => {
+                let json = match Json::from_str(&body) {
+                    Ok(json) => json,
+                    Err(_) => return false
+                };
+                let path: Vec<&str> = path.iter().map(|key| key.as_str()).collect();
+                match (json.find_path(&path), expected) {
+                    (None, _) => false,
+                    (Some(_), &None) => true,
+                    (Some(&Json::String(ref value)), &Some(ref expected)) => value == expected,
+                    (Some(value), &Some(ref expected)) => &value.to_string() == expected
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Decodes body sent with chunked transfer encoding
+pub fn dechunk(mut data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = try!(find(data, b"\r\n").ok_or_else(|| "truncated chunk size".to_string()));
+        let size_line = String::from_utf8_lossy(&data[..line_end]).into_owned();
+        // chunk extensions follow size after semicolon
+        let size_field = size_line.split(';').next().unwrap_or("").trim();
+        let size = try!(usize::from_str_radix(size_field, 16).map_err(|_| format!("invalid chunk size '{}'", size_field)));
+        data = &data[line_end + 2..];
+
+        if size == 0 {
+            return Ok(body)
+        }
+        if data.len() < size {
+            return Err("truncated chunk".to_string())
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[size..];
+        if data.starts_with(b"\r\n") {
+            data = &data[2..];
+        }
+    }
+}
+
+/// Parses raw HTTP/1.x response read until server closed connection
+pub fn parse_response(raw: &[u8]) -> Result<Response, String> {
+    let headers_end = try!(find(raw, b"\r\n\r\n").ok_or_else(|| "incomplete response headers".to_string()));
+    let head = String::from_utf8_lossy(&raw[..headers_end]).into_owned();
+    let body = &raw[headers_end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or("");
+    let fields: Vec<&str> = status_line.split_whitespace().collect();
+    if fields.len() < 2 || !fields[0].starts_with("HTTP/") {
+        return Err(format!("invalid status line: {}", status_line))
+    }
+    let status = try!(fields[1].parse().map_err(|_| format!("invalid status code in: {}", status_line)));
+
+    let mut chunked = false;
+    let mut content_length = None;
+    for header in lines {
+        let colon = match header.find(':') {
+            Some(colon) => colon,
+            None => continue
+        };
+        let value = header[colon + 1..].trim();
+        match header[..colon].trim().to_lowercase().as_str() {
+            "transfer-encoding" => chunked = value.to_lowercase().contains("chunked"),
+            "content-length" => content_length = value.parse::<usize>().ok(),
+            _ => ()
+        }
+    }
+
+    let body = if chunked {
+        try!(dechunk(body))
+    } else {
+        match content_length {
+            Some(length) if length < body.len() => body[..length].to_vec(),
+            _ => body.to_vec()
+        }
+    };
+
+    Ok(Response {
+        status: status,
+        body: body
+    })
+}
+
+/// Durations of request phases in milliseconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timings {
+    pub dns_ms: f64,
+    pub connect_ms: f64,
+    pub tls_ms: Option<f64>,
+    /// From sending request until first byte of response was received
+    pub first_byte_ms: f64,
+    pub total_ms: f64
+}
+
+/// Time left until deadline; fails with given message once it has passed
+fn remaining(deadline: Instant, message: &str) -> Result<Duration, String> {
+    let now = Instant::now();
+    if now >= deadline {
+        return Err(message.to_string())
+    }
+    Ok(deadline - now)
+}
+
+/// Sends request and reads whole response until deadline; returns time to first byte
+///
+/// Timeouts are set on socket underlying the stream before each write and read as single read timeout does not bound slowly sent response.
+/// Body is limited to `max_body_bytes` as received, i.e. including chunked encoding framing.
+fn exchange<S>(stream: &mut S, socket: &TcpStream, request: &[u8], deadline: Instant, max_body_bytes: usize) -> Result<(Vec<u8>, f64), String> where S: Read + Write {
+    let sent = Instant::now();
+    try!(socket.set_write_timeout(Some(try!(remaining(deadline, "timed out sending request")))).map_err(|err| err.to_string()));
+    try!(stream.write_all(request).and_then(|_| stream.flush()).map_err(|err| format!("failed to send request: {}", err)));
+
+    let mut raw = Vec::new();
+    let mut buf = [0; 4096];
+    let mut first_byte_ms = None;
+    let mut body_start = None;
+    loop {
+        try!(socket.set_read_timeout(Some(try!(remaining(deadline, "timed out reading response")))).map_err(|err| err.to_string()));
+        let read = try!(stream.read(&mut buf).map_err(|err| format!("failed to read response: {}", err)));
+        if first_byte_ms.is_none() {
+            first_byte_ms = Some(millis(sent.elapsed()));
+        }
+        if read == 0 {
+            return Ok((raw, first_byte_ms.unwrap_or(0.0)))
+        }
+        raw.extend_from_slice(&buf[..read]);
+
+        if body_start.is_none() {
+            body_start = find(&raw, b"\r\n\r\n").map(|headers_end| headers_end + 4);
+        }
+        match body_start {
+            Some(body_start) if raw.len() - body_start > max_body_bytes => return Err(format!("response body exceeds {} bytes", max_body_bytes)),
+            None if raw.len() > MAX_HEADERS_BYTES => return Err(format!("response headers exceed {} bytes", MAX_HEADERS_BYTES)),
+            _ => ()
+        }
+    }
+}
+
+pub struct HttpProbe {
+    name: String,
+    location: String,
+    path: String,
+    url: String,
+    tls: bool,
+    host: String,
+    port: u16,
+    request: String,
+    assertion: Option<Assertion>,
+    request_timeout: Duration,
+    max_body_bytes: usize
+}
+
+impl HttpProbe {
+    /// Resolves host on helper thread as name resolution cannot be given timeout; thread left behind on timeout finishes on its own
+    fn resolve(&self, deadline: Instant) -> Result<Vec<SocketAddr>, String> {
+        let (resolved, received) = channel();
+        let host = self.host.clone();
+        let port = self.port;
+        program::spawn(&format!("producer/probe/{}/resolver", self.name), move || {
+            resolved.send((host.as_str(), port).to_socket_addrs().map(|addresses| addresses.collect::<Vec<_>>())).ok();
+        });
+
+        match received.recv_timeout(try!(remaining(deadline, "timed out resolving host"))) {
+            Ok(Ok(addresses)) => Ok(addresses),
+            Ok(Err(err)) => Err(format!("failed to resolve {}: {}", self.host, err)),
+            Err(RecvTimeoutError::Timeout) => Err(format!("timed out resolving {}", self.host)),
+            Err(RecvTimeoutError::Disconnected) => Err(format!("failed to resolve {}", self.host))
+        }
+    }
+
+    /// Makes request; whole request including name resolution has to complete within request timeout
+    pub fn request(&self) -> Result<(Response, Timings), String> {
+        let start = Instant::now();
+        let deadline = start + self.request_timeout;
+        let addresses = try!(self.resolve(deadline));
+        let dns_ms = millis(start.elapsed());
+
+        let connecting = Instant::now();
+        let mut last_error = None;
+        let mut stream = None;
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, try!(remaining(deadline, "timed out connecting"))) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break
+                }
+                Err(err) => last_error = Some(format!("failed to connect to {}: {}", address, err))
+            }
+        }
+        let stream = try!(stream.ok_or_else(|| last_error.unwrap_or_else(|| format!("no addresses found for {}", self.host))));
+        let connect_ms = millis(connecting.elapsed());
+        // TLS stream takes ownership of connection; timeouts are set through its clone
+        let socket = try!(stream.try_clone().map_err(|err| err.to_string()));
+
+        let (raw, tls_ms, first_byte_ms) = if self.tls {
+            let handshake = Instant::now();
+            let timeout = try!(remaining(deadline, "timed out before TLS handshake"));
+            try!(socket.set_read_timeout(Some(timeout)).and_then(|_| socket.set_write_timeout(Some(timeout))).map_err(|err| err.to_string()));
+            let connector = try!(TlsConnector::builder().and_then(|builder| builder.build()).map_err(|err| format!("failed to set up TLS: {}", err)));
+            let mut stream = try!(connector.connect(&self.host, stream).map_err(|err| format!("TLS handshake with {} failed: {}", self.host, err)));
+            let tls_ms = millis(handshake.elapsed());
+
+            let (raw, first_byte_ms) = try!(exchange(&mut stream, &socket, self.request.as_bytes(), deadline, self.max_body_bytes));
+            (raw, Some(tls_ms), first_byte_ms)
+        } else {
+            let mut stream = stream;
+            let (raw, first_byte_ms) = try!(exchange(&mut stream, &socket, self.request.as_bytes(), deadline, self.max_body_bytes));
+            (raw, None, first_byte_ms)
+        };
+
+        let response = try!(parse_response(&raw));
+        Ok((response, Timings {
+            dns_ms: dns_ms,
+            connect_ms: connect_ms,
+            tls_ms: tls_ms,
+            first_byte_ms: first_byte_ms,
+            total_ms: millis(start.elapsed())
+        }))
+    }
+}
+
+impl Probe for HttpProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, collector: &mut Collect) -> Result<(), String> {
+        let mut collector = collector;
+        let (response, timings) = match self.request() {
+            Ok(result) => result,
+            Err(err) => {
+                debug!("Probe '{}': {}: {}", self.name, self.url, err);
+                return collector.collect(&self.location, &self.path, "success", DataValue::Bool(false)).map_err(|err| err.to_string())
+            }
+        };
+
+        let mut data_points = vec![
+            ("success", DataValue::Bool(true)),
+            ("dns_ms", DataValue::Float(timings.dns_ms)),
+            ("connect_ms", DataValue::Float(timings.connect_ms))
+        ];
+        if let Some(tls_ms) = timings.tls_ms {
+            data_points.push(("tls_ms", DataValue::Float(tls_ms)));
+        }
+        data_points.push(("first_byte_ms", DataValue::Float(timings.first_byte_ms)));
+        data_points.push(("total_ms", DataValue::Float(timings.total_ms)));
+        data_points.push(("status_code", DataValue::Integer(response.status as i64)));
+        data_points.push(("body_bytes", DataValue::Integer(response.body.len() as i64)));
+        if let Some(ref assertion) = self.assertion {
+            data_points.push(("body_matches", DataValue::Bool(assertion.check(&response.body))));
+        }
+
+        for (component, value) in data_points {
+            try!(collector.collect(&self.location, &self.path, component, value).map_err(|err| err.to_string()));
+        }
+        Ok(())
+    }
+
+    fn run_mode(&self) -> RunMode {
+        RunMode::DedicatedThread
+    }
+}
+
+pub struct HttpModule {
+    schedule: Vec<ProbeRunPlan>
+}
+
+impl Module for HttpModule {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    fn schedule(&self) -> Iter<ProbeRunPlan> {
+        self.schedule.iter()
+    }
+}
+
+/// Each probe checks one URL and reports data points on path http/<probe>
+pub fn init(location: &str, config: &ModuleConfig) -> Result<Box<Module>, ConfigError> {
+    let mut schedule = Vec::new();
+
+    for probe in &config.probes {
+        let options = &probe.options;
+        let url = match try!(options.string("url")) {
+            Some(url) => url,
+            None => return Err(options.error("url", "missing required key"))
+        };
+        let parsed = try!(Url::parse(url).map_err(|err| options.error("url", format!("invalid URL '{}': {}", url, err))));
+
+        let tls = match parsed.scheme.as_str() {
+            "http" => false,
+            "https" => true,
+            scheme => return Err(options.error("url", format!("unsupported URL scheme '{}'; expected http or https", scheme)))
+        };
+        let host = try!(parsed.serialize_host().ok_or_else(|| options.error("url", format!("missing host in URL '{}'", url))));
+        let port = try!(parsed.port_or_default().ok_or_else(|| options.error("url", format!("missing port in URL '{}'", url))));
+        let target = parsed.serialize_path().unwrap_or_else(|| "/".to_string()) + &parsed.query.as_ref().map(|query| format!("?{}", query)).unwrap_or_else(String::new);
+
+        let method = try!(options.string("method")).unwrap_or("GET");
+        if method != "GET" && method != "HEAD" {
+            return Err(options.error("method", format!("unsupported method '{}'; expected GET or HEAD", method)))
+        }
+
+        let host_header = match parsed.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.clone()
+        };
+
+        // leave time to report results before probe run times out
+        let request_timeout = match try!(options.duration("request_timeout")) {
+            Some(request_timeout) if request_timeout >= probe.timeout => return Err(options.error("request_timeout", "must be shorter than probe timeout")),
+            Some(request_timeout) => request_timeout,
+            None => probe.timeout / 2
+        };
+
+        let max_body_bytes = match try!(options.integer("max_body_bytes")) {
+            Some(max_body_bytes) if max_body_bytes <= 0 => return Err(options.error("max_body_bytes", "must be greater than zero")),
+            Some(max_body_bytes) => max_body_bytes as usize,
+            None => DEFAULT_MAX_BODY_BYTES
+        };
+
+        schedule.push(ProbeRunPlan {
+            every: probe.every,
+            timeout: probe.timeout,
+            probe: Arc::new(HttpProbe {
+                name: format!("{}/{}", config.name, probe.name),
+                location: location.to_string(),
+                path: format!("http/{}", probe.name),
+                url: url.to_string(),
+                tls: tls,
+                // IPv6 address is in brackets in URL
+                host: host.trim_left_matches('[').trim_right_matches(']').to_string(),
+                port: port,
+                request: format!("{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: dms-agent\r\nAccept: */*\r\nConnection: close\r\n\r\n", method, target, host_header),
+                assertion: try!(Assertion::from_options(options)),
+                request_timeout: request_timeout.to_std().expect("positive request timeout"),
+                max_body_bytes: max_body_bytes
+            })
+        });
+    }
+
+    Ok(Box::new(HttpModule {
+        schedule: schedule
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::test_support::VecCollector;
+    pub use super::super::{Probe, Module};
+    pub use messaging::DataValue;
+    pub use config::Config;
+    pub use std::path::Path;
+    pub use std::net::TcpListener;
+    pub use std::io::{Read, Write};
+    pub use std::thread::{self, JoinHandle};
+    pub use std::time::{Duration, Instant};
+
+    /// Serves single canned response; returns port and request received
+    fn serve(response: &'static str) -> (u16, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while find(&request, b"\r\n\r\n").is_none() {
+                let read = stream.read(&mut buf).unwrap();
+                assert!(read > 0);
+                request.extend_from_slice(&buf[..read]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (port, server)
+    }
+
+    fn probe(options: &str) -> Arc<Probe> {
+        let config = Config::parse(Path::new("agent.toml"), &format!("location = \"web-01\"\n[modules.http.probes.api]\nevery = \"10s\"\ntimeout = \"5s\"\n{}", options)).unwrap();
+        let module = init(&config.location, &config.modules[0]).unwrap();
+        let plan = module.schedule().next().unwrap();
+        plan.probe.clone()
+    }
+
+    fn run(probe: &Arc<Probe>) -> Vec<(String, String, DataValue)> {
+        let mut collector = VecCollector::new();
+        probe.run(&mut collector).unwrap();
+        collector.values
+    }
+
+    fn value<'v>(values: &'v [(String, String, DataValue)], component: &str) -> Option<&'v DataValue> {
+        values.iter().find(|&&(_, ref value_component, _)| value_component == component).map(|&(_, _, ref value)| value)
+    }
+
+    #[test]
+    fn should_parse_responses() {
+        assert_eq!(parse_response(b"HTTP/1.1 404 Not Found\r\nContent-Length: 5\r\n\r\nnope!trailing").unwrap(), Response { status: 404, body: b"nope!".to_vec() });
+        assert_eq!(parse_response(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n").unwrap().body, b"Wikipedia".to_vec());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nff\r\nshort").is_err());
+        assert!(parse_response(b"SMTP ready\r\n\r\n").is_err());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+    }
+
+    #[test]
+    fn should_check_json_path() {
+        let body = br#"{"status": {"ok": true, "name": "db"}, "count": 3}"#;
+        assert!(Assertion::JsonPath(vec!["status".to_string(), "ok".to_string()], None).check(body));
+        assert!(Assertion::JsonPath(vec!["status".to_string(), "ok".to_string()], Some("true".to_string())).check(body));
+        assert!(Assertion::JsonPath(vec!["status".to_string(), "name".to_string()], Some("db".to_string())).check(body));
+        assert!(Assertion::JsonPath(vec!["count".to_string()], Some("3".to_string())).check(body));
+        assert!(!Assertion::JsonPath(vec!["count".to_string()], Some("4".to_string())).check(body));
+        assert!(!Assertion::JsonPath(vec!["missing".to_string()], None).check(body));
+        assert!(!Assertion::JsonPath(vec!["count".to_string()], None).check(b"not json"));
+    }
+
+    #[test]
+    fn should_report_response_of_local_server() {
+        let (port, server) = serve("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 11\r\n\r\nall is well");
+        let probe = probe(&format!("url = \"http://127.0.0.1:{}/health?full=1\"\nbody_regex = \"is w[a-z]+\"", port));
+
+        let values = run(&probe);
+        assert_eq!(values.iter().map(|&(ref path, ref component, _)| (path.as_str(), component.as_str())).collect::<Vec<_>>(), vec![
+            ("http/api", "success"),
+            ("http/api", "dns_ms"),
+            ("http/api", "connect_ms"),
+            ("http/api", "first_byte_ms"),
+            ("http/api", "total_ms"),
+            ("http/api", "status_code"),
+            ("http/api", "body_bytes"),
+            ("http/api", "body_matches")
+        ]);
+        assert_eq!(value(&values, "success"), Some(&DataValue::Bool(true)));
+        assert_eq!(value(&values, "status_code"), Some(&DataValue::Integer(200)));
+        assert_eq!(value(&values, "body_bytes"), Some(&DataValue::Integer(11)));
+        assert_eq!(value(&values, "body_matches"), Some(&DataValue::Bool(true)));
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /health?full=1 HTTP/1.1\r\n"));
+        assert!(request.contains(&format!("\r\nHost: 127.0.0.1:{}\r\n", port)));
+    }
+
+    #[test]
+    fn should_report_failed_assertion_and_error_status() {
+        let (port, server) = serve("HTTP/1.1 503 Service Unavailable\r\nTransfer-Encoding: chunked\r\n\r\n12\r\n{\"status\": \"down\"}\r\n0\r\n\r\n");
+        let probe = probe(&format!("url = \"http://127.0.0.1:{}/\"\nmethod = \"GET\"\njson_path = \"status\"\njson_value = \"up\"", port));
+
+        let values = run(&probe);
+        assert_eq!(value(&values, "status_code"), Some(&DataValue::Integer(503)));
+        assert_eq!(value(&values, "body_bytes"), Some(&DataValue::Integer(18)));
+        assert_eq!(value(&values, "body_matches"), Some(&DataValue::Bool(false)));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn should_report_failure_when_server_is_down() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let probe = probe(&format!("url = \"http://127.0.0.1:{}/\"", port));
+
+        assert_eq!(run(&probe), vec![("http/api".to_string(), "success".to_string(), DataValue::Bool(false))]);
+    }
+
+    #[test]
+    fn should_give_up_on_slowly_sent_response_after_request_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // each byte arrives well within read timeout but whole response does not
+            for byte in b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok".iter() {
+                if stream.write_all(&[*byte]).is_err() {
+                    return
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        });
+        let probe = probe(&format!("url = \"http://127.0.0.1:{}/\"\nrequest_timeout = \"500ms\"", port));
+
+        let start = Instant::now();
+        assert_eq!(run(&probe), vec![("http/api".to_string(), "success".to_string(), DataValue::Bool(false))]);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn should_report_failure_when_body_exceeds_limit() {
+        let (port, server) = serve("HTTP/1.1 200 OK\r\nContent-Length: 16\r\n\r\n0123456789abcdef");
+        let probe = probe(&format!("url = \"http://127.0.0.1:{}/\"\nmax_body_bytes = 10", port));
+
+        assert_eq!(run(&probe), vec![("http/api".to_string(), "success".to_string(), DataValue::Bool(false))]);
+        server.join().unwrap();
+
+        let (port, server) = serve("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789");
+        let probe = probe(&format!("url = \"http://127.0.0.1:{}/\"\nmax_body_bytes = 10", port));
+        assert_eq!(value(&run(&probe), "success"), Some(&DataValue::Bool(true)));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn should_reject_invalid_options() {
+        let config = |options: &str| Config::parse(Path::new("agent.toml"), &format!("location = \"web-01\"\n[modules.http.probes.api]\nevery = \"10s\"\n{}", options)).unwrap().modules.remove(0);
+
+        assert!(init("web-01", &config("")).is_err());
+        assert!(init("web-01", &config("url = \"ftp://example.com/\"")).is_err());
+        assert!(init("web-01", &config("url = \"http://example.com/\"\nmethod = \"DELETE\"")).is_err());
+        assert!(init("web-01", &config("url = \"http://example.com/\"\nbody_regex = \"(\"")).is_err());
+        assert!(init("web-01", &config("url = \"http://example.com/\"\nbody_regex = \"ok\"\njson_path = \"status\"")).is_err());
+        assert!(init("web-01", &config("url = \"http://example.com/\"\nrequest_timeout = \"1m\"")).is_err());
+        assert!(init("web-01", &config("url = \"http://example.com/\"\nmax_body_bytes = 0")).is_err());
+        assert!(init("web-01", &config("url = \"http://example.com/\"\nmax_body_bytes = \"1MB\"")).is_err());
+        assert!(init("web-01", &config("url = \"https://example.com/\"\njson_path = \"status\"")).is_ok());
+    }
+}
@@ -0,0 +1,598 @@
+use std::sync::{Arc, Mutex};
+use std::slice::Iter;
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{self, Read, Write, Seek, SeekFrom, BufRead, BufReader};
+use std::os::unix::fs::MetadataExt;
+use std::ffi::OsString;
+use regex::Regex;
+
+use sender::Collect;
+use messaging::DataValue;
+use config::{ModuleConfig, ConfigError, Options};
+use super::{RunMode, ProbeRunPlan, Probe, Module};
+
+/// Directory where read offsets are kept between agent restarts unless state_dir option is given
+pub const STATE_DIR: &'static str = "/var/lib/dms-agent/logs";
+
+#[derive(Debug)]
+pub enum RuleKind {
+    /// Number of matching lines since previous run
+    Count,
+    /// Number captured by `value` group or first group of each matching line
+    Value
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    name: String,
+    regex: Regex,
+    kind: RuleKind
+}
+
+impl Rule {
+    fn from_options(name: &str, options: &Options) -> Result<Rule, ConfigError> {
+        let pattern = match try!(options.string("pattern")) {
+            Some(pattern) => pattern,
+            None => return Err(options.error("pattern", "missing required key"))
+        };
+        let regex = try!(Regex::new(pattern).map_err(|err| options.error("pattern", format!("invalid pattern '{}': {}", pattern, err))));
+
+        let kind = match try!(options.string("type")) {
+            None | Some("count") => RuleKind::Count,
+            Some("value") => {
+                if regex.captures_len() < 2 {
+                    return Err(options.error("pattern", "value rule requires pattern with capture group"))
+                }
+                RuleKind::Value
+            }
+            Some(kind) => return Err(options.error("type", format!("unknown rule type '{}'; expected one of: count, value", kind)))
+        };
+
+        Ok(Rule {
+            name: name.to_string(),
+            regex: regex,
+            kind: kind
+        })
+    }
+
+    fn value(&self, line: &str) -> Option<f64> {
+        self.regex.captures(line)
+            .and_then(|captures| captures.name("value").or_else(|| captures.at(1)))
+            .and_then(|value| value.parse().ok())
+    }
+}
+
+/// Log file opened at given offset
+struct OpenFile {
+    file: File,
+    inode: u64,
+    offset: u64
+}
+
+impl OpenFile {
+    fn open(path: &Path, offset: Option<u64>) -> io::Result<OpenFile> {
+        let file = try!(File::open(path));
+        let metadata = try!(file.metadata());
+        Ok(OpenFile {
+            file: file,
+            inode: metadata.ino(),
+            // start from end of file when there is nothing to resume from
+            offset: offset.unwrap_or(metadata.len())
+        })
+    }
+
+    /// Reads lines after offset and returns offset past them; incomplete last line is left for next read unless file will not be written anymore
+    fn read_lines(&mut self, lines: &mut Vec<String>, finished: bool) -> io::Result<u64> {
+        let mut offset = self.offset;
+        if try!(self.file.metadata()).len() < offset {
+            debug!("Log file truncated; reading from beginning");
+            offset = 0;
+        }
+        try!(self.file.seek(SeekFrom::Start(offset)));
+
+        let mut reader = BufReader::new(&self.file);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = try!(reader.read_until(b'\n', &mut line));
+            if read == 0 || (line.last() != Some(&b'\n') && !finished) {
+                break
+            }
+            offset += read as u64;
+
+            while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            lines.push(String::from_utf8_lossy(&line).into_owned());
+        }
+        Ok(offset)
+    }
+}
+
+/// Follows log file across rotation by rename or truncation; lines are read again until they are committed
+pub struct LogTail {
+    path: PathBuf,
+    state_file: PathBuf,
+    open: Option<OpenFile>,
+    /// File renamed on rotation whose remaining lines were not committed yet
+    rotated: Option<OpenFile>,
+    /// Offset in open file past lines read but not committed yet
+    read_offset: Option<u64>,
+    resumed: bool
+}
+
+impl LogTail {
+    pub fn new(path: PathBuf, state_file: PathBuf) -> LogTail {
+        LogTail {
+            path: path,
+            state_file: state_file,
+            open: None,
+            rotated: None,
+            read_offset: None,
+            resumed: false
+        }
+    }
+
+    /// Path the file is expected to be renamed to on rotation, e.g. app.log.1
+    fn rotated_path(&self) -> PathBuf {
+        let mut rotated = OsString::from(self.path.as_os_str());
+        rotated.push(".1");
+        PathBuf::from(rotated)
+    }
+
+    fn load_state(&self) -> Option<(u64, u64)> {
+        let mut state = String::new();
+        if File::open(&self.state_file).and_then(|mut file| file.read_to_string(&mut state)).is_err() {
+            return None
+        }
+        let fields: Vec<u64> = state.split_whitespace().filter_map(|field| field.parse().ok()).collect();
+        match fields.len() {
+            2 => Some((fields[0], fields[1])),
+            _ => {
+                warn!("Ignoring invalid log offset state in {}", self.state_file.display());
+                None
+            }
+        }
+    }
+
+    /// Moves past lines read so far and saves offset so they are not read again after restart; call once data derived from them was accepted
+    pub fn commit(&mut self) -> io::Result<()> {
+        self.rotated = None;
+        if let Some(offset) = self.read_offset.take() {
+            if let Some(ref mut open) = self.open {
+                open.offset = offset;
+            }
+        }
+
+        let open = match self.open {
+            Some(ref open) => open,
+            None => return Ok(())
+        };
+        if let Some(dir) = self.state_file.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+
+        // replace state at once so it is not lost if agent is killed while saving
+        let mut temporary = OsString::from(self.state_file.as_os_str());
+        temporary.push(".tmp");
+        try!(File::create(&temporary).and_then(|mut file| write!(file, "{} {}\n", open.inode, open.offset)));
+        fs::rename(&temporary, &self.state_file)
+    }
+
+    /// Opens file at offset saved by previous agent run; lines appended to file rotated meanwhile are read first
+    fn resume(&mut self) -> io::Result<()> {
+        let state = self.load_state();
+        let mut open = match OpenFile::open(&self.path, None) {
+            Ok(open) => open,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err)
+        };
+
+        match state {
+            Some((inode, offset)) if inode == open.inode => open.offset = offset,
+            Some((inode, offset)) => {
+                if let Ok(rotated) = OpenFile::open(&self.rotated_path(), Some(offset)) {
+                    if rotated.inode == inode {
+                        self.rotated = Some(rotated);
+                    }
+                }
+                open.offset = 0;
+            }
+            None => ()
+        }
+
+        self.open = Some(open);
+        Ok(())
+    }
+
+    /// Lines appended since last commit
+    pub fn read_lines(&mut self) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        if !self.resumed {
+            try!(self.resume());
+            self.resumed = true;
+        }
+
+        let current_inode = fs::metadata(&self.path).ok().map(|metadata| metadata.ino());
+        if self.open.as_ref().map(|open| Some(open.inode) != current_inode).unwrap_or(false) {
+            if self.rotated.is_some() {
+                warn!("Log file {} rotated again before lines of previous rotation were committed; skipping them", self.path.display());
+            }
+            // file was renamed; finish reading it before switching to new one
+            self.rotated = self.open.take();
+            self.read_offset = None;
+        }
+        if let Some(ref mut rotated) = self.rotated {
+            try!(rotated.read_lines(&mut lines, true));
+        }
+
+        if self.open.is_none() && current_inode.is_some() {
+            self.open = Some(try!(OpenFile::open(&self.path, Some(0))));
+        }
+        self.read_offset = match self.open {
+            Some(ref mut open) => Some(try!(open.read_lines(&mut lines, false))),
+            None => None
+        };
+        Ok(lines)
+    }
+}
+
+pub struct LogProbe {
+    name: String,
+    path: String,
+    location: String,
+    rules: Vec<Rule>,
+    tail: Mutex<LogTail>
+}
+
+impl LogProbe {
+    fn data_points(&self, tail: &mut LogTail) -> Result<Vec<(&str, DataValue)>, String> {
+        let lines = try!(tail.read_lines().map_err(|err| format!("failed to read {}: {}", tail.path.display(), err)));
+
+        let mut data_points = Vec::new();
+        for rule in &self.rules {
+            match rule.kind {
+                RuleKind::Count => {
+                    let count = lines.iter().filter(|line| rule.regex.is_match(line)).count();
+                    data_points.push((rule.name.as_str(), DataValue::Integer(count as i64)));
+                }
+                RuleKind::Value => {
+                    for value in lines.iter().filter_map(|line| rule.value(line)) {
+                        data_points.push((rule.name.as_str(), DataValue::Float(value)));
+                    }
+                }
+            }
+        }
+        Ok(data_points)
+    }
+}
+
+impl Probe for LogProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, collector: &mut Collect) -> Result<(), String> {
+        let mut collector = collector;
+        let mut tail = self.tail.lock().unwrap();
+        for (component, value) in try!(self.data_points(&mut tail)) {
+            try!(collector.collect(&self.location, &self.path, component, value).map_err(|err| err.to_string()));
+        }
+
+        // lines are read again after restart unless all their data points were collected
+        if let Err(err) = tail.commit() {
+            warn!("Failed to save log offset of {} to {}: {}", tail.path.display(), tail.state_file.display(), err);
+        }
+        Ok(())
+    }
+
+    fn run_mode(&self) -> RunMode {
+        RunMode::DedicatedThread
+    }
+}
+
+pub struct LogsModule {
+    schedule: Vec<ProbeRunPlan>
+}
+
+impl Module for LogsModule {
+    fn name(&self) -> &str {
+        "logs"
+    }
+
+    fn schedule(&self) -> Iter<ProbeRunPlan> {
+        self.schedule.iter()
+    }
+}
+
+/// Each probe follows one log file; its rules report data points on path log/<probe> with rule name as component
+pub fn init(location: &str, config: &ModuleConfig) -> Result<Box<Module>, ConfigError> {
+    let state_dir = PathBuf::from(try!(config.options.string("state_dir")).unwrap_or(STATE_DIR));
+    let mut schedule = Vec::new();
+
+    for probe in &config.probes {
+        let file = match try!(probe.options.string("file")) {
+            Some(file) => PathBuf::from(file),
+            None => return Err(probe.options.error("file", "missing required key"))
+        };
+
+        let mut rules = Vec::new();
+        for (name, options) in try!(probe.options.tables("rules")) {
+            rules.push(try!(Rule::from_options(&name, &options)));
+        }
+        if rules.is_empty() {
+            return Err(probe.options.error("rules", "at least one rule is required"))
+        }
+
+        schedule.push(ProbeRunPlan {
+            every: probe.every,
+            timeout: probe.timeout,
+            probe: Arc::new(LogProbe {
+                name: format!("{}/{}", config.name, probe.name),
+                path: format!("log/{}", probe.name),
+                location: location.to_string(),
+                rules: rules,
+                tail: Mutex::new(LogTail::new(file, state_dir.join(format!("{}.offset", probe.name))))
+            })
+        });
+    }
+
+    Ok(Box::new(LogsModule {
+        schedule: schedule
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::test_support::VecCollector;
+    pub use super::super::{Probe, Module};
+    pub use config::Config;
+    pub use messaging::DataValue;
+    pub use sender::{Collect, CollectError};
+    pub use std::path::Path;
+    pub use std::fs::{self, File, OpenOptions};
+    pub use std::io::Write;
+    pub use tempdir::TempDir;
+
+    fn append(path: &Path, text: &str) {
+        OpenOptions::new().create(true).append(true).open(path).unwrap().write_all(text.as_bytes()).unwrap();
+    }
+
+    fn tail(dir: &TempDir) -> LogTail {
+        LogTail::new(dir.path().join("app.log"), dir.path().join("state/app.offset"))
+    }
+
+    fn read(tail: &mut LogTail) -> Vec<String> {
+        let lines = tail.read_lines().unwrap();
+        tail.commit().unwrap();
+        lines
+    }
+
+    /// Fails first collect call
+    struct FailingOnceCollector {
+        failed: bool,
+        collector: VecCollector
+    }
+
+    impl Collect for FailingOnceCollector {
+        fn collect(&mut self, location: &str, path: &str, component: &str, value: DataValue) -> Result<(), CollectError> {
+            if !self.failed {
+                self.failed = true;
+                return Err(CollectError::Dropped)
+            }
+            self.collector.collect(location, path, component, value)
+        }
+    }
+
+    #[test]
+    fn should_read_complete_lines_only() {
+        let dir = TempDir::new("dms_logs").unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "old line\n");
+
+        let mut tail = tail(&dir);
+        assert!(read(&mut tail).is_empty());
+
+        append(&log, "first\nsecond\r\nthi");
+        assert_eq!(read(&mut tail), vec!["first".to_string(), "second".to_string()]);
+
+        append(&log, "rd\n");
+        assert_eq!(read(&mut tail), vec!["third".to_string()]);
+        assert!(read(&mut tail).is_empty());
+    }
+
+    #[test]
+    fn should_follow_rotation_by_rename() {
+        let dir = TempDir::new("dms_logs").unwrap();
+        let log = dir.path().join("app.log");
+        let rotated = dir.path().join("app.log.1");
+        append(&log, "");
+
+        let mut tail = tail(&dir);
+        read(&mut tail);
+
+        append(&log, "first\n");
+        fs::rename(&log, &rotated).unwrap();
+        // written by application before it reopened its log file
+        append(&rotated, "second\n");
+        append(&log, "third\n");
+
+        assert_eq!(read(&mut tail), vec!["first".to_string(), "second".to_string(), "third".to_string()]);
+
+        append(&rotated, "too late\n");
+        append(&log, "fourth\n");
+        assert_eq!(read(&mut tail), vec!["fourth".to_string()]);
+    }
+
+    #[test]
+    fn should_follow_truncation() {
+        let dir = TempDir::new("dms_logs").unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "");
+
+        let mut tail = tail(&dir);
+        read(&mut tail);
+        append(&log, "first line\nsecond line\n");
+        assert_eq!(read(&mut tail).len(), 2);
+
+        File::create(&log).unwrap().write_all(b"new\n").unwrap();
+        assert_eq!(read(&mut tail), vec!["new".to_string()]);
+    }
+
+    #[test]
+    fn should_resume_from_saved_offset_after_restart() {
+        let dir = TempDir::new("dms_logs").unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "before start\n");
+
+        {
+            let mut tail = tail(&dir);
+            tail.read_lines().unwrap();
+            tail.commit().unwrap();
+        }
+        append(&log, "while stopped\n");
+        {
+            let mut tail = tail(&dir);
+            assert_eq!(tail.read_lines().unwrap(), vec!["while stopped".to_string()]);
+            tail.commit().unwrap();
+        }
+
+        // rotated while agent was stopped
+        append(&log, "before rotation\n");
+        fs::rename(&log, dir.path().join("app.log.1")).unwrap();
+        append(&log, "after rotation\n");
+        assert_eq!(tail(&dir).read_lines().unwrap(), vec!["before rotation".to_string(), "after rotation".to_string()]);
+
+        // log created after agent has started is read from beginning
+        let dir = TempDir::new("dms_logs").unwrap();
+        let mut tail = tail(&dir);
+        assert!(tail.read_lines().unwrap().is_empty());
+        append(&dir.path().join("app.log"), "created\n");
+        assert_eq!(tail.read_lines().unwrap(), vec!["created".to_string()]);
+    }
+
+    #[test]
+    fn should_read_lines_again_after_restart_unless_committed() {
+        let dir = TempDir::new("dms_logs").unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "");
+        {
+            let mut tail = tail(&dir);
+            tail.read_lines().unwrap();
+            tail.commit().unwrap();
+        }
+
+        append(&log, "not collected\n");
+        assert_eq!(tail(&dir).read_lines().unwrap(), vec!["not collected".to_string()]);
+        assert_eq!(tail(&dir).read_lines().unwrap(), vec!["not collected".to_string()]);
+    }
+
+    #[test]
+    fn should_read_lines_again_unless_committed() {
+        let dir = TempDir::new("dms_logs").unwrap();
+        let log = dir.path().join("app.log");
+        let mut tail = tail(&dir);
+        append(&log, "");
+        read(&mut tail);
+
+        append(&log, "first\n");
+        fs::rename(&log, dir.path().join("app.log.1")).unwrap();
+        append(&log, "second\n");
+        assert_eq!(tail.read_lines().unwrap(), vec!["first".to_string(), "second".to_string()]);
+        assert_eq!(tail.read_lines().unwrap(), vec!["first".to_string(), "second".to_string()]);
+
+        tail.commit().unwrap();
+        assert!(tail.read_lines().unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_report_lines_again_after_failed_collect() {
+        let dir = TempDir::new("dms_logs").unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "");
+
+        let config = Config::parse(Path::new("agent.toml"), &format!(r#"
+            location = "web-01"
+
+            [modules.logs]
+            state_dir = "{}"
+
+            [modules.logs.probes.app]
+            every = "10s"
+            file = "{}"
+
+            [modules.logs.probes.app.rules.errors]
+            pattern = 'ERROR'
+        "#, dir.path().join("state").display(), log.display())).unwrap();
+        let module = init(&config.location, &config.modules[0]).unwrap();
+        let probe = module.schedule().next().unwrap().probe.clone();
+        probe.run(&mut VecCollector::new()).unwrap();
+
+        append(&log, "ERROR one\nERROR two\n");
+        let mut collector = FailingOnceCollector { failed: false, collector: VecCollector::new() };
+        assert!(probe.run(&mut collector).is_err());
+        probe.run(&mut collector).unwrap();
+        assert_eq!(collector.collector.values, vec![
+            ("log/app".to_string(), "errors".to_string(), DataValue::Integer(2))
+        ]);
+    }
+
+    #[test]
+    fn should_report_rule_data_points() {
+        let dir = TempDir::new("dms_logs").unwrap();
+        let log = dir.path().join("access.log");
+        append(&log, "");
+
+        let config = Config::parse(Path::new("agent.toml"), &format!(r#"
+            location = "web-01"
+
+            [modules.logs]
+            state_dir = "{}"
+
+            [modules.logs.probes.nginx]
+            every = "10s"
+            file = "{}"
+
+            [modules.logs.probes.nginx.rules.server_errors]
+            pattern = '" 5\d\d '
+
+            [modules.logs.probes.nginx.rules.response_time]
+            type = "value"
+            pattern = 'rt=(?P<value>[0-9.]+)'
+        "#, dir.path().join("state").display(), log.display())).unwrap();
+
+        let module = init(&config.location, &config.modules[0]).unwrap();
+        let probe = module.schedule().next().unwrap().probe.clone();
+        assert_eq!(probe.name(), "logs/nginx");
+
+        let mut collector = VecCollector::new();
+        probe.run(&mut collector).unwrap();
+        assert_eq!(collector.values, vec![
+            ("log/nginx".to_string(), "server_errors".to_string(), DataValue::Integer(0))
+        ]);
+
+        append(&log, "\"GET / HTTP/1.1\" 200 rt=0.010\n\"GET /a HTTP/1.1\" 502 rt=1.5\n\"GET /b HTTP/1.1\" 503 rt=x\n");
+
+        let mut collector = VecCollector::new();
+        probe.run(&mut collector).unwrap();
+        assert_eq!(collector.values, vec![
+            ("log/nginx".to_string(), "response_time".to_string(), DataValue::Float(0.010)),
+            ("log/nginx".to_string(), "response_time".to_string(), DataValue::Float(1.5)),
+            ("log/nginx".to_string(), "server_errors".to_string(), DataValue::Integer(2))
+        ]);
+        assert!(dir.path().join("state/nginx.offset").exists());
+    }
+
+    #[test]
+    fn should_reject_invalid_rules() {
+        let parse = |rules: &str| Config::parse(Path::new("agent.toml"), &format!("location = \"web-01\"\n[modules.logs.probes.app]\nevery = \"1s\"\nfile = \"/var/log/app.log\"\n{}", rules)).unwrap().modules.remove(0);
+
+        assert!(init("web-01", &parse("")).is_err());
+        assert!(init("web-01", &parse("[modules.logs.probes.app.rules.bad]\npattern = \"(\"")).is_err());
+        assert!(init("web-01", &parse("[modules.logs.probes.app.rules.bad]\npattern = \"x\"\ntype = \"value\"")).is_err());
+        assert!(init("web-01", &parse("[modules.logs.probes.app.rules.bad]\npattern = \"x\"\ntype = \"sum\"")).is_err());
+        assert!(init("web-01", &parse("[modules.logs.probes.app.rules.ok]\npattern = \"x\"")).is_ok());
+    }
+}
@@ -1,60 +1,397 @@
 use std::slice::Iter;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::HashMap;
 use std::fmt;
+use std::io;
+use std::mem;
 use std::error::Error;
-use std::sync::mpsc::{channel, Receiver};
+use std::sync::mpsc::{channel, Receiver, Sender, RecvTimeoutError, TryRecvError};
 use time::Duration;
+use std::time::{Duration as StdDuration, Instant};
 use token_scheduler::{Scheduler, Abort, AbortableWait, AbortableWaitError, SteadyTimeSource};
 
 use program::{self, JoinHandle, Signal};
 use sender::{Collect, Collector};
+use config::{Config, ModuleConfig, ConfigError};
+
+pub use self::health::{ProbeRunResult, ProbeHealth, HealthStatus, HealthRegistry};
+
+mod health;
+
+/// Timeout of probes that have no valid timeout in their run plan
+const DEFAULT_PROBE_TIMEOUT_MS: u64 = 10000;
+
+/// Duration in (fractional) milliseconds as reported and logged by probes
+fn millis(duration: StdDuration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0
+}
 
-#[allow(dead_code)]
 pub enum RunMode {
     SharedThread,
-    //DedicatedThread,
-    //DedicatedProcess
+    DedicatedThread,
+    DedicatedProcess
 }
 
 pub struct ProbeRunPlan {
     every: Duration,
-    probe: Rc<Probe>
+    timeout: Duration,
+    probe: Arc<Probe>
 }
 
-pub trait Probe: Send {
+pub trait Probe: Send + Sync {
     fn name(&self) -> &str;
     fn run(&self, collector: &mut Collect) -> Result<(), String>;
     fn run_mode(&self) -> RunMode;
+
+    /// Runs probe and classifies the outcome; probes enforcing their own deadline can report timeouts
+    fn run_with_result(&self, collector: &mut Collect) -> ProbeRunResult {
+        match self.run(collector) {
+            Ok(()) => ProbeRunResult::Ok,
+            Err(error) => ProbeRunResult::Error(error)
+        }
+    }
 }
 
-pub trait Module {
+pub trait Module: Send {
     fn name(&self) -> &str;
     fn schedule(&self) -> Iter<ProbeRunPlan>;
 }
 
+type SharedRunRequest = (Arc<Probe>, Box<Collect + Send>, Sender<ProbeRunResult>);
+
+/// Runs probes one after another on shared thread; thread stuck on a probe past its timeout is abandoned
 pub struct SharedThreadProbeRunner {
-    probes: Vec<Rc<Probe>>
+    probes: Vec<(Arc<Probe>, StdDuration)>,
+    worker: Option<(Sender<SharedRunRequest>, JoinHandle<()>)>,
+    /// Result channels of runs left on abandoned threads by probe name
+    stuck: HashMap<String, Receiver<ProbeRunResult>>,
+    abandoned: u64
 }
 
 impl SharedThreadProbeRunner {
     pub fn new() -> SharedThreadProbeRunner {
         SharedThreadProbeRunner {
-            probes: Vec::new()
+            probes: Vec::new(),
+            worker: None,
+            stuck: HashMap::new(),
+            abandoned: 0
+        }
+    }
+
+    /// Queues probe for next run; returns false if its run on abandoned thread has not finished yet
+    pub fn push(&mut self, probe: Arc<Probe>, timeout: StdDuration) -> bool {
+        if self.stuck_on(probe.name()) {
+            return false
+        }
+        self.probes.push((probe, timeout));
+        true
+    }
+
+    /// Tells if abandoned thread is still running the probe; forgets the run once it finished
+    fn stuck_on(&mut self, probe: &str) -> bool {
+        let stuck = match self.stuck.get(probe) {
+            Some(run_result) => match run_result.try_recv() {
+                Err(TryRecvError::Empty) => true,
+                _ => false
+            },
+            None => return false
+        };
+        if !stuck {
+            self.stuck.remove(probe);
+        }
+        stuck
+    }
+
+    pub fn run<C, F>(&mut self, mut probe_collector: F) -> Vec<(String, ProbeRunResult)> where F: FnMut(&Probe) -> C, C: Collect + Send + 'static {
+        let probes = mem::replace(&mut self.probes, Vec::new());
+        let mut results = Vec::with_capacity(probes.len());
+
+        for (probe, timeout) in probes {
+            let collector = Box::new(probe_collector(&*probe));
+            let (result, run_result) = channel();
+            self.worker().send((probe.clone(), collector, result)).expect("shared probe thread died");
+
+            let received = run_result.recv_timeout(timeout);
+            let result = match received {
+                Ok(result) => result,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.abandon(probe.name());
+                    self.stuck.insert(probe.name().to_string(), run_result);
+                    ProbeRunResult::Timeout(timeout)
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.abandon(probe.name());
+                    ProbeRunResult::Error("probe panicked".to_string())
+                }
+            };
+            results.push((probe.name().to_string(), result));
+        }
+        results
+    }
+
+    fn worker(&mut self) -> &Sender<SharedRunRequest> {
+        if self.worker.is_none() {
+            let (runs, run_requests): (Sender<SharedRunRequest>, Receiver<SharedRunRequest>) = channel();
+            let thread = program::spawn("producer/probe/shared", move || {
+                for (probe, mut collector, result) in run_requests {
+                    let run_result = probe.run_with_result(&mut *collector);
+                    drop(collector);
+                    // runner may have given up waiting already
+                    result.send(run_result).ok();
+                }
+            });
+            self.worker = Some((runs, thread));
         }
+        &self.worker.as_ref().unwrap().0
+    }
+
+    // Stuck thread can't be killed so it is left to finish on its own and new one will be started for next probe
+    fn abandon(&mut self, probe: &str) {
+        self.abandoned = self.abandoned + 1;
+        warn!("Abandoning shared probe thread stuck on probe '{}'; abandoned threads since start: {}", probe, self.abandoned);
+        self.worker = None;
     }
 
-    pub fn push(&mut self, probe: Rc<Probe>) {
-        self.probes.push(probe);
+    pub fn stop(self) {
+        if let Some((runs, thread)) = self.worker {
+            drop(runs);
+            thread.join().ok();
+        }
+    }
+}
+
+struct ProbeWorker {
+    runs: Sender<Box<Collect + Send>>,
+    started: Arc<Mutex<Option<Instant>>>,
+    abandoned: Arc<AtomicBool>,
+    timeout: Option<StdDuration>,
+    thread: JoinHandle<()>
+}
+
+/// Worker of removed or rescheduled probe finishing its in progress run
+struct RetiredWorker {
+    probe: String,
+    started: Arc<Mutex<Option<Instant>>>,
+    abandoned: Arc<AtomicBool>,
+    timeout: Option<StdDuration>,
+    thread: JoinHandle<()>
+}
+
+impl RetiredWorker {
+    fn busy(&self) -> bool {
+        self.started.lock().unwrap().is_some()
     }
+}
 
-    pub fn run(self, collector: &mut Collect) -> Vec<Result<(), String>> {
-        self.probes.into_iter().map(|probe| probe.run(collector)).collect()
+/// Timeout of run started at given time if it has been exceeded
+fn overdue(started: &Mutex<Option<Instant>>, timeout: Option<StdDuration>) -> Option<StdDuration> {
+    match (*started.lock().unwrap(), timeout) {
+        (Some(started), Some(timeout)) if started.elapsed() > timeout => Some(timeout),
+        _ => None
     }
 }
 
+/// Runs each probe on its own long-lived thread
+pub struct DedicatedThreadProbeRunner {
+    workers: HashMap<String, ProbeWorker>,
+    retired: Vec<RetiredWorker>,
+    /// Run start of abandoned workers by probe name; cleared once the stuck run finishes
+    abandoned: HashMap<String, Arc<Mutex<Option<Instant>>>>,
+    results: Sender<(String, ProbeRunResult)>,
+    run_results: Receiver<(String, ProbeRunResult)>
+}
+
+impl DedicatedThreadProbeRunner {
+    pub fn new() -> DedicatedThreadProbeRunner {
+        let (results, run_results) = channel();
+        DedicatedThreadProbeRunner {
+            workers: HashMap::new(),
+            retired: Vec::new(),
+            abandoned: HashMap::new(),
+            results: results,
+            run_results: run_results
+        }
+    }
+
+    /// Requests probe run on its worker thread; returns false if previous run of the probe is still in progress, including run on its retired or abandoned worker
+    pub fn run(&mut self, probe: Arc<Probe>, timeout: Option<StdDuration>, collector: Box<Collect + Send>) -> bool {
+        if self.stuck_on(probe.name()) {
+            return false
+        }
+        // replacement of a probe may share state with its retired version, e.g. log offset file
+        if self.retired.iter().any(|retired| retired.probe == probe.name() && retired.busy()) {
+            return false
+        }
+
+        let results = &self.results;
+        let worker = self.workers.entry(probe.name().to_string()).or_insert_with(|| DedicatedThreadProbeRunner::spawn_worker(probe.clone(), results.clone()));
+
+        {
+            let mut started = worker.started.lock().unwrap();
+            if started.is_some() {
+                return false
+            }
+            *started = Some(Instant::now());
+        }
+
+        worker.timeout = timeout;
+        worker.runs.send(collector).expect("probe worker thread died");
+        true
+    }
+
+    /// Tells if abandoned worker is still running the probe; forgets the worker once its run finished
+    fn stuck_on(&mut self, probe: &str) -> bool {
+        let stuck = match self.abandoned.get(probe) {
+            Some(started) => started.lock().unwrap().is_some(),
+            None => return false
+        };
+        if !stuck {
+            self.abandoned.remove(probe);
+        }
+        stuck
+    }
+
+    fn spawn_worker(probe: Arc<Probe>, results: Sender<(String, ProbeRunResult)>) -> ProbeWorker {
+        let (runs, run_requests): (Sender<Box<Collect + Send>>, Receiver<Box<Collect + Send>>) = channel();
+        let started = Arc::new(Mutex::new(None));
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let worker_started = started.clone();
+        let worker_abandoned = abandoned.clone();
+
+        let thread = program::spawn(&format!("producer/probe/{}", probe.name()), move || {
+            for mut collector in run_requests {
+                let result = probe.run_with_result(&mut *collector);
+                // drop collector before next run can be requested
+                drop(collector);
+                *worker_started.lock().unwrap() = None;
+
+                if !worker_abandoned.load(Ordering::SeqCst) {
+                    results.send((probe.name().to_string(), result)).ok();
+                }
+            }
+            debug!("Probe '{}' worker done", probe.name());
+        });
+
+        ProbeWorker {
+            runs: runs,
+            started: started,
+            abandoned: abandoned,
+            timeout: None,
+            thread: thread
+        }
+    }
+
+    /// Results of finished runs and of runs that exceeded their timeout; workers of the latter, including retired ones, are abandoned
+    pub fn results(&mut self) -> Vec<(String, ProbeRunResult)> {
+        let mut results = Vec::new();
+        while let Ok(result) = self.run_results.try_recv() {
+            results.push(result);
+        }
+
+        let timed_out: Vec<(String, StdDuration)> = self.workers.iter().filter_map(|(name, worker)| {
+            overdue(&worker.started, worker.timeout).map(|timeout| (name.clone(), timeout))
+        }).collect();
+
+        for (name, timeout) in timed_out {
+            warn!("Abandoning thread of probe '{}' stuck past its timeout", name);
+            let worker = self.workers.remove(&name).unwrap();
+            worker.abandoned.store(true, Ordering::SeqCst);
+            self.abandoned.insert(name.clone(), worker.started);
+            results.push((name, ProbeRunResult::Timeout(timeout)));
+        }
+
+        // finished retired workers are dropped; their threads end as no more runs can be requested
+        let retired = mem::replace(&mut self.retired, Vec::new());
+        for retired in retired {
+            if let Some(timeout) = overdue(&retired.started, retired.timeout) {
+                warn!("Abandoning thread of retired probe '{}' stuck past its timeout", retired.probe);
+                retired.abandoned.store(true, Ordering::SeqCst);
+                self.abandoned.insert(retired.probe.clone(), retired.started);
+                results.push((retired.probe, ProbeRunResult::Timeout(timeout)));
+            } else if retired.busy() {
+                self.retired.push(retired);
+            }
+        }
+
+        results
+    }
+
+    /// Stops worker thread of the probe once its in progress run finishes; next run will start new worker
+    pub fn retire(&mut self, probe: &str) {
+        if let Some(worker) = self.workers.remove(probe) {
+            let ProbeWorker { runs, started, abandoned, timeout, thread } = worker;
+            drop(runs);
+            self.retired.push(RetiredWorker {
+                probe: probe.to_string(),
+                started: started,
+                abandoned: abandoned,
+                timeout: timeout,
+                thread: thread
+            });
+        }
+    }
+
+    /// Waits for all in progress runs to finish and stops worker threads; abandoned threads and threads stuck past run timeout are not waited for
+    pub fn stop(self) {
+        for (name, worker) in self.workers {
+            let ProbeWorker { runs, started, timeout, thread, .. } = worker;
+            drop(runs);
+            if overdue(&started, timeout).is_some() {
+                warn!("Not waiting for thread of probe '{}' stuck past its timeout", name);
+                continue
+            }
+            thread.join().ok();
+        }
+        for retired in self.retired {
+            if overdue(&retired.started, retired.timeout).is_some() {
+                warn!("Not waiting for thread of retired probe '{}' stuck past its timeout", retired.probe);
+                continue
+            }
+            retired.thread.join().ok();
+        }
+    }
+}
+
+/// Probe currently scheduled to run; scheduler entries with different id belong to replaced or removed probes
+struct ScheduledProbe {
+    id: u64,
+    module: String,
+    every: Duration,
+    timeout: StdDuration,
+    probe: Arc<Probe>
+}
+
+type AbortHandle = <SteadyTimeSource as AbortableWait>::AbortHandle;
+
+/// Aborts wait of probe scheduler; keeps working after scheduler was rebuilt on reschedule
+#[derive(Clone)]
+pub struct ProbeSchedulerAbortHandle {
+    handle: Arc<Mutex<AbortHandle>>
+}
+
+impl ProbeSchedulerAbortHandle {
+    pub fn abort(&self) {
+        self.handle.lock().unwrap().abort();
+    }
+}
+
+/// Probes affected by rescheduling
+#[derive(Debug, Default, PartialEq)]
+pub struct ScheduleChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub rescheduled: Vec<String>
+}
+
 pub struct ProbeScheduler {
-    scheduler: Scheduler<Rc<Probe>, SteadyTimeSource>,
-    overrun: u64
+    scheduler: Scheduler<(u64, Arc<Probe>), SteadyTimeSource>,
+    abort_handle: ProbeSchedulerAbortHandle,
+    scheduled: HashMap<String, ScheduledProbe>,
+    next_id: u64,
+    health: HealthRegistry,
+    overrun: u64,
+    skipped: u64
 }
 
 #[derive(Debug)]
@@ -80,60 +417,370 @@ impl Error for ProbeSchedulerError {
 
 impl ProbeScheduler {
     pub fn new() -> ProbeScheduler {
+        let scheduler = Scheduler::new(Duration::milliseconds(100));
+        let abort_handle = ProbeSchedulerAbortHandle { handle: Arc::new(Mutex::new(scheduler.abort_handle())) };
+
         ProbeScheduler {
-            scheduler: Scheduler::new(Duration::milliseconds(100)),
-            overrun: 0
+            scheduler: scheduler,
+            abort_handle: abort_handle,
+            scheduled: HashMap::new(),
+            next_id: 0,
+            health: HealthRegistry::new(),
+            overrun: 0,
+            skipped: 0
         }
     }
 
     pub fn schedule<'m>(&mut self, module: &'m Module) {
         for probe_schedule in module.schedule() {
-            self.scheduler.every(probe_schedule.every, probe_schedule.probe.clone());
+            let id = self.add(module.name(), probe_schedule);
+            self.scheduler.every(probe_schedule.every, (id, probe_schedule.probe.clone()));
         }
     }
 
-    pub fn abort_handle(&self) -> <SteadyTimeSource as AbortableWait>::AbortHandle {
-        self.scheduler.abort_handle()
+    fn add(&mut self, module: &str, probe_schedule: &ProbeRunPlan) -> u64 {
+        let timeout = probe_schedule.timeout.to_std().unwrap_or_else(|_| {
+            warn!("Invalid timeout of probe '{}'; using default", probe_schedule.probe.name());
+            StdDuration::from_millis(DEFAULT_PROBE_TIMEOUT_MS)
+        });
+
+        let id = self.next_id;
+        self.next_id = self.next_id + 1;
+
+        self.scheduled.insert(probe_schedule.probe.name().to_string(), ScheduledProbe {
+            id: id,
+            module: module.to_string(),
+            every: probe_schedule.every,
+            timeout: timeout,
+            probe: probe_schedule.probe.clone()
+        });
+        id
+    }
+
+    /// Replaces scheduled probes with probes of given modules; probes of modules for which unchanged returns true keep their current schedule
+    pub fn reschedule<F>(&mut self, modules: &[Box<Module>], unchanged: F) -> ScheduleChanges where F: Fn(&str) -> bool {
+        let mut changes = ScheduleChanges::default();
+        let mut names = Vec::new();
+
+        for module in modules {
+            for probe_schedule in module.schedule() {
+                let name = probe_schedule.probe.name().to_string();
+                names.push(name.clone());
+
+                match self.scheduled.get(&name).map(|scheduled| scheduled.module == module.name()) {
+                    Some(true) if unchanged(module.name()) => continue,
+                    Some(_) => changes.rescheduled.push(name),
+                    None => changes.added.push(name)
+                }
+                self.add(module.name(), probe_schedule);
+            }
+        }
+
+        changes.removed = self.scheduled.keys().filter(|name| !names.contains(name)).cloned().collect();
+        changes.removed.sort();
+        for name in &changes.removed {
+            self.scheduled.remove(name);
+            self.health.remove(name);
+        }
+
+        if changes != ScheduleChanges::default() {
+            self.rebuild();
+        }
+        changes
     }
 
-    pub fn abortable_wait(&mut self) -> Result<Vec<Rc<Probe>>, ProbeSchedulerError> {
-         match self.scheduler.abortable_wait() {
-             Err(AbortableWaitError::Overrun(probe_runs)) => {
-                 //TODO: trace each overrun
-                 self.overrun = self.overrun + probe_runs.len() as u64;
-                 warn!("{} probes overrun their scheduled run time; overruns since start: {}", probe_runs.len(), self.overrun);
-                 self.abortable_wait()
-             },
-             Err(AbortableWaitError::Empty) => Err(ProbeSchedulerError::Empty),
-             Err(AbortableWaitError::Aborted) => Err(ProbeSchedulerError::Aborted),
-             Ok(probes) => {
-                 Ok(probes)
-             }
-         }
+    // Scheduler can't drop entries of replaced or removed probes so new one is built from probes scheduled now
+    fn rebuild(&mut self) {
+        let mut scheduled: Vec<&ScheduledProbe> = self.scheduled.values().collect();
+        scheduled.sort_by_key(|scheduled| scheduled.id);
+
+        let mut scheduler = Scheduler::new(Duration::milliseconds(100));
+        for scheduled in scheduled {
+            scheduler.every(scheduled.every, (scheduled.id, scheduled.probe.clone()));
+        }
+
+        *self.abort_handle.handle.lock().unwrap() = scheduler.abort_handle();
+        self.scheduler = scheduler;
+    }
+
+    /// Name of module the scheduled probe belongs to
+    pub fn module(&self, probe: &str) -> Option<&str> {
+        self.scheduled.get(probe).map(|scheduled| scheduled.module.as_str())
+    }
+
+    /// Time given to single run of the probe before it is abandoned
+    pub fn timeout(&self, probe: &Probe) -> StdDuration {
+        self.scheduled.get(probe.name()).map(|scheduled| scheduled.timeout).unwrap_or(StdDuration::from_millis(DEFAULT_PROBE_TIMEOUT_MS))
+    }
+
+    #[allow(dead_code)]
+    pub fn every(&self, probe: &str) -> Option<Duration> {
+        self.scheduled.get(probe).map(|scheduled| scheduled.every)
+    }
+
+    pub fn record(&mut self, probe: &str, result: &ProbeRunResult) {
+        self.health.record(probe, result);
+    }
+
+    #[allow(dead_code)]
+    pub fn health(&self, probe: &str) -> Option<&ProbeHealth> {
+        self.health.get(probe)
+    }
+
+    pub fn abort_handle(&self) -> ProbeSchedulerAbortHandle {
+        self.abort_handle.clone()
+    }
+
+    pub fn abortable_wait(&mut self) -> Result<Vec<Arc<Probe>>, ProbeSchedulerError> {
+        loop {
+            match self.scheduler.abortable_wait() {
+                Err(AbortableWaitError::Overrun(probe_runs)) => {
+                    //TODO: trace each overrun
+                    self.overrun = self.overrun + probe_runs.len() as u64;
+                    warn!("{} probes overrun their scheduled run time; overruns since start: {}", probe_runs.len(), self.overrun);
+                },
+                Err(AbortableWaitError::Empty) => return Err(ProbeSchedulerError::Empty),
+                Err(AbortableWaitError::Aborted) => return Err(ProbeSchedulerError::Aborted),
+                Ok(probes) => {
+                    let probes: Vec<Arc<Probe>> = probes.into_iter().filter(|&(id, ref probe)| {
+                        self.scheduled.get(probe.name()).map(|scheduled| scheduled.id == id).unwrap_or(false)
+                    }).map(|(_, probe)| probe).collect();
+
+                    // wait again if only probes that are no longer scheduled were due
+                    if !probes.is_empty() {
+                        return Ok(probes)
+                    }
+                }
+            }
+        }
     }
 
     #[allow(dead_code)]
     pub fn overrun(&self) -> u64 {
         self.overrun
     }
+
+    /// Counts run of a probe that was not started as its previous run has not finished yet
+    pub fn skip(&mut self, probe: &Probe) {
+        self.skipped = self.skipped + 1;
+        self.health.skip(probe.name());
+        warn!("Skipping run of probe '{}' as its previous run has not finished; skipped runs since start: {}", probe.name(), self.skipped);
+    }
+
+    #[allow(dead_code)]
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+/// Starts runs of due probes with runner of their run mode
+struct ProbeRunners {
+    shared: SharedThreadProbeRunner,
+    dedicated: DedicatedThreadProbeRunner,
+    child_command: ChildCommand,
+    /// Probes running in dedicated process; child process is kept between runs
+    processes: HashMap<String, Arc<ProcessProbe>>
+}
+
+impl ProbeRunners {
+    fn new(child_command: ChildCommand) -> ProbeRunners {
+        ProbeRunners {
+            shared: SharedThreadProbeRunner::new(),
+            dedicated: DedicatedThreadProbeRunner::new(),
+            child_command: child_command,
+            processes: HashMap::new()
+        }
+    }
+
+    /// Runs given probes and records results of runs finished so far with the scheduler; configuration of probe module is passed to child processes
+    fn run<C, F>(&mut self, ps: &mut ProbeScheduler, config: &Config, probes: Vec<Arc<Probe>>, probe_collector: F) where F: Fn(&Probe) -> C, C: Collect + Send + 'static {
+        for probe in probes {
+            let timeout = ps.timeout(&*probe);
+            match probe.run_mode() {
+                RunMode::SharedThread => if !self.shared.push(probe.clone(), timeout) {
+                    ps.skip(&*probe);
+                },
+                RunMode::DedicatedThread => {
+                    let collector = Box::new(probe_collector(&*probe));
+                    if !self.dedicated.run(probe.clone(), Some(timeout), collector) {
+                        ps.skip(&*probe);
+                    }
+                }
+                RunMode::DedicatedProcess => {
+                    let child_command = &self.child_command;
+                    let process_probe = self.processes.entry(probe.name().to_string()).or_insert_with(|| {
+                        let module_config = ps.module(probe.name()).map(|module| config.module_toml(module)).unwrap_or_else(String::new);
+                        Arc::new(ProcessProbe::for_probe(&*probe, child_command, module_config, timeout))
+                    }).clone();
+                    let collector = Box::new(probe_collector(&*probe));
+                    // child process is killed by the process probe itself on timeout
+                    if !self.dedicated.run(process_probe, None, collector) {
+                        ps.skip(&*probe);
+                    }
+                }
+            }
+        }
+
+        for (probe, result) in self.shared.run(|probe| probe_collector(probe)) {
+            ps.record(&probe, &result);
+        }
+        for (probe, result) in self.dedicated.results() {
+            ps.record(&probe, &result);
+        }
+    }
+
+    /// Stops runner of probe that was removed or rescheduled once its in progress run finishes
+    fn retire(&mut self, probe: &str) {
+        self.dedicated.retire(probe);
+        // child process exits once retired run finishes and the probe is dropped
+        self.processes.remove(probe);
+    }
+
+    fn stop(self) {
+        self.shared.stop();
+        self.dedicated.stop();
+        drop(self.processes);
+    }
 }
 
 mod hello_world;
+mod procfs;
+mod system;
+mod exec;
+mod disk;
+mod net;
+mod processes;
+mod logs;
+mod http;
+mod tcp;
+mod process;
+mod plugin;
+
+/// Helpers shared by tests of probe modules
+#[cfg(test)]
+mod test_support {
+    use std::path::{Path, PathBuf};
+    use sender::{Collect, CollectError};
+    use messaging::DataValue;
+    use config::{Config, ModuleConfig};
 
-pub fn spawn(signals: Receiver<Signal>, collector: Collector) -> JoinHandle<()> {
-    program::spawn("producer/probe", move || {
-        let mut ps = ProbeScheduler::new();
+    /// Keeps collected data points as (path, component, value) and their locations
+    pub struct VecCollector {
+        pub values: Vec<(String, String, DataValue)>,
+        pub locations: Vec<String>
+    }
+
+    impl VecCollector {
+        pub fn new() -> VecCollector {
+            VecCollector { values: Vec::new(), locations: Vec::new() }
+        }
+    }
 
-        let mut modules: Vec<Box<Module>> = vec![];
+    impl Collect for VecCollector {
+        fn collect(&mut self, location: &str, path: &str, component: &str, value: DataValue) -> Result<(), CollectError> {
+            self.values.push((path.to_string(), component.to_string(), value));
+            self.locations.push(location.to_string());
+            Ok(())
+        }
+    }
 
-        modules.push(hello_world::init());
+    pub fn point(path: &str, component: &str, value: DataValue) -> (String, String, DataValue) {
+        (path.to_string(), component.to_string(), value)
+    }
+
+    /// Path of file or directory in fixtures directory, e.g. proc/1 snapshot of /proc
+    pub fn fixture(path: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(path)
+    }
+
+    /// Configuration of module with single probe reading proc/1 fixture for location web-01
+    pub fn fixture_config(module: &str, probe: &str, options: &str) -> ModuleConfig {
+        let text = format!("location = \"web-01\"\n[modules.{module}]\nproc_root = \"{proc_root}\"\n[modules.{module}.probes.{probe}]\nevery = \"1s\"\n{options}",
+                           module = module, probe = probe, proc_root = fixture("proc/1").display(), options = options);
+        Config::parse(Path::new("agent.toml"), &text).unwrap().modules.remove(0)
+    }
+}
+
+use self::process::{ProcessProbe, ChildCommand};
+
+/// Creates module from its configuration; probes report data points for given location
+type ModuleInit = fn(&str, &ModuleConfig) -> Result<Box<Module>, ConfigError>;
+
+fn module_init(name: &str) -> Option<ModuleInit> {
+    match name {
+        "hello_world" => Some(hello_world::init as ModuleInit),
+        "system" => Some(system::init as ModuleInit),
+        "exec" => Some(exec::init as ModuleInit),
+        "disk" => Some(disk::init as ModuleInit),
+        "net" => Some(net::init as ModuleInit),
+        "processes" => Some(processes::init as ModuleInit),
+        "logs" => Some(logs::init as ModuleInit),
+        "http" => Some(http::init as ModuleInit),
+        "tcp" => Some(tcp::init as ModuleInit),
+        _ => None
+    }
+}
+
+/// Creates modules enabled in the configuration; modules not built in are looked up in plugins
+pub fn modules(config: &Config) -> Result<Vec<Box<Module>>, ConfigError> {
+    let plugins = match config.plugin_dir {
+        Some(ref dir) => try!(plugin::load_plugins(dir).map_err(|err| config.error("plugin_dir", err.to_string()))),
+        None => Vec::new()
+    };
+    if let Some(plugin) = plugins.iter().find(|plugin| module_init(plugin.name()).is_some()) {
+        return Err(config.error("plugin_dir", format!("plugin provides module '{}' which is built in", plugin.name())))
+    }
+
+    let mut modules = Vec::with_capacity(config.modules.len());
+    for module_config in &config.modules {
+        if let Some(init) = module_init(&module_config.name) {
+            modules.push(try!(init(&config.location, module_config)));
+            continue
+        }
+
+        let plugin = try!(plugins.iter().find(|plugin| plugin.name() == module_config.name).ok_or_else(|| module_config.unknown()));
+        modules.push(try!(plugin.init(&config.location, module_config)));
+    }
+    Ok(modules)
+}
+
+/// Runs probe of given name in this process each time run is requested on stdin; used by child process of probe in dedicated process run mode
+pub fn run_probe_process(name: &str) -> Result<(), String> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    // configuration of the probe module only is passed by parent process
+    let config = try!(process::read_child_config(&mut input));
+
+    for module in try!(modules(&config).map_err(|err| err.to_string())) {
+        for probe_schedule in module.schedule() {
+            if probe_schedule.probe.name() == name {
+                let stdout = io::stdout();
+                return process::run_probe_child(&*probe_schedule.probe, &mut input, &mut stdout.lock())
+            }
+        }
+    }
+    Err(format!("no probe named '{}' found", name))
+}
+
+/// Loads configuration again from the file current configuration was loaded from
+fn reload_config(config: &Config) -> Result<(Config, Vec<Box<Module>>), String> {
+    let file = try!(config.file.as_ref().ok_or_else(|| "agent was started without configuration file".to_string()));
+    let new_config = try!(Config::load(file).map_err(|err| err.to_string()));
+    let modules = try!(modules(&new_config).map_err(|err| err.to_string()));
+    Ok((new_config, modules))
+}
+
+pub fn spawn(signals: Receiver<Signal>, collector: Collector, config: Config, modules: Vec<Box<Module>>) -> JoinHandle<()> {
+    program::spawn("producer/probe", move || {
+        let mut config = config;
+        let mut ps = ProbeScheduler::new();
 
         for module in modules {
+            info!("Scheduling probes of module '{}'", module.name());
             ps.schedule(&*module);
         }
 
-        //TODO: load modules
-        //TODO: schedule modules
+        let mut runners = ProbeRunners::new(ChildCommand::current());
 
         let abort_handle = ps.abort_handle();
         let (signal_forward, signals_forward) = channel();
@@ -162,26 +809,36 @@ pub fn spawn(signals: Receiver<Signal>, collector: Collector) -> JoinHandle<()>
                 Err(ProbeSchedulerError::Empty) => panic!("no probes configured to run"), //TODO: stop with nice msg
                 Err(ProbeSchedulerError::Aborted) => {
                     match signals_forward.recv() {
-                        Ok(Signal::Reload) => panic!("Reload is unsupported"),
+                        Ok(Signal::Reload) => {
+                            let (new_config, modules) = match reload_config(&config) {
+                                Ok(reloaded) => reloaded,
+                                Err(err) => {
+                                    error!("Failed to reload configuration; keeping current one: {}", err);
+                                    continue
+                                }
+                            };
+
+                            let changes = ps.reschedule(&modules, |module| {
+                                config.location == new_config.location && config.module(module) == new_config.module(module)
+                            });
+                            // in progress runs finish with their collectors so no collected data is lost
+                            for probe in changes.removed.iter().chain(changes.rescheduled.iter()) {
+                                runners.retire(probe);
+                            }
+                            info!("Configuration reloaded; added probes: {:?}, removed probes: {:?}, rescheduled probes: {:?}", changes.added, changes.removed, changes.rescheduled);
+
+                            config = new_config;
+                        }
                         Err(_) => {
                             signal_handler.join().ok();
+                            runners.stop();
                             break
                         }
                     }
                 }
                 Ok(probes) => {
-                    let mut run_collector = collector.clone();
-                    let mut shared_exec = SharedThreadProbeRunner::new();
-
-                    for probe in probes {
-                        match probe.run_mode() {
-                            RunMode::SharedThread => shared_exec.push(probe)
-                        }
-                    }
-
-                    for error in shared_exec.run(&mut run_collector).into_iter().filter(|r| r.is_err()).map(|r| r.unwrap_err()) {
-                        error!("Probe reported an error: {}", error);
-                    }
+                    let run_collector = collector.clone();
+                    runners.run(&mut ps, &config, probes, |probe| run_collector.for_probe(probe.name()));
                 }
             }
         }
@@ -193,12 +850,16 @@ pub fn spawn(signals: Receiver<Signal>, collector: Collector) -> JoinHandle<()>
 #[cfg(test)]
 mod test {
     use super::*;
-    use super::ProbeSchedulerError;
-    use sender::Collect;
+    use super::{ProbeSchedulerError, ProbeRunners};
+    use super::process::ChildCommand;
+    use sender::{Collect, CollectError};
     use messaging::DataValue;
     use time::Duration;
+    use std::time::{Duration as StdDuration, Instant};
+    use std::thread::sleep;
     use std::slice::Iter;
-    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::sync::Mutex;
 
     struct StubModule {
         name: String,
@@ -210,8 +871,8 @@ mod test {
     }
 
     impl StubProbe {
-        fn new(name: &str) -> Rc<Self> {
-            Rc::new(
+        fn new(name: &str) -> Arc<Self> {
+            Arc::new(
                 StubProbe {
                     name: name.to_string()
                 }
@@ -226,8 +887,8 @@ mod test {
 
         fn run(&self, collector: &mut Collect) -> Result<(), String> {
             let mut collector = collector;
-            collector.collect("foo", &self.name, "c1", DataValue::Text(format!("{}-{}", self.name, "c1")));
-            collector.collect("bar", &self.name, "c2", DataValue::Text(format!("{}-{}", self.name, "c2")));
+            try!(collector.collect("foo", &self.name, "c1", DataValue::Text(format!("{}-{}", self.name, "c1"))).map_err(|err| err.to_string()));
+            try!(collector.collect("bar", &self.name, "c2", DataValue::Text(format!("{}-{}", self.name, "c2"))).map_err(|err| err.to_string()));
             Ok(())
         }
 
@@ -244,10 +905,11 @@ mod test {
             }
         }
 
-        fn add_schedule(&mut self, every: Duration, probe: Rc<Probe>) {
+        fn add_schedule(&mut self, every: Duration, probe: Arc<Probe>) {
             self.schedule.push(
                 ProbeRunPlan {
                     every: every,
+                    timeout: every,
                     probe: probe
                 }
             );
@@ -264,19 +926,26 @@ mod test {
         }
     }
 
+    #[derive(Clone)]
     struct StubCollector {
-        pub values: Vec<DataValue>
+        pub values: Arc<Mutex<Vec<DataValue>>>
     }
 
     impl Collect for StubCollector {
-        fn collect(&mut self, _location: &str, _path: &str, _component: &str, value: DataValue) -> () {
-            self.values.push(value);
+        fn collect(&mut self, _location: &str, _path: &str, _component: &str, value: DataValue) -> Result<(), CollectError> {
+            self.values.lock().unwrap().push(value);
+            Ok(())
         }
     }
 
     impl StubCollector {
+        fn new() -> StubCollector {
+            StubCollector { values: Arc::new(Mutex::new(Vec::new())) }
+        }
+
         fn text_values(self) -> Vec<String> {
-            self.values.into_iter().map(|v| if let DataValue::Text(c) = v { c } else { "none".to_string() }).collect()
+            let values = self.values.lock().unwrap().iter().map(|v| if let &DataValue::Text(ref c) = v { c.clone() } else { "none".to_string() }).collect();
+            values
         }
     }
 
@@ -287,12 +956,19 @@ mod test {
         let p3 = StubProbe::new("p3");
 
         let mut exec = SharedThreadProbeRunner::new();
-        exec.push(p1);
-        exec.push(p2);
-        exec.push(p3);
-
-        let mut collector = StubCollector { values: Vec::new() };
-        exec.run(&mut collector);
+        exec.push(p1, StdDuration::from_millis(1000));
+        exec.push(p2, StdDuration::from_millis(1000));
+        exec.push(p3, StdDuration::from_millis(1000));
+
+        let collector = StubCollector::new();
+        let results = exec.run(|_| collector.clone());
+        exec.stop();
+
+        assert_eq!(results, vec![
+           ("p1".to_string(), ProbeRunResult::Ok),
+           ("p2".to_string(), ProbeRunResult::Ok),
+           ("p3".to_string(), ProbeRunResult::Ok)
+        ]);
 
         assert_eq!(collector.text_values(), vec![
            "p1-c1", "p1-c2",
@@ -301,6 +977,152 @@ mod test {
         ]);
     }
 
+    struct SlowProbe;
+
+    impl Probe for SlowProbe {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        fn run(&self, collector: &mut Collect) -> Result<(), String> {
+            sleep(StdDuration::from_millis(200));
+            try!(collector.collect("foo", "slow", "c1", DataValue::Text("slow-c1".to_string())).map_err(|err| err.to_string()));
+            Ok(())
+        }
+
+        fn run_mode(&self) -> RunMode {
+            RunMode::DedicatedThread
+        }
+    }
+
+    struct IsolatedProbe;
+
+    impl Probe for IsolatedProbe {
+        fn name(&self) -> &str {
+            "isolated"
+        }
+
+        fn run(&self, _collector: &mut Collect) -> Result<(), String> {
+            panic!("isolated probe should run in child process only")
+        }
+
+        fn run_mode(&self) -> RunMode {
+            RunMode::DedicatedProcess
+        }
+    }
+
+    #[test]
+    fn probe_runners_should_keep_running_probes_when_child_process_crashes() {
+        use config::Config;
+
+        let mut m1 = StubModule::new("m1");
+        m1.add_schedule(Duration::milliseconds(100), Arc::new(IsolatedProbe));
+        m1.add_schedule(Duration::milliseconds(100), StubProbe::new("p1"));
+
+        let mut ps = ProbeScheduler::new();
+        ps.schedule(&m1);
+
+        let config = Config::default();
+        let collector = StubCollector::new();
+        let mut runners = ProbeRunners::new(ChildCommand::new("sh", vec!["-c".to_string(), "kill -SEGV $$".to_string()]));
+        for _ in 0..3 {
+            let probes = ps.abortable_wait().ok().expect("probes to run");
+            runners.run(&mut ps, &config, probes, |_| collector.clone());
+        }
+
+        // record result of last run
+        sleep(StdDuration::from_millis(200));
+        runners.run(&mut ps, &config, Vec::new(), |_| collector.clone());
+        runners.stop();
+
+        assert_eq!(collector.text_values(), vec![
+           "p1-c1", "p1-c2",
+           "p1-c1", "p1-c2",
+           "p1-c1", "p1-c2"
+        ]);
+        let health = ps.health("isolated").unwrap();
+        assert!(health.errors >= 2);
+        assert_eq!(health.errors, health.runs);
+    }
+
+    #[test]
+    fn dedicated_thread_probe_executor() {
+        let collector = StubCollector::new();
+
+        let mut exec = DedicatedThreadProbeRunner::new();
+        assert!(exec.run(StubProbe::new("p1"), None, Box::new(collector.clone())));
+        assert!(exec.run(StubProbe::new("p2"), None, Box::new(collector.clone())));
+        exec.stop();
+
+        let mut values = collector.text_values();
+        values.sort();
+        assert_eq!(values, vec![
+           "p1-c1", "p1-c2",
+           "p2-c1", "p2-c2"
+        ]);
+    }
+
+    #[test]
+    fn dedicated_thread_probe_executor_should_not_start_run_until_previous_one_finished() {
+        let collector = StubCollector::new();
+        let probe = Arc::new(SlowProbe);
+
+        let mut exec = DedicatedThreadProbeRunner::new();
+        assert!(exec.run(probe.clone(), None, Box::new(collector.clone())));
+        assert!(!exec.run(probe.clone(), None, Box::new(collector.clone())));
+        exec.stop();
+
+        assert_eq!(collector.text_values(), vec!["slow-c1"]);
+    }
+
+    #[test]
+    fn shared_thread_probe_executor_should_abandon_probe_running_past_its_timeout() {
+        let mut exec = SharedThreadProbeRunner::new();
+        exec.push(Arc::new(SlowProbe), StdDuration::from_millis(50));
+        exec.push(StubProbe::new("p1"), StdDuration::from_millis(1000));
+
+        let collector = StubCollector::new();
+        let results = exec.run(|_| collector.clone());
+
+        assert_eq!(results, vec![
+           ("slow".to_string(), ProbeRunResult::Timeout(StdDuration::from_millis(50))),
+           ("p1".to_string(), ProbeRunResult::Ok)
+        ]);
+
+        // probe is not run again until its abandoned run finished
+        assert!(!exec.push(Arc::new(SlowProbe), StdDuration::from_millis(1000)));
+        sleep(StdDuration::from_millis(200));
+        assert!(exec.push(Arc::new(SlowProbe), StdDuration::from_millis(1000)));
+        exec.stop();
+    }
+
+    #[test]
+    fn dedicated_thread_probe_executor_should_report_results() {
+        let mut exec = DedicatedThreadProbeRunner::new();
+        assert!(exec.run(StubProbe::new("p1"), Some(StdDuration::from_millis(1000)), Box::new(StubCollector::new())));
+
+        sleep(StdDuration::from_millis(100));
+        assert_eq!(exec.results(), vec![("p1".to_string(), ProbeRunResult::Ok)]);
+        exec.stop();
+    }
+
+    #[test]
+    fn dedicated_thread_probe_executor_should_abandon_probe_running_past_its_timeout() {
+        let probe = Arc::new(SlowProbe);
+
+        let mut exec = DedicatedThreadProbeRunner::new();
+        assert!(exec.run(probe.clone(), Some(StdDuration::from_millis(50)), Box::new(StubCollector::new())));
+
+        sleep(StdDuration::from_millis(100));
+        assert_eq!(exec.results(), vec![("slow".to_string(), ProbeRunResult::Timeout(StdDuration::from_millis(50)))]);
+
+        // next run is skipped until abandoned run finished
+        assert!(!exec.run(probe.clone(), Some(StdDuration::from_millis(1000)), Box::new(StubCollector::new())));
+        sleep(StdDuration::from_millis(150));
+        assert!(exec.run(probe.clone(), Some(StdDuration::from_millis(1000)), Box::new(StubCollector::new())));
+        exec.stop();
+    }
+
     #[test]
     fn probe_scheduler_abortable_wait_should_provide_porbes_according_to_schedule() {
         let mut m1 = StubModule::new("m1");
@@ -315,7 +1137,7 @@ mod test {
         ps.schedule(&m1);
         ps.schedule(&m2);
 
-        let mut collector = StubCollector { values: Vec::new() };
+        let mut collector = StubCollector::new();
         let probes = ps.abortable_wait().unwrap();
         for probe in probes {
             probe.run(&mut collector).unwrap();
@@ -330,7 +1152,6 @@ mod test {
     #[test]
     fn probe_scheduler_abortable_wait_should_count_overrun_schedules() {
         use std::thread::sleep;
-        use std::time::Duration as StdDuration;
 
         let mut m1 = StubModule::new("m1");
         m1.add_schedule(Duration::milliseconds(100), StubProbe::new("m1-p1"));
@@ -349,7 +1170,7 @@ mod test {
             let result = ps.abortable_wait();
             assert!(result.is_ok());
 
-            let mut collector = StubCollector { values: Vec::new() };
+            let mut collector = StubCollector::new();
             let probes = result.unwrap();
             for probe in probes {
                 probe.run(&mut collector).unwrap();
@@ -368,14 +1189,13 @@ mod test {
     #[test]
     fn probe_scheduler_abortable_wait_should_return_abort_on_abort() {
         use std::thread::{spawn, sleep};
-        use std::time::Duration as StdDuration;
-        use token_scheduler::{Abort, AbortableWait};
 
         let mut m1 = StubModule::new("m1");
         m1.add_schedule(Duration::milliseconds(1000), StubProbe::new("m1-p1"));
         m1.add_schedule(Duration::milliseconds(1000), StubProbe::new("m1-p2"));
 
         let mut ps: ProbeScheduler = ProbeScheduler::new();
+
         let abort_handle = ps.abort_handle();
 
         ps.schedule(&m1);
@@ -391,5 +1211,181 @@ mod test {
             panic!("expected scheduler to be aborted")
         }
     }
-}
 
+    #[test]
+    fn probe_scheduler_abort_handle_should_abort_wait_after_reschedule() {
+        use std::thread::spawn;
+
+        let mut m1 = StubModule::new("m1");
+        m1.add_schedule(Duration::milliseconds(1000), StubProbe::new("p1"));
+
+        let mut ps = ProbeScheduler::new();
+        let abort_handle = ps.abort_handle();
+        ps.schedule(&m1);
+
+        let mut m1 = StubModule::new("m1");
+        m1.add_schedule(Duration::milliseconds(1000), StubProbe::new("p2"));
+        let modules: Vec<Box<Module>> = vec![Box::new(m1)];
+        ps.reschedule(&modules, |_| false);
+
+        spawn(move || {
+            sleep(StdDuration::from_millis(100));
+            abort_handle.abort();
+        });
+
+        match ps.abortable_wait() {
+            Err(ProbeSchedulerError::Aborted) => (),
+            _ => panic!("expected scheduler to be aborted")
+        }
+    }
+
+    #[test]
+    fn probe_scheduler_reschedule_should_report_changes() {
+        let mut m1 = StubModule::new("m1");
+        m1.add_schedule(Duration::milliseconds(100), StubProbe::new("p1"));
+        m1.add_schedule(Duration::milliseconds(100), StubProbe::new("p2"));
+
+        let mut ps = ProbeScheduler::new();
+        ps.schedule(&m1);
+
+        let mut m1 = StubModule::new("m1");
+        m1.add_schedule(Duration::milliseconds(200), StubProbe::new("p1"));
+        m1.add_schedule(Duration::milliseconds(100), StubProbe::new("p3"));
+        let modules: Vec<Box<Module>> = vec![Box::new(m1)];
+
+        let changes = ps.reschedule(&modules, |_| false);
+        assert_eq!(changes, ScheduleChanges {
+            added: vec!["p3".to_string()],
+            removed: vec!["p2".to_string()],
+            rescheduled: vec!["p1".to_string()]
+        });
+        assert_eq!(ps.every("p1"), Some(Duration::milliseconds(200)));
+        assert_eq!(ps.every("p2"), None);
+
+        // probes of unchanged modules keep running
+        let changes = ps.reschedule(&modules, |_| true);
+        assert_eq!(changes, ScheduleChanges::default());
+    }
+
+    #[test]
+    fn probe_scheduler_should_not_run_removed_probes() {
+        let mut m1 = StubModule::new("m1");
+        m1.add_schedule(Duration::milliseconds(100), StubProbe::new("p1"));
+        m1.add_schedule(Duration::milliseconds(100), StubProbe::new("p2"));
+
+        let mut ps = ProbeScheduler::new();
+        ps.schedule(&m1);
+
+        let mut m1 = StubModule::new("m1");
+        m1.add_schedule(Duration::milliseconds(100), StubProbe::new("p1"));
+        let modules: Vec<Box<Module>> = vec![Box::new(m1)];
+        ps.reschedule(&modules, |_| true);
+
+        for _ in 0..3 {
+            let probes = ps.abortable_wait().ok().expect("probes to run");
+            assert_eq!(probes.iter().map(|probe| probe.name().to_string()).collect::<Vec<_>>(), vec!["p1".to_string()]);
+        }
+    }
+
+    #[test]
+    fn dedicated_thread_probe_executor_should_finish_run_of_retired_probe() {
+        let probe = Arc::new(SlowProbe);
+        let collector = StubCollector::new();
+
+        let mut exec = DedicatedThreadProbeRunner::new();
+        assert!(exec.run(probe.clone(), None, Box::new(collector.clone())));
+        exec.retire("slow");
+
+        // new worker is started once retired run finished
+        assert!(!exec.run(probe.clone(), None, Box::new(collector.clone())));
+        sleep(StdDuration::from_millis(300));
+        assert!(exec.run(probe.clone(), None, Box::new(collector.clone())));
+        exec.stop();
+
+        assert_eq!(collector.text_values(), vec!["slow-c1", "slow-c1"]);
+    }
+
+    #[test]
+    fn dedicated_thread_probe_executor_should_abandon_retired_probe_running_past_its_timeout() {
+        let probe = Arc::new(SlowProbe);
+
+        let mut exec = DedicatedThreadProbeRunner::new();
+        assert!(exec.run(probe.clone(), Some(StdDuration::from_millis(50)), Box::new(StubCollector::new())));
+        exec.retire("slow");
+
+        sleep(StdDuration::from_millis(100));
+        assert_eq!(exec.results(), vec![("slow".to_string(), ProbeRunResult::Timeout(StdDuration::from_millis(50)))]);
+        assert!(exec.retired.is_empty());
+
+        // replacement does not run concurrently with the stuck run
+        assert!(!exec.run(probe.clone(), Some(StdDuration::from_millis(1000)), Box::new(StubCollector::new())));
+        sleep(StdDuration::from_millis(150));
+        assert!(exec.run(probe.clone(), Some(StdDuration::from_millis(1000)), Box::new(StubCollector::new())));
+        exec.stop();
+    }
+
+    #[test]
+    fn dedicated_thread_probe_executor_should_not_wait_for_stuck_retired_probe_on_stop() {
+        let mut exec = DedicatedThreadProbeRunner::new();
+        assert!(exec.run(Arc::new(SlowProbe), Some(StdDuration::from_millis(10)), Box::new(StubCollector::new())));
+        exec.retire("slow");
+        sleep(StdDuration::from_millis(20));
+
+        let stopping = Instant::now();
+        exec.stop();
+        assert!(stopping.elapsed() < StdDuration::from_millis(150));
+    }
+
+    #[test]
+    fn dedicated_thread_probe_executor_should_drop_finished_retired_workers() {
+        let mut exec = DedicatedThreadProbeRunner::new();
+        assert!(exec.run(StubProbe::new("p1"), Some(StdDuration::from_millis(1000)), Box::new(StubCollector::new())));
+        sleep(StdDuration::from_millis(50));
+        exec.retire("p1");
+
+        assert_eq!(exec.results(), vec![("p1".to_string(), ProbeRunResult::Ok)]);
+        assert!(exec.retired.is_empty());
+        exec.stop();
+    }
+
+    #[test]
+    fn modules_should_create_configured_probes() {
+        use std::path::Path;
+        use config::Config;
+
+        let config = Config::parse(Path::new("agent.toml"), r#"
+            location = "web-01"
+
+            [modules.hello_world.probes.hello]
+            every = "2s"
+            timeout = "1s"
+            greeting = "Hi!"
+        "#).unwrap();
+
+        let modules = modules(&config).unwrap();
+        assert_eq!(modules.len(), 1);
+        assert_eq!(modules[0].name(), "hello_world");
+
+        let schedule: Vec<&ProbeRunPlan> = modules[0].schedule().collect();
+        assert_eq!(schedule.len(), 1);
+        assert_eq!(schedule[0].probe.name(), "hello_world/hello");
+        assert_eq!(schedule[0].every, Duration::seconds(2));
+        assert_eq!(schedule[0].timeout, Duration::seconds(1));
+
+        let collector = StubCollector::new();
+        schedule[0].probe.run(&mut collector.clone()).unwrap();
+        assert_eq!(collector.text_values(), vec!["Hi!", "none"]);
+    }
+
+    #[test]
+    fn modules_should_reject_unknown_modules_and_probes() {
+        use std::path::Path;
+        use config::Config;
+
+        let config = Config::parse(Path::new("agent.toml"), "location = \"web-01\"\n[modules.foo.probes.bar]\nevery = \"1s\"").unwrap();
+        assert_eq!(modules(&config).err().unwrap().to_string(), "Configuration error: agent.toml: key 'modules.foo': no such module".to_string());
+
+        let config = Config::parse(Path::new("agent.toml"), "location = \"web-01\"\n[modules.hello_world.probes.bar]\nevery = \"1s\"").unwrap();
+        assert_eq!(modules(&config).err().unwrap().to_string(), "Configuration error: agent.toml: key 'modules.hello_world.probes.bar': module has no such probe".to_string());
+    }
+}
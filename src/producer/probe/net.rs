@@ -0,0 +1,390 @@
+use std::sync::{Arc, Mutex};
+use std::slice::Iter;
+use std::path::PathBuf;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use sender::Collect;
+use messaging::DataValue;
+use config::{ModuleConfig, ProbeConfig, ConfigError};
+use super::{RunMode, ProbeRunPlan, Probe, Module};
+use super::procfs::{self, Filter};
+
+/// Interfaces not reported unless exclude_interfaces option is given
+const DEFAULT_EXCLUDE_INTERFACES: &'static [&'static str] = &["lo"];
+
+/// Components reported for each interface and index of counter in net/dev line they are calculated from
+const INTERFACE_RATES: &'static [(&'static str, usize)] = &[
+    ("rx_bytes_per_second", 0), ("rx_packets_per_second", 1), ("rx_errors_per_second", 2), ("rx_drops_per_second", 3),
+    ("tx_bytes_per_second", 8), ("tx_packets_per_second", 9), ("tx_errors_per_second", 10), ("tx_drops_per_second", 11)
+];
+
+/// Counters from net/snmp reported as rates: path, key and component
+const PROTOCOL_RATES: &'static [(&'static str, &'static str, &'static str)] = &[
+    ("os/tcp", "Tcp.ActiveOpens", "active_opens_per_second"),
+    ("os/tcp", "Tcp.PassiveOpens", "passive_opens_per_second"),
+    ("os/tcp", "Tcp.AttemptFails", "attempt_fails_per_second"),
+    ("os/tcp", "Tcp.EstabResets", "resets_per_second"),
+    ("os/tcp", "Tcp.InSegs", "in_segments_per_second"),
+    ("os/tcp", "Tcp.OutSegs", "out_segments_per_second"),
+    ("os/tcp", "Tcp.RetransSegs", "retransmits_per_second"),
+    ("os/udp", "Udp.InDatagrams", "in_datagrams_per_second"),
+    ("os/udp", "Udp.OutDatagrams", "out_datagrams_per_second"),
+    ("os/udp", "Udp.InErrors", "receive_errors_per_second"),
+    ("os/udp", "Udp.NoPorts", "no_port_per_second")
+];
+
+/// Values from net/snmp and net/sockstat reported as they are: path, key and component
+const SOCKET_GAUGES: &'static [(&'static str, &'static str, &'static str)] = &[
+    ("os/tcp", "Tcp.CurrEstab", "established"),
+    ("os/tcp", "TCP.tw", "time_wait"),
+    ("os/tcp", "TCP.orphan", "orphaned"),
+    ("os/tcp", "TCP.inuse", "in_use"),
+    ("os/tcp", "TCP.alloc", "allocated"),
+    ("os/udp", "UDP.inuse", "in_use"),
+    ("os/sockets", "sockets.used", "used"),
+    ("os/sockets", "RAW.inuse", "raw")
+];
+
+/// Parses net/dev into counters by interface name
+pub fn parse_net_dev(dev: &str) -> Result<HashMap<String, Vec<u64>>, String> {
+    let mut interfaces = HashMap::new();
+    // header lines have no colon
+    for line in dev.lines().filter(|line| line.contains(':')) {
+        let colon = line.find(':').unwrap();
+        let fields: Vec<&str> = line[colon + 1..].split_whitespace().collect();
+        if fields.len() < 16 {
+            return Err(format!("expected 16 counters in net/dev line but got: {}", line))
+        }
+        interfaces.insert(line[..colon].trim().to_string(), try!(procfs::parse_fields(&fields[..16], "net/dev")));
+    }
+    Ok(interfaces)
+}
+
+/// Parses net/snmp pairs of header and value lines into values keyed by protocol and name, e.g. Tcp.RetransSegs
+pub fn parse_snmp(snmp: &str) -> Result<HashMap<String, i64>, String> {
+    let mut values = HashMap::new();
+    let lines: Vec<&str> = snmp.lines().filter(|line| !line.trim().is_empty()).collect();
+    for pair in lines.chunks(2) {
+        if pair.len() != 2 {
+            return Err(format!("missing values for net/snmp line: {}", pair[0]))
+        }
+        let mut names = pair[0].split_whitespace();
+        let mut numbers = pair[1].split_whitespace();
+        let protocol = names.next().unwrap_or("");
+        if numbers.next() != Some(protocol) {
+            return Err(format!("expected values of {} in net/snmp line: {}", protocol, pair[1]))
+        }
+
+        let protocol = protocol.trim_right_matches(':');
+        for (name, number) in names.zip(numbers) {
+            let value = try!(number.parse().map_err(|_| format!("invalid value '{}' of {}.{} in net/snmp", number, protocol, name)));
+            values.insert(format!("{}.{}", protocol, name), value);
+        }
+    }
+    Ok(values)
+}
+
+/// Parses net/sockstat into values keyed by protocol and name, e.g. TCP.inuse
+pub fn parse_sockstat(sockstat: &str) -> Result<HashMap<String, i64>, String> {
+    let mut values = HashMap::new();
+    for line in sockstat.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue
+        }
+
+        let protocol = fields[0].trim_right_matches(':');
+        for pair in fields[1..].chunks(2) {
+            if pair.len() != 2 {
+                return Err(format!("missing value of {}.{} in net/sockstat", protocol, pair[0]))
+            }
+            let value = try!(pair[1].parse().map_err(|_| format!("invalid value '{}' of {}.{} in net/sockstat", pair[1], protocol, pair[0])));
+            values.insert(format!("{}.{}", protocol, pair[0]), value);
+        }
+    }
+    Ok(values)
+}
+
+enum Metrics {
+    Interfaces {
+        interfaces: Filter,
+        previous: Mutex<Option<(Instant, HashMap<String, Vec<u64>>)>>
+    },
+    Sockets {
+        previous: Mutex<Option<(Instant, HashMap<String, i64>)>>
+    }
+}
+
+pub struct NetProbe {
+    name: String,
+    location: String,
+    proc_root: PathBuf,
+    metrics: Metrics
+}
+
+impl NetProbe {
+    fn new(location: &str, config: &ModuleConfig, probe: &ProbeConfig) -> Result<NetProbe, ConfigError> {
+        let metrics = match probe.name.as_str() {
+            "interfaces" => Metrics::Interfaces {
+                interfaces: try!(Filter::from_options(&probe.options, "interfaces", "exclude_interfaces", DEFAULT_EXCLUDE_INTERFACES)),
+                previous: Mutex::new(None)
+            },
+            "sockets" => Metrics::Sockets {
+                previous: Mutex::new(None)
+            },
+            _ => return Err(probe.unknown())
+        };
+
+        Ok(NetProbe {
+            name: format!("{}/{}", config.name, probe.name),
+            location: location.to_string(),
+            proc_root: try!(procfs::proc_root(&config.options)),
+            metrics: metrics
+        })
+    }
+
+    fn data_points(&self, now: Instant) -> Result<Vec<(String, &'static str, DataValue)>, String> {
+        let mut data_points = Vec::new();
+
+        match self.metrics {
+            Metrics::Interfaces { ref interfaces, ref previous } => {
+                let counters: HashMap<String, Vec<u64>> = try!(parse_net_dev(&try!(procfs::read(&self.proc_root, "net/dev"))))
+                    .into_iter().filter(|&(ref interface, _)| interfaces.matches(interface)).collect();
+
+                let mut previous = previous.lock().unwrap();
+                if let Some((previous_time, ref previous_counters)) = *previous {
+                    let seconds = procfs::seconds_between(previous_time, now);
+
+                    let mut interface_names: Vec<&String> = counters.keys().collect();
+                    interface_names.sort();
+
+                    for interface in interface_names {
+                        // interfaces that just appeared are reported from next run
+                        let previous_counters = match previous_counters.get(interface) {
+                            Some(previous_counters) => previous_counters,
+                            None => continue
+                        };
+                        let current_counters = &counters[interface];
+
+                        // counters go back to zero when interface is recreated or driver reloaded
+                        let deltas: Option<Vec<u64>> = INTERFACE_RATES.iter()
+                            .map(|&(_, index)| procfs::delta(previous_counters[index], current_counters[index]))
+                            .collect();
+                        let deltas = match deltas {
+                            Some(deltas) => deltas,
+                            None => {
+                                debug!("Probe '{}': counters of interface {} were reset", self.name, interface);
+                                continue
+                            }
+                        };
+                        if seconds <= 0.0 {
+                            continue
+                        }
+
+                        let path = format!("os/network/{}", interface);
+                        for (&(component, _), delta) in INTERFACE_RATES.iter().zip(deltas) {
+                            data_points.push((path.clone(), component, DataValue::Float(delta as f64 / seconds)));
+                        }
+                    }
+                }
+                *previous = Some((now, counters));
+            }
+            Metrics::Sockets { ref previous } => {
+                let snmp = try!(parse_snmp(&try!(procfs::read(&self.proc_root, "net/snmp"))));
+                let sockstat = try!(parse_sockstat(&try!(procfs::read(&self.proc_root, "net/sockstat"))));
+
+                for &(path, key, component) in SOCKET_GAUGES {
+                    if let Some(&value) = snmp.get(key).or_else(|| sockstat.get(key)) {
+                        data_points.push((path.to_string(), component, DataValue::Integer(value)));
+                    }
+                }
+
+                let mut previous = previous.lock().unwrap();
+                if let Some((previous_time, ref previous_snmp)) = *previous {
+                    let seconds = procfs::seconds_between(previous_time, now);
+
+                    for &(path, key, component) in PROTOCOL_RATES {
+                        // counter missing on this kernel or reset is skipped
+                        let delta = match (previous_snmp.get(key), snmp.get(key)) {
+                            (Some(&previous), Some(&current)) if previous >= 0 && current >= 0 => procfs::delta(previous as u64, current as u64),
+                            _ => None
+                        };
+                        if let Some(delta) = delta {
+                            if seconds > 0.0 {
+                                data_points.push((path.to_string(), component, DataValue::Float(delta as f64 / seconds)));
+                            }
+                        }
+                    }
+                }
+                *previous = Some((now, snmp));
+            }
+        }
+
+        Ok(data_points)
+    }
+}
+
+impl Probe for NetProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, collector: &mut Collect) -> Result<(), String> {
+        let mut collector = collector;
+        for (path, component, value) in try!(self.data_points(Instant::now())) {
+            try!(collector.collect(&self.location, &path, component, value).map_err(|err| err.to_string()));
+        }
+        Ok(())
+    }
+
+    fn run_mode(&self) -> RunMode {
+        RunMode::SharedThread
+    }
+}
+
+pub struct NetModule {
+    schedule: Vec<ProbeRunPlan>
+}
+
+impl Module for NetModule {
+    fn name(&self) -> &str {
+        "net"
+    }
+
+    fn schedule(&self) -> Iter<ProbeRunPlan> {
+        self.schedule.iter()
+    }
+}
+
+pub fn init(location: &str, config: &ModuleConfig) -> Result<Box<Module>, ConfigError> {
+    let mut schedule = Vec::new();
+
+    for probe in &config.probes {
+        schedule.push(ProbeRunPlan {
+            every: probe.every,
+            timeout: probe.timeout,
+            probe: Arc::new(try!(NetProbe::new(location, config, probe)))
+        });
+    }
+
+    Ok(Box::new(NetModule {
+        schedule: schedule
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::test_support::{fixture, fixture_config};
+    pub use messaging::DataValue;
+    pub use std::time::{Duration, Instant};
+
+    fn probe(name: &str, options: &str) -> NetProbe {
+        let config = fixture_config("net", name, options);
+        NetProbe::new("web-01", &config, &config.probes[0]).unwrap()
+    }
+
+    fn components(data_points: &[(String, &'static str, DataValue)], path: &str) -> Vec<(&'static str, DataValue)> {
+        data_points.iter().filter(|&&(ref point_path, _, _)| point_path == path).map(|&(_, component, ref value)| (component, value.clone())).collect()
+    }
+
+    #[test]
+    fn should_parse_snmp() {
+        let snmp = parse_snmp("Tcp: RtoAlgorithm MaxConn RetransSegs\nTcp: 1 -1 200\nUdp: NoPorts\nUdp: 10\n").unwrap();
+        assert_eq!(snmp.get("Tcp.MaxConn"), Some(&-1));
+        assert_eq!(snmp.get("Tcp.RetransSegs"), Some(&200));
+        assert_eq!(snmp.get("Udp.NoPorts"), Some(&10));
+
+        assert!(parse_snmp("Tcp: RetransSegs\nUdp: 10\n").is_err());
+        assert!(parse_snmp("Tcp: RetransSegs\n").is_err());
+    }
+
+    #[test]
+    fn should_report_interface_rates() {
+        let mut probe = probe("interfaces", "");
+        let start = Instant::now();
+
+        assert!(probe.data_points(start).unwrap().is_empty());
+
+        probe.proc_root = fixture("proc/2");
+        let data_points = probe.data_points(start + Duration::from_secs(10)).unwrap();
+
+        assert_eq!(components(&data_points, "os/network/eth0"), vec![
+            ("rx_bytes_per_second", DataValue::Float(10000.0)),
+            ("rx_packets_per_second", DataValue::Float(100.0)),
+            ("rx_errors_per_second", DataValue::Float(1.0)),
+            ("rx_drops_per_second", DataValue::Float(1.0)),
+            ("tx_bytes_per_second", DataValue::Float(50000.0)),
+            ("tx_packets_per_second", DataValue::Float(100.0)),
+            ("tx_errors_per_second", DataValue::Float(0.0)),
+            ("tx_drops_per_second", DataValue::Float(1.0))
+        ]);
+
+        // lo is excluded by default, eth1 counters were reset and eth2 has no previous reading yet
+        let mut paths: Vec<&str> = data_points.iter().map(|&(ref path, _, _)| path.as_str()).collect();
+        paths.dedup();
+        assert_eq!(paths, vec!["os/network/eth0"]);
+
+        // eth1 is reported again once it has previous reading after reset
+        let data_points = probe.data_points(start + Duration::from_secs(20)).unwrap();
+        assert_eq!(components(&data_points, "os/network/eth1")[0], ("rx_bytes_per_second", DataValue::Float(0.0)));
+    }
+
+    #[test]
+    fn should_filter_interfaces() {
+        let mut probe = probe("interfaces", "interfaces = [\"eth.*\", \"lo\"]\nexclude_interfaces = [\"eth0\"]");
+        let start = Instant::now();
+        probe.data_points(start).unwrap();
+
+        probe.proc_root = fixture("proc/2");
+        let data_points = probe.data_points(start + Duration::from_secs(10)).unwrap();
+
+        let mut paths: Vec<&str> = data_points.iter().map(|&(ref path, _, _)| path.as_str()).collect();
+        paths.dedup();
+        assert_eq!(paths, vec!["os/network/lo"]);
+    }
+
+    #[test]
+    fn should_report_socket_statistics() {
+        let mut probe = probe("sockets", "");
+        let start = Instant::now();
+
+        // only gauges on first run
+        let data_points = probe.data_points(start).unwrap();
+        assert_eq!(components(&data_points, "os/tcp"), vec![
+            ("established", DataValue::Integer(30)),
+            ("time_wait", DataValue::Integer(3)),
+            ("orphaned", DataValue::Integer(1)),
+            ("in_use", DataValue::Integer(12)),
+            ("allocated", DataValue::Integer(15))
+        ]);
+        assert_eq!(components(&data_points, "os/sockets"), vec![
+            ("used", DataValue::Integer(290)),
+            ("raw", DataValue::Integer(0))
+        ]);
+
+        probe.proc_root = fixture("proc/2");
+        let data_points = probe.data_points(start + Duration::from_secs(10)).unwrap();
+        assert_eq!(&components(&data_points, "os/tcp")[5..], &[
+            ("active_opens_per_second", DataValue::Float(10.0)),
+            ("passive_opens_per_second", DataValue::Float(5.0)),
+            ("attempt_fails_per_second", DataValue::Float(1.0)),
+            ("resets_per_second", DataValue::Float(0.5)),
+            ("in_segments_per_second", DataValue::Float(1000.0)),
+            ("out_segments_per_second", DataValue::Float(900.0)),
+            ("retransmits_per_second", DataValue::Float(10.0))
+        ][..]);
+        assert_eq!(&components(&data_points, "os/udp")[1..], &[
+            ("in_datagrams_per_second", DataValue::Float(100.0)),
+            ("out_datagrams_per_second", DataValue::Float(100.0)),
+            ("receive_errors_per_second", DataValue::Float(0.0)),
+            ("no_port_per_second", DataValue::Float(1.0))
+        ][..]);
+
+        // counters reset by reboot are not reported as rates
+        probe.proc_root = fixture("proc/1");
+        let data_points = probe.data_points(start + Duration::from_secs(20)).unwrap();
+        assert_eq!(components(&data_points, "os/tcp").len(), 5);
+    }
+}
@@ -0,0 +1,339 @@
+use std::sync::Arc;
+use std::slice::Iter;
+use std::path::{Path, PathBuf};
+use std::ffi::{CStr, CString, OsStr};
+use std::os::raw::{c_char, c_int, c_void};
+use std::env::consts::DLL_EXTENSION;
+use std::fs;
+use std::io;
+use std::fmt;
+use std::error::Error;
+use libloading::Library;
+use dms_plugin_api::*;
+
+use sender::Collect;
+use messaging::DataValue;
+use config::{ModuleConfig, ConfigError};
+use super::{RunMode, ProbeRunPlan, Probe, Module};
+
+#[derive(Debug)]
+pub enum PluginError {
+    Io(PathBuf, io::Error),
+    MissingSymbol(PathBuf, &'static str),
+    AbiVersion(PathBuf, u32),
+    InvalidName(PathBuf),
+    Duplicate(PathBuf, String)
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &PluginError::Io(ref path, ref err) => write!(f, "{}: {}: {}", self.description(), path.display(), err),
+            &PluginError::MissingSymbol(ref path, symbol) => write!(f, "{}: {}: missing entry point '{}'", self.description(), path.display(), symbol),
+            &PluginError::AbiVersion(ref path, version) => write!(f, "{}: {}: plugin built for ABI version {} but agent supports version {}", self.description(), path.display(), version, ABI_VERSION),
+            &PluginError::InvalidName(ref path) => write!(f, "{}: {}: plugin returned invalid module or probe name", self.description(), path.display()),
+            &PluginError::Duplicate(ref path, ref name) => write!(f, "{}: {}: module '{}' is already provided by other plugin", self.description(), path.display(), name)
+        }
+    }
+}
+
+impl Error for PluginError {
+    fn description(&self) -> &str {
+        "Plugin error"
+    }
+}
+
+/// Module provided by shared library
+pub struct Plugin {
+    name: String,
+    probes: Vec<String>,
+    run: RunFn,
+    // keeps plugin code loaded while probes are using it
+    library: Arc<Library>
+}
+
+impl Plugin {
+    pub fn load(path: &Path) -> Result<Plugin, PluginError> {
+        let library = try!(Library::new(path).map_err(|err| PluginError::Io(path.to_path_buf(), err)));
+
+        let (abi_version, name, probe, run) = unsafe {
+            let abi_version = try!(Plugin::symbol::<AbiVersionFn>(&library, path, ABI_VERSION_SYMBOL));
+            if abi_version() != ABI_VERSION {
+                return Err(PluginError::AbiVersion(path.to_path_buf(), abi_version()))
+            }
+
+            (abi_version,
+             try!(Plugin::symbol::<NameFn>(&library, path, NAME_SYMBOL)),
+             try!(Plugin::symbol::<ProbeFn>(&library, path, PROBE_SYMBOL)),
+             try!(Plugin::symbol::<RunFn>(&library, path, RUN_SYMBOL)))
+        };
+        debug!("Loaded plugin {} with ABI version {}", path.display(), abi_version());
+
+        let name = try!(unsafe { from_c_str(name()) }.ok_or_else(|| PluginError::InvalidName(path.to_path_buf()))).to_string();
+
+        let mut probes = Vec::new();
+        loop {
+            let probe_name = probe(probes.len());
+            if probe_name.is_null() {
+                break
+            }
+            probes.push(try!(unsafe { from_c_str(probe_name) }.ok_or_else(|| PluginError::InvalidName(path.to_path_buf()))).to_string());
+        }
+
+        Ok(Plugin {
+            name: name,
+            probes: probes,
+            run: run,
+            library: Arc::new(library)
+        })
+    }
+
+    unsafe fn symbol<T>(library: &Library, path: &Path, symbol: &'static [u8]) -> Result<T, PluginError> where T: Copy {
+        match library.get::<T>(symbol) {
+            Ok(function) => Ok(*function),
+            Err(_) => Err(PluginError::MissingSymbol(path.to_path_buf(), ::std::str::from_utf8(&symbol[..symbol.len() - 1]).unwrap()))
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn init(&self, location: &str, config: &ModuleConfig) -> Result<Box<Module>, ConfigError> {
+        let mut schedule = Vec::new();
+
+        for probe in &config.probes {
+            if !self.probes.contains(&probe.name) {
+                return Err(probe.unknown())
+            }
+
+            schedule.push(ProbeRunPlan {
+                every: probe.every,
+                timeout: probe.timeout,
+                probe: Arc::new(PluginProbe {
+                    name: format!("{}/{}", self.name, probe.name),
+                    location: location.to_string(),
+                    probe: CString::new(probe.name.clone()).unwrap(),
+                    options: CString::new(probe.options.to_toml()).expect("TOML without NUL bytes"),
+                    run: self.run,
+                    library: self.library.clone()
+                })
+            });
+        }
+
+        Ok(Box::new(PluginModule {
+            name: self.name.clone(),
+            schedule: schedule
+        }))
+    }
+}
+
+/// Loads all plugins from shared libraries in given directory
+pub fn load_plugins(dir: &Path) -> Result<Vec<Plugin>, PluginError> {
+    let mut paths = Vec::new();
+    for entry in try!(fs::read_dir(dir).map_err(|err| PluginError::Io(dir.to_path_buf(), err))) {
+        let path = try!(entry.map_err(|err| PluginError::Io(dir.to_path_buf(), err))).path();
+        if path.extension() == Some(OsStr::new(DLL_EXTENSION)) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut plugins: Vec<Plugin> = Vec::new();
+    for path in paths {
+        let plugin = try!(Plugin::load(&path));
+        if plugins.iter().any(|loaded| loaded.name == plugin.name) {
+            return Err(PluginError::Duplicate(path, plugin.name))
+        }
+        info!("Loaded plugin module '{}' from {}", plugin.name, path.display());
+        plugins.push(plugin);
+    }
+    Ok(plugins)
+}
+
+pub struct PluginModule {
+    name: String,
+    schedule: Vec<ProbeRunPlan>
+}
+
+impl Module for PluginModule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn schedule(&self) -> Iter<ProbeRunPlan> {
+        self.schedule.iter()
+    }
+}
+
+pub struct PluginProbe {
+    name: String,
+    location: String,
+    probe: CString,
+    options: CString,
+    run: RunFn,
+    #[allow(dead_code)]
+    library: Arc<Library>
+}
+
+struct RunContext<'c> {
+    location: &'c str,
+    collector: &'c mut Collect,
+    error: Option<String>
+}
+
+extern "C" fn collect(context: *mut c_void, path: *const c_char, component: *const c_char, value: *const DmsValue) -> c_int {
+    let context = unsafe { &mut *(context as *mut RunContext) };
+    let (path, component, value) = unsafe {
+        let value = &*value;
+        let data_value = match value.kind {
+            VALUE_INTEGER => Some(DataValue::Integer(value.integer)),
+            VALUE_FLOAT => Some(DataValue::Float(value.float)),
+            VALUE_BOOL => Some(DataValue::Bool(value.boolean != 0)),
+            VALUE_TEXT => from_c_str(value.text).map(|text| DataValue::Text(text.to_string())),
+            _ => None
+        };
+        (from_c_str(path), from_c_str(component), data_value)
+    };
+
+    match (path, component, value) {
+        (Some(path), Some(component), Some(value)) => {
+            let location = context.location;
+            match context.collector.collect(location, path, component, value) {
+                Ok(()) => 0,
+                Err(err) => {
+                    context.error = Some(err.to_string());
+                    1
+                }
+            }
+        }
+        _ => {
+            context.error = Some("plugin passed invalid data point".to_string());
+            1
+        }
+    }
+}
+
+extern "C" fn error(context: *mut c_void, message: *const c_char) {
+    let context = unsafe { &mut *(context as *mut RunContext) };
+    context.error = Some(unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned());
+}
+
+impl Probe for PluginProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, collector: &mut Collect) -> Result<(), String> {
+        let mut context = RunContext {
+            location: &self.location,
+            collector: collector,
+            error: None
+        };
+
+        let result = {
+            let dms_collector = DmsCollector {
+                context: &mut context as *mut RunContext as *mut c_void,
+                collect: collect,
+                error: error
+            };
+            // plugin has to catch its panics; crash of plugin code takes down only the probe process
+            (self.run)(self.probe.as_ptr(), self.options.as_ptr(), &dms_collector)
+        };
+
+        match (result, context.error) {
+            (0, _) => Ok(()),
+            (_, Some(error)) => Err(error),
+            (result, None) => Err(format!("plugin probe failed with code {}", result))
+        }
+    }
+
+    // crash of plugin code must not take the agent down
+    fn run_mode(&self) -> RunMode {
+        RunMode::DedicatedProcess
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::test_support::VecCollector;
+    pub use super::super::{Probe, Module};
+    pub use messaging::DataValue;
+    pub use config::Config;
+    pub use std::path::Path;
+    pub use std::fs::File;
+    pub use std::io::Write;
+    pub use std::process::Command;
+    pub use tempdir::TempDir;
+
+    fn build_example_plugin(target_dir: &Path) -> PathBuf {
+        let status = Command::new("cargo")
+            .arg("build")
+            .arg("--manifest-path").arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("plugins/example/Cargo.toml"))
+            .arg("--target-dir").arg(target_dir)
+            .status()
+            .expect("cargo to run");
+        assert!(status.success());
+        target_dir.join("debug")
+    }
+
+    #[test]
+    fn should_load_and_run_example_plugin() {
+        let target_dir = TempDir::new("dms_example_plugin").unwrap();
+        let plugin_dir = build_example_plugin(target_dir.path());
+
+        let plugins = load_plugins(&plugin_dir).unwrap();
+        assert_eq!(plugins.len(), 1);
+        assert_eq!(plugins[0].name(), "example");
+
+        let config = Config::parse(Path::new("agent.toml"), r#"
+            location = "web-01"
+
+            [modules.example.probes.answer]
+            every = "10s"
+
+            [modules.example.probes.options]
+            every = "10s"
+            foo = "bar"
+
+            [modules.example.probes.failing]
+            every = "10s"
+
+            [modules.example.probes.panicking]
+            every = "10s"
+        "#).unwrap();
+
+        let module = plugins[0].init(&config.location, config.module("example").unwrap()).unwrap();
+        let probes: Vec<&ProbeRunPlan> = module.schedule().collect();
+        assert_eq!(probes.iter().map(|plan| plan.probe.name()).collect::<Vec<_>>(), vec!["example/answer", "example/failing", "example/options", "example/panicking"]);
+
+        let mut collector = VecCollector::new();
+        probes[0].probe.run(&mut collector).unwrap();
+        assert_eq!(probes[1].probe.run(&mut collector).unwrap_err(), "this probe always fails".to_string());
+        probes[2].probe.run(&mut collector).unwrap();
+        assert_eq!(probes[3].probe.run(&mut collector).unwrap_err(), "probe panicked".to_string());
+
+        assert_eq!(collector.values, vec![
+            ("example/answer".to_string(), "value".to_string(), DataValue::Integer(42)),
+            ("example/options".to_string(), "toml".to_string(), DataValue::Text("foo = \"bar\"\n".to_string()))
+        ]);
+        assert_eq!(collector.locations, vec!["web-01".to_string(), "web-01".to_string()]);
+
+        let config = Config::parse(Path::new("agent.toml"), "location = \"web-01\"\n[modules.example.probes.bogus]\nevery = \"1s\"").unwrap();
+        assert!(plugins[0].init(&config.location, config.module("example").unwrap()).is_err());
+    }
+
+    #[test]
+    fn should_fail_to_load_invalid_library() {
+        let plugin_dir = TempDir::new("dms_plugins").unwrap();
+        let path = plugin_dir.path().join(format!("bogus.{}", DLL_EXTENSION));
+        File::create(&path).unwrap().write_all(b"not a library").unwrap();
+
+        match load_plugins(plugin_dir.path()) {
+            Err(PluginError::Io(error_path, _)) => assert_eq!(error_path, path),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("expected error")
+        }
+    }
+}
@@ -0,0 +1,499 @@
+use std::io::{self, Read, Write};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio, Child, ChildStdin, ExitStatus};
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, UTC};
+
+use program::{self, JoinHandle};
+use messaging::*;
+use config::Config;
+use sender::{Collect, CollectError};
+use super::{Probe, RunMode, ProbeRunResult, millis};
+
+/// Command line argument used to run probe of given name in child process
+pub const RUN_PROBE_ARG: &'static str = "--run-probe";
+
+/// Name used in errors about configuration passed to child process
+const CHILD_CONFIG_FILE: &'static str = "<probe process>";
+
+/// Tag of frame sent by child process carrying collected raw data point
+const DATA_POINT_FRAME: u8 = b'D';
+
+/// Tag of frame sent by child process when probe run finished; followed by error message if the run failed
+const RUN_RESULT_FRAME: u8 = b'R';
+
+/// Largest frame accepted; longer length can only come from corrupted or misbehaving peer
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Writes message frame prefixed with its length
+pub fn write_frame<W>(out: &mut W, frame: &[u8]) -> io::Result<()> where W: Write {
+    let length = frame.len() as u32;
+    try!(out.write_all(&[length as u8, (length >> 8) as u8, (length >> 16) as u8, (length >> 24) as u8]));
+    try!(out.write_all(frame));
+    out.flush()
+}
+
+/// Reads length prefixed message frame; returns None on end of stream and error on frame longer than `MAX_FRAME_SIZE`
+pub fn read_frame<R>(input: &mut R) -> io::Result<Option<Vec<u8>>> where R: Read {
+    let mut length = [0u8; 4];
+    let mut read = 0;
+    while read < 4 {
+        match try!(input.read(&mut length[read..])) {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated frame length")),
+            count => read += count
+        }
+    }
+
+    let length = length[0] as usize | (length[1] as usize) << 8 | (length[2] as usize) << 16 | (length[3] as usize) << 24;
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes exceeds limit of {} bytes", length, MAX_FRAME_SIZE)))
+    }
+    let mut frame = vec![0u8; length];
+    try!(input.read_exact(&mut frame));
+    Ok(Some(frame))
+}
+
+/// Collector used in child process writing raw data points to stdout
+pub struct PipeCollector<W> where W: Write {
+    timestamp: DateTime<UTC>,
+    out: W
+}
+
+impl<W> PipeCollector<W> where W: Write {
+    pub fn new(out: W) -> PipeCollector<W> {
+        PipeCollector {
+            timestamp: UTC::now(),
+            out: out
+        }
+    }
+}
+
+impl<W> Collect for PipeCollector<W> where W: Write {
+    fn collect(&mut self, location: &str, path: &str, component: &str, value: DataValue) -> Result<(), CollectError> {
+        let raw_data_point = RawDataPoint {
+            location: location.to_string(),
+            path: path.to_string(),
+            component: component.to_string(),
+            timestamp: self.timestamp,
+            value: value
+        };
+
+        let mut frame = vec![DATA_POINT_FRAME];
+        match encode_message("", raw_data_point, Encoding::Capnp) {
+            Ok(message) => frame.extend_from_slice(&message),
+            Err(err) => {
+                error!("Failed to encode raw data point: {}", err);
+                return Err(CollectError::Dropped)
+            }
+        };
+
+        write_frame(&mut self.out, &frame).map_err(|_| CollectError::Disconnected)
+    }
+}
+
+/// Reads configuration passed to child process by `ProcessProbe` before first run request
+pub fn read_child_config<R>(input: &mut R) -> Result<Config, String> where R: Read {
+    let frame = try!(try!(read_frame(input).map_err(|err| format!("failed to read configuration: {}", err))).ok_or_else(|| "no configuration received".to_string()));
+    let text = try!(String::from_utf8(frame).map_err(|err| format!("configuration is not valid UTF-8: {}", err)));
+    Config::parse(Path::new(CHILD_CONFIG_FILE), &text).map_err(|err| err.to_string())
+}
+
+/// Runs given probe each time run is requested on input sending collected data points and run result to output; used by child process
+pub fn run_probe_child<R, W>(probe: &Probe, input: &mut R, out: &mut W) -> Result<(), String> where R: Read, W: Write {
+    while let Some(_) = try!(read_frame(input).map_err(|err| format!("failed to read run request: {}", err))) {
+        let result = {
+            let mut collector = PipeCollector::new(&mut *out);
+            probe.run(&mut collector)
+        };
+
+        let mut frame = vec![RUN_RESULT_FRAME];
+        if let Err(error) = result {
+            frame.extend_from_slice(error.as_bytes());
+        }
+        try!(write_frame(out, &frame).map_err(|err| format!("failed to send run result: {}", err)));
+    }
+    Ok(())
+}
+
+/// Command starting child process of a probe; name of the probe to run is appended to its arguments
+#[derive(Debug, Clone)]
+pub struct ChildCommand {
+    program: PathBuf,
+    args: Vec<String>
+}
+
+impl ChildCommand {
+    /// This executable in probe child mode
+    pub fn current() -> ChildCommand {
+        ChildCommand::new(env::current_exe().expect("path to current executable"), Vec::new())
+    }
+
+    pub fn new<P>(program: P, args: Vec<String>) -> ChildCommand where P: Into<PathBuf> {
+        ChildCommand {
+            program: program.into(),
+            args: args
+        }
+    }
+}
+
+/// Child process waiting for run requests between runs
+struct ProbeChild {
+    process: Child,
+    requests: Option<ChildStdin>,
+    /// Frames read from child; ends with error if child broke the protocol
+    frames: Receiver<io::Result<Vec<u8>>>,
+    reader: Option<JoinHandle<()>>
+}
+
+/// How run requested from child process ended
+enum ChildRun {
+    Finished(ProbeRunResult),
+    TimedOut,
+    Exited,
+    /// Child sent data that could not be read as frame
+    ProtocolError(io::Error)
+}
+
+impl ProbeChild {
+    /// Closes requests so idle child exits, or kills child in the middle of the run, and waits for it
+    fn stop(&mut self, kill: bool) -> io::Result<ExitStatus> {
+        drop(self.requests.take());
+        if kill {
+            self.process.kill().ok();
+        }
+        let status = self.process.wait();
+        if let Some(reader) = self.reader.take() {
+            reader.join().ok();
+        }
+        status
+    }
+}
+
+impl Drop for ProbeChild {
+    fn drop(&mut self) {
+        if self.requests.is_some() {
+            self.stop(false).ok();
+        }
+    }
+}
+
+/// Runs probe in long-lived child process forwarding data points it collected to the collector; child is started again after it exited or was killed
+pub struct ProcessProbe {
+    name: String,
+    program: PathBuf,
+    args: Vec<String>,
+    config: String,
+    timeout: Duration,
+    child: Mutex<Option<ProbeChild>>
+}
+
+impl ProcessProbe {
+    /// Probe running given probe in child process started with given command; child builds the probe from given configuration
+    pub fn for_probe(probe: &Probe, command: &ChildCommand, config: String, timeout: Duration) -> ProcessProbe {
+        let mut args = command.args.clone();
+        args.push(RUN_PROBE_ARG.to_string());
+        args.push(probe.name().to_string());
+        ProcessProbe::new(probe.name(), command.program.clone(), args, config, timeout)
+    }
+
+    pub fn new<N, P>(name: N, program: P, args: Vec<String>, config: String, timeout: Duration) -> ProcessProbe where N: Into<String>, P: Into<PathBuf> {
+        ProcessProbe {
+            name: name.into(),
+            program: program.into(),
+            args: args,
+            config: config,
+            timeout: timeout,
+            child: Mutex::new(None)
+        }
+    }
+
+    fn spawn(&self) -> Result<ProbeChild, String> {
+        let mut process = try!(Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|err| format!("failed to start probe process '{}': {}", self.program.display(), err)));
+
+        let mut requests = process.stdin.take().expect("child stdin");
+        let mut stdout = process.stdout.take().expect("child stdout");
+        let (frames, received_frames) = channel();
+
+        let reader = program::spawn(&format!("producer/probe/{}/reader", self.name), move || {
+            loop {
+                match read_frame(&mut stdout) {
+                    Ok(Some(frame)) => if frames.send(Ok(frame)).is_err() {
+                        break
+                    },
+                    Ok(None) => break,
+                    Err(err) => {
+                        frames.send(Err(err)).ok();
+                        break
+                    }
+                }
+            }
+        });
+
+        // child that exited right away is reported by the run
+        if let Err(err) = write_frame(&mut requests, self.config.as_bytes()) {
+            debug!("Failed to send configuration to probe '{}' process: {}", self.name, err);
+        }
+
+        Ok(ProbeChild {
+            process: process,
+            requests: Some(requests),
+            frames: received_frames,
+            reader: Some(reader)
+        })
+    }
+
+    fn run_child(&self, child: &mut ProbeChild, collector: &mut Collect) -> ChildRun {
+        if let Err(err) = write_frame(child.requests.as_mut().expect("child requests"), &[]) {
+            debug!("Failed to request run from probe '{}' process: {}", self.name, err);
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        let mut collect_error = None;
+        loop {
+            let now = Instant::now();
+            let remaining = if deadline > now { deadline - now } else { Duration::new(0, 0) };
+
+            let frame = match child.frames.recv_timeout(remaining) {
+                Ok(Ok(frame)) => frame,
+                Ok(Err(err)) => return ChildRun::ProtocolError(err),
+                Err(RecvTimeoutError::Timeout) => return ChildRun::TimedOut,
+                Err(RecvTimeoutError::Disconnected) => return ChildRun::Exited
+            };
+
+            match frame.split_first() {
+                Some((&DATA_POINT_FRAME, message)) => {
+                    let raw_data_point: RawDataPoint = match split_message(message.to_vec()).and_then(|(header, body)| decode_message_body(&header, &body)) {
+                        Ok(raw_data_point) => raw_data_point,
+                        Err(err) => {
+                            error!("Failed to decode data from probe '{}' process: {}", self.name, err);
+                            continue
+                        }
+                    };
+                    if let Err(err) = collector.collect(&raw_data_point.location, &raw_data_point.path, &raw_data_point.component, raw_data_point.value) {
+                        collect_error = Some(err.to_string());
+                    }
+                }
+                Some((&RUN_RESULT_FRAME, error)) => {
+                    return ChildRun::Finished(match (error.is_empty(), collect_error) {
+                        (false, _) => ProbeRunResult::Error(String::from_utf8_lossy(error).into_owned()),
+                        (true, Some(err)) => ProbeRunResult::Error(err),
+                        (true, None) => ProbeRunResult::Ok
+                    })
+                }
+                _ => error!("Received unexpected frame from probe '{}' process", self.name)
+            }
+        }
+    }
+}
+
+impl Probe for ProcessProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, collector: &mut Collect) -> Result<(), String> {
+        match self.run_with_result(collector) {
+            ProbeRunResult::Ok => Ok(()),
+            ProbeRunResult::Error(error) => Err(error),
+            timeout => Err(timeout.to_string())
+        }
+    }
+
+    fn run_with_result(&self, collector: &mut Collect) -> ProbeRunResult {
+        let mut child = self.child.lock().unwrap();
+        if child.is_none() {
+            match self.spawn() {
+                Ok(spawned) => *child = Some(spawned),
+                Err(err) => return ProbeRunResult::Error(err)
+            }
+        }
+
+        let run = self.run_child(child.as_mut().unwrap(), collector);
+        match run {
+            ChildRun::Finished(result) => result,
+            ChildRun::TimedOut => {
+                warn!("Probe '{}' process did not finish within {}ms; killing it", self.name, millis(self.timeout));
+                child.take().unwrap().stop(true).ok();
+                ProbeRunResult::Timeout(self.timeout)
+            }
+            ChildRun::ProtocolError(err) => {
+                // child may be blocked writing rest of the frame so it is not waited for to exit by itself
+                child.take().unwrap().stop(true).ok();
+                ProbeRunResult::Error(format!("failed to read data from probe process: {}", err))
+            }
+            ChildRun::Exited => {
+                match child.take().unwrap().stop(false) {
+                    Ok(status) if status.success() => ProbeRunResult::Error("probe process exited before finishing the run".to_string()),
+                    Ok(status) => ProbeRunResult::Error(format!("probe process failed: {}", status)),
+                    Err(err) => ProbeRunResult::Error(format!("failed to wait for probe process: {}", err))
+                }
+            }
+        }
+    }
+
+    fn run_mode(&self) -> RunMode {
+        RunMode::DedicatedThread
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::test_support::VecCollector;
+    pub use super::super::{Probe, RunMode, ProbeRunResult};
+    pub use std::sync::Mutex;
+    pub use sender::Collect;
+    pub use messaging::*;
+    pub use std::io::{self, Cursor};
+    pub use std::time::{Duration, Instant};
+
+    #[test]
+    fn should_read_frames_written_by_pipe_collector() {
+        let mut out = Vec::new();
+        {
+            let mut collector = PipeCollector::new(&mut out);
+            collector.collect("myserver", "cpu/usage", "user", DataValue::Float(0.4)).unwrap();
+            collector.collect("myserver", "cpu/count", "online", DataValue::Integer(8)).unwrap();
+        }
+
+        assert_eq!(values(out), vec![DataValue::Float(0.4), DataValue::Integer(8)]);
+    }
+
+    // Values of data point frames written by child process
+    fn values(out: Vec<u8>) -> Vec<DataValue> {
+        let mut input = Cursor::new(out);
+        let mut values = Vec::new();
+        while let Some(frame) = read_frame(&mut input).unwrap() {
+            if frame[0] == DATA_POINT_FRAME {
+                let (header, body) = split_message(frame[1..].to_vec()).unwrap();
+                let raw_data_point: RawDataPoint = decode_message_body(&header, &body).unwrap();
+                values.push(raw_data_point.value);
+            }
+        }
+        values
+    }
+
+    struct CountingProbe {
+        runs: Mutex<i64>
+    }
+
+    impl Probe for CountingProbe {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn run(&self, collector: &mut Collect) -> Result<(), String> {
+            let mut runs = self.runs.lock().unwrap();
+            *runs = *runs + 1;
+            collector.collect("myserver", "counting", "runs", DataValue::Integer(*runs)).map_err(|err| err.to_string())
+        }
+
+        fn run_mode(&self) -> RunMode {
+            RunMode::DedicatedProcess
+        }
+    }
+
+    #[test]
+    fn child_should_run_probe_on_each_request_keeping_its_state() {
+        let mut input = Vec::new();
+        write_frame(&mut input, &[]).unwrap();
+        write_frame(&mut input, &[]).unwrap();
+
+        let mut out = Vec::new();
+        run_probe_child(&CountingProbe { runs: Mutex::new(0) }, &mut Cursor::new(input), &mut out).unwrap();
+
+        let mut frames = Cursor::new(out.clone());
+        let mut tags = Vec::new();
+        while let Some(frame) = read_frame(&mut frames).unwrap() {
+            tags.push(frame[0]);
+        }
+        assert_eq!(tags, vec![DATA_POINT_FRAME, RUN_RESULT_FRAME, DATA_POINT_FRAME, RUN_RESULT_FRAME]);
+        assert_eq!(values(out), vec![DataValue::Integer(1), DataValue::Integer(2)]);
+    }
+
+    #[test]
+    fn child_should_read_configuration_from_input() {
+        let mut input = Vec::new();
+        write_frame(&mut input, b"location = \"web-01\"\n[modules.hello_world.probes.hello]\nevery = \"1s\"\n").unwrap();
+
+        let config = read_child_config(&mut Cursor::new(input)).unwrap();
+        assert_eq!(config.location, "web-01".to_string());
+        assert_eq!(config.modules[0].name, "hello_world".to_string());
+
+        assert!(read_child_config(&mut Cursor::new(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn should_fail_on_truncated_frame() {
+        let mut input = Cursor::new(vec![10, 0, 0, 0, 1, 2]);
+        assert!(read_frame(&mut input).is_err());
+
+        let mut input = Cursor::new(vec![10, 0]);
+        assert!(read_frame(&mut input).is_err());
+    }
+
+    #[test]
+    fn should_fail_on_frame_exceeding_limit() {
+        let mut input = Cursor::new(vec![255, 255, 255, 127]);
+        assert_eq!(read_frame(&mut input).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn should_forward_frames_from_child_process() {
+        let mut out = Vec::new();
+        {
+            let mut collector = PipeCollector::new(&mut out);
+            collector.collect("myserver", "cpu/usage", "user", DataValue::Float(0.4)).unwrap();
+        }
+        write_frame(&mut out, &[RUN_RESULT_FRAME]).unwrap();
+        let escaped: String = out.iter().map(|byte| format!("\\{:03o}", byte)).collect();
+
+        let probe = ProcessProbe::new("printf", "printf", vec![escaped], String::new(), Duration::from_secs(5));
+        let mut collector = VecCollector::new();
+        probe.run(&mut collector).unwrap();
+
+        assert_eq!(collector.values, vec![("cpu/usage".to_string(), "user".to_string(), DataValue::Float(0.4))]);
+        assert_eq!(collector.locations, vec!["myserver".to_string()]);
+    }
+
+    #[test]
+    fn should_report_crashed_child_process() {
+        let probe = ProcessProbe::new("crash", "sh", vec!["-c".to_string(), "kill -SEGV $$".to_string()], String::new(), Duration::from_secs(5));
+        let mut collector = VecCollector::new();
+        assert!(probe.run(&mut collector).is_err());
+    }
+
+    #[test]
+    fn should_kill_child_process_sending_oversized_frame() {
+        let probe = ProcessProbe::new("oversized", "sh", vec!["-c".to_string(), "printf '\\377\\377\\377\\177'; exec sleep 10".to_string()], String::new(), Duration::from_secs(5));
+        let mut collector = VecCollector::new();
+
+        let started = Instant::now();
+        match probe.run_with_result(&mut collector) {
+            ProbeRunResult::Error(error) => assert!(error.contains("exceeds limit"), "{}", error),
+            result => panic!("unexpected result: {:?}", result)
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn should_kill_child_process_on_timeout() {
+        let probe = ProcessProbe::new("sleep", "sleep", vec!["10".to_string()], String::new(), Duration::from_millis(100));
+        let mut collector = VecCollector::new();
+
+        let started = Instant::now();
+        let result = probe.run_with_result(&mut collector);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(result, ProbeRunResult::Timeout(Duration::from_millis(100)));
+    }
+}
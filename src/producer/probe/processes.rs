@@ -0,0 +1,404 @@
+use std::sync::{Arc, Mutex};
+use std::slice::Iter;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::time::Instant;
+use std::fs;
+use std::io::Read;
+use regex::Regex;
+use libc;
+
+use sender::Collect;
+use messaging::DataValue;
+use config::{ModuleConfig, ProbeConfig, ConfigError};
+use super::{RunMode, ProbeRunPlan, Probe, Module};
+use super::procfs;
+
+/// How processes of a group are found
+#[derive(Debug)]
+pub enum Matcher {
+    /// Process name as in stat; kernel truncates it to `MAX_PROCESS_NAME_BYTES`
+    Name(String),
+    /// Regular expression searched in command line with arguments separated by spaces
+    Cmdline(Regex),
+    /// File containing PID of the main process of a service
+    Pidfile(PathBuf)
+}
+
+/// Resource usage of single process
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessStats {
+    pub pid: u32,
+    /// Start time since boot in clock ticks; tells apart processes with reused PID
+    pub start_time: u64,
+    /// User and system time in clock ticks
+    pub cpu_ticks: u64,
+    pub rss_bytes: u64,
+    pub threads: u64,
+    /// None if file descriptors or IO counters of the process are not readable by the agent
+    pub open_fds: Option<u64>,
+    pub io_bytes: Option<(u64, u64)>
+}
+
+/// Parses /proc/<pid>/stat into process name, CPU ticks and start time
+pub fn parse_process_stat(stat: &str) -> Result<(String, u64, u64), String> {
+    // name is in parentheses and may itself contain spaces and parentheses
+    let (open, close) = match (stat.find('('), stat.rfind(')')) {
+        (Some(open), Some(close)) if open < close => (open, close),
+        _ => return Err(format!("missing process name in stat: {}", stat))
+    };
+    let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+    if fields.len() < 20 {
+        return Err(format!("expected at least 20 fields after process name in stat but got: {}", stat))
+    }
+
+    let utime = try!(fields[11].parse::<u64>().map_err(|_| format!("invalid utime '{}' in stat", fields[11])));
+    let stime = try!(fields[12].parse::<u64>().map_err(|_| format!("invalid stime '{}' in stat", fields[12])));
+    let start_time = try!(fields[19].parse().map_err(|_| format!("invalid starttime '{}' in stat", fields[19])));
+    Ok((stat[open + 1..close].to_string(), utime + stime, start_time))
+}
+
+/// Parses resident memory in bytes and thread count from /proc/<pid>/status
+pub fn parse_process_status(status: &str) -> Result<(u64, u64), String> {
+    // kernel threads have no VmRSS
+    let mut rss_bytes = 0;
+    let mut threads = None;
+    for line in status.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.get(0) {
+            Some(&"VmRSS:") => rss_bytes = try!(procfs::parse_fields(&fields[1..2], "VmRSS line of status"))[0] * 1024,
+            Some(&"Threads:") => threads = Some(try!(procfs::parse_fields(&fields[1..2], "Threads line of status"))[0]),
+            _ => ()
+        }
+    }
+    Ok((rss_bytes, try!(threads.ok_or_else(|| "missing Threads in status".to_string()))))
+}
+
+/// Parses bytes read from and written to storage from /proc/<pid>/io
+pub fn parse_process_io(io: &str) -> Result<(u64, u64), String> {
+    let mut read_bytes = None;
+    let mut write_bytes = None;
+    for line in io.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 2 {
+            continue
+        }
+        match fields[0] {
+            "read_bytes:" => read_bytes = Some(try!(procfs::parse_fields(&fields[1..], "read_bytes line of io"))[0]),
+            "write_bytes:" => write_bytes = Some(try!(procfs::parse_fields(&fields[1..], "write_bytes line of io"))[0]),
+            _ => ()
+        }
+    }
+    match (read_bytes, write_bytes) {
+        (Some(read_bytes), Some(write_bytes)) => Ok((read_bytes, write_bytes)),
+        _ => Err("missing read_bytes or write_bytes in io".to_string())
+    }
+}
+
+fn pids(proc_root: &Path) -> Result<Vec<u32>, String> {
+    let mut pids = Vec::new();
+    for entry in try!(fs::read_dir(proc_root).map_err(|err| format!("failed to list {}: {}", proc_root.display(), err))) {
+        if let Some(pid) = entry.ok().and_then(|entry| entry.file_name().to_str().and_then(|name| name.parse().ok())) {
+            pids.push(pid);
+        }
+    }
+    pids.sort();
+    Ok(pids)
+}
+
+/// Reads stats of process; None if process has exited meanwhile
+fn read_process(proc_root: &Path, pid: u32) -> Option<ProcessStats> {
+    let process = format!("{}", pid);
+    let (_, cpu_ticks, start_time) = match procfs::read(proc_root, &format!("{}/stat", process)).and_then(|stat| parse_process_stat(&stat)) {
+        Ok(stat) => stat,
+        Err(err) => {
+            debug!("Process {}: {}", pid, err);
+            return None
+        }
+    };
+    let (rss_bytes, threads) = match procfs::read(proc_root, &format!("{}/status", process)).and_then(|status| parse_process_status(&status)) {
+        Ok(status) => status,
+        Err(err) => {
+            debug!("Process {}: {}", pid, err);
+            return None
+        }
+    };
+
+    Some(ProcessStats {
+        pid: pid,
+        start_time: start_time,
+        cpu_ticks: cpu_ticks,
+        rss_bytes: rss_bytes,
+        threads: threads,
+        open_fds: fs::read_dir(proc_root.join(&process).join("fd")).ok().map(|fds| fds.count() as u64),
+        io_bytes: procfs::read(proc_root, &format!("{}/io", process)).and_then(|io| parse_process_io(&io)).ok()
+    })
+}
+
+impl Matcher {
+    fn matching_pids(&self, proc_root: &Path) -> Result<Vec<u32>, String> {
+        match self {
+            &Matcher::Pidfile(ref pidfile) => {
+                // service is not running if there is no pidfile
+                let mut content = String::new();
+                if fs::File::open(pidfile).and_then(|mut file| file.read_to_string(&mut content)).is_err() {
+                    return Ok(Vec::new())
+                }
+                let pid = try!(content.trim().parse::<u32>().map_err(|_| format!("invalid PID '{}' in {}", content.trim(), pidfile.display())));
+                Ok(if proc_root.join(format!("{}", pid)).exists() { vec![pid] } else { Vec::new() })
+            }
+            &Matcher::Name(ref name) => {
+                Ok(try!(pids(proc_root)).into_iter().filter(|pid| {
+                    procfs::read(proc_root, &format!("{}/stat", pid)).and_then(|stat| parse_process_stat(&stat))
+                        .map(|(process_name, _, _)| &process_name == name).unwrap_or(false)
+                }).collect())
+            }
+            &Matcher::Cmdline(ref regex) => {
+                Ok(try!(pids(proc_root)).into_iter().filter(|pid| {
+                    procfs::read(proc_root, &format!("{}/cmdline", pid))
+                        .map(|cmdline| regex.is_match(&cmdline.trim_right_matches('\0').replace('\0', " "))).unwrap_or(false)
+                }).collect())
+            }
+        }
+    }
+}
+
+pub struct ProcessesProbe {
+    name: String,
+    group: String,
+    location: String,
+    proc_root: PathBuf,
+    matcher: Matcher,
+    clock_ticks: u64,
+    // stats of previous run by PID needed to calculate CPU usage and IO rates
+    previous: Mutex<Option<(Instant, HashMap<u32, ProcessStats>)>>
+}
+
+impl ProcessesProbe {
+    fn new(location: &str, config: &ModuleConfig, probe: &ProbeConfig) -> Result<ProcessesProbe, ConfigError> {
+        let options = &probe.options;
+        let matcher = match (try!(options.string("name")), try!(options.string("cmdline")), try!(options.string("pidfile"))) {
+            (Some(name), None, None) if name.len() > MAX_PROCESS_NAME_BYTES => return Err(options.error("name",
+                format!("process name '{}' is longer than {} bytes kept by kernel; use cmdline option instead", name, MAX_PROCESS_NAME_BYTES))),
+            (Some(name), None, None) => Matcher::Name(name.to_string()),
+            (None, Some(cmdline), None) => Matcher::Cmdline(try!(Regex::new(cmdline).map_err(|err| options.error("cmdline", format!("invalid pattern '{}': {}", cmdline, err))))),
+            (None, None, Some(pidfile)) => Matcher::Pidfile(PathBuf::from(pidfile)),
+            _ => return Err(options.error("name", "exactly one of name, cmdline or pidfile options is required"))
+        };
+
+        Ok(ProcessesProbe {
+            name: format!("{}/{}", config.name, probe.name),
+            group: probe.name.clone(),
+            location: location.to_string(),
+            proc_root: try!(procfs::proc_root(&config.options)),
+            matcher: matcher,
+            clock_ticks: unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64,
+            previous: Mutex::new(None)
+        })
+    }
+
+    fn data_points(&self, now: Instant) -> Result<Vec<(&'static str, DataValue)>, String> {
+        let processes: Vec<ProcessStats> = try!(self.matcher.matching_pids(&self.proc_root)).into_iter()
+            .filter_map(|pid| read_process(&self.proc_root, pid)).collect();
+
+        let mut data_points = vec![
+            ("os/process/count", DataValue::Integer(processes.len() as i64)),
+            ("os/process/absent", DataValue::Bool(processes.is_empty()))
+        ];
+
+        let mut previous = self.previous.lock().unwrap();
+        if !processes.is_empty() {
+            data_points.push(("os/process/rss_bytes", DataValue::Integer(processes.iter().map(|process| process.rss_bytes).sum::<u64>() as i64)));
+            data_points.push(("os/process/threads", DataValue::Integer(processes.iter().map(|process| process.threads).sum::<u64>() as i64)));
+            data_points.push(("os/process/open_fds", DataValue::Integer(processes.iter().filter_map(|process| process.open_fds).sum::<u64>() as i64)));
+
+            if let Some((previous_time, ref previous_processes)) = *previous {
+                let seconds = procfs::seconds_between(previous_time, now);
+                if seconds > 0.0 {
+                    let mut cpu_ticks = 0;
+                    let mut read_bytes = 0;
+                    let mut write_bytes = 0;
+
+                    // processes that just started are accounted from next run
+                    for process in &processes {
+                        let previous_process = match previous_processes.get(&process.pid) {
+                            Some(previous_process) if previous_process.start_time == process.start_time => previous_process,
+                            _ => continue
+                        };
+                        cpu_ticks += procfs::delta(previous_process.cpu_ticks, process.cpu_ticks).unwrap_or(0);
+                        if let (Some((previous_read, previous_write)), Some((read, write))) = (previous_process.io_bytes, process.io_bytes) {
+                            read_bytes += procfs::delta(previous_read, read).unwrap_or(0);
+                            write_bytes += procfs::delta(previous_write, write).unwrap_or(0);
+                        }
+                    }
+
+                    data_points.push(("os/process/cpu_percent", DataValue::Float(cpu_ticks as f64 * 100.0 / self.clock_ticks as f64 / seconds)));
+                    data_points.push(("os/process/read_bytes_per_second", DataValue::Float(read_bytes as f64 / seconds)));
+                    data_points.push(("os/process/write_bytes_per_second", DataValue::Float(write_bytes as f64 / seconds)));
+                }
+            }
+        }
+        *previous = Some((now, processes.into_iter().map(|process| (process.pid, process)).collect()));
+
+        Ok(data_points)
+    }
+}
+
+impl Probe for ProcessesProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, collector: &mut Collect) -> Result<(), String> {
+        let mut collector = collector;
+        for (path, value) in try!(self.data_points(Instant::now())) {
+            try!(collector.collect(&self.location, path, &self.group, value).map_err(|err| err.to_string()));
+        }
+        Ok(())
+    }
+
+    fn run_mode(&self) -> RunMode {
+        RunMode::SharedThread
+    }
+}
+
+pub struct ProcessesModule {
+    schedule: Vec<ProbeRunPlan>
+}
+
+impl Module for ProcessesModule {
+    fn name(&self) -> &str {
+        "processes"
+    }
+
+    fn schedule(&self) -> Iter<ProbeRunPlan> {
+        self.schedule.iter()
+    }
+}
+
+/// Length of process name kept by kernel
+const MAX_PROCESS_NAME_BYTES: usize = 15;
+
+/// Each probe watches one group of processes named after the probe
+pub fn init(location: &str, config: &ModuleConfig) -> Result<Box<Module>, ConfigError> {
+    let mut schedule = Vec::new();
+
+    for probe in &config.probes {
+        schedule.push(ProbeRunPlan {
+            every: probe.every,
+            timeout: probe.timeout,
+            probe: Arc::new(try!(ProcessesProbe::new(location, config, probe)))
+        });
+    }
+
+    Ok(Box::new(ProcessesModule {
+        schedule: schedule
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::test_support::{fixture, fixture_config};
+    pub use super::super::Module;
+    pub use config::Config;
+    pub use messaging::DataValue;
+    pub use regex::Regex;
+    pub use std::path::Path;
+    pub use std::time::{Duration, Instant};
+
+    fn probe(options: &str) -> ProcessesProbe {
+        let config = fixture_config("processes", "test", options);
+        let mut probe = ProcessesProbe::new("web-01", &config, &config.probes[0]).unwrap();
+        // CPU times in fixtures are in ticks of 10ms
+        probe.clock_ticks = 100;
+        probe
+    }
+
+    #[test]
+    fn should_parse_stat_with_odd_process_name() {
+        assert_eq!(parse_process_stat("42 (my (odd) name) S 1 42 42 0 -1 4194560 500 0 0 0 10 5 0 0 20 0 1 0 777 123456789 1024").unwrap(),
+                   ("my (odd) name".to_string(), 15, 777));
+        assert!(parse_process_stat("42 my process").is_err());
+    }
+
+    #[test]
+    fn should_match_processes() {
+        let proc_root = fixture("proc/1");
+        assert_eq!(Matcher::Name("nginx".to_string()).matching_pids(&proc_root).unwrap(), vec![100, 101]);
+        assert_eq!(Matcher::Cmdline(Regex::new("^nginx: worker").unwrap()).matching_pids(&proc_root).unwrap(), vec![101]);
+        assert_eq!(Matcher::Cmdline(Regex::new("postgres -D").unwrap()).matching_pids(&proc_root).unwrap(), vec![200]);
+        assert_eq!(Matcher::Pidfile(fixture("run/nginx.pid")).matching_pids(&proc_root).unwrap(), vec![100]);
+        assert!(Matcher::Pidfile(fixture("run/stale.pid")).matching_pids(&proc_root).unwrap().is_empty());
+        assert!(Matcher::Pidfile(fixture("run/missing.pid")).matching_pids(&proc_root).unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_report_group_usage() {
+        let mut probe = probe("name = \"nginx\"");
+        let start = Instant::now();
+
+        assert_eq!(probe.data_points(start).unwrap(), vec![
+            ("os/process/count", DataValue::Integer(2)),
+            ("os/process/absent", DataValue::Bool(false)),
+            ("os/process/rss_bytes", DataValue::Integer((4096 + 8192) * 1024)),
+            ("os/process/threads", DataValue::Integer(5)),
+            ("os/process/open_fds", DataValue::Integer(8))
+        ]);
+
+        probe.proc_root = fixture("proc/2");
+        assert_eq!(&probe.data_points(start + Duration::from_secs(10)).unwrap()[5..], &[
+            ("os/process/cpu_percent", DataValue::Float(30.0)),
+            // worker has no readable io file
+            ("os/process/read_bytes_per_second", DataValue::Float(10000.0)),
+            ("os/process/write_bytes_per_second", DataValue::Float(50000.0))
+        ][..]);
+    }
+
+    #[test]
+    fn should_report_absent_group() {
+        let mut probe = probe("name = \"postgres\"");
+        let start = Instant::now();
+        assert_eq!(probe.data_points(start).unwrap().len(), 5);
+
+        probe.proc_root = fixture("proc/2");
+        assert_eq!(probe.data_points(start + Duration::from_secs(10)).unwrap(), vec![
+            ("os/process/count", DataValue::Integer(0)),
+            ("os/process/absent", DataValue::Bool(true))
+        ]);
+    }
+
+    #[test]
+    fn should_require_single_matcher() {
+        let config = Config::parse(Path::new("agent.toml"), r#"
+            location = "web-01"
+
+            [modules.processes.probes.nginx]
+            every = "10s"
+            name = "nginx"
+            pidfile = "/run/nginx.pid"
+        "#).unwrap();
+        assert!(init(&config.location, &config.modules[0]).is_err());
+
+        let config = Config::parse(Path::new("agent.toml"), r#"
+            location = "web-01"
+
+            [modules.processes.probes.nginx]
+            every = "10s"
+            pidfile = "/run/nginx.pid"
+
+            [modules.processes.probes.workers]
+            every = "10s"
+            cmdline = "nginx: worker"
+        "#).unwrap();
+        assert_eq!(init(&config.location, &config.modules[0]).unwrap().schedule().count(), 2);
+    }
+
+    #[test]
+    fn should_reject_name_longer_than_kept_by_kernel() {
+        let config = |name: &str| Config::parse(Path::new("agent.toml"), &format!("location = \"web-01\"\n[modules.processes.probes.app]\nevery = \"10s\"\nname = \"{}\"", name)).unwrap().modules.remove(0);
+
+        assert!(init("web-01", &config("php-fpm-worker7")).is_ok());
+        assert!(init("web-01", &config("php-fpm-worker72")).is_err());
+    }
+}
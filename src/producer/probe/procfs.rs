@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use regex::Regex;
+
+use config::{Options, ConfigError};
+
+/// Default mount point of proc filesystem
+pub const PROC_ROOT: &'static str = "/proc";
+
+/// Location of proc filesystem from `proc_root` option; tests point it to fixture files
+pub fn proc_root(options: &Options) -> Result<PathBuf, ConfigError> {
+    Ok(PathBuf::from(try!(options.string("proc_root")).unwrap_or(PROC_ROOT)))
+}
+
+/// Reads file relative to proc root
+pub fn read(root: &Path, name: &str) -> Result<String, String> {
+    let path = root.join(name);
+    let mut content = String::new();
+    try!(File::open(&path).and_then(|mut file| file.read_to_string(&mut content)).map_err(|err| format!("failed to read {}: {}", path.display(), err)));
+    Ok(content)
+}
+
+/// Increase of counter between two readings; None if counter was reset
+pub fn delta(previous: u64, current: u64) -> Option<u64> {
+    if current < previous {
+        return None
+    }
+    Some(current - previous)
+}
+
+/// Like delta but for counters that kernel may keep in 32 bits; decrease is taken as wrap around only if counter was close to the 32 bit limit, otherwise as reset
+pub fn wrapping_delta(previous: u64, current: u64) -> Option<u64> {
+    let max = u32::max_value() as u64;
+    if current < previous && previous > max / 2 && previous <= max {
+        let wrapped = current + (max - previous) + 1;
+        // counter can't wrap around by more than half of its range between readings
+        return if wrapped <= max / 2 { Some(wrapped) } else { None }
+    }
+    delta(previous, current)
+}
+
+/// Time between two readings in seconds
+pub fn seconds_between(previous: Instant, now: Instant) -> f64 {
+    let elapsed = now.duration_since(previous);
+    elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+/// Selects names matching any include pattern and no exclude pattern; patterns are regular expressions matched against whole name
+#[derive(Debug, Clone)]
+pub struct Filter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>
+}
+
+impl Filter {
+    /// Filter from include and exclude options; no include option means everything is included
+    pub fn from_options(options: &Options, include: &str, exclude: &str, default_exclude: &[&str]) -> Result<Filter, ConfigError> {
+        let default_exclude = default_exclude.iter().map(|pattern| pattern.to_string()).collect();
+        Ok(Filter {
+            include: try!(Filter::patterns(options, include, try!(options.strings(include)).unwrap_or_else(Vec::new))),
+            exclude: try!(Filter::patterns(options, exclude, try!(options.strings(exclude)).unwrap_or(default_exclude)))
+        })
+    }
+
+    fn patterns(options: &Options, key: &str, patterns: Vec<String>) -> Result<Vec<Regex>, ConfigError> {
+        let mut regexes = Vec::with_capacity(patterns.len());
+        for pattern in patterns {
+            regexes.push(try!(Regex::new(&format!("^(?:{})$", pattern)).map_err(|err| options.error(key, format!("invalid pattern '{}': {}", pattern, err)))));
+        }
+        Ok(regexes)
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|regex| regex.is_match(name))) && !self.exclude.iter().any(|regex| regex.is_match(name))
+    }
+}
+
+/// Parses whitespace separated unsigned integer fields
+pub fn parse_fields(fields: &[&str], name: &str) -> Result<Vec<u64>, String> {
+    let mut values = Vec::with_capacity(fields.len());
+    for field in fields {
+        values.push(try!(field.parse().map_err(|_| format!("invalid value '{}' in {}", field, name))));
+    }
+    Ok(values)
+}
@@ -0,0 +1,359 @@
+use std::sync::{Arc, Mutex};
+use std::slice::Iter;
+use std::path::PathBuf;
+use std::collections::HashMap;
+
+use sender::Collect;
+use messaging::DataValue;
+use config::{ModuleConfig, ProbeConfig, ConfigError};
+use super::{RunMode, ProbeRunPlan, Probe, Module};
+use super::procfs;
+
+/// Time spent by all CPUs in each state since boot in clock ticks
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuTimes {
+    pub user: u64,
+    pub nice: u64,
+    pub system: u64,
+    pub idle: u64,
+    pub iowait: u64,
+    pub irq: u64,
+    pub softirq: u64,
+    pub steal: u64
+}
+
+impl CpuTimes {
+    fn values(&self) -> [(&'static str, u64); 8] {
+        [("user", self.user), ("nice", self.nice), ("system", self.system), ("idle", self.idle),
+         ("iowait", self.iowait), ("irq", self.irq), ("softirq", self.softirq), ("steal", self.steal)]
+    }
+
+    /// Percentage of time spent in each state since previous reading; None if counters were reset or no time passed
+    pub fn usage(&self, previous: &CpuTimes) -> Option<Vec<(&'static str, f64)>> {
+        let mut deltas = Vec::with_capacity(8);
+        for (&(state, current), &(_, previous)) in self.values().iter().zip(previous.values().iter()) {
+            match procfs::delta(previous, current) {
+                Some(delta) => deltas.push((state, delta)),
+                None => return None
+            }
+        }
+
+        let total = deltas.iter().fold(0, |total, &(_, delta)| total + delta);
+        if total == 0 {
+            return None
+        }
+        Some(deltas.into_iter().map(|(state, delta)| (state, delta as f64 * 100.0 / total as f64)).collect())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stat {
+    pub cpu: CpuTimes,
+    pub cpu_count: u64,
+    pub procs_running: u64,
+    pub procs_blocked: u64
+}
+
+pub fn parse_stat(stat: &str) -> Result<Stat, String> {
+    let mut cpu = None;
+    let mut cpu_count = 0;
+    let mut procs_running = 0;
+    let mut procs_blocked = 0;
+
+    for line in stat.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.first() {
+            Some(&"cpu") => {
+                let values = try!(procfs::parse_fields(&fields[1..], "cpu line of stat"));
+                if values.len() < 8 {
+                    return Err(format!("expected at least 8 values in cpu line of stat but got {}", values.len()))
+                }
+                cpu = Some(CpuTimes {
+                    user: values[0],
+                    nice: values[1],
+                    system: values[2],
+                    idle: values[3],
+                    iowait: values[4],
+                    irq: values[5],
+                    softirq: values[6],
+                    steal: values[7]
+                });
+            }
+            Some(name) if name.starts_with("cpu") => cpu_count = cpu_count + 1,
+            Some(&"procs_running") => procs_running = try!(procfs::parse_fields(&fields[1..], "procs_running line of stat")).get(0).cloned().unwrap_or(0),
+            Some(&"procs_blocked") => procs_blocked = try!(procfs::parse_fields(&fields[1..], "procs_blocked line of stat")).get(0).cloned().unwrap_or(0),
+            _ => ()
+        }
+    }
+
+    Ok(Stat {
+        cpu: try!(cpu.ok_or_else(|| "no cpu line in stat".to_string())),
+        cpu_count: cpu_count,
+        procs_running: procs_running,
+        procs_blocked: procs_blocked
+    })
+}
+
+/// Parses meminfo into values in bytes by field name
+pub fn parse_meminfo(meminfo: &str) -> Result<HashMap<String, u64>, String> {
+    let mut values = HashMap::new();
+    for line in meminfo.lines() {
+        let mut parts = line.splitn(2, ':');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name.trim(), value.trim()),
+            _ => continue
+        };
+
+        let mut value_parts = value.split_whitespace();
+        let number: u64 = try!(value_parts.next().unwrap_or("").parse().map_err(|_| format!("invalid value of {} in meminfo", name)));
+        let bytes = match value_parts.next() {
+            Some("kB") => number * 1024,
+            _ => number
+        };
+        values.insert(name.to_string(), bytes);
+    }
+    Ok(values)
+}
+
+/// Parses 1, 5 and 15 minute load averages
+pub fn parse_loadavg(loadavg: &str) -> Result<(f64, f64, f64), String> {
+    let fields: Vec<f64> = try!(loadavg.split_whitespace().take(3).map(|field| field.parse().map_err(|_| format!("invalid load average '{}'", field))).collect());
+    if fields.len() != 3 {
+        return Err("expected 3 load averages in loadavg".to_string())
+    }
+    Ok((fields[0], fields[1], fields[2]))
+}
+
+/// Parses seconds since boot
+pub fn parse_uptime(uptime: &str) -> Result<f64, String> {
+    let field = uptime.split_whitespace().next().unwrap_or("");
+    field.parse().map_err(|_| format!("invalid uptime '{}'", field))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Metrics {
+    Cpu,
+    Memory,
+    Load,
+    Uptime
+}
+
+pub struct SystemProbe {
+    name: String,
+    location: String,
+    proc_root: PathBuf,
+    metrics: Metrics,
+    // CPU times of previous run needed to calculate usage over probe interval
+    previous_cpu: Mutex<Option<CpuTimes>>
+}
+
+impl SystemProbe {
+    fn new(location: &str, config: &ModuleConfig, probe: &ProbeConfig) -> Result<SystemProbe, ConfigError> {
+        let metrics = match probe.name.as_str() {
+            "cpu" => Metrics::Cpu,
+            "memory" => Metrics::Memory,
+            "load" => Metrics::Load,
+            "uptime" => Metrics::Uptime,
+            _ => return Err(probe.unknown())
+        };
+
+        Ok(SystemProbe {
+            name: format!("{}/{}", config.name, probe.name),
+            location: location.to_string(),
+            proc_root: try!(procfs::proc_root(&config.options)),
+            metrics: metrics,
+            previous_cpu: Mutex::new(None)
+        })
+    }
+
+    fn data_points(&self) -> Result<Vec<(&'static str, &'static str, DataValue)>, String> {
+        let mut data_points = Vec::new();
+
+        match self.metrics {
+            Metrics::Cpu => {
+                let stat = try!(parse_stat(&try!(procfs::read(&self.proc_root, "stat"))));
+
+                let mut previous_cpu = self.previous_cpu.lock().unwrap();
+                if let Some(usage) = previous_cpu.as_ref().and_then(|previous| stat.cpu.usage(previous)) {
+                    for (state, percent) in usage {
+                        data_points.push(("os/cpu/usage", state, DataValue::Float(percent)));
+                    }
+                }
+                *previous_cpu = Some(stat.cpu);
+
+                data_points.push(("os/cpu/count", "online", DataValue::Integer(stat.cpu_count as i64)));
+                data_points.push(("os/processes", "running", DataValue::Integer(stat.procs_running as i64)));
+                data_points.push(("os/processes", "blocked", DataValue::Integer(stat.procs_blocked as i64)));
+            }
+            Metrics::Memory => {
+                let meminfo = try!(parse_meminfo(&try!(procfs::read(&self.proc_root, "meminfo"))));
+                let value = |name: &str| meminfo.get(name).cloned().ok_or_else(|| format!("no {} in meminfo", name));
+
+                let total = try!(value("MemTotal"));
+                let free = try!(value("MemFree"));
+                let buffers = try!(value("Buffers"));
+                let cached = try!(value("Cached"));
+                let used = total.saturating_sub(free + buffers + cached);
+                let swap_total = try!(value("SwapTotal"));
+                let swap_free = try!(value("SwapFree"));
+
+                data_points.push(("os/memory", "total", DataValue::Integer(total as i64)));
+                data_points.push(("os/memory", "free", DataValue::Integer(free as i64)));
+                if let Ok(available) = value("MemAvailable") {
+                    data_points.push(("os/memory", "available", DataValue::Integer(available as i64)));
+                }
+                data_points.push(("os/memory", "buffers", DataValue::Integer(buffers as i64)));
+                data_points.push(("os/memory", "cached", DataValue::Integer(cached as i64)));
+                data_points.push(("os/memory", "used", DataValue::Integer(used as i64)));
+                if total > 0 {
+                    data_points.push(("os/memory", "used_percent", DataValue::Float(used as f64 * 100.0 / total as f64)));
+                }
+                data_points.push(("os/swap", "total", DataValue::Integer(swap_total as i64)));
+                data_points.push(("os/swap", "free", DataValue::Integer(swap_free as i64)));
+                data_points.push(("os/swap", "used", DataValue::Integer(swap_total.saturating_sub(swap_free) as i64)));
+            }
+            Metrics::Load => {
+                let (load1, load5, load15) = try!(parse_loadavg(&try!(procfs::read(&self.proc_root, "loadavg"))));
+                data_points.push(("os/load", "1min", DataValue::Float(load1)));
+                data_points.push(("os/load", "5min", DataValue::Float(load5)));
+                data_points.push(("os/load", "15min", DataValue::Float(load15)));
+            }
+            Metrics::Uptime => {
+                let uptime = try!(parse_uptime(&try!(procfs::read(&self.proc_root, "uptime"))));
+                data_points.push(("os/uptime", "seconds", DataValue::Float(uptime)));
+            }
+        }
+
+        Ok(data_points)
+    }
+}
+
+impl Probe for SystemProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, collector: &mut Collect) -> Result<(), String> {
+        let mut collector = collector;
+        for (path, component, value) in try!(self.data_points()) {
+            try!(collector.collect(&self.location, path, component, value).map_err(|err| err.to_string()));
+        }
+        Ok(())
+    }
+
+    fn run_mode(&self) -> RunMode {
+        RunMode::SharedThread
+    }
+}
+
+pub struct SystemModule {
+    schedule: Vec<ProbeRunPlan>
+}
+
+impl Module for SystemModule {
+    fn name(&self) -> &str {
+        "system"
+    }
+
+    fn schedule(&self) -> Iter<ProbeRunPlan> {
+        self.schedule.iter()
+    }
+}
+
+pub fn init(location: &str, config: &ModuleConfig) -> Result<Box<Module>, ConfigError> {
+    let mut schedule = Vec::new();
+
+    for probe in &config.probes {
+        schedule.push(ProbeRunPlan {
+            every: probe.every,
+            timeout: probe.timeout,
+            probe: Arc::new(try!(SystemProbe::new(location, config, probe)))
+        });
+    }
+
+    Ok(Box::new(SystemModule {
+        schedule: schedule
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::test_support::{VecCollector, point, fixture, fixture_config};
+    pub use super::super::Probe;
+    pub use messaging::DataValue;
+
+    fn probe(name: &str) -> SystemProbe {
+        let config = fixture_config("system", name, "");
+        SystemProbe::new("web-01", &config, &config.probes[0]).unwrap()
+    }
+
+    fn run(probe: &SystemProbe) -> Vec<(String, String, DataValue)> {
+        let mut collector = VecCollector::new();
+        probe.run(&mut collector).unwrap();
+        collector.values
+    }
+
+    #[test]
+    fn should_report_cpu_usage_over_probe_interval() {
+        let mut probe = probe("cpu");
+
+        // no usage until second run
+        assert_eq!(run(&probe), vec![
+            point("os/cpu/count", "online", DataValue::Integer(2)),
+            point("os/processes", "running", DataValue::Integer(1)),
+            point("os/processes", "blocked", DataValue::Integer(0))
+        ]);
+
+        probe.proc_root = fixture("proc/2");
+        let values = run(&probe);
+        assert_eq!(&values[..8], &[
+            point("os/cpu/usage", "user", DataValue::Float(50.0)),
+            point("os/cpu/usage", "nice", DataValue::Float(0.0)),
+            point("os/cpu/usage", "system", DataValue::Float(20.0)),
+            point("os/cpu/usage", "idle", DataValue::Float(25.0)),
+            point("os/cpu/usage", "iowait", DataValue::Float(5.0)),
+            point("os/cpu/usage", "irq", DataValue::Float(0.0)),
+            point("os/cpu/usage", "softirq", DataValue::Float(0.0)),
+            point("os/cpu/usage", "steal", DataValue::Float(0.0))
+        ][..]);
+        assert_eq!(values[9], point("os/processes", "running", DataValue::Integer(3)));
+    }
+
+    #[test]
+    fn should_skip_cpu_usage_after_counter_reset() {
+        let mut probe = probe("cpu");
+        probe.proc_root = fixture("proc/2");
+        run(&probe);
+
+        probe.proc_root = fixture("proc/1");
+        assert_eq!(run(&probe).len(), 3);
+    }
+
+    #[test]
+    fn should_report_memory() {
+        let values = run(&probe("memory"));
+        assert_eq!(values[0], point("os/memory", "total", DataValue::Integer(16318852 * 1024)));
+        assert_eq!(values[2], point("os/memory", "available", DataValue::Integer(10385744 * 1024)));
+        assert_eq!(values[5], point("os/memory", "used", DataValue::Integer((16318852 - 1189308 - 868508 - 8313148) * 1024)));
+        assert_eq!(values[9], point("os/swap", "used", DataValue::Integer((8388604 - 8375548) * 1024)));
+    }
+
+    #[test]
+    fn should_report_load_and_uptime() {
+        assert_eq!(run(&probe("load")), vec![
+            point("os/load", "1min", DataValue::Float(0.52)),
+            point("os/load", "5min", DataValue::Float(0.58)),
+            point("os/load", "15min", DataValue::Float(0.59))
+        ]);
+        assert_eq!(run(&probe("uptime")), vec![point("os/uptime", "seconds", DataValue::Float(350735.47))]);
+    }
+
+    #[test]
+    fn should_fail_on_missing_proc_file() {
+        let mut probe = probe("load");
+        probe.proc_root = fixture("proc/2");
+        let mut collector = VecCollector::new();
+        assert!(probe.run(&mut collector).unwrap_err().starts_with("failed to read"));
+    }
+}
@@ -0,0 +1,340 @@
+use std::sync::{Arc, mpsc};
+use std::slice::Iter;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+use regex::Regex;
+
+use program;
+use sender::Collect;
+use messaging::DataValue;
+use config::{ModuleConfig, ConfigError};
+use super::{RunMode, ProbeRunPlan, Probe, Module, millis};
+
+/// Most of banner read when looking for expected response
+const MAX_BANNER_SIZE: usize = 4096;
+
+/// Outcome of checking single target
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetResult {
+    /// None if connection could not be established
+    pub connect_ms: Option<f64>,
+    pub error: Option<String>
+}
+
+/// Reads from stream until expected pattern is found; gives up on end of stream or timeout
+fn read_expected(stream: &mut TcpStream, expect: &Regex, deadline: Instant) -> Result<(), String> {
+    let mut banner = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err("timed out waiting for expected response".to_string())
+        }
+        try!(stream.set_read_timeout(Some(deadline - now)).map_err(|err| err.to_string()));
+
+        let read = try!(stream.read(&mut buf).map_err(|err| format!("failed to read response: {}", err)));
+        banner.extend_from_slice(&buf[..read]);
+        if expect.is_match(&String::from_utf8_lossy(&banner)) {
+            return Ok(())
+        }
+        if read == 0 || banner.len() >= MAX_BANNER_SIZE {
+            return Err(format!("response did not match '{}': {:?}", expect, String::from_utf8_lossy(&banner)))
+        }
+    }
+}
+
+/// Connects to host:port target, optionally sends data and checks response; gives up once timeout has passed
+pub fn check_target(target: &str, send: Option<&str>, expect: Option<&Regex>, timeout: Duration) -> TargetResult {
+    let deadline = Instant::now() + timeout;
+
+    let addresses = match target.to_socket_addrs() {
+        Ok(addresses) => addresses,
+        Err(err) => return TargetResult { connect_ms: None, error: Some(format!("failed to resolve {}: {}", target, err)) }
+    };
+
+    let mut error = format!("no addresses found for {}", target);
+    for address in addresses {
+        let connecting = Instant::now();
+        if connecting >= deadline {
+            error = "timed out connecting".to_string();
+            break
+        }
+        let mut stream = match TcpStream::connect_timeout(&address, deadline - connecting) {
+            Ok(stream) => stream,
+            Err(err) => {
+                error = format!("failed to connect to {}: {}", address, err);
+                continue
+            }
+        };
+        let connect_ms = millis(connecting.elapsed());
+
+        let result = match send {
+            Some(send) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    Err("timed out sending".to_string())
+                } else {
+                    stream.set_write_timeout(Some(deadline - now))
+                        .and_then(|_| stream.write_all(send.as_bytes()))
+                        .map_err(|err| format!("failed to send: {}", err))
+                }
+            }
+            None => Ok(())
+        }.and_then(|_| match expect {
+            Some(expect) => read_expected(&mut stream, expect, deadline),
+            None => Ok(())
+        });
+
+        return TargetResult {
+            connect_ms: Some(connect_ms),
+            error: result.err()
+        }
+    }
+
+    TargetResult {
+        connect_ms: None,
+        error: Some(error)
+    }
+}
+
+pub struct TcpProbe {
+    name: String,
+    location: String,
+    path: String,
+    targets: Vec<String>,
+    send: Option<String>,
+    expect: Option<Regex>,
+    target_timeout: Duration
+}
+
+impl TcpProbe {
+    /// Checks all targets concurrently; targets not checked within target timeout are reported as failed
+    pub fn check_targets(&self) -> Vec<TargetResult> {
+        let (sender, receiver) = mpsc::channel();
+        for (index, target) in self.targets.iter().enumerate() {
+            let sender = sender.clone();
+            let target = target.clone();
+            let send = self.send.clone();
+            let expect = self.expect.clone();
+            let timeout = self.target_timeout;
+
+            // check gives up by target timeout so thread does not outlive the run unless name resolution hangs
+            program::spawn(&format!("producer/probe/{}/{}", self.name, target), move || {
+                let result = check_target(&target, send.as_ref().map(|send| send.as_str()), expect.as_ref(), timeout);
+                let _ = sender.send((index, result));
+            });
+        }
+        drop(sender);
+
+        let mut results = vec![None; self.targets.len()];
+        let deadline = Instant::now() + self.target_timeout;
+        for _ in 0..self.targets.len() {
+            let now = Instant::now();
+            if now >= deadline {
+                break
+            }
+            match receiver.recv_timeout(deadline - now) {
+                Ok((index, result)) => results[index] = Some(result),
+                Err(_) => break
+            }
+        }
+
+        results.into_iter().map(|result| result.unwrap_or_else(|| TargetResult {
+            connect_ms: None,
+            error: Some("timed out connecting".to_string())
+        })).collect()
+    }
+}
+
+impl Probe for TcpProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, collector: &mut Collect) -> Result<(), String> {
+        let mut collector = collector;
+        for (target, result) in self.targets.iter().zip(self.check_targets()) {
+            if let Some(ref error) = result.error {
+                debug!("Probe '{}': {}: {}", self.name, target, error);
+            }
+
+            let path = format!("{}/{}", self.path, target);
+            try!(collector.collect(&self.location, &path, "success", DataValue::Bool(result.error.is_none())).map_err(|err| err.to_string()));
+            if let Some(connect_ms) = result.connect_ms {
+                try!(collector.collect(&self.location, &path, "connect_ms", DataValue::Float(connect_ms)).map_err(|err| err.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    fn run_mode(&self) -> RunMode {
+        RunMode::DedicatedThread
+    }
+}
+
+pub struct TcpModule {
+    schedule: Vec<ProbeRunPlan>
+}
+
+impl Module for TcpModule {
+    fn name(&self) -> &str {
+        "tcp"
+    }
+
+    fn schedule(&self) -> Iter<ProbeRunPlan> {
+        self.schedule.iter()
+    }
+}
+
+/// Each probe checks list of targets and reports data points on path tcp/<probe>/<host:port>
+pub fn init(location: &str, config: &ModuleConfig) -> Result<Box<Module>, ConfigError> {
+    let mut schedule = Vec::new();
+
+    for probe in &config.probes {
+        let options = &probe.options;
+        let targets = match try!(options.strings("targets")) {
+            Some(ref targets) if targets.is_empty() => return Err(options.error("targets", "at least one target is required")),
+            Some(targets) => targets,
+            None => return Err(options.error("targets", "missing required key"))
+        };
+        for target in &targets {
+            if target.rfind(':').and_then(|colon| target[colon + 1..].parse::<u16>().ok()).is_none() {
+                return Err(options.error("targets", format!("expected host:port but got '{}'", target)))
+            }
+        }
+
+        let expect = match try!(options.string("expect")) {
+            Some(pattern) => Some(try!(Regex::new(pattern).map_err(|err| options.error("expect", format!("invalid pattern '{}': {}", pattern, err))))),
+            None => None
+        };
+
+        // leave time to report results before probe run times out
+        let target_timeout = match try!(options.duration("target_timeout")) {
+            Some(target_timeout) if target_timeout >= probe.timeout => return Err(options.error("target_timeout", "must be shorter than probe timeout")),
+            Some(target_timeout) => target_timeout,
+            None => probe.timeout / 2
+        };
+
+        schedule.push(ProbeRunPlan {
+            every: probe.every,
+            timeout: probe.timeout,
+            probe: Arc::new(TcpProbe {
+                name: format!("{}/{}", config.name, probe.name),
+                location: location.to_string(),
+                path: format!("tcp/{}", probe.name),
+                targets: targets,
+                send: try!(options.string("send")).map(|send| send.to_string()),
+                expect: expect,
+                target_timeout: target_timeout.to_std().expect("positive target timeout")
+            })
+        });
+    }
+
+    Ok(Box::new(TcpModule {
+        schedule: schedule
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::super::test_support::VecCollector;
+    pub use super::super::{Probe, Module};
+    pub use messaging::DataValue;
+    pub use config::Config;
+    pub use std::path::Path;
+    pub use std::net::TcpListener;
+    pub use std::io::{Read, Write};
+    pub use std::thread;
+    pub use std::time::{Duration, Instant};
+
+    /// Accepts single connection, answers with reply after receiving request if given
+    fn serve(request: &'static str, reply: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = vec![0; request.len()];
+            stream.read_exact(&mut received).unwrap();
+            assert_eq!(received, request.as_bytes());
+            stream.write_all(reply.as_bytes()).unwrap();
+        });
+        port
+    }
+
+    fn closed_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    fn probe(options: &str) -> Arc<Probe> {
+        let config = Config::parse(Path::new("agent.toml"), &format!("location = \"web-01\"\n[modules.tcp.probes.services]\nevery = \"10s\"\ntimeout = \"2s\"\n{}", options)).unwrap();
+        let module = init(&config.location, &config.modules[0]).unwrap();
+        let plan = module.schedule().next().unwrap();
+        plan.probe.clone()
+    }
+
+    fn run(probe: &Arc<Probe>) -> Vec<(String, String, DataValue)> {
+        let mut collector = VecCollector::new();
+        probe.run(&mut collector).unwrap();
+        collector.values
+    }
+
+    #[test]
+    fn should_report_connectivity_of_targets() {
+        let open = serve("", "");
+        let closed = closed_port();
+        let values = run(&probe(&format!("targets = [\"127.0.0.1:{}\", \"127.0.0.1:{}\"]", open, closed)));
+
+        assert_eq!(values.iter().map(|&(ref path, ref component, _)| (path.clone(), component.as_str())).collect::<Vec<_>>(), vec![
+            (format!("tcp/services/127.0.0.1:{}", open), "success"),
+            (format!("tcp/services/127.0.0.1:{}", open), "connect_ms"),
+            (format!("tcp/services/127.0.0.1:{}", closed), "success")
+        ]);
+        assert_eq!(values[0].2, DataValue::Bool(true));
+        assert_eq!(values[2].2, DataValue::Bool(false));
+    }
+
+    #[test]
+    fn should_send_and_expect_banner() {
+        let target = format!("127.0.0.1:{}", serve("PING\r\n", "+PONG\r\n"));
+        let regex = Regex::new("^\\+PONG").unwrap();
+        let result = check_target(&target, Some("PING\r\n"), Some(&regex), Duration::from_secs(1));
+        assert!(result.connect_ms.is_some());
+        assert_eq!(result.error, None);
+
+        let target = format!("127.0.0.1:{}", serve("PING\r\n", "-ERR unknown\r\n"));
+        let result = check_target(&target, Some("PING\r\n"), Some(&regex), Duration::from_secs(1));
+        assert!(result.connect_ms.is_some());
+        assert!(result.error.unwrap().contains("did not match"));
+    }
+
+    #[test]
+    fn should_not_let_unresponsive_targets_stall_others() {
+        // listeners that accept connections but never answer
+        let silent: Vec<TcpListener> = (0..3).map(|_| TcpListener::bind("127.0.0.1:0").unwrap()).collect();
+        let mut targets: Vec<String> = silent.iter().map(|listener| format!("\"127.0.0.1:{}\"", listener.local_addr().unwrap().port())).collect();
+        let answering = serve("", "SSH-2.0-OpenSSH\r\n");
+        targets.push(format!("\"127.0.0.1:{}\"", answering));
+
+        let probe = probe(&format!("targets = [{}]\nexpect = \"^SSH-\"\ntarget_timeout = \"500ms\"", targets.join(", ")));
+        let start = Instant::now();
+        let values = run(&probe);
+        assert!(start.elapsed() < Duration::from_millis(1500));
+
+        let success: Vec<&DataValue> = values.iter().filter(|&&(_, ref component, _)| component == "success").map(|&(_, _, ref value)| value).collect();
+        assert_eq!(success, vec![&DataValue::Bool(false), &DataValue::Bool(false), &DataValue::Bool(false), &DataValue::Bool(true)]);
+    }
+
+    #[test]
+    fn should_reject_invalid_options() {
+        let config = |options: &str| Config::parse(Path::new("agent.toml"), &format!("location = \"web-01\"\n[modules.tcp.probes.services]\nevery = \"10s\"\n{}", options)).unwrap().modules.remove(0);
+
+        assert!(init("web-01", &config("")).is_err());
+        assert!(init("web-01", &config("targets = []")).is_err());
+        assert!(init("web-01", &config("targets = [\"localhost\"]")).is_err());
+        assert!(init("web-01", &config("targets = [\"localhost:22\"]\nexpect = \"(\"")).is_err());
+        assert!(init("web-01", &config("targets = [\"localhost:22\"]\ntarget_timeout = \"10s\"")).is_err());
+        assert!(init("web-01", &config("targets = [\"localhost:22\", \"[::1]:22\"]\nsend = \"QUIT\\r\\n\"")).is_ok());
+    }
+}
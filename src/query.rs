@@ -0,0 +1,314 @@
+use std::sync::{Arc, Mutex};
+use std::io::Read;
+use std::collections::BTreeMap;
+
+use nanomsg::{Socket, Protocol};
+use nanomsg::endpoint::Endpoint;
+use url::Url;
+
+use program::{self, JoinHandle};
+use messaging::*;
+use storage::{Storage, StorageError, SeriesKey, timestamp_to_nanos, nanos_to_timestamp};
+use receiver::ReceiverError;
+
+/// Matches text against pattern where `*` matches any sequence of characters and `?` matches single character
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn numeric(value: &DataValue) -> Option<f64> {
+    match value {
+        &DataValue::Integer(value) => Some(value as f64),
+        &DataValue::Float(value) => Some(value),
+        &DataValue::Bool(value) => Some(if value { 1.0 } else { 0.0 }),
+        &DataValue::Text(_) => None
+    }
+}
+
+/// Aggregates points of single bucket; None if aggregation has no value for given points
+pub fn aggregate(aggregation: Aggregation, points: &[(i64, DataValue)]) -> Option<DataValue> {
+    let numbers: Vec<(i64, f64)> = points.iter().filter_map(|&(timestamp, ref value)| numeric(value).map(|value| (timestamp, value))).collect();
+
+    match aggregation {
+        Aggregation::Count => Some(DataValue::Integer(points.len() as i64)),
+        Aggregation::Last => points.last().map(|&(_, ref value)| value.clone()),
+        _ if numbers.is_empty() => None,
+        Aggregation::Min => Some(DataValue::Float(numbers.iter().map(|&(_, value)| value).fold(numbers[0].1, f64::min))),
+        Aggregation::Max => Some(DataValue::Float(numbers.iter().map(|&(_, value)| value).fold(numbers[0].1, f64::max))),
+        Aggregation::Sum => Some(DataValue::Float(numbers.iter().map(|&(_, value)| value).fold(0.0, |sum, value| sum + value))),
+        Aggregation::Avg => Some(DataValue::Float(numbers.iter().map(|&(_, value)| value).fold(0.0, |sum, value| sum + value) / numbers.len() as f64)),
+        Aggregation::Rate => {
+            let (first_timestamp, first) = numbers[0];
+            let (last_timestamp, last) = numbers[numbers.len() - 1];
+            if last_timestamp == first_timestamp {
+                None
+            } else {
+                Some(DataValue::Float((last - first) / ((last_timestamp - first_timestamp) as f64 / 1_000_000_000.0)))
+            }
+        }
+    }
+}
+
+/// Runs query against storage aggregating points of each matching series in buckets of requested step
+pub fn execute(storage: &Storage, request: &QueryRequest) -> Result<QueryResponse, StorageError> {
+    let from = try!(timestamp_to_nanos(&request.from));
+    let step = request.step.num_nanoseconds().unwrap_or(i64::max_value());
+
+    let keys: Vec<SeriesKey> = storage.series_keys().into_iter()
+        .filter(|key| glob_match(&request.location, &key.location) && glob_match(&request.path, &key.path) && glob_match(&request.component, &key.component))
+        .cloned()
+        .collect();
+
+    let mut series = Vec::new();
+    for key in keys {
+        let mut buckets: BTreeMap<i64, Vec<(i64, DataValue)>> = BTreeMap::new();
+        for (timestamp, value) in try!(storage.query(&key, &request.from, &request.to)) {
+            let timestamp = try!(timestamp_to_nanos(&timestamp));
+            let bucket = from + (timestamp - from) / step * step;
+            buckets.entry(bucket).or_insert_with(Vec::new).push((timestamp, value));
+        }
+
+        for aggregation in request.aggregations.iter() {
+            series.push(SeriesData {
+                location: key.location.clone(),
+                path: key.path.clone(),
+                component: key.component.clone(),
+                aggregation: *aggregation,
+                points: buckets.iter()
+                    .filter_map(|(bucket, points)| aggregate(*aggregation, points).map(|value| (nanos_to_timestamp(*bucket), value)))
+                    .collect()
+            });
+        }
+    }
+
+    Ok(QueryResponse {
+        error: None,
+        series: series
+    })
+}
+
+pub struct QueryServer {
+    thread: JoinHandle<()>,
+    endpoint: Endpoint
+}
+
+impl QueryServer {
+    pub fn start(listen_url: Url, storage: Arc<Mutex<Storage>>) -> Result<QueryServer, ReceiverError> {
+        let mut socket = try!(Socket::new(Protocol::Rep));
+
+        info!("Listening for queries on: {}", &listen_url);
+        let endpoint = try!(socket.bind(&listen_url.serialize()[..]));
+
+        let thread = program::spawn("query", move || {
+            loop {
+                let mut data = Vec::new();
+                if let Err(err) = socket.read_to_end(&mut data) {
+                    info!("Query thread finished: {}", err);
+                    break;
+                }
+
+                let (topic, response) = match split_message(data) {
+                    Ok((header, body)) => {
+                        let response = match decode_message_body::<QueryRequest>(&header, &body) {
+                            Ok(request) => {
+                                debug!("Executing query: {:?}", request);
+                                execute(&*storage.lock().unwrap(), &request).unwrap_or_else(|err| {
+                                    error!("Failed to execute query: {}", err);
+                                    QueryResponse::error(err.to_string())
+                                })
+                            },
+                            Err(err) => QueryResponse::error(err.to_string())
+                        };
+                        (header.topic, response)
+                    },
+                    Err(err) => ("".to_string(), QueryResponse::error(err.to_string()))
+                };
+
+                // responses are always JSON encoded as that is the only encoding query messages support
+                if let Err(err) = socket.send_message(topic, response, Encoding::Json) {
+                    error!("Failed to send query response: {}", err);
+                }
+            }
+        });
+
+        Ok(QueryServer {
+            thread: thread,
+            endpoint: endpoint
+        })
+    }
+
+    pub fn stop(self) {
+        let QueryServer {thread, endpoint: _endpoint} = self;
+        info!("Stopping query server...");
+        //NOTE: this will make blocked receive fail with IO error
+        Socket::terminate();
+        debug!("Joining query thread...");
+        thread.join().ok();
+        info!("Query server done");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use messaging::*;
+    pub use storage::Storage;
+    pub use nanomsg::{Socket, Protocol};
+    pub use std::sync::{Arc, Mutex};
+    pub use url::Url;
+    pub use chrono::*;
+    pub use tempdir::TempDir;
+
+    pub fn at(seconds: i64) -> DateTime<UTC> {
+        UTC.timestamp(1455200000 + seconds, 0)
+    }
+
+    pub fn store(storage: &mut Storage, location: &str, component: &str, seconds: i64, value: DataValue) {
+        storage.store(&RawDataPoint {
+            location: location.to_string(),
+            path: "os/cpu/usage".to_string(),
+            component: component.to_string(),
+            timestamp: at(seconds),
+            value: value
+        }).unwrap();
+    }
+
+    pub fn request(location: &str, component: &str, aggregations: Vec<Aggregation>) -> QueryRequest {
+        QueryRequest {
+            location: location.to_string(),
+            path: "os/*".to_string(),
+            component: component.to_string(),
+            from: at(0),
+            to: at(20),
+            step: Duration::seconds(10),
+            aggregations: aggregations
+        }
+    }
+
+    #[test]
+    fn glob_match_should_support_wildcards() {
+        assert!(glob_match("os/cpu/usage", "os/cpu/usage"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("os/*", "os/cpu/usage"));
+        assert!(glob_match("os/*/usage", "os/cpu/usage"));
+        assert!(glob_match("*u*a*", "os/cpu/usage"));
+        assert!(glob_match("?ser", "user"));
+        assert!(!glob_match("?ser", "ser"));
+        assert!(!glob_match("os/*/usage", "os/cpu/load"));
+        assert!(!glob_match("os", "os/cpu"));
+        assert!(glob_match("zaż*", "zażółć"));
+    }
+
+    #[test]
+    fn aggregate_should_compute_each_aggregation() {
+        let points = vec![(0, DataValue::Integer(4)), (1_000_000_000, DataValue::Float(1.0)), (2_000_000_000, DataValue::Integer(10))];
+
+        assert_eq!(aggregate(Aggregation::Min, &points), Some(DataValue::Float(1.0)));
+        assert_eq!(aggregate(Aggregation::Max, &points), Some(DataValue::Float(10.0)));
+        assert_eq!(aggregate(Aggregation::Sum, &points), Some(DataValue::Float(15.0)));
+        assert_eq!(aggregate(Aggregation::Avg, &points), Some(DataValue::Float(5.0)));
+        assert_eq!(aggregate(Aggregation::Count, &points), Some(DataValue::Integer(3)));
+        assert_eq!(aggregate(Aggregation::Last, &points), Some(DataValue::Integer(10)));
+        assert_eq!(aggregate(Aggregation::Rate, &points), Some(DataValue::Float(3.0)));
+    }
+
+    #[test]
+    fn aggregate_should_skip_non_numeric_values() {
+        let points = vec![(0, DataValue::Text("ok".to_string())), (1_000_000_000, DataValue::Text("failed".to_string()))];
+
+        assert_eq!(aggregate(Aggregation::Avg, &points), None);
+        assert_eq!(aggregate(Aggregation::Rate, &points), None);
+        assert_eq!(aggregate(Aggregation::Count, &points), Some(DataValue::Integer(2)));
+        assert_eq!(aggregate(Aggregation::Last, &points), Some(DataValue::Text("failed".to_string())));
+        assert_eq!(aggregate(Aggregation::Rate, &[(0, DataValue::Integer(1))]), None);
+    }
+
+    #[test]
+    fn execute_should_aggregate_matching_series_in_steps() {
+        let dir = TempDir::new("dms-query").unwrap();
+        let mut storage = Storage::open(dir.path()).unwrap();
+
+        for second in 0..20 {
+            store(&mut storage, "myserver", "user", second, DataValue::Integer(second));
+            store(&mut storage, "myserver", "system", second, DataValue::Integer(100));
+            store(&mut storage, "otherserver", "user", second, DataValue::Integer(0));
+        }
+
+        let response = execute(&storage, &request("my*", "user", vec![Aggregation::Avg, Aggregation::Max])).unwrap();
+        assert_eq!(response.error, None);
+        assert_eq!(response.series, vec![
+            SeriesData {
+                location: "myserver".to_string(),
+                path: "os/cpu/usage".to_string(),
+                component: "user".to_string(),
+                aggregation: Aggregation::Avg,
+                points: vec![(at(0), DataValue::Float(4.5)), (at(10), DataValue::Float(14.5))]
+            },
+            SeriesData {
+                location: "myserver".to_string(),
+                path: "os/cpu/usage".to_string(),
+                component: "user".to_string(),
+                aggregation: Aggregation::Max,
+                points: vec![(at(0), DataValue::Float(9.0)), (at(10), DataValue::Float(19.0))]
+            }
+        ]);
+
+        let response = execute(&storage, &request("*", "*", vec![Aggregation::Count])).unwrap();
+        assert_eq!(response.series.len(), 3);
+        for series in response.series {
+            assert_eq!(series.points, vec![(at(0), DataValue::Integer(10)), (at(10), DataValue::Integer(10))]);
+        }
+    }
+
+    #[test]
+    fn query_server_should_respond_to_requests() {
+        let dir = TempDir::new("dms-query").unwrap();
+        let storage = Arc::new(Mutex::new(Storage::open(dir.path()).unwrap()));
+        for second in 0..20 {
+            store(&mut *storage.lock().unwrap(), "myserver", "user", second, DataValue::Integer(second));
+        }
+
+        let _server = QueryServer::start(Url::parse("ipc:///tmp/test-query.ipc").unwrap(), storage.clone()).unwrap();
+
+        let mut req = Socket::new(Protocol::Req).unwrap();
+        let mut _endpoint = req.connect("ipc:///tmp/test-query.ipc").unwrap();
+
+        req.send_message("q1", request("myserver", "user", vec![Aggregation::Rate]), Encoding::Json).unwrap();
+        let (topic, response): (String, QueryResponse) = req.receive_message().unwrap();
+        assert_eq!(topic, "q1".to_string());
+        assert_eq!(response.series.len(), 1);
+        assert_eq!(response.series[0].points, vec![(at(0), DataValue::Float(1.0)), (at(10), DataValue::Float(1.0))]);
+
+        req.send_message("q2", RawDataPoint {
+            location: "myserver".to_string(),
+            path: "os/cpu/usage".to_string(),
+            component: "user".to_string(),
+            timestamp: at(0),
+            value: DataValue::Integer(1)
+        }, Encoding::Json).unwrap();
+        let (_, response): (String, QueryResponse) = req.receive_message().unwrap();
+        assert_eq!(response.error, Some("failed to receive message caused by: expected message of type QueryRequest but got RawDataPoint".to_string()));
+    }
+}
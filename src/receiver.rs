@@ -0,0 +1,199 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::io::Read;
+
+use nanomsg::{Socket, Protocol, Error as NanoError};
+use nanomsg::endpoint::Endpoint;
+use url::Url;
+
+use program::{self, JoinHandle};
+use messaging::*;
+use storage::Storage;
+
+#[derive(Debug)]
+pub enum ReceiverError {
+    Connection(NanoError),
+    Configuration(NanoError),
+    Transport(NanoError)
+}
+
+impl From<NanoError> for ReceiverError {
+    fn from(err: NanoError) -> ReceiverError {
+        match err {
+            NanoError::ProtocolNotSupported => ReceiverError::Configuration(err),
+            NanoError::ProtocolNotAvailable => ReceiverError::Configuration(err),
+            NanoError::AddressFamilyNotSupported => ReceiverError::Configuration(err),
+            NanoError::AddressInUse => ReceiverError::Connection(err),
+            NanoError::NetworkDown => ReceiverError::Connection(err),
+            _ => ReceiverError::Transport(err)
+        }
+    }
+}
+
+impl Error for ReceiverError {
+    fn description(&self) -> &str {
+        match self {
+            &ReceiverError::Connection(_) => "Failed to bind receiver",
+            &ReceiverError::Configuration(_) => "Receiver configuration error",
+            &ReceiverError::Transport(_) => "Transport error",
+        }
+    }
+}
+
+impl fmt::Display for ReceiverError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ReceiverError::Connection(err) => write!(f, "{}: {}", self.description(), err),
+            &ReceiverError::Configuration(err) => write!(f, "{}: {}", self.description(), err),
+            &ReceiverError::Transport(err) => write!(f, "{}: {}", self.description(), err),
+        }
+    }
+}
+
+// Single raw data point and batch messages are both accepted
+fn decode_raw_data_points(data: Vec<u8>) -> Result<(String, Vec<RawDataPoint>), ReceivingError> {
+    let (header, body) = try!(split_message(data));
+
+    let raw_data_points = match header.data_type {
+        DataType::RawDataPointBatch => {
+            let batch: RawDataPointBatch = try!(decode_message_body(&header, &body));
+            batch.into_raw_data_points()
+        },
+        _ => {
+            let raw_data_point: RawDataPoint = try!(decode_message_body(&header, &body));
+            vec![raw_data_point]
+        }
+    };
+
+    Ok((header.topic, raw_data_points))
+}
+
+pub struct DataReceiver {
+    thread: JoinHandle<()>,
+    endpoint: Endpoint
+}
+
+impl DataReceiver {
+    pub fn start(listen_url: Url, storage: Arc<Mutex<Storage>>) -> Result<DataReceiver, ReceiverError> {
+        let mut socket = try!(Socket::new(Protocol::Pull));
+
+        info!("Listening for raw data points on: {}", &listen_url);
+        let endpoint = try!(socket.bind(&listen_url.serialize()[..]));
+
+        let thread = program::spawn("receiver", move || {
+            loop {
+                let mut data = Vec::new();
+                if let Err(err) = socket.read_to_end(&mut data) {
+                    info!("Receiver thread finished: {}", err);
+                    break;
+                }
+
+                match decode_raw_data_points(data) {
+                    Ok((topic, raw_data_points)) => {
+                        trace!("Received {} raw data points on topic '{}'", raw_data_points.len(), topic);
+                        let mut storage = storage.lock().unwrap();
+                        for raw_data_point in raw_data_points {
+                            if let Err(err) = storage.store(&raw_data_point) {
+                                error!("Failed to store raw data point: {}", err);
+                            }
+                        }
+                    },
+                    Err(err) => error!("Failed to receive raw data points: {}", err)
+                }
+            }
+
+            if let Err(err) = storage.lock().unwrap().flush() {
+                error!("Failed to flush storage: {}", err);
+            }
+        });
+
+        Ok(DataReceiver {
+            thread: thread,
+            endpoint: endpoint
+        })
+    }
+
+    pub fn stop(self) {
+        let DataReceiver {thread, endpoint: _endpoint} = self;
+        info!("Stopping receiver...");
+        debug!("Shutting down nanomsg...");
+        //NOTE: this will make blocked receive fail with IO error
+        Socket::terminate();
+        debug!("Joining receiver thread...");
+        thread.join().ok();
+        info!("Receiver done");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use messaging::*;
+    pub use storage::{Storage, SeriesKey};
+    pub use nanomsg::{Socket, Protocol};
+    pub use url::Url;
+    pub use chrono::*;
+    pub use tempdir::TempDir;
+    pub use std::thread::sleep;
+    pub use std::time::Duration;
+    pub use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn should_store_received_raw_data_points() {
+        let dir = TempDir::new("dms-receiver").unwrap();
+        let storage = Arc::new(Mutex::new(Storage::open(dir.path()).unwrap()));
+
+        let _receiver = DataReceiver::start(Url::parse("ipc:///tmp/test-receiver.ipc").unwrap(), storage.clone()).unwrap();
+
+        let mut push = Socket::new(Protocol::Push).unwrap();
+        let mut _endpoint = push.connect("ipc:///tmp/test-receiver.ipc").unwrap();
+        push.send_message("", RawDataPoint {
+            location: "myserver".to_string(),
+            path: "os/cpu/usage".to_string(),
+            component: "user".to_string(),
+            timestamp: UTC.timestamp(1455200000, 0),
+            value: DataValue::Float(0.4)
+        }, Encoding::Capnp).unwrap();
+
+        // give receiver thread time to store the message
+        sleep(Duration::from_millis(200));
+
+        assert_eq!(storage.lock().unwrap().query(&SeriesKey::new("myserver", "os/cpu/usage", "user"), &UTC.timestamp(1455200000, 0), &UTC.timestamp(1455200001, 0)).unwrap(), vec![
+            (UTC.timestamp(1455200000, 0), DataValue::Float(0.4))
+        ]);
+    }
+
+    #[test]
+    fn should_store_received_raw_data_point_batches() {
+        let dir = TempDir::new("dms-receiver").unwrap();
+        let storage = Arc::new(Mutex::new(Storage::open(dir.path()).unwrap()));
+
+        let _receiver = DataReceiver::start(Url::parse("ipc:///tmp/test-receiver-batch.ipc").unwrap(), storage.clone()).unwrap();
+
+        let mut batch = RawDataPointBatch::new("myserver", UTC.timestamp(1455200000, 0));
+        for &(component, value) in &[("user", 0.4), ("sys", 0.2)] {
+            batch.push(RawDataPoint {
+                location: "myserver".to_string(),
+                path: "os/cpu/usage".to_string(),
+                component: component.to_string(),
+                timestamp: UTC.timestamp(1455200000, 0),
+                value: DataValue::Float(value)
+            }).unwrap();
+        }
+
+        let mut push = Socket::new(Protocol::Push).unwrap();
+        let mut _endpoint = push.connect("ipc:///tmp/test-receiver-batch.ipc").unwrap();
+        push.send_message("", batch, Encoding::Capnp).unwrap();
+
+        // give receiver thread time to store the message
+        sleep(Duration::from_millis(200));
+
+        let storage = storage.lock().unwrap();
+        for &(component, value) in &[("user", 0.4), ("sys", 0.2)] {
+            assert_eq!(storage.query(&SeriesKey::new("myserver", "os/cpu/usage", component), &UTC.timestamp(1455200000, 0), &UTC.timestamp(1455200001, 0)).unwrap(), vec![
+                (UTC.timestamp(1455200000, 0), DataValue::Float(value))
+            ]);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+use messaging::{RawDataPoint, RawDataPointBatch};
+
+/// Groups raw data points sharing location and timestamp into batches
+pub struct Batcher {
+    max_points: usize,
+    max_age: Duration,
+    batches: Vec<(Instant, RawDataPointBatch)>
+}
+
+impl Batcher {
+    pub fn new(max_points: usize, max_age: Duration) -> Batcher {
+        Batcher {
+            max_points: max_points,
+            max_age: max_age,
+            batches: Vec::new()
+        }
+    }
+
+    /// Adds raw data point to matching batch; returns the batch if it reached size limit
+    pub fn push(&mut self, raw_data_point: RawDataPoint) -> Option<RawDataPointBatch> {
+        let position = self.batches.iter().position(|&(_, ref batch)| batch.location == raw_data_point.location && batch.timestamp == raw_data_point.timestamp);
+
+        if let Some(index) = position {
+            self.batches[index].1.push(raw_data_point).ok().expect("matching batch to accept data point");
+            if self.batches[index].1.len() >= self.max_points {
+                return Some(self.batches.remove(index).1)
+            }
+            return None
+        }
+
+        let mut batch = RawDataPointBatch::new(raw_data_point.location.clone(), raw_data_point.timestamp);
+        batch.push(raw_data_point).ok().expect("new batch to accept data point");
+
+        if batch.len() >= self.max_points {
+            return Some(batch)
+        }
+        self.batches.push((Instant::now(), batch));
+        None
+    }
+
+    /// Takes out batches older than age limit
+    pub fn take_expired(&mut self) -> Vec<RawDataPointBatch> {
+        let now = Instant::now();
+        let max_age = self.max_age;
+        let (expired, pending): (Vec<_>, Vec<_>) = self.batches.drain(..).partition(|&(started, _)| now.duration_since(started) >= max_age);
+        self.batches = pending;
+        expired.into_iter().map(|(_, batch)| batch).collect()
+    }
+
+    pub fn take_all(&mut self) -> Vec<RawDataPointBatch> {
+        self.batches.drain(..).map(|(_, batch)| batch).collect()
+    }
+
+    /// Time left until oldest batch reaches age limit
+    pub fn next_expiry(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.batches.iter().map(|&(started, _)| {
+            let age = now.duration_since(started);
+            if age >= self.max_age { Duration::new(0, 0) } else { self.max_age - age }
+        }).min()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use messaging::*;
+    pub use chrono::*;
+    pub use std::time::Duration;
+    pub use std::thread::sleep;
+
+    pub fn raw_data_point(location: &str, second: i64) -> RawDataPoint {
+        RawDataPoint {
+            location: location.to_string(),
+            path: "cpu/usage".to_string(),
+            component: "user".to_string(),
+            timestamp: UTC.timestamp(1455200000 + second, 0),
+            value: DataValue::Float(0.4)
+        }
+    }
+
+    #[test]
+    fn should_group_points_by_location_and_timestamp() {
+        let mut batcher = Batcher::new(100, Duration::from_millis(1000));
+        assert!(batcher.push(raw_data_point("foo", 0)).is_none());
+        assert!(batcher.push(raw_data_point("bar", 0)).is_none());
+        assert!(batcher.push(raw_data_point("foo", 0)).is_none());
+        assert!(batcher.push(raw_data_point("foo", 1)).is_none());
+
+        let batches = batcher.take_all();
+        assert_eq!(batches.iter().map(|batch| (batch.location.as_str(), batch.len())).collect::<Vec<_>>(), vec![("foo", 2), ("bar", 1), ("foo", 1)]);
+        assert!(batcher.take_all().is_empty());
+    }
+
+    #[test]
+    fn should_return_full_batch() {
+        let mut batcher = Batcher::new(2, Duration::from_millis(1000));
+        assert!(batcher.push(raw_data_point("foo", 0)).is_none());
+        assert!(batcher.push(raw_data_point("bar", 0)).is_none());
+
+        let batch = batcher.push(raw_data_point("foo", 0)).unwrap();
+        assert_eq!(batch.location, "foo".to_string());
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batcher.take_all().len(), 1);
+    }
+
+    #[test]
+    fn should_expire_old_batches() {
+        let mut batcher = Batcher::new(100, Duration::from_millis(50));
+        assert!(batcher.next_expiry().is_none());
+
+        batcher.push(raw_data_point("foo", 0));
+        assert!(batcher.take_expired().is_empty());
+        assert!(batcher.next_expiry().unwrap() <= Duration::from_millis(50));
+
+        sleep(Duration::from_millis(60));
+        assert_eq!(batcher.next_expiry(), Some(Duration::new(0, 0)));
+        assert_eq!(batcher.take_expired().len(), 1);
+        assert!(batcher.next_expiry().is_none());
+    }
+}
@@ -0,0 +1,589 @@
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+use std::io::Write;
+use std::cmp;
+
+use nanomsg::{Socket, Protocol, Error as NanoError};
+use nanomsg::endpoint::Endpoint;
+use url::Url;
+use chrono::{DateTime, UTC};
+
+use program::{self, JoinHandle};
+use messaging::*;
+
+pub use self::spool::{Spool, SpoolConfig, SpoolError, OverflowPolicy};
+pub use self::queue::BackPressure;
+use self::queue::{QueueSender, PushError, PopError};
+use self::batch::Batcher;
+
+mod spool;
+mod queue;
+mod batch;
+
+/// Number of collected data points waiting to be sent before back-pressure policy applies
+const QUEUE_CAPACITY: usize = 1000;
+/// Number of data points after which batch is sent
+const MAX_BATCH_POINTS: usize = 500;
+/// Time after which batch is sent even if not full
+const MAX_BATCH_AGE_MS: u64 = 100;
+
+/// How long to wait for processor to accept message before it gets spooled
+const SEND_TIMEOUT_MS: isize = 1000;
+/// How long to wait after processor did not accept message before trying to replay spooled messages again
+const REPLAY_INTERVAL: u64 = 1;
+
+#[derive(Debug)]
+pub enum SenderError {
+    Connection(NanoError),
+    Configuration(NanoError),
+    Transport(NanoError),
+    Spool(SpoolError)
+}
+
+impl From<SpoolError> for SenderError {
+    fn from(err: SpoolError) -> SenderError {
+        SenderError::Spool(err)
+    }
+}
+
+impl From<NanoError> for SenderError {
+    fn from(err: NanoError) -> SenderError {
+        match err {
+            NanoError::ProtocolNotSupported => SenderError::Configuration(err),
+            NanoError::ProtocolNotAvailable => SenderError::Configuration(err),
+            NanoError::AddressFamilyNotSupported => SenderError::Configuration(err),
+            NanoError::ConnectionRefused => SenderError::Connection(err),
+            NanoError::AddressInUse => SenderError::Connection(err),
+            NanoError::NetworkDown => SenderError::Connection(err),
+            NanoError::NetworkUnreachable => SenderError::Connection(err),
+            NanoError::HostUnreachable => SenderError::Connection(err),
+            NanoError::ConnectionReset => SenderError::Connection(err),
+            NanoError::TimedOut => SenderError::Connection(err),
+            _ => SenderError::Transport(err)
+        }
+    }
+}
+
+impl Error for SenderError {
+    fn description(&self) -> &str {
+        match self {
+            &SenderError::Connection(_) => "Processor connecitivity issue",
+            &SenderError::Configuration(_) => "Sender configuration error",
+            &SenderError::Transport(_) => "Transport error",
+            &SenderError::Spool(_) => "Spool error",
+        }
+    }
+}
+
+impl fmt::Display for SenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &SenderError::Connection(err) => write!(f, "{}: {}", self.description(), err),
+            &SenderError::Configuration(err) => write!(f, "{}: {}", self.description(), err),
+            &SenderError::Transport(err) => write!(f, "{}: {}", self.description(), err),
+            &SenderError::Spool(ref err) => write!(f, "{}: {}", self.description(), err),
+        }
+    }
+}
+
+/// Counts of data points collected by single probe
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProbeStats {
+    pub collected: u64,
+    pub delayed: u64,
+    pub dropped: u64
+}
+
+type ProbesStats = Arc<Mutex<HashMap<String, ProbeStats>>>;
+
+struct QueuedDataPoint {
+    probe: String,
+    raw_data_point: Box<RawDataPoint>
+}
+
+pub struct Sender {
+    sink: QueueSender<QueuedDataPoint>,
+    back_pressure: BackPressure,
+    stats: ProbesStats,
+    thread: JoinHandle<()>,
+    endpoint: Endpoint
+}
+
+impl Sender {
+    pub fn start(processor_url: Url, spool_config: SpoolConfig, back_pressure: BackPressure) -> Result<Sender, SenderError> {
+        let (tx, rx) = queue::bounded(QUEUE_CAPACITY);
+
+        let mut socket = try!(Socket::new(Protocol::Push));
+        socket.set_linger(1).unwrap(); //TODO: configurable
+        try!(socket.set_send_timeout(SEND_TIMEOUT_MS));
+
+        info!("Using processor URL: {}", &processor_url);
+        let endpoint = try!(socket.connect(&processor_url.serialize()[..]));
+
+        info!("Using spool directory: {}", spool_config.dir.display());
+        let mut spool = try!(Spool::open(&spool_config));
+
+        let thread = program::spawn("sender", move || {
+            let mut batcher = Batcher::new(MAX_BATCH_POINTS, Duration::from_millis(MAX_BATCH_AGE_MS));
+            // sending to processor that is down blocks for send timeout so new batches go to spool until replay is due
+            let mut replay_due: Option<Instant> = None;
+
+            loop {
+                // wake up when oldest batch is due or to retry replaying spool
+                let replay_wait = replay_due.map(|due| {
+                    let now = Instant::now();
+                    if due > now { due - now } else { Duration::from_secs(0) }
+                });
+                let timeout = match (batcher.next_expiry(), spool.is_empty()) {
+                    (Some(expiry), true) => Some(expiry),
+                    (Some(expiry), false) => Some(cmp::min(expiry, replay_wait.unwrap_or(expiry))),
+                    (None, false) => Some(replay_wait.unwrap_or(Duration::from_secs(0))),
+                    (None, true) => None
+                };
+
+                let received = match timeout {
+                    None => rx.pop().ok_or(PopError::Disconnected).map(Some),
+                    Some(timeout) => match rx.pop_timeout(timeout) {
+                        Ok(queued) => Ok(Some(queued)),
+                        Err(PopError::Timeout) => Ok(None),
+                        Err(err) => Err(err)
+                    }
+                };
+
+                match received {
+                    Ok(Some(QueuedDataPoint { raw_data_point, .. })) => {
+                        if let Some(batch) = batcher.push(*raw_data_point) {
+                            send_batch(batch, &mut spool, &mut socket, &processor_url, &mut replay_due);
+                        }
+                    },
+                    Ok(None) => (),
+                    Err(_) => {
+                        for batch in batcher.take_all() {
+                            send_batch(batch, &mut spool, &mut socket, &processor_url, &mut replay_due);
+                        }
+                        info!("Sender thread finished: all collectors are gone");
+                        return;
+                    }
+                }
+
+                for batch in batcher.take_expired() {
+                    send_batch(batch, &mut spool, &mut socket, &processor_url, &mut replay_due);
+                }
+
+                if !spool.is_empty() && replay_due.map(|due| Instant::now() >= due).unwrap_or(true) {
+                    replay_due = if replay(&mut spool, &mut socket, &processor_url) {
+                        None
+                    } else {
+                        Some(Instant::now() + Duration::from_secs(REPLAY_INTERVAL))
+                    };
+                }
+            }
+        });
+
+        Ok(Sender {
+            sink: tx,
+            back_pressure: back_pressure,
+            stats: Arc::new(Mutex::new(HashMap::new())),
+            thread: thread,
+            endpoint: endpoint
+        })
+    }
+
+    pub fn stop(self) {
+        let Sender {sink, stats, thread, mut endpoint, ..} = self;
+        info!("Stopping sender...");
+        for (probe, stats) in stats.lock().unwrap().iter().filter(|&(_, stats)| stats.delayed > 0 || stats.dropped > 0) {
+            warn!("Probe '{}' had {} of {} collected data points delayed and {} dropped", probe, stats.delayed, stats.collected, stats.dropped);
+        }
+        //NOTE: all collectors needs to be dropped as well before thread will join
+        drop(sink);
+        debug!("Shutting down nanomsg endpoint...");
+        //endpoint.shutdown().expect("sender endpoint shutdown");
+        Socket::terminate();
+        debug!("Joining sender thread...");
+        thread.join().ok();
+        info!("Sender done");
+    }
+
+    pub fn collector(&self) -> Collector {
+        Collector {
+            timestamp: UTC::now(),
+            probe: String::new(),
+            sink: self.sink.clone(),
+            back_pressure: self.back_pressure,
+            stats: self.stats.clone(),
+            run_stats: ProbeStats::default()
+        }
+    }
+
+    /// Data point counts per probe since start
+    #[allow(dead_code)]
+    pub fn stats(&self) -> HashMap<String, ProbeStats> {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+// Sends batch to processor or spools it if processor is not accepting messages or there are older messages to replay first;
+// failed send delays next replay attempt
+fn send_batch(batch: RawDataPointBatch, spool: &mut Spool, socket: &mut Socket, processor_url: &Url, replay_due: &mut Option<Instant>) {
+    trace!("Sending batch of {} raw data points for location '{}'", batch.len(), batch.location);
+    let message = match encode_message("", batch, Encoding::Capnp) {
+        Ok(message) => message,
+        Err(err) => {
+            error!("Failed to encode raw data point batch: {}", err);
+            return
+        }
+    };
+
+    if !spool.is_empty() || socket.write_all(&message).is_err() {
+        if spool.is_empty() {
+            warn!("Processor at '{}' is not accepting messages; spooling", processor_url);
+            *replay_due = Some(Instant::now() + Duration::from_secs(REPLAY_INTERVAL));
+        }
+        if let Err(err) = spool.push(&message) {
+            error!("Failed to spool raw data point batch: {}", err);
+        }
+    }
+}
+
+// Sends spooled messages in order until spool is empty or processor stops accepting them; false if messages are left in spool
+fn replay(spool: &mut Spool, socket: &mut Socket, processor_url: &Url) -> bool {
+    let mut replayed = 0;
+    let emptied = loop {
+        let message = match spool.peek() {
+            Ok(Some(message)) => message,
+            Ok(None) => break true,
+            Err(err) => {
+                error!("Failed to read spooled message: {}", err);
+                break false
+            }
+        };
+
+        if socket.write_all(&message).is_err() {
+            break false
+        }
+
+        if let Err(err) = spool.pop() {
+            error!("Failed to remove replayed message from spool: {}", err);
+            break false
+        }
+        replayed += 1;
+    };
+
+    if replayed > 0 {
+        info!("Replayed {} spooled messages to processor at '{}'", replayed, processor_url);
+    }
+    emptied
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CollectError {
+    Timeout,
+    Dropped,
+    Disconnected
+}
+
+impl Error for CollectError {
+    fn description(&self) -> &str {
+        match self {
+            &CollectError::Timeout => "Timed out waiting for sender to accept data point",
+            &CollectError::Dropped => "Data point dropped due to back-pressure",
+            &CollectError::Disconnected => "Sender is not running",
+        }
+    }
+}
+
+impl fmt::Display for CollectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+pub trait Collect {
+    fn collect(&mut self, location: &str, path: &str, component: &str, value: DataValue) -> Result<(), CollectError>;
+}
+
+pub struct Collector {
+    timestamp: DateTime<UTC>,
+    probe: String,
+    sink: QueueSender<QueuedDataPoint>,
+    back_pressure: BackPressure,
+    stats: ProbesStats,
+    run_stats: ProbeStats
+}
+
+impl Collector {
+    /// Creates collector accounting data points to given probe; data points are timestamped with time of its creation
+    pub fn for_probe(&self, probe: &str) -> Collector {
+        let mut collector = self.clone();
+        collector.timestamp = UTC::now();
+        collector.probe = probe.to_string();
+        collector
+    }
+}
+
+impl Clone for Collector {
+    fn clone(&self) -> Self {
+        Collector {
+            timestamp: self.timestamp,
+            probe: self.probe.clone(),
+            sink: self.sink.clone(),
+            back_pressure: self.back_pressure,
+            stats: self.stats.clone(),
+            run_stats: ProbeStats::default()
+        }
+    }
+}
+
+impl Drop for Collector {
+    fn drop(&mut self) {
+        if self.run_stats.delayed > 0 || self.run_stats.dropped > 0 {
+            let stats = self.stats.lock().unwrap().get(&self.probe).cloned().unwrap_or_else(ProbeStats::default);
+            warn!("Probe '{}' had {} of {} collected data points delayed and {} dropped; since start: {} delayed and {} dropped",
+                  self.probe, self.run_stats.delayed, self.run_stats.collected, self.run_stats.dropped, stats.delayed, stats.dropped);
+        }
+    }
+}
+
+impl Collect for Collector {
+    fn collect(&mut self, location: &str, path: &str, component: &str, value: DataValue) -> Result<(), CollectError> {
+        let queued = QueuedDataPoint {
+            probe: self.probe.clone(),
+            raw_data_point: Box::new(RawDataPoint {
+                location: location.to_string(),
+                path: path.to_string(),
+                component: component.to_string(),
+                timestamp: self.timestamp,
+                value: value
+            })
+        };
+
+        let result = self.sink.push(queued, self.back_pressure);
+
+        let mut stats = self.stats.lock().unwrap();
+        self.run_stats.collected += 1;
+        stats.entry(self.probe.clone()).or_insert_with(ProbeStats::default).collected += 1;
+
+        let error = match result {
+            Ok(pushed) => {
+                if pushed.delayed {
+                    self.run_stats.delayed += 1;
+                    stats.get_mut(&self.probe).unwrap().delayed += 1;
+                }
+                if let Some(evicted) = pushed.evicted {
+                    if evicted.probe == self.probe {
+                        self.run_stats.dropped += 1;
+                    }
+                    stats.entry(evicted.probe).or_insert_with(ProbeStats::default).dropped += 1;
+                }
+                debug!("Collected raw data point for location: '{}', path: '{}', component: '{}'", location, path, component);
+                return Ok(())
+            }
+            Err(PushError::Timeout(_)) => CollectError::Timeout,
+            Err(PushError::Dropped(_)) => CollectError::Dropped,
+            Err(PushError::Disconnected(_)) => CollectError::Disconnected
+        };
+
+        self.run_stats.dropped += 1;
+        stats.get_mut(&self.probe).unwrap().dropped += 1;
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use std::io::Read;
+    pub use std::error::Error;
+    pub use messaging::*;
+    pub use nanomsg::{Socket, Protocol};
+    pub use url::Url;
+    pub use tempdir::TempDir;
+    pub use std::time::Duration;
+
+    pub fn spool_config(dir: &TempDir) -> SpoolConfig {
+        SpoolConfig {
+            dir: dir.path().join("spool"),
+            max_size: 1024 * 1024,
+            overflow_policy: OverflowPolicy::DropOldest
+        }
+    }
+
+    mod sender {
+        pub use super::*;
+        #[test]
+        fn should_shut_down_after_going_out_of_scope() {
+            let dir = TempDir::new("dms-sender").unwrap();
+            {
+                let _ = Sender::start(Url::parse("ipc:///tmp/test-collector1.ipc").unwrap(), spool_config(&dir), BackPressure::Block(Duration::from_millis(100)));
+            }
+            assert!(true);
+        }
+
+        #[test]
+        fn should_fail_to_spawn_on_bad_url() {
+            let dir = TempDir::new("dms-sender").unwrap();
+            let result =  Sender::start(Url::parse("foo:///bar").unwrap(), spool_config(&dir), BackPressure::Block(Duration::from_millis(100)));
+            assert!(result.is_err());
+            if let Err(err) = result {
+                assert_eq!(err.description(), "Sender configuration error");
+            }
+        }
+
+        mod collector {
+            pub use super::*;
+
+            #[test]
+             fn should_pass_data_points_to_nanosmg_pull_socket() {
+                let mut pull = Socket::new(Protocol::Pull).unwrap();
+                let mut _endpoint = pull.bind("ipc:///tmp/test-collector.ipc").unwrap();
+                let dir = TempDir::new("dms-sender").unwrap();
+                {
+                    let sender = Sender::start(Url::parse("ipc:///tmp/test-collector.ipc").unwrap(), spool_config(&dir), BackPressure::Block(Duration::from_millis(100))).unwrap();
+                    let mut collector = sender.collector();
+
+                    collector.collect("myserver", "os/cpu/usage", "user", DataValue::Float(0.4)).unwrap();
+                    collector.collect("foobar", "os/cpu/sys", "user", DataValue::Float(0.4)).unwrap();
+
+                    let mut msg = Vec::new();
+                    pull.read_to_end(&mut msg).unwrap();
+                    let msg_string = String::from_utf8_lossy(&msg);
+                    assert!(msg_string.contains("RawDataPointBatch/\n0\ncapnp\n\n"));
+                    assert!(msg_string.contains("myserver"));
+
+                    let mut msg = Vec::new();
+                    pull.read_to_end(&mut msg).unwrap();
+                    let msg_string = String::from_utf8_lossy(&msg);
+                    assert!(msg_string.contains("RawDataPointBatch/\n0\ncapnp\n\n"));
+                    assert!(msg_string.contains("foobar"));
+                }
+            }
+            #[test]
+            fn should_batch_data_points_with_same_location_and_timestamp() {
+                let mut pull = Socket::new(Protocol::Pull).unwrap();
+                let mut _endpoint = pull.bind("ipc:///tmp/test-collector-batch.ipc").unwrap();
+                let dir = TempDir::new("dms-sender").unwrap();
+                {
+                    let sender = Sender::start(Url::parse("ipc:///tmp/test-collector-batch.ipc").unwrap(), spool_config(&dir), BackPressure::Block(Duration::from_millis(100))).unwrap();
+                    let mut collector = sender.collector();
+
+                    collector.collect("myserver", "os/cpu/usage", "user", DataValue::Float(0.4)).unwrap();
+                    collector.collect("myserver", "os/cpu/usage", "sys", DataValue::Float(0.2)).unwrap();
+                    collector.collect("myserver", "os/cpu/count", "online", DataValue::Integer(8)).unwrap();
+
+                    let mut msg = Vec::new();
+                    pull.read_to_end(&mut msg).unwrap();
+                    let (header, body) = split_message(msg).unwrap();
+                    let batch: RawDataPointBatch = decode_message_body(&header, &body).unwrap();
+
+                    assert_eq!(batch.location, "myserver".to_string());
+                    assert_eq!(batch.points.iter().map(|point| point.component.as_str()).collect::<Vec<_>>(), vec!["user", "sys", "online"]);
+                }
+            }
+
+            #[test]
+            fn should_spool_data_points_until_processor_is_available() {
+                let dir = TempDir::new("dms-sender").unwrap();
+                let sender = Sender::start(Url::parse("ipc:///tmp/test-collector-spool.ipc").unwrap(), spool_config(&dir), BackPressure::Block(Duration::from_millis(100))).unwrap();
+                let mut collector = sender.collector();
+
+                collector.collect("myserver", "os/cpu/usage", "user", DataValue::Float(0.4)).unwrap();
+                collector.collect("foobar", "os/cpu/sys", "user", DataValue::Float(0.4)).unwrap();
+
+                let mut pull = Socket::new(Protocol::Pull).unwrap();
+                let mut _endpoint = pull.bind("ipc:///tmp/test-collector-spool.ipc").unwrap();
+
+                let mut msg = Vec::new();
+                pull.read_to_end(&mut msg).unwrap();
+                assert!(String::from_utf8_lossy(&msg).contains("myserver"));
+
+                let mut msg = Vec::new();
+                pull.read_to_end(&mut msg).unwrap();
+                assert!(String::from_utf8_lossy(&msg).contains("foobar"));
+            }
+
+            #[test]
+            fn should_spool_data_points_without_waiting_for_processor() {
+                let dir = TempDir::new("dms-sender").unwrap();
+                let sender = Sender::start(Url::parse("ipc:///tmp/test-collector-spool-fast.ipc").unwrap(), spool_config(&dir), BackPressure::Block(Duration::from_secs(5))).unwrap();
+                let mut collector = sender.collector().for_probe("p1");
+
+                let started = Instant::now();
+                for _ in 0..(3 * QUEUE_CAPACITY) {
+                    collector.collect("myserver", "os/cpu/usage", "user", DataValue::Float(0.4)).unwrap();
+                }
+                assert!(started.elapsed() < Duration::from_secs(10));
+                assert_eq!(sender.stats()["p1"].dropped, 0);
+
+                let mut pull = Socket::new(Protocol::Pull).unwrap();
+                let mut _endpoint = pull.bind("ipc:///tmp/test-collector-spool-fast.ipc").unwrap();
+
+                let mut received = 0;
+                while received < 3 * QUEUE_CAPACITY {
+                    let mut msg = Vec::new();
+                    pull.read_to_end(&mut msg).unwrap();
+                    let (header, body) = split_message(msg).unwrap();
+                    let batch: RawDataPointBatch = decode_message_body(&header, &body).unwrap();
+                    received += batch.points.len();
+                }
+                assert_eq!(received, 3 * QUEUE_CAPACITY);
+            }
+
+            #[test]
+            fn should_timestamp_data_points_with_time_probe_collector_was_created() {
+                let mut pull = Socket::new(Protocol::Pull).unwrap();
+                let mut _endpoint = pull.bind("ipc:///tmp/test-collector-timestamp.ipc").unwrap();
+                let dir = TempDir::new("dms-sender").unwrap();
+                {
+                    let sender = Sender::start(Url::parse("ipc:///tmp/test-collector-timestamp.ipc").unwrap(), spool_config(&dir), BackPressure::Block(Duration::from_millis(100))).unwrap();
+                    let collector = sender.collector();
+
+                    let mut timestamps = Vec::new();
+                    for _ in 0..2 {
+                        ::std::thread::sleep(Duration::from_millis(10));
+                        collector.for_probe("p1").collect("myserver", "os/cpu/usage", "user", DataValue::Float(0.4)).unwrap();
+
+                        let mut msg = Vec::new();
+                        pull.read_to_end(&mut msg).unwrap();
+                        let (header, body) = split_message(msg).unwrap();
+                        let batch: RawDataPointBatch = decode_message_body(&header, &body).unwrap();
+                        timestamps.push(batch.timestamp);
+                    }
+
+                    assert!(timestamps[1] > timestamps[0]);
+                }
+            }
+
+            #[test]
+            fn should_count_collected_data_points_per_probe() {
+                let dir = TempDir::new("dms-sender").unwrap();
+                let sender = Sender::start(Url::parse("ipc:///tmp/test-collector-stats.ipc").unwrap(), spool_config(&dir), BackPressure::DropNewest).unwrap();
+
+                let mut collector = sender.collector().for_probe("p1");
+                collector.collect("myserver", "os/cpu/usage", "user", DataValue::Float(0.4)).unwrap();
+                collector.collect("myserver", "os/cpu/sys", "user", DataValue::Float(0.4)).unwrap();
+
+                let mut collector = sender.collector().for_probe("p2");
+                collector.collect("myserver", "os/cpu/usage", "user", DataValue::Float(0.4)).unwrap();
+
+                let stats = sender.stats();
+                assert_eq!(stats["p1"], ProbeStats { collected: 2, delayed: 0, dropped: 0 });
+                assert_eq!(stats["p2"], ProbeStats { collected: 1, delayed: 0, dropped: 0 });
+            }
+
+            /*
+            #[test]
+            fn collect_should_fail_if_sender_paniced() {
+                let sender = Sender::spawn(Url::parse("foo:///bar").unwrap()).unwrap();
+                let mut collector = sender.collector();
+
+                collector.collect("myserver", "os/cpu/usage", "user", DataValue::Float(0.4)).unwrap();
+            }
+            */
+        }
+    }
+}
+
@@ -0,0 +1,324 @@
+use std::sync::{Arc, Mutex, Condvar};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use std::str::FromStr;
+
+/// What to do when queue is full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackPressure {
+    /// Wait up to given time for space in the queue
+    Block(Duration),
+    /// Reject new item
+    DropNewest,
+    /// Remove oldest item to make space for new one
+    DropOldest,
+    /// Accept only every n-th item while the queue is full, replacing oldest one
+    Sample(u32)
+}
+
+impl ToString for BackPressure {
+    fn to_string(&self) -> String {
+        match self {
+            &BackPressure::Block(timeout) => format!("block:{}", timeout.as_secs() * 1000 + timeout.subsec_nanos() as u64 / 1000000),
+            &BackPressure::DropNewest => "drop-newest".to_string(),
+            &BackPressure::DropOldest => "drop-oldest".to_string(),
+            &BackPressure::Sample(every) => format!("sample:{}", every),
+        }
+    }
+}
+
+impl FromStr for BackPressure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next().unwrap(), parts.next()) {
+            ("block", None) => Ok(BackPressure::Block(Duration::from_millis(1000))),
+            ("block", Some(millis)) => millis.parse().map(|millis| BackPressure::Block(Duration::from_millis(millis))).map_err(|_| format!("invalid block timeout: {}", millis)),
+            ("drop-newest", None) => Ok(BackPressure::DropNewest),
+            ("drop-oldest", None) => Ok(BackPressure::DropOldest),
+            ("sample", Some(every)) => match every.parse() {
+                Ok(every) if every > 0 => Ok(BackPressure::Sample(every)),
+                _ => Err(format!("invalid sample rate: {}", every))
+            },
+            _ => Err(format!("unknown back-pressure policy: {}", s))
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PushError<T> {
+    /// Item was not accepted within timeout
+    Timeout(T),
+    /// Item was dropped due to policy
+    Dropped(T),
+    /// Receiving end is gone
+    Disconnected(T)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PopError {
+    Timeout,
+    Disconnected
+}
+
+/// Result of successful push
+#[derive(Debug, PartialEq)]
+pub struct Pushed<T> {
+    /// Item had to wait for space in the queue
+    pub delayed: bool,
+    /// Item removed from the queue to make space
+    pub evicted: Option<T>
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    senders: usize,
+    receiver: bool,
+    sample_count: u32
+}
+
+struct Shared<T> {
+    capacity: usize,
+    state: Mutex<State<T>>,
+    not_empty: Condvar,
+    not_full: Condvar
+}
+
+/// Creates bounded multi-producer single-consumer queue with back-pressure policy applied on push
+pub fn bounded<T>(capacity: usize) -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(Shared {
+        capacity: capacity,
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            senders: 1,
+            receiver: true,
+            sample_count: 0
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new()
+    });
+
+    (QueueSender { shared: shared.clone() }, QueueReceiver { shared: shared })
+}
+
+pub struct QueueSender<T> {
+    shared: Arc<Shared<T>>
+}
+
+impl<T> QueueSender<T> {
+    pub fn push(&self, item: T, back_pressure: BackPressure) -> Result<Pushed<T>, PushError<T>> {
+        let mut state = self.shared.state.lock().unwrap();
+        let mut delayed = false;
+        let mut evicted = None;
+
+        if !state.receiver {
+            return Err(PushError::Disconnected(item))
+        }
+
+        if state.items.len() >= self.shared.capacity {
+            match back_pressure {
+                BackPressure::Block(timeout) => {
+                    let deadline = Instant::now() + timeout;
+                    delayed = true;
+                    while state.items.len() >= self.shared.capacity && state.receiver {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            return Err(PushError::Timeout(item))
+                        }
+                        state = self.shared.not_full.wait_timeout(state, deadline - now).unwrap().0;
+                    }
+                    if !state.receiver {
+                        return Err(PushError::Disconnected(item))
+                    }
+                }
+                BackPressure::DropNewest => return Err(PushError::Dropped(item)),
+                BackPressure::DropOldest => evicted = state.items.pop_front(),
+                BackPressure::Sample(every) => {
+                    state.sample_count += 1;
+                    if state.sample_count % every != 0 {
+                        return Err(PushError::Dropped(item))
+                    }
+                    evicted = state.items.pop_front();
+                }
+            }
+        } else {
+            state.sample_count = 0;
+        }
+
+        state.items.push_back(item);
+        self.shared.not_empty.notify_one();
+
+        Ok(Pushed {
+            delayed: delayed,
+            evicted: evicted
+        })
+    }
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        QueueSender { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+pub struct QueueReceiver<T> {
+    shared: Arc<Shared<T>>
+}
+
+impl<T> QueueReceiver<T> {
+    /// Waits for next item; returns None when all senders are gone and queue is empty
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.shared.not_full.notify_one();
+                return Some(item)
+            }
+            if state.senders == 0 {
+                return None
+            }
+            state = self.shared.not_empty.wait(state).unwrap();
+        }
+    }
+
+    pub fn pop_timeout(&self, timeout: Duration) -> Result<T, PopError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.shared.not_full.notify_one();
+                return Ok(item)
+            }
+            if state.senders == 0 {
+                return Err(PopError::Disconnected)
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(PopError::Timeout)
+            }
+            state = self.shared.not_empty.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receiver = false;
+        self.shared.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use std::time::Duration;
+    pub use std::thread::{spawn, sleep};
+
+    #[test]
+    fn should_pass_items_in_order() {
+        let (tx, rx) = bounded(10);
+        tx.push(1, BackPressure::DropNewest).unwrap();
+        tx.push(2, BackPressure::DropNewest).unwrap();
+
+        assert_eq!(rx.pop(), Some(1));
+        assert_eq!(rx.pop_timeout(Duration::from_millis(10)), Ok(2));
+        assert_eq!(rx.pop_timeout(Duration::from_millis(10)), Err(PopError::Timeout));
+    }
+
+    #[test]
+    fn should_report_disconnect_when_all_senders_are_dropped() {
+        let (tx, rx) = bounded(10);
+        let tx2 = tx.clone();
+        tx.push(1, BackPressure::DropNewest).unwrap();
+        drop(tx);
+        drop(tx2);
+
+        assert_eq!(rx.pop(), Some(1));
+        assert_eq!(rx.pop(), None);
+        assert_eq!(rx.pop_timeout(Duration::from_millis(10)), Err(PopError::Disconnected));
+    }
+
+    #[test]
+    fn should_report_disconnect_when_receiver_is_dropped() {
+        let (tx, rx) = bounded(10);
+        drop(rx);
+        assert_eq!(tx.push(1, BackPressure::DropNewest), Err(PushError::Disconnected(1)));
+    }
+
+    #[test]
+    fn should_time_out_blocking_push() {
+        let (tx, _rx) = bounded(1);
+        tx.push(1, BackPressure::Block(Duration::from_millis(10))).unwrap();
+        assert_eq!(tx.push(2, BackPressure::Block(Duration::from_millis(10))), Err(PushError::Timeout(2)));
+    }
+
+    #[test]
+    fn should_mark_blocked_push_as_delayed() {
+        let (tx, rx) = bounded(1);
+        tx.push(1, BackPressure::Block(Duration::from_millis(10))).unwrap();
+
+        let consumer = spawn(move || {
+            sleep(Duration::from_millis(50));
+            (rx.pop(), rx.pop())
+        });
+
+        let pushed = tx.push(2, BackPressure::Block(Duration::from_millis(5000))).unwrap();
+        assert!(pushed.delayed);
+        drop(tx);
+
+        assert_eq!(consumer.join().unwrap(), (Some(1), Some(2)));
+    }
+
+    #[test]
+    fn should_drop_newest() {
+        let (tx, rx) = bounded(1);
+        tx.push(1, BackPressure::DropNewest).unwrap();
+        assert_eq!(tx.push(2, BackPressure::DropNewest), Err(PushError::Dropped(2)));
+        assert_eq!(rx.pop(), Some(1));
+    }
+
+    #[test]
+    fn should_drop_oldest() {
+        let (tx, rx) = bounded(2);
+        tx.push(1, BackPressure::DropOldest).unwrap();
+        tx.push(2, BackPressure::DropOldest).unwrap();
+        assert_eq!(tx.push(3, BackPressure::DropOldest), Ok(Pushed { delayed: false, evicted: Some(1) }));
+        assert_eq!(rx.pop(), Some(2));
+        assert_eq!(rx.pop(), Some(3));
+    }
+
+    #[test]
+    fn should_sample_while_full() {
+        let (tx, rx) = bounded(1);
+        tx.push(0, BackPressure::Sample(3)).unwrap();
+
+        let accepted: Vec<i32> = (1..10).filter(|&i| tx.push(i, BackPressure::Sample(3)).is_ok()).collect();
+        assert_eq!(accepted, vec![3, 6, 9]);
+        assert_eq!(rx.pop(), Some(9));
+    }
+
+    #[test]
+    fn should_parse_back_pressure() {
+        assert_eq!("block".parse::<BackPressure>().unwrap(), BackPressure::Block(Duration::from_millis(1000)));
+        assert_eq!("block:250".parse::<BackPressure>().unwrap(), BackPressure::Block(Duration::from_millis(250)));
+        assert_eq!("drop-newest".parse::<BackPressure>().unwrap(), BackPressure::DropNewest);
+        assert_eq!("drop-oldest".parse::<BackPressure>().unwrap(), BackPressure::DropOldest);
+        assert_eq!("sample:10".parse::<BackPressure>().unwrap(), BackPressure::Sample(10));
+        assert!("sample:0".parse::<BackPressure>().is_err());
+        assert!("sample".parse::<BackPressure>().is_err());
+        assert!("foo".parse::<BackPressure>().is_err());
+        assert_eq!(BackPressure::Block(Duration::from_millis(250)).to_string(), "block:250");
+    }
+}
@@ -0,0 +1,520 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::error::Error;
+use std::fmt;
+
+/// Size after which new segment file is started
+const MAX_SEGMENT_SIZE: u64 = 1024 * 1024;
+/// Name of the file holding read position of the oldest segment
+const CURSOR_FILE: &'static str = "cursor";
+
+#[derive(Debug)]
+pub enum SpoolError {
+    Io(IoError),
+    Corrupted(PathBuf, String)
+}
+
+impl From<IoError> for SpoolError {
+    fn from(err: IoError) -> SpoolError {
+        SpoolError::Io(err)
+    }
+}
+
+impl Error for SpoolError {
+    fn description(&self) -> &str {
+        match self {
+            &SpoolError::Io(_) => "Spool IO error",
+            &SpoolError::Corrupted(_, _) => "Spool data corrupted",
+        }
+    }
+}
+
+impl fmt::Display for SpoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &SpoolError::Io(ref err) => write!(f, "{}: {}", self.description(), err),
+            &SpoolError::Corrupted(ref path, ref err) => write!(f, "{} in '{}': {}", self.description(), path.display(), err),
+        }
+    }
+}
+
+/// What to do with messages when spool reached its size limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    /// Remove oldest segment to make space for new messages
+    DropOldest,
+    /// Discard new messages until spool is drained
+    DropNewest
+}
+
+impl ToString for OverflowPolicy {
+    fn to_string(&self) -> String {
+        match self {
+            &OverflowPolicy::DropOldest => "drop-oldest".to_string(),
+            &OverflowPolicy::DropNewest => "drop-newest".to_string(),
+        }
+    }
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "drop-newest" => Ok(OverflowPolicy::DropNewest),
+            _ => Err(format!("unknown overflow policy: {}", s))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpoolConfig {
+    pub dir: PathBuf,
+    pub max_size: u64,
+    pub overflow_policy: OverflowPolicy
+}
+
+#[derive(Debug)]
+struct Segment {
+    number: u64,
+    path: PathBuf,
+    size: u64
+}
+
+impl Segment {
+    fn new(dir: &Path, number: u64) -> Segment {
+        Segment {
+            number: number,
+            path: dir.join(format!("{:010}.seg", number)),
+            size: 0
+        }
+    }
+
+    // Scans length prefixed records and truncates incomplete one left after crash
+    fn open(dir: &Path, number: u64) -> Result<Segment, SpoolError> {
+        let mut segment = Segment::new(dir, number);
+        let mut file = try!(OpenOptions::new().read(true).write(true).open(&segment.path));
+
+        let mut data = Vec::new();
+        try!(file.read_to_end(&mut data));
+
+        let mut offset = 0;
+        while offset + 4 <= data.len() {
+            let length = record_length(&data[offset..offset + 4]) as usize;
+            if offset + 4 + length > data.len() {
+                break;
+            }
+            offset += 4 + length;
+        }
+
+        if offset < data.len() {
+            warn!("Truncating incomplete record at offset {} of spool segment '{}'", offset, segment.path.display());
+            try!(file.set_len(offset as u64));
+        }
+
+        segment.size = offset as u64;
+        Ok(segment)
+    }
+}
+
+fn record_length(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+/// Bounded on-disk log of encoded messages stored as sequence of segment files
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    max_size: u64,
+    overflow_policy: OverflowPolicy,
+    segments: VecDeque<Segment>,
+    writer: Option<File>,
+    next_segment: u64,
+    read_offset: u64,
+    peeked_size: Option<u64>,
+    dropped: u64
+}
+
+impl Spool {
+    pub fn open(config: &SpoolConfig) -> Result<Spool, SpoolError> {
+        try!(fs::create_dir_all(&config.dir));
+
+        let mut numbers = Vec::new();
+        for entry in try!(fs::read_dir(&config.dir)) {
+            let entry = try!(entry);
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            if file_name.ends_with(".seg") {
+                match file_name.trim_right_matches(".seg").parse::<u64>() {
+                    Ok(number) => numbers.push(number),
+                    Err(_) => warn!("Ignoring unexpected file in spool directory: '{}'", entry.path().display())
+                }
+            }
+        }
+        numbers.sort();
+
+        let next_segment = numbers.last().map(|number| number + 1).unwrap_or(0);
+        let mut segments = VecDeque::new();
+        for number in numbers {
+            segments.push_back(try!(Segment::open(&config.dir, number)));
+        }
+
+        let mut spool = Spool {
+            dir: config.dir.clone(),
+            max_size: config.max_size,
+            overflow_policy: config.overflow_policy,
+            segments: segments,
+            writer: None,
+            next_segment: next_segment,
+            read_offset: 0,
+            peeked_size: None,
+            dropped: 0
+        };
+
+        try!(spool.load_cursor());
+        if !spool.is_empty() {
+            info!("Spool in '{}' contains {} bytes of messages to replay", spool.dir.display(), spool.size() - spool.read_offset);
+        }
+        Ok(spool)
+    }
+
+    fn load_cursor(&mut self) -> Result<(), SpoolError> {
+        let path = self.dir.join(CURSOR_FILE);
+        let mut cursor = String::new();
+        match File::open(&path) {
+            Ok(mut file) => { try!(file.read_to_string(&mut cursor)); },
+            Err(_) => return Ok(())
+        }
+
+        let mut parts = cursor.split_whitespace().map(|part| part.parse::<u64>());
+        let (number, offset) = match (parts.next(), parts.next()) {
+            (Some(Ok(number)), Some(Ok(offset))) => (number, offset),
+            _ => return Err(SpoolError::Corrupted(path, format!("invalid cursor: {:?}", cursor)))
+        };
+
+        // keep numbering increasing so that stale segments can be told apart
+        if number > self.next_segment {
+            self.next_segment = number;
+        }
+
+        // segments read before the cursor was written could have not been removed
+        while self.segments.front().map(|segment| segment.number < number).unwrap_or(false) {
+            let segment = self.segments.pop_front().unwrap();
+            try!(fs::remove_file(&segment.path));
+        }
+
+        if let Some(segment) = self.segments.front() {
+            if segment.number == number {
+                if offset > segment.size {
+                    return Err(SpoolError::Corrupted(path, format!("cursor offset {} past the end of segment {}", offset, number)))
+                }
+                self.read_offset = offset;
+            }
+        }
+        Ok(())
+    }
+
+    fn store_cursor(&self) -> Result<(), SpoolError> {
+        let number = self.segments.front().map(|segment| segment.number).unwrap_or(self.next_segment);
+        let mut file = try!(File::create(self.dir.join(CURSOR_FILE)));
+        try!(write!(file, "{} {}\n", number, self.read_offset));
+        Ok(())
+    }
+
+    /// Total size of segment files on disk
+    pub fn size(&self) -> u64 {
+        self.segments.iter().fold(0, |size, segment| size + segment.size)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self.segments.len() {
+            0 => true,
+            1 => self.read_offset >= self.segments[0].size,
+            _ => false
+        }
+    }
+
+    /// Number of messages discarded due to size limit
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn push(&mut self, message: &[u8]) -> Result<(), SpoolError> {
+        let record_size = 4 + message.len() as u64;
+
+        while self.size() + record_size > self.max_size {
+            match self.overflow_policy {
+                OverflowPolicy::DropNewest => {
+                    self.dropped += 1;
+                    warn!("Spool is full; dropping newest message");
+                    return Ok(())
+                }
+                OverflowPolicy::DropOldest => {
+                    if self.segments.is_empty() {
+                        self.dropped += 1;
+                        warn!("Message of {} bytes does not fit in spool; dropping", message.len());
+                        return Ok(())
+                    }
+                    try!(self.drop_oldest_segment());
+                }
+            }
+        }
+
+        let start_new = match self.segments.back() {
+            None => true,
+            Some(segment) => segment.size + record_size > MAX_SEGMENT_SIZE && segment.size > 0
+        };
+
+        if start_new {
+            self.segments.push_back(Segment::new(&self.dir, self.next_segment));
+            self.next_segment += 1;
+            self.writer = None;
+        }
+
+        if self.writer.is_none() {
+            let path = &self.segments.back().unwrap().path;
+            self.writer = Some(try!(OpenOptions::new().append(true).create(true).open(path)));
+        }
+
+        let length = message.len() as u32;
+        let mut record = Vec::with_capacity(record_size as usize);
+        record.extend(&[length as u8, (length >> 8) as u8, (length >> 16) as u8, (length >> 24) as u8]);
+        record.extend(message);
+
+        try!(self.writer.as_mut().unwrap().write_all(&record));
+        self.segments.back_mut().unwrap().size += record_size;
+        Ok(())
+    }
+
+    fn drop_oldest_segment(&mut self) -> Result<(), SpoolError> {
+        let segment = self.segments.pop_front().unwrap();
+        let mut file = try!(File::open(&segment.path));
+        let mut data = Vec::new();
+        try!(file.read_to_end(&mut data));
+
+        let mut offset = self.read_offset as usize;
+        while offset + 4 <= data.len() {
+            offset += 4 + record_length(&data[offset..offset + 4]) as usize;
+            self.dropped += 1;
+        }
+        warn!("Spool is full; dropped oldest segment '{}'", segment.path.display());
+
+        try!(fs::remove_file(&segment.path));
+        if self.segments.is_empty() {
+            self.writer = None;
+        }
+        self.read_offset = 0;
+        self.peeked_size = None;
+        self.store_cursor()
+    }
+
+    /// Reads oldest message without removing it from the spool
+    pub fn peek(&mut self) -> Result<Option<Vec<u8>>, SpoolError> {
+        loop {
+            if self.is_empty() {
+                return Ok(None)
+            }
+
+            if self.read_offset >= self.segments[0].size {
+                // fully read segment that is not the last one
+                try!(self.remove_read_segment());
+                continue
+            }
+
+            let (path, size) = (self.segments[0].path.clone(), self.segments[0].size);
+            let mut file = try!(File::open(&path));
+            try!(file.seek(SeekFrom::Start(self.read_offset)));
+
+            let mut length = [0u8; 4];
+            try!(file.read_exact(&mut length));
+            let length = record_length(&length) as u64;
+
+            if self.read_offset + 4 + length > size {
+                return Err(SpoolError::Corrupted(path, format!("record at offset {} runs past the end of segment", self.read_offset)))
+            }
+
+            let mut message = vec![0u8; length as usize];
+            try!(file.read_exact(&mut message));
+
+            self.peeked_size = Some(4 + length);
+            return Ok(Some(message))
+        }
+    }
+
+    /// Removes message returned by last call to peek
+    pub fn pop(&mut self) -> Result<(), SpoolError> {
+        let size = match self.peeked_size.take() {
+            Some(size) => size,
+            None => return Ok(())
+        };
+        self.read_offset += size;
+
+        if self.read_offset >= self.segments[0].size {
+            try!(self.remove_read_segment());
+        }
+        self.store_cursor()
+    }
+
+    fn remove_read_segment(&mut self) -> Result<(), SpoolError> {
+        let segment = self.segments.pop_front().unwrap();
+        try!(fs::remove_file(&segment.path));
+        if self.segments.is_empty() {
+            self.writer = None;
+        }
+        self.read_offset = 0;
+        self.store_cursor()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use super::MAX_SEGMENT_SIZE;
+    pub use std::fs::OpenOptions;
+    pub use std::io::Write;
+    pub use tempdir::TempDir;
+
+    fn spool_config(dir: &TempDir, max_size: u64, overflow_policy: OverflowPolicy) -> SpoolConfig {
+        SpoolConfig {
+            dir: dir.path().join("spool"),
+            max_size: max_size,
+            overflow_policy: overflow_policy
+        }
+    }
+
+    fn drain(spool: &mut Spool) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        while let Some(message) = spool.peek().unwrap() {
+            messages.push(message);
+            spool.pop().unwrap();
+        }
+        messages
+    }
+
+    #[test]
+    fn should_replay_messages_in_order() {
+        let dir = TempDir::new("dms-spool").unwrap();
+        let mut spool = Spool::open(&spool_config(&dir, 1024 * 1024, OverflowPolicy::DropOldest)).unwrap();
+        assert!(spool.is_empty());
+
+        spool.push(b"foo").unwrap();
+        spool.push(b"").unwrap();
+        spool.push(b"bar").unwrap();
+        assert!(!spool.is_empty());
+
+        assert_eq!(drain(&mut spool), vec![b"foo".to_vec(), b"".to_vec(), b"bar".to_vec()]);
+        assert!(spool.is_empty());
+        assert_eq!(spool.size(), 0);
+    }
+
+    #[test]
+    fn should_keep_message_until_popped() {
+        let dir = TempDir::new("dms-spool").unwrap();
+        let mut spool = Spool::open(&spool_config(&dir, 1024 * 1024, OverflowPolicy::DropOldest)).unwrap();
+
+        spool.push(b"foo").unwrap();
+        assert_eq!(spool.peek().unwrap(), Some(b"foo".to_vec()));
+        assert_eq!(spool.peek().unwrap(), Some(b"foo".to_vec()));
+        spool.pop().unwrap();
+        assert_eq!(spool.peek().unwrap(), None);
+    }
+
+    #[test]
+    fn should_span_messages_over_segments() {
+        let dir = TempDir::new("dms-spool").unwrap();
+        let mut spool = Spool::open(&spool_config(&dir, 16 * 1024 * 1024, OverflowPolicy::DropOldest)).unwrap();
+
+        let message = vec![42u8; 300 * 1024];
+        for _ in 0..8 {
+            spool.push(&message).unwrap();
+        }
+        assert!(spool.segments.len() > 1);
+
+        assert_eq!(drain(&mut spool).len(), 8);
+        assert_eq!(spool.segments.len(), 0);
+    }
+
+    #[test]
+    fn should_survive_reopening() {
+        let dir = TempDir::new("dms-spool").unwrap();
+        let config = spool_config(&dir, 1024 * 1024, OverflowPolicy::DropOldest);
+        {
+            let mut spool = Spool::open(&config).unwrap();
+            spool.push(b"foo").unwrap();
+            spool.push(b"bar").unwrap();
+            spool.push(b"baz").unwrap();
+
+            spool.peek().unwrap();
+            spool.pop().unwrap();
+        }
+
+        let mut spool = Spool::open(&config).unwrap();
+        assert_eq!(drain(&mut spool), vec![b"bar".to_vec(), b"baz".to_vec()]);
+
+        let mut spool = Spool::open(&config).unwrap();
+        assert!(spool.is_empty());
+        spool.push(b"quix").unwrap();
+        assert_eq!(drain(&mut spool), vec![b"quix".to_vec()]);
+    }
+
+    #[test]
+    fn should_ignore_incomplete_record() {
+        let dir = TempDir::new("dms-spool").unwrap();
+        let config = spool_config(&dir, 1024 * 1024, OverflowPolicy::DropOldest);
+        {
+            let mut spool = Spool::open(&config).unwrap();
+            spool.push(b"foo").unwrap();
+        }
+        {
+            let mut file = OpenOptions::new().append(true).open(config.dir.join("0000000000.seg")).unwrap();
+            file.write_all(&[10, 0, 0, 0, b'b', b'a']).unwrap();
+        }
+
+        let mut spool = Spool::open(&config).unwrap();
+        spool.push(b"bar").unwrap();
+        assert_eq!(drain(&mut spool), vec![b"foo".to_vec(), b"bar".to_vec()]);
+    }
+
+    #[test]
+    fn should_drop_newest_messages_when_full() {
+        let dir = TempDir::new("dms-spool").unwrap();
+        let mut spool = Spool::open(&spool_config(&dir, 20, OverflowPolicy::DropNewest)).unwrap();
+
+        spool.push(b"foo").unwrap();
+        spool.push(b"bar").unwrap();
+        spool.push(b"baz").unwrap();
+        assert_eq!(spool.dropped(), 1);
+
+        assert_eq!(drain(&mut spool), vec![b"foo".to_vec(), b"bar".to_vec()]);
+    }
+
+    #[test]
+    fn should_drop_oldest_segment_when_full() {
+        let dir = TempDir::new("dms-spool").unwrap();
+        let mut spool = Spool::open(&spool_config(&dir, 3 * MAX_SEGMENT_SIZE, OverflowPolicy::DropOldest)).unwrap();
+
+        let message = vec![42u8; MAX_SEGMENT_SIZE as usize - 4];
+        for _ in 0..3 {
+            spool.push(&message).unwrap();
+        }
+        spool.push(b"foo").unwrap();
+        assert_eq!(spool.dropped(), 1);
+        assert!(spool.size() <= 3 * MAX_SEGMENT_SIZE);
+
+        let messages = drain(&mut spool);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages.last().unwrap(), &b"foo".to_vec());
+    }
+
+    #[test]
+    fn should_parse_overflow_policy() {
+        assert_eq!("drop-oldest".parse::<OverflowPolicy>().unwrap(), OverflowPolicy::DropOldest);
+        assert_eq!("drop-newest".parse::<OverflowPolicy>().unwrap(), OverflowPolicy::DropNewest);
+        assert!("foo".parse::<OverflowPolicy>().is_err());
+    }
+}
@@ -0,0 +1,467 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::cmp::min;
+
+use super::StorageError;
+use super::encoding::*;
+
+// Numeric series are split into windows of 2^levels samples and each window is decomposed with Haar
+// wavelet into an approximation band (window mean) and `levels` detail bands of growing frequency.
+// Each band is kept in its own directory so resolution of stored data can be reduced by deleting
+// directories of the highest bands. Samples within a window are assumed to be taken at regular intervals.
+// Number of detail bands kept after dropping is recorded so that later windows are not stored in higher bands.
+
+/// Haar wavelet decomposition; returns approximation band followed by detail bands from lowest to highest frequency
+pub fn haar_forward(samples: &[f64]) -> Vec<Vec<f64>> {
+    let mut current = samples.to_vec();
+    let mut details = Vec::new();
+
+    while current.len() > 1 {
+        let approximation = current.chunks(2).map(|pair| (pair[0] + pair[1]) / 2.0).collect();
+        let detail = current.chunks(2).map(|pair| (pair[0] - pair[1]) / 2.0).collect();
+        details.push(detail);
+        current = approximation;
+    }
+
+    let mut bands = vec![current];
+    bands.extend(details.into_iter().rev());
+    bands
+}
+
+/// Reconstructs 2^(bands.len() - 1) samples from approximation band and given detail bands;
+/// each sample is the mean of original samples it covers when highest bands are missing
+pub fn haar_inverse(bands: &[Vec<f64>]) -> Vec<f64> {
+    let mut current = bands[0].clone();
+
+    for detail in bands[1..].iter() {
+        let mut next = Vec::with_capacity(current.len() * 2);
+        for (approximation, detail) in current.iter().zip(detail.iter()) {
+            next.push(approximation + detail);
+            next.push(approximation - detail);
+        }
+        current = next;
+    }
+    current
+}
+
+/// Coefficients of single band for one window
+struct BandRecord {
+    window_start: i64,
+    window_end: i64,
+    count: usize,
+    coefficients: Vec<f64>
+}
+
+// Record layout: u32 LE body length followed by body of: zigzag varint window start and end timestamp,
+// varint sample count, varint coefficient count and XOR encoded coefficients
+impl BandRecord {
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        write_varint(&mut body, zigzag(self.window_start));
+        write_varint(&mut body, zigzag(self.window_end));
+        write_varint(&mut body, self.count as u64);
+        write_varint(&mut body, self.coefficients.len() as u64);
+        body.extend(encode_floats(&self.coefficients));
+
+        let length = body.len() as u32;
+        let mut record = vec![length as u8, (length >> 8) as u8, (length >> 16) as u8, (length >> 24) as u8];
+        record.extend(body);
+        record
+    }
+
+    fn decode(body: &[u8]) -> Option<BandRecord> {
+        let mut position = 0;
+        let window_start = read_varint(body, &mut position);
+        let window_end = read_varint(body, &mut position);
+        let count = read_varint(body, &mut position);
+        let coefficient_count = read_varint(body, &mut position);
+
+        match (window_start, window_end, count, coefficient_count) {
+            (Some(window_start), Some(window_end), Some(count), Some(coefficient_count)) => {
+                decode_floats(&body[position..], coefficient_count as usize).map(|coefficients|
+                    BandRecord {
+                        window_start: unzigzag(window_start),
+                        window_end: unzigzag(window_end),
+                        count: count as usize,
+                        coefficients: coefficients
+                    }
+                )
+            },
+            _ => None
+        }
+    }
+}
+
+fn band_path(dir: &Path, band: u8) -> PathBuf {
+    dir.join(format!("band-{:02}", band)).join("coefficients.dat")
+}
+
+fn kept_bands_path(dir: &Path) -> PathBuf {
+    dir.join("detail_bands")
+}
+
+/// Reads number of detail bands kept after highest bands were dropped
+fn read_kept_bands(dir: &Path) -> Result<Option<u8>, StorageError> {
+    let path = kept_bands_path(dir);
+    let mut data = String::new();
+    match File::open(&path) {
+        Ok(mut file) => try!(file.read_to_string(&mut data)),
+        Err(_) => return Ok(None)
+    };
+    data.trim().parse::<u8>().map(Some).map_err(|_| StorageError::Corrupted(path.clone(), format!("invalid number of detail bands: {:?}", data)))
+}
+
+fn append_record(path: &Path, record: &BandRecord) -> Result<(), StorageError> {
+    try!(fs::create_dir_all(path.parent().unwrap()));
+    let mut file = try!(OpenOptions::new().append(true).create(true).open(path));
+    try!(file.write_all(&record.encode()));
+    Ok(())
+}
+
+/// Reads all complete records of a band; missing band file yields no records
+fn read_records(path: &Path) -> Result<Vec<BandRecord>, StorageError> {
+    let mut data = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => try!(file.read_to_end(&mut data)),
+        Err(_) => return Ok(Vec::new())
+    };
+
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= data.len() {
+        let length = data[offset] as usize | (data[offset + 1] as usize) << 8 | (data[offset + 2] as usize) << 16 | (data[offset + 3] as usize) << 24;
+        if offset + 4 + length > data.len() {
+            warn!("Ignoring incomplete band record at offset {} of '{}'", offset, path.display());
+            break;
+        }
+        match BandRecord::decode(&data[offset + 4..offset + 4 + length]) {
+            Some(record) => records.push(record),
+            None => return Err(StorageError::Corrupted(path.to_path_buf(), format!("invalid band record at offset {}", offset)))
+        }
+        offset += 4 + length;
+    }
+    Ok(records)
+}
+
+/// Frequency band storage of single numeric series
+pub struct BandSeries {
+    dir: PathBuf,
+    levels: u8,
+    /// Detail bands written for new windows; lower than `levels` once highest bands were dropped
+    kept_bands: u8,
+    buffer: Vec<(i64, f64)>
+}
+
+impl BandSeries {
+    pub fn open(dir: PathBuf, levels: u8) -> Result<BandSeries, StorageError> {
+        let kept_bands = min(try!(read_kept_bands(&dir)).unwrap_or(levels), levels);
+        Ok(BandSeries {
+            dir: dir,
+            levels: levels,
+            kept_bands: kept_bands,
+            buffer: Vec::new()
+        })
+    }
+
+    fn window_size(&self) -> usize {
+        1 << self.levels
+    }
+
+    pub fn append(&mut self, timestamp: i64, value: f64) -> Result<(), StorageError> {
+        self.buffer.push((timestamp, value));
+        if self.buffer.len() >= self.window_size() {
+            try!(self.flush());
+        }
+        Ok(())
+    }
+
+    /// Writes buffered samples as a window; partial window is padded with its last sample
+    pub fn flush(&mut self) -> Result<(), StorageError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let window = self.buffer.split_off(0);
+        let count = window.len();
+        let window_start = window[0].0;
+        let window_end = window[count - 1].0;
+
+        let mut samples: Vec<f64> = window.iter().map(|&(_, value)| value).collect();
+        let last = samples[count - 1];
+        samples.resize(self.window_size(), last);
+
+        for (band, coefficients) in haar_forward(&samples).into_iter().take(self.kept_bands as usize + 1).enumerate() {
+            try!(append_record(&band_path(&self.dir, band as u8), &BandRecord {
+                window_start: window_start,
+                window_end: window_end,
+                count: count,
+                coefficients: coefficients
+            }));
+        }
+        Ok(())
+    }
+
+    /// Number of bands available on disk starting from the approximation band
+    pub fn stored_bands(&self) -> u8 {
+        (0..self.levels + 1).take_while(|band| band_path(&self.dir, *band).exists()).count() as u8
+    }
+
+    /// Removes highest bands so that only approximation band and `detail_bands` lowest detail bands are kept;
+    /// windows written later are stored in the kept bands only
+    pub fn drop_bands(&mut self, detail_bands: u8) -> Result<(), StorageError> {
+        if detail_bands < self.kept_bands {
+            try!(fs::create_dir_all(&self.dir));
+            try!(try!(File::create(kept_bands_path(&self.dir))).write_all(format!("{}\n", detail_bands).as_bytes()));
+            self.kept_bands = detail_bands;
+        }
+        if detail_bands >= self.levels {
+            return Ok(());
+        }
+
+        for band in (detail_bands + 1)..(self.levels + 1) {
+            let band_dir = self.dir.join(format!("band-{:02}", band));
+            if band_dir.exists() {
+                info!("Removing band {} from: {}", band, self.dir.display());
+                try!(fs::remove_dir_all(band_dir));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reconstructs samples in given time range using at most `detail_bands` detail bands;
+    /// with fewer bands each sample is the mean of 2^(levels - detail_bands) original samples
+    pub fn query(&self, from: i64, to: i64, detail_bands: u8) -> Result<Vec<(i64, f64)>, StorageError> {
+        let mut windows = try!(read_records(&band_path(&self.dir, 0)));
+        windows.retain(|window| window.window_end >= from && window.window_start < to);
+
+        // windows are told apart by their end too as a partial window may start at the same time as the next one
+        let mut details: Vec<HashMap<(i64, i64), Vec<f64>>> = Vec::new();
+        for band in 1..(min(detail_bands, self.levels) + 1) {
+            let records = try!(read_records(&band_path(&self.dir, band)));
+            if records.is_empty() {
+                break;
+            }
+            details.push(records.into_iter()
+                         .filter(|record| record.window_end >= from && record.window_start < to)
+                         .map(|record| ((record.window_start, record.window_end), record.coefficients))
+                         .collect());
+        }
+
+        let mut points = Vec::new();
+        for window in windows {
+            let mut bands = vec![window.coefficients];
+            for band in details.iter() {
+                match band.get(&(window.window_start, window.window_end)) {
+                    Some(coefficients) => bands.push(coefficients.clone()),
+                    None => break
+                }
+            }
+
+            let group = self.window_size() >> (bands.len() - 1);
+            let samples = haar_inverse(&bands);
+            let interval = if window.count > 1 {
+                (window.window_end - window.window_start) as f64 / (window.count - 1) as f64
+            } else {
+                0.0
+            };
+
+            for (index, value) in samples.into_iter().enumerate() {
+                let first_sample = index * group;
+                if first_sample >= window.count {
+                    break;
+                }
+                let timestamp = window.window_start + (first_sample as f64 * interval).round() as i64;
+                if timestamp >= from && timestamp < to {
+                    points.push((timestamp, value));
+                }
+            }
+        }
+
+        let buffer_group = self.window_size() >> min(detail_bands, self.levels);
+        for group in self.buffer.chunks(buffer_group) {
+            let timestamp = group[0].0;
+            if timestamp >= from && timestamp < to {
+                points.push((timestamp, group.iter().map(|&(_, value)| value).sum::<f64>() / group.len() as f64));
+            }
+        }
+
+        points.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(points)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use tempdir::TempDir;
+    pub use std::f64::consts::PI;
+
+    pub fn sine(count: usize) -> Vec<f64> {
+        (0..count).map(|i| (2.0 * PI * i as f64 / 64.0).sin() + 0.3 * (2.0 * PI * i as f64 / 5.0).sin()).collect()
+    }
+
+    #[test]
+    fn haar_transform_should_be_reversible() {
+        let samples = sine(256);
+        let bands = haar_forward(&samples);
+
+        assert_eq!(bands.len(), 9);
+        assert_eq!(bands[0].len(), 1);
+        assert_eq!(bands[8].len(), 128);
+
+        for (reconstructed, original) in haar_inverse(&bands).iter().zip(samples.iter()) {
+            assert!((reconstructed - original).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn haar_inverse_without_highest_bands_should_give_means_of_sample_groups() {
+        let samples = vec![1.0, 3.0, 2.0, 2.0, 10.0, 0.0, 4.0, 8.0];
+        let bands = haar_forward(&samples);
+
+        assert_eq!(haar_inverse(&bands[..1]), vec![3.75]);
+        assert_eq!(haar_inverse(&bands[..2]), vec![2.0, 5.5]);
+        assert_eq!(haar_inverse(&bands[..3]), vec![2.0, 2.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn reconstruction_error_should_be_bounded_by_sample_group_range() {
+        let dir = TempDir::new("dms-bands").unwrap();
+        let mut series = BandSeries::open(dir.path().to_path_buf(), 8).unwrap();
+
+        let samples = sine(512);
+        for (index, value) in samples.iter().enumerate() {
+            series.append(index as i64 * 1000, *value).unwrap();
+        }
+
+        let full = series.query(0, 512 * 1000, 8).unwrap();
+        assert_eq!(full.len(), 512);
+        for (index, &(timestamp, value)) in full.iter().enumerate() {
+            assert_eq!(timestamp, index as i64 * 1000);
+            assert!((value - samples[index]).abs() < 1e-9);
+        }
+
+        for detail_bands in 0..8 {
+            let group = 1 << (8 - detail_bands);
+            let reduced = series.query(0, 512 * 1000, detail_bands).unwrap();
+            assert_eq!(reduced.len(), 512 / group);
+
+            for (index, &(timestamp, value)) in reduced.iter().enumerate() {
+                let originals = &samples[index * group..(index + 1) * group];
+                let min = originals.iter().cloned().fold(f64::INFINITY, f64::min);
+                let max = originals.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+                assert_eq!(timestamp, (index * group) as i64 * 1000);
+                for original in originals {
+                    assert!((value - original).abs() <= max - min + 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dropping_highest_bands_should_reduce_stored_resolution() {
+        let dir = TempDir::new("dms-bands").unwrap();
+        let mut series = BandSeries::open(dir.path().to_path_buf(), 4).unwrap();
+
+        for index in 0..32 {
+            series.append(index * 1000, index as f64).unwrap();
+        }
+        assert_eq!(series.stored_bands(), 5);
+        assert_eq!(series.query(0, 32000, 4).unwrap().len(), 32);
+
+        series.drop_bands(2).unwrap();
+        assert_eq!(series.stored_bands(), 3);
+
+        let reduced = series.query(0, 32000, 4).unwrap();
+        assert_eq!(reduced, vec![
+            (0, 1.5), (4000, 5.5), (8000, 9.5), (12000, 13.5),
+            (16000, 17.5), (20000, 21.5), (24000, 25.5), (28000, 29.5)
+        ]);
+    }
+
+    #[test]
+    fn dropped_bands_should_not_be_written_again() {
+        let dir = TempDir::new("dms-bands").unwrap();
+        {
+            let mut series = BandSeries::open(dir.path().to_path_buf(), 4).unwrap();
+            for index in 0..16 {
+                series.append(index * 1000, index as f64).unwrap();
+            }
+            series.drop_bands(2).unwrap();
+            for index in 16..32 {
+                series.append(index * 1000, index as f64).unwrap();
+            }
+            assert_eq!(series.stored_bands(), 3);
+        }
+
+        let mut series = BandSeries::open(dir.path().to_path_buf(), 4).unwrap();
+        for index in 32..48 {
+            series.append(index * 1000, index as f64).unwrap();
+        }
+        assert_eq!(series.stored_bands(), 3);
+        assert_eq!(series.query(0, 48000, 4).unwrap().len(), 12);
+
+        // dropping fewer bands than already dropped does not bring them back
+        series.drop_bands(3).unwrap();
+        for index in 48..64 {
+            series.append(index * 1000, index as f64).unwrap();
+        }
+        assert_eq!(series.stored_bands(), 3);
+    }
+
+    #[test]
+    fn dropping_no_bands_should_keep_all_of_them() {
+        let dir = TempDir::new("dms-bands").unwrap();
+        let mut series = BandSeries::open(dir.path().to_path_buf(), 4).unwrap();
+        for index in 0..16 {
+            series.append(index * 1000, index as f64).unwrap();
+        }
+
+        series.drop_bands(4).unwrap();
+        series.drop_bands(255).unwrap();
+        assert_eq!(series.stored_bands(), 5);
+        assert_eq!(series.query(0, 16000, 4).unwrap().len(), 16);
+    }
+
+    #[test]
+    fn partial_window_should_be_reconstructed_without_padding() {
+        let dir = TempDir::new("dms-bands").unwrap();
+        {
+            let mut series = BandSeries::open(dir.path().to_path_buf(), 4).unwrap();
+            for index in 0..5 {
+                series.append(index * 1000, index as f64).unwrap();
+            }
+            assert_eq!(series.query(0, 10000, 4).unwrap().len(), 5);
+            series.flush().unwrap();
+        }
+
+        let series = BandSeries::open(dir.path().to_path_buf(), 4).unwrap();
+        let points = series.query(0, 10000, 4).unwrap();
+        assert_eq!(points.len(), 5);
+        for (index, &(timestamp, value)) in points.iter().enumerate() {
+            assert_eq!(timestamp, index as i64 * 1000);
+            assert!((value - index as f64).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn windows_starting_at_same_time_should_be_reconstructed_from_own_details() {
+        let dir = TempDir::new("dms-bands").unwrap();
+        let mut series = BandSeries::open(dir.path().to_path_buf(), 2).unwrap();
+        series.append(0, 5.0).unwrap();
+        series.flush().unwrap();
+        for index in 0..4 {
+            series.append(index * 1000, index as f64).unwrap();
+        }
+
+        let points = series.query(0, 4000, 2).unwrap();
+        assert_eq!(points.len(), 5);
+        assert!(points.iter().any(|&(timestamp, value)| timestamp == 0 && (value - 5.0).abs() < 1e-9));
+        for index in 0..4 {
+            assert!(points.iter().any(|&(timestamp, value)| timestamp == index * 1000 && (value - index as f64).abs() < 1e-9));
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use messaging::DataValue;
+use super::StorageError;
+use super::encoding::*;
+
+/// Values of single type kept together so they can be encoded with type specific encoding
+#[derive(Debug, Clone, PartialEq)]
+pub enum Values {
+    Integer(Vec<i64>),
+    Float(Vec<f64>),
+    Bool(Vec<bool>),
+    Text(Vec<String>)
+}
+
+impl Values {
+    pub fn for_value(value: &DataValue) -> Values {
+        match value {
+            &DataValue::Integer(_) => Values::Integer(Vec::new()),
+            &DataValue::Float(_) => Values::Float(Vec::new()),
+            &DataValue::Bool(_) => Values::Bool(Vec::new()),
+            &DataValue::Text(_) => Values::Text(Vec::new())
+        }
+    }
+
+    /// Returns value back if it is of different type than values already stored
+    pub fn push(&mut self, value: DataValue) -> Result<(), DataValue> {
+        match (self, value) {
+            (&mut Values::Integer(ref mut values), DataValue::Integer(value)) => values.push(value),
+            (&mut Values::Float(ref mut values), DataValue::Float(value)) => values.push(value),
+            (&mut Values::Bool(ref mut values), DataValue::Bool(value)) => values.push(value),
+            (&mut Values::Text(ref mut values), DataValue::Text(value)) => values.push(value),
+            (_, value) => return Err(value)
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            &Values::Integer(ref values) => values.len(),
+            &Values::Float(ref values) => values.len(),
+            &Values::Bool(ref values) => values.len(),
+            &Values::Text(ref values) => values.len()
+        }
+    }
+
+    pub fn get(&self, index: usize) -> DataValue {
+        match self {
+            &Values::Integer(ref values) => DataValue::Integer(values[index]),
+            &Values::Float(ref values) => DataValue::Float(values[index]),
+            &Values::Bool(ref values) => DataValue::Bool(values[index]),
+            &Values::Text(ref values) => DataValue::Text(values[index].clone())
+        }
+    }
+
+    fn type_tag(&self) -> u8 {
+        match self {
+            &Values::Integer(_) => 0,
+            &Values::Float(_) => 1,
+            &Values::Bool(_) => 2,
+            &Values::Text(_) => 3
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            &Values::Integer(ref values) => encode_integers(values),
+            &Values::Float(ref values) => encode_floats(values),
+            &Values::Bool(ref values) => encode_bools(values),
+            &Values::Text(ref values) => encode_texts(values)
+        }
+    }
+
+    fn decode(type_tag: u8, bytes: &[u8], count: usize) -> Option<Values> {
+        match type_tag {
+            0 => decode_integers(bytes, count).map(Values::Integer),
+            1 => decode_floats(bytes, count).map(Values::Float),
+            2 => decode_bools(bytes, count).map(Values::Bool),
+            3 => decode_texts(bytes, count).map(Values::Text),
+            _ => None
+        }
+    }
+}
+
+/// Points of single series with nanosecond timestamps
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub timestamps: Vec<i64>,
+    pub values: Values
+}
+
+// Block layout: u32 LE body length followed by body of:
+// type tag, varint count, zigzag varint min and max timestamp, varint timestamps length,
+// encoded timestamps and encoded values
+impl Block {
+    pub fn encode(&self) -> Vec<u8> {
+        let timestamps = encode_timestamps(&self.timestamps);
+        let values = self.values.encode();
+
+        let mut body = Vec::with_capacity(timestamps.len() + values.len() + 32);
+        body.push(self.values.type_tag());
+        write_varint(&mut body, self.timestamps.len() as u64);
+        write_varint(&mut body, zigzag(self.min_timestamp()));
+        write_varint(&mut body, zigzag(self.max_timestamp()));
+        write_varint(&mut body, timestamps.len() as u64);
+        body.extend_from_slice(&timestamps);
+        body.extend_from_slice(&values);
+
+        let length = body.len() as u32;
+        let mut block = vec![length as u8, (length >> 8) as u8, (length >> 16) as u8, (length >> 24) as u8];
+        block.extend(body);
+        block
+    }
+
+    pub fn decode(body: &[u8]) -> Option<Block> {
+        let info = match BlockHeader::decode(body) {
+            Some(info) => info,
+            None => return None
+        };
+
+        let mut position = info.payload_offset;
+        let timestamps_length = match read_varint(body, &mut position) {
+            Some(length) => length as usize,
+            None => return None
+        };
+        if timestamps_length > body.len() - position {
+            return None;
+        }
+
+        let timestamps = match decode_timestamps(&body[position..position + timestamps_length], info.count) {
+            Some(timestamps) => timestamps,
+            None => return None
+        };
+
+        Values::decode(info.type_tag, &body[position + timestamps_length..], info.count).map(|values|
+            Block {
+                timestamps: timestamps,
+                values: values
+            }
+        )
+    }
+
+    pub fn min_timestamp(&self) -> i64 {
+        self.timestamps.iter().cloned().min().unwrap_or(0)
+    }
+
+    pub fn max_timestamp(&self) -> i64 {
+        self.timestamps.iter().cloned().max().unwrap_or(0)
+    }
+}
+
+struct BlockHeader {
+    type_tag: u8,
+    count: usize,
+    min_timestamp: i64,
+    max_timestamp: i64,
+    payload_offset: usize
+}
+
+impl BlockHeader {
+    fn decode(body: &[u8]) -> Option<BlockHeader> {
+        let type_tag = match body.get(0) {
+            Some(type_tag) => *type_tag,
+            None => return None
+        };
+
+        let mut position = 1;
+        let count = read_varint(body, &mut position);
+        let min_timestamp = read_varint(body, &mut position);
+        let max_timestamp = read_varint(body, &mut position);
+
+        match (count, min_timestamp, max_timestamp) {
+            (Some(count), Some(min_timestamp), Some(max_timestamp)) => Some(BlockHeader {
+                type_tag: type_tag,
+                count: count as usize,
+                min_timestamp: unzigzag(min_timestamp),
+                max_timestamp: unzigzag(max_timestamp),
+                payload_offset: position
+            }),
+            _ => None
+        }
+    }
+}
+
+struct BlockIndex {
+    offset: u64,
+    length: usize,
+    min_timestamp: i64,
+    max_timestamp: i64
+}
+
+/// Append only file of blocks
+pub struct Chunk {
+    path: PathBuf,
+    size: u64,
+    blocks: Vec<BlockIndex>
+}
+
+impl Chunk {
+    /// Opens or creates chunk file and indexes its blocks; incomplete block left by crash is truncated
+    pub fn open(path: &Path) -> Result<Chunk, StorageError> {
+        let mut file = try!(OpenOptions::new().read(true).write(true).create(true).open(path));
+
+        let mut data = Vec::new();
+        try!(file.read_to_end(&mut data));
+
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while offset + 4 <= data.len() {
+            let length = data[offset] as usize | (data[offset + 1] as usize) << 8 | (data[offset + 2] as usize) << 16 | (data[offset + 3] as usize) << 24;
+            if offset + 4 + length > data.len() {
+                break;
+            }
+
+            match BlockHeader::decode(&data[offset + 4..offset + 4 + length]) {
+                Some(header) => blocks.push(BlockIndex {
+                    offset: offset as u64 + 4,
+                    length: length,
+                    min_timestamp: header.min_timestamp,
+                    max_timestamp: header.max_timestamp
+                }),
+                None => return Err(StorageError::Corrupted(path.to_path_buf(), format!("invalid block header at offset {}", offset)))
+            }
+            offset += 4 + length;
+        }
+
+        if offset < data.len() {
+            warn!("Truncating incomplete block at offset {} of chunk '{}'", offset, path.display());
+            try!(file.set_len(offset as u64));
+        }
+
+        Ok(Chunk {
+            path: path.to_path_buf(),
+            size: offset as u64,
+            blocks: blocks
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn append(&mut self, block: &Block) -> Result<(), StorageError> {
+        let data = block.encode();
+        let mut file = try!(OpenOptions::new().append(true).open(&self.path));
+        try!(file.write_all(&data));
+
+        self.blocks.push(BlockIndex {
+            offset: self.size + 4,
+            length: data.len() - 4,
+            min_timestamp: block.min_timestamp(),
+            max_timestamp: block.max_timestamp()
+        });
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// Reads blocks that may contain points from given time range (from inclusive, to exclusive)
+    pub fn read_blocks(&self, from: i64, to: i64) -> Result<Vec<Block>, StorageError> {
+        let mut file = None;
+        let mut blocks = Vec::new();
+
+        for index in self.blocks.iter().filter(|index| index.max_timestamp >= from && index.min_timestamp < to) {
+            if file.is_none() {
+                file = Some(try!(File::open(&self.path)));
+            }
+            let file = file.as_mut().unwrap();
+
+            let mut body = vec![0; index.length];
+            try!(file.seek(SeekFrom::Start(index.offset)));
+            try!(file.read_exact(&mut body));
+
+            match Block::decode(&body) {
+                Some(block) => blocks.push(block),
+                None => return Err(StorageError::Corrupted(self.path.clone(), format!("invalid block at offset {}", index.offset)))
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use tempdir::TempDir;
+    pub use std::fs::OpenOptions;
+    pub use std::io::Write;
+
+    pub fn block(start: i64, values: Values) -> Block {
+        Block {
+            timestamps: (0..values.len() as i64).map(|i| start + i * 1000).collect(),
+            values: values
+        }
+    }
+
+    #[test]
+    fn block_should_round_trip_each_value_type() {
+        for values in vec![
+            Values::Integer(vec![1, 2, -3]),
+            Values::Float(vec![0.1, 0.2, 0.2]),
+            Values::Bool(vec![true, false, true]),
+            Values::Text(vec!["a".to_string(), "".to_string(), "a".to_string()])
+        ] {
+            let block = block(1455200000000000000, values);
+            let encoded = block.encode();
+            assert_eq!(Block::decode(&encoded[4..]), Some(block));
+        }
+    }
+
+    #[test]
+    fn block_with_corrupted_count_should_not_decode() {
+        let mut body = vec![0];
+        write_varint(&mut body, u64::max_value());
+        // body of encoded block without its type tag and count of 3 points
+        let encoded = block(1455200000000000000, Values::Integer(vec![1, 2, 3])).encode();
+        body.extend_from_slice(&encoded[4 + 2..]);
+        assert_eq!(Block::decode(&body), None);
+    }
+
+    #[test]
+    fn chunk_should_read_back_blocks_overlapping_time_range() {
+        let dir = TempDir::new("dms-chunk").unwrap();
+        let path = dir.path().join("0.chunk");
+
+        {
+            let mut chunk = Chunk::open(&path).unwrap();
+            chunk.append(&block(0, Values::Integer(vec![1, 2, 3]))).unwrap();
+            chunk.append(&block(10000, Values::Integer(vec![4, 5, 6]))).unwrap();
+        }
+
+        let chunk = Chunk::open(&path).unwrap();
+        assert_eq!(chunk.read_blocks(0, 20000).unwrap().len(), 2);
+        assert_eq!(chunk.read_blocks(2001, 10000).unwrap().len(), 0);
+        assert_eq!(chunk.read_blocks(2000, 10001).unwrap(), vec![
+            block(0, Values::Integer(vec![1, 2, 3])),
+            block(10000, Values::Integer(vec![4, 5, 6]))
+        ]);
+        assert_eq!(chunk.read_blocks(10000, 10001).unwrap(), vec![block(10000, Values::Integer(vec![4, 5, 6]))]);
+    }
+
+    #[test]
+    fn chunk_should_truncate_incomplete_block_on_open() {
+        let dir = TempDir::new("dms-chunk").unwrap();
+        let path = dir.path().join("0.chunk");
+
+        let size = {
+            let mut chunk = Chunk::open(&path).unwrap();
+            chunk.append(&block(0, Values::Float(vec![1.0, 2.0]))).unwrap();
+            chunk.size()
+        };
+
+        {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            let partial = block(10000, Values::Float(vec![3.0])).encode();
+            file.write_all(&partial[..partial.len() - 1]).unwrap();
+        }
+
+        let mut chunk = Chunk::open(&path).unwrap();
+        assert_eq!(chunk.size(), size);
+
+        chunk.append(&block(20000, Values::Float(vec![4.0]))).unwrap();
+        assert_eq!(chunk.read_blocks(0, 30000).unwrap(), vec![
+            block(0, Values::Float(vec![1.0, 2.0])),
+            block(20000, Values::Float(vec![4.0]))
+        ]);
+    }
+}
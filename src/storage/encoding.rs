@@ -0,0 +1,515 @@
+use std::mem;
+use std::collections::HashMap;
+
+// Compact encodings used for storing series data in chunk blocks.
+// Decoders return None when input is truncated or malformed.
+
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    used: u8
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            used: 0
+        }
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        if self.used == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= 0x80 >> self.used;
+        }
+        self.used = (self.used + 1) % 8;
+    }
+
+    pub fn write_bits(&mut self, value: u64, count: u8) {
+        for shift in (0..count).rev() {
+            self.write_bit((value >> shift) & 1 == 1);
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct BitReader<'b> {
+    bytes: &'b [u8],
+    position: usize
+}
+
+impl<'b> BitReader<'b> {
+    pub fn new(bytes: &'b [u8]) -> BitReader<'b> {
+        BitReader {
+            bytes: bytes,
+            position: 0
+        }
+    }
+
+    pub fn read_bit(&mut self) -> Option<bool> {
+        match self.bytes.get(self.position / 8) {
+            Some(byte) => {
+                let bit = byte & (0x80 >> (self.position % 8)) != 0;
+                self.position += 1;
+                Some(bit)
+            },
+            None => None
+        }
+    }
+
+    pub fn read_bits(&mut self, count: u8) -> Option<u64> {
+        let mut value = 0u64;
+        for _ in 0..count {
+            match self.read_bit() {
+                Some(bit) => value = (value << 1) | bit as u64,
+                None => return None
+            }
+        }
+        Some(value)
+    }
+}
+
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value = value >> 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn read_varint(bytes: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = match bytes.get(*position) {
+            Some(byte) => *byte,
+            None => return None
+        };
+        *position += 1;
+
+        if shift >= 64 {
+            return None;
+        }
+        value = value | ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+/// Tells if `count` values taking at least `min_bits` each could be encoded in given bytes;
+/// guards allocations sized by counts read from possibly corrupted block headers
+fn can_hold(bytes: &[u8], count: usize, min_bits: usize) -> bool {
+    count <= bytes.len().saturating_mul(8) / min_bits
+}
+
+pub fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+pub fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Delta-of-delta encoding of nanosecond timestamps; regular sampling intervals encode to single zero bytes
+pub fn encode_timestamps(timestamps: &[i64]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut previous = 0i64;
+    let mut previous_delta = 0i64;
+
+    for (index, &timestamp) in timestamps.iter().enumerate() {
+        if index == 0 {
+            write_varint(&mut out, zigzag(timestamp));
+        } else {
+            let delta = timestamp.wrapping_sub(previous);
+            write_varint(&mut out, zigzag(delta.wrapping_sub(previous_delta)));
+            previous_delta = delta;
+        }
+        previous = timestamp;
+    }
+    out
+}
+
+pub fn decode_timestamps(bytes: &[u8], count: usize) -> Option<Vec<i64>> {
+    if !can_hold(bytes, count, 8) {
+        return None
+    }
+    let mut timestamps = Vec::with_capacity(count);
+    let mut position = 0;
+    let mut previous = 0i64;
+    let mut previous_delta = 0i64;
+
+    for index in 0..count {
+        let value = match read_varint(bytes, &mut position) {
+            Some(value) => unzigzag(value),
+            None => return None
+        };
+
+        let timestamp = if index == 0 {
+            value
+        } else {
+            let delta = previous_delta.wrapping_add(value);
+            previous_delta = delta;
+            previous.wrapping_add(delta)
+        };
+
+        timestamps.push(timestamp);
+        previous = timestamp;
+    }
+    Some(timestamps)
+}
+
+fn float_bits(value: f64) -> u64 {
+    unsafe { mem::transmute(value) }
+}
+
+fn bits_float(bits: u64) -> f64 {
+    unsafe { mem::transmute(bits) }
+}
+
+/// XOR compression of floats as described in Facebook's Gorilla paper
+pub fn encode_floats(values: &[f64]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    let mut previous = 0u64;
+    let mut window: Option<(u8, u8)> = None;
+
+    for (index, &value) in values.iter().enumerate() {
+        let bits = float_bits(value);
+
+        if index == 0 {
+            writer.write_bits(bits, 64);
+        } else {
+            let xor = bits ^ previous;
+            if xor == 0 {
+                writer.write_bit(false);
+            } else {
+                writer.write_bit(true);
+
+                let leading = if xor.leading_zeros() > 31 { 31 } else { xor.leading_zeros() as u8 };
+                let trailing = xor.trailing_zeros() as u8;
+
+                match window {
+                    Some((window_leading, window_trailing)) if leading >= window_leading && trailing >= window_trailing => {
+                        writer.write_bit(false);
+                        writer.write_bits(xor >> window_trailing, 64 - window_leading - window_trailing);
+                    },
+                    _ => {
+                        let significant = 64 - leading - trailing;
+                        writer.write_bit(true);
+                        writer.write_bits(leading as u64, 5);
+                        writer.write_bits((significant - 1) as u64, 6);
+                        writer.write_bits(xor >> trailing, significant);
+                        window = Some((leading, trailing));
+                    }
+                }
+            }
+        }
+        previous = bits;
+    }
+    writer.into_bytes()
+}
+
+pub fn decode_floats(bytes: &[u8], count: usize) -> Option<Vec<f64>> {
+    if !can_hold(bytes, count, 1) {
+        return None
+    }
+    let mut reader = BitReader::new(bytes);
+    let mut values = Vec::with_capacity(count);
+    let mut previous = 0u64;
+    let mut window = (0u8, 0u8);
+
+    for index in 0..count {
+        let bits = if index == 0 {
+            match reader.read_bits(64) {
+                Some(bits) => bits,
+                None => return None
+            }
+        } else {
+            match reader.read_bit() {
+                Some(false) => previous,
+                Some(true) => {
+                    match reader.read_bit() {
+                        Some(false) => {
+                            let (leading, trailing) = window;
+                            match reader.read_bits(64 - leading - trailing) {
+                                Some(xor) => previous ^ (xor << trailing),
+                                None => return None
+                            }
+                        },
+                        Some(true) => {
+                            let leading = match reader.read_bits(5) {
+                                Some(leading) => leading as u8,
+                                None => return None
+                            };
+                            let significant = match reader.read_bits(6) {
+                                Some(significant) => significant as u8 + 1,
+                                None => return None
+                            };
+                            if leading + significant > 64 {
+                                return None;
+                            }
+                            let trailing = 64 - leading - significant;
+                            window = (leading, trailing);
+                            match reader.read_bits(significant) {
+                                Some(xor) => previous ^ (xor << trailing),
+                                None => return None
+                            }
+                        },
+                        None => return None
+                    }
+                },
+                None => return None
+            }
+        };
+
+        values.push(bits_float(bits));
+        previous = bits;
+    }
+    Some(values)
+}
+
+/// Zigzag varint encoded deltas between consecutive integers
+pub fn encode_integers(values: &[i64]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut previous = 0i64;
+    for &value in values {
+        write_varint(&mut out, zigzag(value.wrapping_sub(previous)));
+        previous = value;
+    }
+    out
+}
+
+pub fn decode_integers(bytes: &[u8], count: usize) -> Option<Vec<i64>> {
+    if !can_hold(bytes, count, 8) {
+        return None
+    }
+    let mut values = Vec::with_capacity(count);
+    let mut position = 0;
+    let mut previous = 0i64;
+    for _ in 0..count {
+        match read_varint(bytes, &mut position) {
+            Some(delta) => previous = previous.wrapping_add(unzigzag(delta)),
+            None => return None
+        }
+        values.push(previous);
+    }
+    Some(values)
+}
+
+pub fn encode_bools(values: &[bool]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    for &value in values {
+        writer.write_bit(value);
+    }
+    writer.into_bytes()
+}
+
+pub fn decode_bools(bytes: &[u8], count: usize) -> Option<Vec<bool>> {
+    if !can_hold(bytes, count, 1) {
+        return None
+    }
+    let mut reader = BitReader::new(bytes);
+    let mut values = Vec::with_capacity(count);
+    for _ in 0..count {
+        match reader.read_bit() {
+            Some(value) => values.push(value),
+            None => return None
+        }
+    }
+    Some(values)
+}
+
+/// Dictionary of distinct strings followed by varint indexes into it
+pub fn encode_texts(values: &[String]) -> Vec<u8> {
+    let mut dictionary: Vec<&str> = Vec::new();
+    let mut lookup: HashMap<&str, u64> = HashMap::new();
+    let mut indexes = Vec::with_capacity(values.len());
+
+    for value in values {
+        let index = match lookup.get(&value[..]) {
+            Some(index) => *index,
+            None => {
+                dictionary.push(&value[..]);
+                (dictionary.len() - 1) as u64
+            }
+        };
+        lookup.insert(&value[..], index);
+        indexes.push(index);
+    }
+
+    let mut out = Vec::new();
+    write_varint(&mut out, dictionary.len() as u64);
+    for entry in dictionary {
+        write_varint(&mut out, entry.len() as u64);
+        out.extend_from_slice(entry.as_bytes());
+    }
+    for index in indexes {
+        write_varint(&mut out, index);
+    }
+    out
+}
+
+pub fn decode_texts(bytes: &[u8], count: usize) -> Option<Vec<String>> {
+    if !can_hold(bytes, count, 8) {
+        return None
+    }
+    let mut position = 0;
+
+    let dictionary_size = match read_varint(bytes, &mut position) {
+        Some(size) => size as usize,
+        None => return None
+    };
+
+    let mut dictionary = Vec::new();
+    for _ in 0..dictionary_size {
+        let length = match read_varint(bytes, &mut position) {
+            Some(length) => length as usize,
+            None => return None
+        };
+        if length > bytes.len() - position {
+            return None;
+        }
+        match String::from_utf8(bytes[position..position + length].to_vec()) {
+            Ok(entry) => dictionary.push(entry),
+            Err(_) => return None
+        }
+        position += length;
+    }
+
+    let mut values = Vec::with_capacity(count);
+    for _ in 0..count {
+        match read_varint(bytes, &mut position).and_then(|index| dictionary.get(index as usize)) {
+            Some(entry) => values.push(entry.clone()),
+            None => return None
+        }
+    }
+    Some(values)
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use std::{i64, f64};
+    use super::float_bits;
+
+    #[test]
+    fn varint_should_round_trip() {
+        let values = vec![0, 1, 127, 128, 300, u64::max_value()];
+        let mut bytes = Vec::new();
+        for value in values.iter() {
+            write_varint(&mut bytes, *value);
+        }
+
+        let mut position = 0;
+        for value in values {
+            assert_eq!(read_varint(&bytes, &mut position), Some(value));
+        }
+        assert_eq!(read_varint(&bytes, &mut position), None);
+    }
+
+    #[test]
+    fn zigzag_should_round_trip() {
+        for value in vec![0, 1, -1, 42, -42, i64::MIN, i64::MAX] {
+            assert_eq!(unzigzag(zigzag(value)), value);
+        }
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+    }
+
+    #[test]
+    fn timestamps_should_round_trip() {
+        let timestamps = vec![1455200000000000000, 1455200001000000000, 1455200002000000000, 1455200002500000001, 1455199000000000000, i64::MIN, i64::MAX, 0];
+        let bytes = encode_timestamps(&timestamps);
+        assert_eq!(decode_timestamps(&bytes, timestamps.len()), Some(timestamps));
+    }
+
+    #[test]
+    fn regular_timestamps_should_take_single_byte_per_point() {
+        let timestamps: Vec<i64> = (0..100).map(|i| 1455200000000000000 + i * 1000000000).collect();
+        let bytes = encode_timestamps(&timestamps);
+        assert_eq!(bytes.len(), 9 + 5 + 98);
+    }
+
+    #[test]
+    fn floats_should_round_trip() {
+        let values = vec![0.2, 0.2, 0.4, -0.0, 0.0, 1.5e300, f64::MIN_POSITIVE, f64::INFINITY, f64::NEG_INFINITY, 12.0, 12.5, 13.0];
+        let bytes = encode_floats(&values);
+        let decoded = decode_floats(&bytes, values.len()).unwrap();
+        for (decoded, value) in decoded.into_iter().zip(values.into_iter()) {
+            assert_eq!(float_bits(decoded), float_bits(value));
+        }
+    }
+
+    #[test]
+    fn floats_should_preserve_nan() {
+        let bytes = encode_floats(&[1.0, f64::NAN, 1.0]);
+        let decoded = decode_floats(&bytes, 3).unwrap();
+        assert_eq!(decoded[0], 1.0);
+        assert!(decoded[1].is_nan());
+        assert_eq!(decoded[2], 1.0);
+    }
+
+    #[test]
+    fn repeated_floats_should_take_single_bit_per_point() {
+        let values = vec![0.2; 81];
+        assert_eq!(encode_floats(&values).len(), 8 + 10);
+    }
+
+    #[test]
+    fn integers_should_round_trip() {
+        let values = vec![0, 42, -42, i64::MIN, i64::MAX, i64::MIN, 7];
+        let bytes = encode_integers(&values);
+        assert_eq!(decode_integers(&bytes, values.len()), Some(values));
+    }
+
+    #[test]
+    fn bools_should_round_trip() {
+        let values = vec![true, false, false, true, true, true, false, true, true];
+        let bytes = encode_bools(&values);
+        assert_eq!(bytes.len(), 2);
+        assert_eq!(decode_bools(&bytes, values.len()), Some(values));
+    }
+
+    #[test]
+    fn texts_should_round_trip_using_dictionary() {
+        let values: Vec<String> = vec!["ok", "ok", "", "zażółć", "ok", ""].into_iter().map(|s| s.to_string()).collect();
+        let bytes = encode_texts(&values);
+        assert_eq!(bytes.len(), 1 + 3 + 1 + 11 + 6);
+        assert_eq!(decode_texts(&bytes, values.len()), Some(values));
+    }
+
+    #[test]
+    fn decoders_should_fail_on_truncated_input() {
+        let bytes = encode_integers(&[1, 1000, 100000]);
+        assert_eq!(decode_integers(&bytes[..bytes.len() - 1], 3), None);
+
+        let bytes = encode_floats(&[1.0, 2.0, 3.0]);
+        assert_eq!(decode_floats(&bytes[..4], 3), None);
+
+        let texts = vec!["foo".to_string()];
+        let bytes = encode_texts(&texts);
+        assert_eq!(decode_texts(&bytes[..3], 1), None);
+    }
+
+    #[test]
+    fn decoders_should_fail_on_count_exceeding_input() {
+        let huge = usize::max_value();
+        assert_eq!(decode_timestamps(&encode_timestamps(&[1, 2, 3]), huge), None);
+        assert_eq!(decode_floats(&encode_floats(&[1.0, 2.0, 3.0]), huge), None);
+        assert_eq!(decode_integers(&encode_integers(&[1, 2, 3]), huge), None);
+        assert_eq!(decode_bools(&encode_bools(&[true, false]), huge), None);
+        assert_eq!(decode_texts(&encode_texts(&["foo".to_string()]), huge), None);
+        assert_eq!(decode_texts(&[1, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01], 1), None);
+    }
+}
@@ -0,0 +1,519 @@
+use std::fs;
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use chrono::{DateTime, UTC, Timelike, TimeZone};
+
+use messaging::*;
+
+use self::chunk::{Chunk, Block, Values};
+use self::bands::BandSeries;
+
+mod encoding;
+mod chunk;
+mod bands;
+
+/// Number of points of single series buffered in memory before they are encoded and appended to chunk
+const BLOCK_POINTS: usize = 512;
+/// Size after which new chunk file is started for a series
+const MAX_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+/// Numeric series are also stored as frequency bands in windows of 2^BAND_LEVELS samples
+pub const BAND_LEVELS: u8 = 8;
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(IoError),
+    Corrupted(PathBuf, String),
+    TimestampOutOfRange(DateTime<UTC>)
+}
+
+impl From<IoError> for StorageError {
+    fn from(err: IoError) -> StorageError {
+        StorageError::Io(err)
+    }
+}
+
+impl Error for StorageError {
+    fn description(&self) -> &str {
+        match self {
+            &StorageError::Io(_) => "Storage IO error",
+            &StorageError::Corrupted(_, _) => "Storage data corrupted",
+            &StorageError::TimestampOutOfRange(_) => "Timestamp out of storage range",
+        }
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &StorageError::Io(ref err) => write!(f, "{}: {}", self.description(), err),
+            &StorageError::Corrupted(ref path, ref err) => write!(f, "{} in '{}': {}", self.description(), path.display(), err),
+            &StorageError::TimestampOutOfRange(ref timestamp) => write!(f, "{}: {}", self.description(), timestamp),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SeriesKey {
+    pub location: String,
+    pub path: String,
+    pub component: String
+}
+
+impl SeriesKey {
+    pub fn new<L, P, C>(location: L, path: P, component: C) -> SeriesKey where L: Into<String>, P: Into<String>, C: Into<String> {
+        SeriesKey {
+            location: location.into(),
+            path: path.into(),
+            component: component.into()
+        }
+    }
+
+    // Directory name is made of percent encoded key parts separated with commas
+    fn dir_name(&self) -> String {
+        fn encode(part: &str) -> String {
+            let mut encoded = String::new();
+            for byte in part.bytes() {
+                match byte {
+                    b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'_' | b'.' => encoded.push(byte as char),
+                    _ => encoded.push_str(&format!("%{:02X}", byte))
+                }
+            }
+            encoded
+        }
+        format!("{},{},{}", encode(&self.location), encode(&self.path), encode(&self.component))
+    }
+
+    fn from_dir_name(dir_name: &str) -> Option<SeriesKey> {
+        fn decode(part: &str) -> Option<String> {
+            let bytes = part.as_bytes();
+            let mut decoded = Vec::new();
+            let mut position = 0;
+            while position < bytes.len() {
+                if bytes[position] == b'%' {
+                    if position + 3 > bytes.len() {
+                        return None;
+                    }
+                    match String::from_utf8(bytes[position + 1..position + 3].to_vec()).ok().and_then(|hex| u8::from_str_radix(&hex, 16).ok()) {
+                        Some(byte) => decoded.push(byte),
+                        None => return None
+                    }
+                    position += 3;
+                } else {
+                    decoded.push(bytes[position]);
+                    position += 1;
+                }
+            }
+            String::from_utf8(decoded).ok()
+        }
+
+        let parts: Vec<&str> = dir_name.split(',').collect();
+        if parts.len() != 3 {
+            return None;
+        }
+
+        match (decode(parts[0]), decode(parts[1]), decode(parts[2])) {
+            (Some(location), Some(path), Some(component)) => Some(SeriesKey::new(location, path, component)),
+            _ => None
+        }
+    }
+}
+
+pub fn timestamp_to_nanos(timestamp: &DateTime<UTC>) -> Result<i64, StorageError> {
+    timestamp.timestamp().checked_mul(1_000_000_000)
+        .and_then(|nanos| nanos.checked_add(timestamp.nanosecond() as i64))
+        .ok_or(StorageError::TimestampOutOfRange(*timestamp))
+}
+
+pub fn nanos_to_timestamp(nanos: i64) -> DateTime<UTC> {
+    let mut seconds = nanos / 1_000_000_000;
+    let mut nanosecond = nanos % 1_000_000_000;
+    if nanosecond < 0 {
+        seconds -= 1;
+        nanosecond += 1_000_000_000;
+    }
+    UTC.timestamp(seconds, nanosecond as u32)
+}
+
+/// Points of single series: chunk files on disk and a buffer of points not yet written
+struct Series {
+    dir: PathBuf,
+    chunks: Vec<Chunk>,
+    next_chunk: u64,
+    timestamps: Vec<i64>,
+    values: Option<Values>
+}
+
+impl Series {
+    fn open(dir: PathBuf) -> Result<Series, StorageError> {
+        try!(fs::create_dir_all(&dir));
+
+        let mut chunk_numbers = Vec::new();
+        for entry in try!(fs::read_dir(&dir)) {
+            let path = try!(entry).path();
+            if path.extension().map(|extension| extension == "chunk").unwrap_or(false) {
+                match path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse::<u64>().ok()) {
+                    Some(number) => chunk_numbers.push(number),
+                    None => warn!("Ignoring unexpected file in series directory: {}", path.display())
+                }
+            }
+        }
+        chunk_numbers.sort();
+
+        let mut chunks = Vec::new();
+        for number in chunk_numbers.iter() {
+            chunks.push(try!(Chunk::open(&Series::chunk_path(&dir, *number))));
+        }
+
+        Ok(Series {
+            dir: dir,
+            chunks: chunks,
+            next_chunk: chunk_numbers.last().map(|number| number + 1).unwrap_or(0),
+            timestamps: Vec::new(),
+            values: None
+        })
+    }
+
+    fn chunk_path(dir: &Path, number: u64) -> PathBuf {
+        dir.join(format!("{:010}.chunk", number))
+    }
+
+    fn append(&mut self, timestamp: i64, value: DataValue) -> Result<(), StorageError> {
+        let value = match self.values {
+            Some(ref mut values) => values.push(value).err(),
+            None => Some(value)
+        };
+
+        // new series or value type changed
+        if let Some(value) = value {
+            try!(self.flush());
+            let mut values = Values::for_value(&value);
+            values.push(value).ok().expect("value of matching type");
+            self.values = Some(values);
+        }
+        self.timestamps.push(timestamp);
+
+        if self.timestamps.len() >= BLOCK_POINTS {
+            try!(self.flush());
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), StorageError> {
+        let values = match self.values.take() {
+            Some(values) => values,
+            None => return Ok(())
+        };
+
+        let block = Block {
+            timestamps: self.timestamps.split_off(0),
+            values: values
+        };
+
+        if self.chunks.last().map(|chunk| chunk.size() >= MAX_CHUNK_SIZE).unwrap_or(true) {
+            let path = Series::chunk_path(&self.dir, self.next_chunk);
+            debug!("Starting new chunk: {}", path.display());
+            self.chunks.push(try!(Chunk::open(&path)));
+            self.next_chunk += 1;
+        }
+
+        self.chunks.last_mut().unwrap().append(&block)
+    }
+
+    fn query(&self, from: i64, to: i64) -> Result<Vec<(i64, DataValue)>, StorageError> {
+        let mut points = Vec::new();
+
+        for chunk in self.chunks.iter() {
+            for block in try!(chunk.read_blocks(from, to)) {
+                points.extend(block_points(&block.timestamps, &block.values, from, to));
+            }
+        }
+
+        if let Some(ref values) = self.values {
+            points.extend(block_points(&self.timestamps, values, from, to));
+        }
+
+        points.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(points)
+    }
+}
+
+fn block_points(timestamps: &[i64], values: &Values, from: i64, to: i64) -> Vec<(i64, DataValue)> {
+    timestamps.iter().enumerate()
+        .filter(|&(_, timestamp)| *timestamp >= from && *timestamp < to)
+        .map(|(index, timestamp)| (*timestamp, values.get(index)))
+        .collect()
+}
+
+/// Time-series storage keeping points grouped by location, path and component
+pub struct Storage {
+    series_dir: PathBuf,
+    bands_dir: PathBuf,
+    series: HashMap<SeriesKey, Series>,
+    bands: HashMap<SeriesKey, BandSeries>
+}
+
+impl Storage {
+    pub fn open(data_dir: &Path) -> Result<Storage, StorageError> {
+        let series_dir = data_dir.join("series");
+        let bands_dir = data_dir.join("bands");
+        try!(fs::create_dir_all(&series_dir));
+        try!(fs::create_dir_all(&bands_dir));
+        info!("Storing series in: {}", series_dir.display());
+        info!("Storing frequency bands in: {}", bands_dir.display());
+
+        let mut series = HashMap::new();
+        for entry in try!(fs::read_dir(&series_dir)) {
+            let path = try!(entry).path();
+            match path.file_name().and_then(|name| name.to_str()).and_then(SeriesKey::from_dir_name) {
+                Some(key) => {
+                    series.insert(key, try!(Series::open(path)));
+                },
+                None => warn!("Ignoring unexpected entry in series directory: {}", path.display())
+            }
+        }
+        info!("Opened {} series", series.len());
+
+        Ok(Storage {
+            series_dir: series_dir,
+            bands_dir: bands_dir,
+            series: series,
+            bands: HashMap::new()
+        })
+    }
+
+    pub fn store(&mut self, raw_data_point: &RawDataPoint) -> Result<(), StorageError> {
+        let key = SeriesKey::new(&raw_data_point.location[..], &raw_data_point.path[..], &raw_data_point.component[..]);
+        let timestamp = try!(timestamp_to_nanos(&raw_data_point.timestamp));
+
+        if !self.series.contains_key(&key) {
+            let series = try!(Series::open(self.series_dir.join(key.dir_name())));
+            self.series.insert(key.clone(), series);
+        }
+
+        let numeric = match raw_data_point.value {
+            DataValue::Integer(value) => Some(value as f64),
+            DataValue::Float(value) => Some(value),
+            _ => None
+        };
+
+        if let Some(value) = numeric {
+            if !self.bands.contains_key(&key) {
+                let band_series = try!(BandSeries::open(self.bands_dir.join(key.dir_name()), BAND_LEVELS));
+                self.bands.insert(key.clone(), band_series);
+            }
+            try!(self.bands.get_mut(&key).unwrap().append(timestamp, value));
+        }
+
+        self.series.get_mut(&key).unwrap().append(timestamp, raw_data_point.value.clone())
+    }
+
+    pub fn series_keys(&self) -> Vec<&SeriesKey> {
+        let mut keys: Vec<&SeriesKey> = self.series.keys().collect();
+        keys.sort();
+        keys
+    }
+
+    /// Points of given series with timestamps from given range (from inclusive, to exclusive) ordered by timestamp
+    pub fn query(&self, key: &SeriesKey, from: &DateTime<UTC>, to: &DateTime<UTC>) -> Result<Vec<(DateTime<UTC>, DataValue)>, StorageError> {
+        let series = match self.series.get(key) {
+            Some(series) => series,
+            None => return Ok(Vec::new())
+        };
+
+        let points = try!(series.query(try!(timestamp_to_nanos(from)), try!(timestamp_to_nanos(to))));
+        Ok(points.into_iter().map(|(timestamp, value)| (nanos_to_timestamp(timestamp), value)).collect())
+    }
+
+    /// Numeric points of given series reconstructed from approximation band and up to `detail_bands` detail bands;
+    /// each point is the mean of 2^(BAND_LEVELS - detail_bands) stored samples
+    pub fn query_bands(&self, key: &SeriesKey, from: &DateTime<UTC>, to: &DateTime<UTC>, detail_bands: u8) -> Result<Vec<(DateTime<UTC>, f64)>, StorageError> {
+        let from = try!(timestamp_to_nanos(from));
+        let to = try!(timestamp_to_nanos(to));
+
+        let points = try!(match self.bands.get(key) {
+            Some(band_series) => band_series.query(from, to, detail_bands),
+            None => try!(BandSeries::open(self.bands_dir.join(key.dir_name()), BAND_LEVELS)).query(from, to, detail_bands)
+        });
+        Ok(points.into_iter().map(|(timestamp, value)| (nanos_to_timestamp(timestamp), value)).collect())
+    }
+
+    /// Reduces stored resolution of numeric series by deleting its detail bands above `detail_bands`
+    pub fn drop_bands(&mut self, key: &SeriesKey, detail_bands: u8) -> Result<(), StorageError> {
+        if let Some(band_series) = self.bands.get_mut(key) {
+            return band_series.drop_bands(detail_bands)
+        }
+        try!(BandSeries::open(self.bands_dir.join(key.dir_name()), BAND_LEVELS)).drop_bands(detail_bands)
+    }
+
+    pub fn flush(&mut self) -> Result<(), StorageError> {
+        for series in self.series.values_mut() {
+            try!(series.flush());
+        }
+        for band_series in self.bands.values_mut() {
+            try!(band_series.flush());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            error!("Failed to flush storage at '{}': {}", self.series_dir.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    pub use messaging::*;
+    pub use chrono::*;
+    pub use tempdir::TempDir;
+
+    pub fn raw_data_point(component: &str, seconds: i64, value: DataValue) -> RawDataPoint {
+        RawDataPoint {
+            location: "myserver".to_string(),
+            path: "cpu/usage".to_string(),
+            component: component.to_string(),
+            timestamp: UTC.timestamp(1455200000 + seconds, 0),
+            value: value
+        }
+    }
+
+    pub fn at(seconds: i64) -> DateTime<UTC> {
+        UTC.timestamp(1455200000 + seconds, 0)
+    }
+
+    #[test]
+    fn series_key_dir_name_should_round_trip() {
+        for key in vec![
+            SeriesKey::new("myserver", "os/cpu/usage", "user"),
+            SeriesKey::new("", "", ""),
+            SeriesKey::new("my server,1", "..", "zażółć%20")
+        ] {
+            let dir_name = key.dir_name();
+            assert!(!dir_name.contains('/'));
+            assert_eq!(SeriesKey::from_dir_name(&dir_name), Some(key));
+        }
+    }
+
+    #[test]
+    fn nanos_should_convert_to_timestamp_and_back() {
+        for timestamp in vec![UTC.timestamp(1455200000, 123456789), UTC.timestamp(-1000, 1), UTC.timestamp(0, 0)] {
+            assert_eq!(nanos_to_timestamp(timestamp_to_nanos(&timestamp).unwrap()), timestamp);
+        }
+        assert!(timestamp_to_nanos(&UTC.timestamp(10000000000, 0)).is_err());
+    }
+
+    #[test]
+    fn should_query_points_of_series_in_time_range() {
+        let dir = TempDir::new("dms-storage").unwrap();
+        let mut storage = Storage::open(dir.path()).unwrap();
+
+        for second in 0..10 {
+            storage.store(&raw_data_point("user", second, DataValue::Float(second as f64 / 10.0))).unwrap();
+            storage.store(&raw_data_point("system", second, DataValue::Integer(second))).unwrap();
+        }
+
+        assert_eq!(storage.query(&SeriesKey::new("myserver", "cpu/usage", "user"), &at(2), &at(5)).unwrap(), vec![
+            (at(2), DataValue::Float(0.2)),
+            (at(3), DataValue::Float(0.3)),
+            (at(4), DataValue::Float(0.4))
+        ]);
+        assert_eq!(storage.query(&SeriesKey::new("myserver", "cpu/usage", "system"), &at(8), &at(100)).unwrap(), vec![
+            (at(8), DataValue::Integer(8)),
+            (at(9), DataValue::Integer(9))
+        ]);
+        assert_eq!(storage.query(&SeriesKey::new("myserver", "cpu/usage", "iowait"), &at(0), &at(100)).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn should_persist_points_across_reopen() {
+        let dir = TempDir::new("dms-storage").unwrap();
+        {
+            let mut storage = Storage::open(dir.path()).unwrap();
+            for second in 0..(BLOCK_POINTS as i64 + 10) {
+                storage.store(&raw_data_point("user", second, DataValue::Integer(second))).unwrap();
+            }
+            storage.store(&raw_data_point("status", 0, DataValue::Text("ok".to_string()))).unwrap();
+        }
+
+        let storage = Storage::open(dir.path()).unwrap();
+        assert_eq!(storage.series_keys(), vec![
+            &SeriesKey::new("myserver", "cpu/usage", "status"),
+            &SeriesKey::new("myserver", "cpu/usage", "user")
+        ]);
+
+        let points = storage.query(&SeriesKey::new("myserver", "cpu/usage", "user"), &at(0), &at(10000)).unwrap();
+        assert_eq!(points.len(), BLOCK_POINTS + 10);
+        assert_eq!(points[BLOCK_POINTS + 9], (at(BLOCK_POINTS as i64 + 9), DataValue::Integer(BLOCK_POINTS as i64 + 9)));
+
+        assert_eq!(storage.query(&SeriesKey::new("myserver", "cpu/usage", "status"), &at(0), &at(1)).unwrap(), vec![
+            (at(0), DataValue::Text("ok".to_string()))
+        ]);
+    }
+
+    #[test]
+    fn should_store_series_changing_value_type() {
+        let dir = TempDir::new("dms-storage").unwrap();
+        let mut storage = Storage::open(dir.path()).unwrap();
+
+        storage.store(&raw_data_point("user", 0, DataValue::Integer(1))).unwrap();
+        storage.store(&raw_data_point("user", 1, DataValue::Float(1.5))).unwrap();
+        storage.store(&raw_data_point("user", 2, DataValue::Bool(true))).unwrap();
+        storage.store(&raw_data_point("user", 3, DataValue::Bool(false))).unwrap();
+
+        assert_eq!(storage.query(&SeriesKey::new("myserver", "cpu/usage", "user"), &at(0), &at(4)).unwrap(), vec![
+            (at(0), DataValue::Integer(1)),
+            (at(1), DataValue::Float(1.5)),
+            (at(2), DataValue::Bool(true)),
+            (at(3), DataValue::Bool(false))
+        ]);
+    }
+
+    #[test]
+    fn should_store_numeric_series_in_frequency_bands() {
+        let dir = TempDir::new("dms-storage").unwrap();
+        let key = SeriesKey::new("myserver", "cpu/usage", "user");
+        {
+            let mut storage = Storage::open(dir.path()).unwrap();
+            for second in 0..512 {
+                storage.store(&raw_data_point("user", second, DataValue::Integer(second % 4))).unwrap();
+                storage.store(&raw_data_point("status", second, DataValue::Text("ok".to_string()))).unwrap();
+            }
+
+            assert_eq!(storage.query_bands(&key, &at(0), &at(8), BAND_LEVELS).unwrap(), vec![
+                (at(0), 0.0), (at(1), 1.0), (at(2), 2.0), (at(3), 3.0),
+                (at(4), 0.0), (at(5), 1.0), (at(6), 2.0), (at(7), 3.0)
+            ]);
+            assert_eq!(storage.query_bands(&SeriesKey::new("myserver", "cpu/usage", "status"), &at(0), &at(8), BAND_LEVELS).unwrap(), vec![]);
+        }
+
+        let mut storage = Storage::open(dir.path()).unwrap();
+        storage.drop_bands(&key, BAND_LEVELS - 2).unwrap();
+        assert_eq!(storage.query_bands(&key, &at(0), &at(8), BAND_LEVELS).unwrap(), vec![
+            (at(0), 1.5), (at(4), 1.5)
+        ]);
+    }
+
+    #[test]
+    fn should_order_points_stored_out_of_order() {
+        let dir = TempDir::new("dms-storage").unwrap();
+        let mut storage = Storage::open(dir.path()).unwrap();
+
+        storage.store(&raw_data_point("user", 5, DataValue::Integer(5))).unwrap();
+        storage.store(&raw_data_point("user", 1, DataValue::Integer(1))).unwrap();
+        storage.flush().unwrap();
+        storage.store(&raw_data_point("user", 3, DataValue::Integer(3))).unwrap();
+
+        assert_eq!(storage.query(&SeriesKey::new("myserver", "cpu/usage", "user"), &at(0), &at(10)).unwrap(), vec![
+            (at(1), DataValue::Integer(1)),
+            (at(3), DataValue::Integer(3)),
+            (at(5), DataValue::Integer(5))
+        ]);
+    }
+}